@@ -0,0 +1,60 @@
+//! `pw-volume mic-agc`: automatic gain control for the microphone, so that
+//! call apps no longer need to fight over its volume.
+//!
+//! The WebRTC echo canceller's gain control runs as a [`filter`] in front of
+//! the default source.
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde_json::Value;
+
+use crate::filter;
+
+/// An echo canceller with only gain control enabled, recording from `source`.
+/// It also creates a sink for the echo reference, which is passive so that it
+/// doesn't keep other devices awake.
+fn echo_cancel(source: &str) -> Value {
+    serde_json::json!({
+        "name": "libpipewire-module-echo-cancel",
+        "args": {
+            "library.name": "aec/libspa-aec-webrtc",
+            "aec.args": {
+                "webrtc.gain_control": true,
+                "webrtc.extended_filter": false,
+                "webrtc.noise_suppression": false,
+                "webrtc.high_pass_filter": false,
+            },
+            "capture.props": {
+                "node.name": "pw-volume-agc.capture",
+                "node.passive": true,
+                "target.object": source,
+            },
+            "source.props": {
+                "node.name": "pw-volume-agc",
+                "node.description": "Microphone (automatic gain)",
+            },
+            "sink.props": {
+                "node.name": "pw-volume-agc.sink",
+                "node.description": "Echo reference (automatic gain)",
+                "node.passive": true,
+            },
+            "playback.props": {
+                "node.name": "pw-volume-agc.playback",
+            },
+        },
+    })
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("mic-agc")
+        .about("lets PipeWire control the microphone's gain instead of call apps")
+        .setting(AppSettings::ArgRequiredElseHelp)
+        .arg(
+            Arg::with_name("TRANSITION")
+                .possible_values(&["on", "off", "toggle", "status"])
+                .required(true),
+        )
+}
+
+pub fn run(matches: &ArgMatches<'_>) -> anyhow::Result<i32> {
+    filter::AGC.run(matches, echo_cancel)
+}
@@ -0,0 +1,529 @@
+//! `pw-volume apply state.toml`: converges the graph to a declared state, the
+//! default devices, the volume and mute of devices and applications, and the
+//! devices applications play to, so that it can be kept with the rest of a
+//! system's configuration. Only what differs is changed, so applying the same
+//! state twice changes nothing. `pw-volume export-state` writes out the
+//! current state in the same form.
+
+use std::{fmt, fs};
+
+use anyhow::Context;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Config, Scale},
+    cork::{self, Corked},
+    default_name, endpoints, move_stream, node_id, parse_dump, pw_dump, route_command, scale,
+    set_default_name, set_param, set_route, streams, unmarshal, volume_config, PipeWireCommand,
+    PipeWireInterfaceNode, PipeWireObject, Target, VolumeOp,
+};
+
+/// Volumes closer than this to the declared one are left alone.
+const TOLERANCE: f64 = 1e-4;
+
+/// The volume and mute state of a device, by node name or alias.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Level {
+    pub name: String,
+    /// A percentage on the device's scale.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mute: Option<bool>,
+}
+
+/// The volume and mute state of every stream of an application, by name, and
+/// the device they play to or record from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AppLevel {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mute: Option<bool>,
+    /// A node name or alias.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Whether it is paused by `app NAME cork`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub corked: Option<bool>,
+}
+
+/// The contents of a state file. Anything left out is left as it is.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct State {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_sink: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_source: Option<String>,
+    #[serde(rename = "device", skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<Level>,
+    #[serde(rename = "app", skip_serializing_if = "Vec::is_empty")]
+    pub apps: Vec<AppLevel>,
+}
+
+/// A channel volume as a percentage on `scale`, to a hundredth, which is well
+/// within [`TOLERANCE`].
+fn percent(scale: Scale, volume: f64) -> f64 {
+    (scale.scaled(volume) * 10000.0).round() / 100.0
+}
+
+/// How a change is made.
+#[derive(Debug)]
+enum Action<'a> {
+    Default {
+        key: &'static str,
+        name: &'a str,
+    },
+    Route {
+        node: &'a PipeWireInterfaceNode<'a>,
+        cmd: PipeWireCommand,
+    },
+    Stream {
+        id: i64,
+        props: serde_json::Value,
+    },
+    Move {
+        id: i64,
+        node: &'a PipeWireInterfaceNode<'a>,
+    },
+    Cork {
+        name: &'a str,
+        cork: bool,
+    },
+}
+
+/// One change that brings the graph closer to the state: what it changes,
+/// e.g. a device, and which of its settings go from what to what.
+#[derive(Debug)]
+struct Change<'a> {
+    subject: String,
+    diffs: Vec<(&'static str, String, String)>,
+    action: Action<'a>,
+}
+
+/// E.g. `~ speakers: volume 25% -> 40%, mute true -> false`.
+impl fmt::Display for Change<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "~ {}:", self.subject)?;
+        for (i, (field, from, to)) in self.diffs.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(f, "{} {} {} -> {}", separator, field, from, to)?;
+        }
+        Ok(())
+    }
+}
+
+fn differs(current: &[f64], want: f64) -> bool {
+    current.iter().any(|v| (v - want).abs() > TOLERANCE)
+}
+
+/// The volume and mute differences between channel volumes and a level, as
+/// fractions on `scale`.
+fn level_diffs(
+    scale: Scale,
+    volumes: &[f64],
+    mute: bool,
+    want_volume: Option<f64>,
+    want_mute: Option<bool>,
+) -> Vec<(&'static str, String, String)> {
+    let mut diffs = Vec::new();
+    if let Some(want) = want_volume {
+        let scaled: Vec<f64> = volumes.iter().map(|v| scale.scaled(*v)).collect();
+        if differs(&scaled, want / 100.0) {
+            let from = volumes.first().map_or(0.0, |v| percent(scale, *v));
+            diffs.push(("volume", format!("{}%", from), format!("{}%", want)));
+        }
+    }
+    if let Some(want) = want_mute.filter(|want| *want != mute) {
+        diffs.push(("mute", mute.to_string(), want.to_string()));
+    }
+    diffs
+}
+
+/// The changes needed to reach `state`, in the order they should be made.
+/// Devices that aren't there are reported on stderr and skipped, since a
+/// state usually names devices that come and go.
+fn plan<'a>(
+    state: &'a State,
+    config: &'a Config,
+    obj: &'a [PipeWireObject<'a>],
+    corked: &Corked,
+) -> Vec<Change<'a>> {
+    let resolve = |name: &'a str| config.aliases.get(name).map_or(name, String::as_str);
+    // streams aren't devices, so `[devices]` doesn't apply to them
+    let stream_scale = volume_config().scale;
+    let mut changes = Vec::new();
+    for (key, name) in [
+        ("default.audio.sink", &state.default_sink),
+        ("default.audio.source", &state.default_source),
+    ] {
+        if let Some(name) = name.as_deref().map(resolve) {
+            let current = default_name(obj, key);
+            if current != Some(name) {
+                changes.push(Change {
+                    subject: key.to_string(),
+                    diffs: vec![(
+                        "name",
+                        current.unwrap_or("(none)").to_string(),
+                        name.to_string(),
+                    )],
+                    action: Action::Default { key, name },
+                });
+            }
+        }
+    }
+    for level in &state.devices {
+        let name = resolve(&level.name);
+        let (node, route) = match node_id(obj, name).and_then(|id| parse_dump(obj, Target::Id(id)))
+        {
+            Ok(found) => found,
+            Err(e) => {
+                eprintln!("pw-volume: skipping {}: {:#}", level.name, e);
+                continue;
+            }
+        };
+        let diffs = level_diffs(
+            scale(node.info.props.node_name),
+            &route.props.channel_volumes,
+            route.props.mute,
+            level.volume,
+            level.mute,
+        );
+        let mute = level.mute.unwrap_or(route.props.mute);
+        let cmd = match (level.volume, diffs.first()) {
+            (_, None) => continue,
+            (Some(volume), Some(("volume", ..))) => {
+                let mut cmd = route_command(VolumeOp::Set(volume / 100.0), node, route);
+                cmd.props.mute = mute;
+                cmd
+            }
+            _ => route_command(VolumeOp::Mute(Some(mute)), node, route),
+        };
+        changes.push(Change {
+            subject: level.name.clone(),
+            diffs,
+            action: Action::Route { node, cmd },
+        });
+    }
+    for level in &state.apps {
+        let target = match level.target.as_deref() {
+            Some(target) => {
+                match node_id(obj, resolve(target)).and_then(|id| parse_dump(obj, Target::Id(id))) {
+                    Ok((node, _)) => Some((target, node)),
+                    Err(e) => {
+                        eprintln!("pw-volume: not moving {}: {:#}", level.name, e);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        for (stream, peers) in streams(obj) {
+            let props = &stream.info.props;
+            if props.application_name.as_deref() != Some(level.name.as_str()) {
+                continue;
+            }
+            let subject = format!("{} ({})", level.name, stream.id);
+            if let Some(current) = stream.info.params.volume() {
+                let diffs = level_diffs(
+                    stream_scale,
+                    &current.channel_volumes,
+                    current.mute,
+                    level.volume,
+                    level.mute,
+                );
+                let mut want = serde_json::Map::new();
+                for (field, ..) in &diffs {
+                    match *field {
+                        "volume" => {
+                            let volume =
+                                stream_scale.linear(level.volume.unwrap_or_default() / 100.0);
+                            want.insert(
+                                "channelVolumes".to_string(),
+                                vec![volume; current.channel_volumes.len()].into(),
+                            );
+                        }
+                        _ => {
+                            want.insert("mute".to_string(), level.mute.into());
+                        }
+                    }
+                }
+                if !diffs.is_empty() {
+                    changes.push(Change {
+                        subject: subject.clone(),
+                        diffs,
+                        action: Action::Stream {
+                            id: stream.id,
+                            props: want.into(),
+                        },
+                    });
+                }
+            }
+            // a target only moves the streams going its way
+            if let Some((target, node)) = target.filter(|(_, node)| {
+                let direction = match node.info.props.media_class {
+                    Some("Audio/Source") => "Stream/Input/",
+                    _ => "Stream/Output/",
+                };
+                !peers.contains(&node.id) && props.media_class.starts_with(direction)
+            }) {
+                let from = obj
+                    .iter()
+                    .find_map(|o| match o {
+                        PipeWireObject::Node(n) if peers.contains(&n.id) => {
+                            Some(n.info.props.node_name)
+                        }
+                        _ => None,
+                    })
+                    .unwrap_or("(none)");
+                changes.push(Change {
+                    subject,
+                    diffs: vec![("target", from.to_string(), target.to_string())],
+                    action: Action::Move {
+                        id: stream.id,
+                        node,
+                    },
+                });
+            }
+        }
+        // only a playing application has anything to pause
+        if let Some(want) = level.corked {
+            let current = corked.contains_key(&level.name);
+            if want != current && (!want || cork::playing(obj, &level.name)) {
+                changes.push(Change {
+                    subject: level.name.clone(),
+                    diffs: vec![("corked", current.to_string(), want.to_string())],
+                    action: Action::Cork {
+                        name: &level.name,
+                        cork: want,
+                    },
+                });
+            }
+        }
+    }
+    changes
+}
+
+fn make(action: &Action<'_>) -> anyhow::Result<()> {
+    match action {
+        Action::Default { key, name } => {
+            set_default_name(&key.replace("default.", "default.configured."), name)
+        }
+        Action::Route { node, cmd } => set_route(node, cmd),
+        Action::Stream { id, props } => set_param(*id, "Props", &props.to_string()),
+        Action::Move { id, node } => move_stream(*id, node),
+        Action::Cork { name, cork } => cork::set(name, *cork),
+    }
+}
+
+/// The state of the graph, naming nodes by their alias where they have one.
+/// An application's level and device are taken from its first stream.
+pub fn export(config: &Config, obj: &[PipeWireObject<'_>], corked: &Corked) -> State {
+    let alias = |name: &str| {
+        config
+            .aliases
+            .iter()
+            .find(|(_, node)| *node == name)
+            .map_or(name, |(alias, _)| alias.as_str())
+            .to_string()
+    };
+    let endpoints = endpoints(obj);
+    let stream_scale = volume_config().scale;
+    let mut apps: Vec<AppLevel> = Vec::new();
+    for (stream, peers) in streams(obj) {
+        let name = match &stream.info.props.application_name {
+            Some(name) if !apps.iter().any(|app| app.name == *name) => name,
+            _ => continue,
+        };
+        let volume = stream.info.params.volume();
+        let target = endpoints
+            .iter()
+            .find(|(node, _)| peers.contains(&node.id))
+            .map(|(node, _)| alias(node.info.props.node_name));
+        apps.push(AppLevel {
+            name: name.to_string(),
+            volume: volume
+                .and_then(|v| v.channel_volumes.first().map(|v| percent(stream_scale, *v))),
+            mute: volume.map(|v| v.mute),
+            target,
+            corked: corked.contains_key(name.as_ref()).then_some(true),
+        });
+    }
+    State {
+        default_sink: default_name(obj, "default.audio.sink").map(alias),
+        default_source: default_name(obj, "default.audio.source").map(alias),
+        devices: endpoints
+            .iter()
+            .map(|(node, route)| Level {
+                name: alias(node.info.props.node_name),
+                volume: route
+                    .props
+                    .channel_volumes
+                    .first()
+                    .map(|v| percent(scale(node.info.props.node_name), *v)),
+                mute: Some(route.props.mute),
+            })
+            .collect(),
+        apps,
+    }
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("apply")
+        .about("changes the default devices and volumes to match a state file")
+        .arg(
+            Arg::with_name("FILE")
+                .help("TOML file declaring default-sink, default-source, [[device]]s, and [[app]]s")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("check").long("check").help(
+                "only list what would change and from what, exiting with 1 if anything would",
+            ),
+        )
+}
+
+pub fn export_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("export-state")
+        .about("prints the default devices and volumes as a state file for apply")
+}
+
+pub fn run_export(config: &Config) -> anyhow::Result<()> {
+    let buf = pw_dump()?;
+    let obj = unmarshal(&buf)?;
+    print!("{}", toml::to_string(&export(config, &obj, &cork::load()))?);
+    Ok(())
+}
+
+/// Prints the changes needed to reach `state` and, unless only checking,
+/// makes them, returning how many there were.
+pub fn converge(state: &State, config: &Config, check: bool) -> anyhow::Result<usize> {
+    let buf = pw_dump()?;
+    let obj = unmarshal(&buf)?;
+    let changes = plan(state, config, &obj, &cork::load());
+    for change in &changes {
+        println!("{}", change);
+        if !check {
+            make(&change.action)?;
+        }
+    }
+    Ok(changes.len())
+}
+
+pub fn run(matches: &ArgMatches<'_>, config: &Config) -> anyhow::Result<i32> {
+    let path = matches.value_of("FILE").unwrap_or_default();
+    let contents = fs::read_to_string(path).with_context(|| format!("failed to read {}", path))?;
+    let state: State =
+        toml::from_str(&contents).with_context(|| format!("failed to parse {}", path))?;
+    let check = matches.is_present("check");
+    let changes = converge(&state, config, check)?;
+    if !check {
+        return Ok(0);
+    }
+    match changes {
+        0 => {
+            println!("nothing to change");
+            Ok(0)
+        }
+        1 => {
+            println!("1 change to make");
+            Ok(1)
+        }
+        n => {
+            println!("{} changes to make", n);
+            Ok(1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn export_round_trip() -> anyhow::Result<()> {
+        let path: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "testdata",
+            "with_discord.txt",
+        ]
+        .iter()
+        .collect();
+        let buf = fs::read(path)?;
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf)?;
+        let config = Config::default();
+        let state: State = toml::from_str(&toml::to_string(&export(
+            &config,
+            &obj,
+            &Corked::default(),
+        ))?)?;
+        assert!(!state.devices.is_empty());
+        assert!(state
+            .apps
+            .iter()
+            .any(|app| app.name == "WEBRTC VoiceEngine"));
+        // the graph is already in the state it was exported from
+        assert!(plan(&state, &config, &obj, &Corked::default()).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn plan_changes() -> anyhow::Result<()> {
+        let path: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "testdata",
+            "with_discord.txt",
+        ]
+        .iter()
+        .collect();
+        let buf = fs::read(path)?;
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf)?;
+        let sink = default_name(&obj, "default.audio.sink").unwrap_or_default();
+        let state: State = toml::from_str(&format!(
+            r#"
+            default-sink = "{}"
+
+            [[device]]
+            name = "{}"
+            mute = false
+
+            [[device]]
+            name = "not-plugged-in"
+            volume = 50
+
+            [[app]]
+            name = "WEBRTC VoiceEngine"
+            volume = 100
+            mute = true
+            corked = false
+            "#,
+            sink, sink
+        ))?;
+        let config = Config::default();
+        let corked = Corked::from([("WEBRTC VoiceEngine".to_string(), Vec::new())]);
+        let changes: Vec<String> = plan(&state, &config, &obj, &corked)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        // the default and the device are already as declared, and the
+        // streams are already at 100%
+        assert_eq!(
+            changes,
+            [
+                "~ WEBRTC VoiceEngine (77): mute false -> true",
+                "~ WEBRTC VoiceEngine (85): mute false -> true",
+                "~ WEBRTC VoiceEngine: corked true -> false",
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+//! The basic subcommands on top of other tools than pw-dump and pw-cli, for
+//! systems where PipeWire's own tools aren't there: WirePlumber's wpctl, or
+//! pactl talking to pipewire-pulse, e.g. from inside a Flatpak sandbox.
+//!
+//! `--backend` picks one. By default pw-volume uses PipeWire's tools, and
+//! only falls back to wpctl, and then pactl, when they can't be found. A
+//! build with the `native` feature can also talk to PipeWire directly, with
+//! no tools at all.
+
+use std::{io::Write, sync::atomic::Ordering};
+
+use clap::ArgMatches;
+
+#[cfg(feature = "native")]
+use crate::native;
+use crate::{
+    change_delta, check_expected,
+    config::Config,
+    error::{self, Failure, Kind},
+    fade, feedback,
+    format::Status,
+    hook, log, notify, pactl, query, render_statuses, scale, stats, volume_config, wpctl, DRY_RUN,
+};
+
+/// The subcommands that the backends can run.
+pub const COMMANDS: [&str; 8] = [
+    "mute",
+    "change",
+    "fade-to",
+    "status",
+    "get-volume",
+    "get-mute",
+    "is-muted",
+    "is-over",
+];
+
+/// The backends that `--backend` can choose, besides `auto` and `pipewire`,
+/// in the order they are tried when falling back.
+pub const FALLBACKS: [&str; 2] = ["wpctl", "pulse"];
+
+/// A sink or source as a backend sees it.
+pub trait Node {
+    fn status(&self) -> anyhow::Result<Status>;
+
+    /// The volume on the node's scale and the mute state, which may take
+    /// less to find out than the whole status.
+    fn volume(&self) -> anyhow::Result<(f64, bool)> {
+        let status = self.status()?;
+        Ok((status.volume, status.mute))
+    }
+
+    /// Sets every channel to a linear volume.
+    fn set_volume(&self, volume: f64) -> anyhow::Result<()>;
+
+    /// Mutes or unmutes the node, or toggles it with `None`.
+    fn set_mute(&self, mute: Option<bool>) -> anyhow::Result<()>;
+}
+
+/// Runs one of the [`COMMANDS`] on the `--target` node through `backend`,
+/// one of the [`FALLBACKS`] or `native`.
+pub fn run(
+    backend: &str,
+    matches: &ArgMatches<'_>,
+    config: &Config,
+    out: &mut dyn Write,
+) -> anyhow::Result<i32> {
+    // a group's members are looked up by name in pw-dump's output, which
+    // would otherwise go unnoticed as a change to the default node alone
+    if let ("change", Some(arg)) = matches.subcommand() {
+        if arg.is_present("group") {
+            return Err(Failure::new(
+                Kind::MissingTool,
+                format!(
+                    "change --group needs pw-dump, so can't run with --backend {}",
+                    backend
+                ),
+            )
+            .into());
+        }
+    }
+    match backend {
+        "wpctl" => run_on(&wpctl::node(matches, config)?, matches, config, out),
+        "pulse" => run_on(&pactl::node(matches, config)?, matches, config, out),
+        #[cfg(feature = "native")]
+        "native" => run_on(&native::node(matches, config)?, matches, config, out),
+        #[cfg(not(feature = "native"))]
+        "native" => Err(Failure::new(
+            Kind::MissingTool,
+            "--backend native needs pw-volume built with --features native",
+        )
+        .into()),
+        _ => unreachable!("{} is not a backend of its own", backend),
+    }
+}
+
+/// Runs the command through each of the [`FALLBACKS`] in turn, after
+/// PipeWire's own tools failed with `err`, until one of them can run it.
+pub fn fallback(
+    matches: &ArgMatches<'_>,
+    config: &Config,
+    err: anyhow::Error,
+    out: &mut dyn Write,
+) -> anyhow::Result<i32> {
+    log::debug(1, format_args!("{:#}", err));
+    for backend in FALLBACKS {
+        log::debug(1, format_args!("falling back to {}", backend));
+        match run(backend, matches, config, out) {
+            Err(e) if error::kind(&e) == Kind::MissingTool => {
+                log::debug(1, format_args!("{:#}", e))
+            }
+            result => return result,
+        }
+    }
+    Err(err)
+}
+
+fn run_on(
+    node: &dyn Node,
+    matches: &ArgMatches<'_>,
+    config: &Config,
+    out: &mut dyn Write,
+) -> anyhow::Result<i32> {
+    let (name, arg) = match matches.subcommand() {
+        (name, Some(arg)) => (name, arg),
+        _ => unreachable!("argument parsing should have failed by now"),
+    };
+    if matches!(name, "mute" | "change" | "fade-to")
+        && (arg.is_present("if-volume") || arg.is_present("if-mute"))
+    {
+        let (volume, mute) = node.volume()?;
+        let current = Status {
+            volume,
+            mute,
+            ..Status::default()
+        };
+        check_expected(arg, &current)?;
+    }
+    // what the node was at, where it was read anyway, for the hooks
+    let mut before = None;
+    match name {
+        "change" | "fade-to" if arg.is_present("over") => {
+            let (current, _) = node.volume()?;
+            let to = fade::target(name, arg, config, current)?;
+            let over = fade::duration(arg.value_of("over").unwrap_or_default())
+                .map_err(anyhow::Error::msg)?;
+            return fade::run(node, to, over, config.fade).map(|()| 0);
+        }
+        "mute" => node.set_mute(match arg.value_of("TRANSITION") {
+            Some("on") => Some(true),
+            Some("toggle") => None,
+            _ => Some(false),
+        })?,
+        "change" => {
+            let status = node.status()?;
+            let max = volume_config().max / 100.0;
+            let new = (status.volume + change_delta(arg, config)?).clamp(0.0, max);
+            node.set_volume(scale(&status.node).linear(new))?;
+            // as with a route, changing the volume unmutes
+            node.set_mute(Some(false))?;
+            before = Some(status);
+        }
+        "status" => {
+            if arg.is_present("all") {
+                return Err(Failure::new(Kind::MissingTool, "status --all needs pw-dump").into());
+            }
+            let status = node.status()?;
+            let status = Status {
+                fade: fade::running(status.id),
+                ..status
+            };
+            let (report, muted) = render_statuses(arg, config, &[status])?;
+            writeln!(out, "{}", report)?;
+            return Ok(if arg.is_present("exit-status") && muted {
+                1
+            } else {
+                0
+            });
+        }
+        _ => return query(name, arg, &node.status()?, out),
+    }
+    // reading the result back takes running the tool again
+    let notify = notify::config(config.notify, arg);
+    let feedback = feedback::config(&config.feedback, arg);
+    let click = feedback.enabled && name == "change";
+    let hooks = hook::commands(config.on_change.as_deref(), arg);
+    let count = stats::enabled() && name == "change";
+    if (notify.enabled || click || hook::any(&hooks) || count) && !DRY_RUN.load(Ordering::Relaxed) {
+        let status = node.status()?;
+        notify::show(notify, &status.name, status.volume, status.mute);
+        if click {
+            feedback::play(&feedback, &status.node);
+        }
+        hook::run(&hooks, before.as_ref(), &status);
+        if count {
+            stats::volume(&status);
+        }
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::app;
+
+    #[test]
+    fn group_needs_pw_dump() {
+        let matches = app().get_matches_from(["pw-volume", "change", "--group", "media", "+5%"]);
+        for backend in FALLBACKS.iter().chain(&["native"]) {
+            let err = run(backend, &matches, &Config::default(), &mut Vec::new()).unwrap_err();
+            assert_eq!(error::kind(&err), Kind::MissingTool);
+            assert!(err.to_string().starts_with("change --group needs pw-dump"));
+        }
+    }
+}
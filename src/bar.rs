@@ -0,0 +1,133 @@
+//! Refreshing a status bar after a change. Bars such as Waybar, i3blocks, and
+//! dwmblocks re-run a module when sent a signal, which is usually done by
+//! following pw-volume with `pkill -RTMIN+8 waybar`. `--notify-bar
+//! RTMIN+8:waybar`, or `notify-bar` in `[status]`, sends it from pw-volume
+//! instead, once a subcommand that changes something has succeeded.
+
+use std::{convert::TryFrom, str::FromStr};
+
+use clap::Arg;
+use serde::Deserialize;
+
+use crate::{dry_run, log, shell_words, tools};
+
+/// The subcommands that change something, after which the bar is signalled.
+pub const WRITES: [&str; 14] = [
+    "mute",
+    "change",
+    "fade-to",
+    "pamixer",
+    "apply",
+    "preset",
+    "scene",
+    "reset",
+    "app",
+    "upmix",
+    "doctor",
+    "set-port",
+    "set-profile",
+    "bluetooth",
+];
+
+/// The signals that aren't real-time ones, without their `SIG` prefix.
+const NAMES: [&str; 10] = [
+    "HUP", "INT", "QUIT", "USR1", "USR2", "ALRM", "TERM", "CONT", "WINCH", "IO",
+];
+
+/// A signal and the name of the processes it is sent to, e.g.
+/// `RTMIN+8:waybar`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String")]
+pub struct BarSignal {
+    signal: String,
+    process: String,
+}
+
+/// Whether pkill knows the signal: a name, `RTMIN+N`, `RTMAX-N`, or a
+/// number.
+fn is_signal(signal: &str) -> bool {
+    let signal = signal.strip_prefix("SIG").unwrap_or(signal);
+    let offset = |n: &str| n.parse::<u8>().is_ok_and(|n| n <= 30);
+    NAMES.contains(&signal)
+        || matches!(signal, "RTMIN" | "RTMAX")
+        || signal.strip_prefix("RTMIN+").is_some_and(offset)
+        || signal.strip_prefix("RTMAX-").is_some_and(offset)
+        || signal.parse::<u8>().is_ok_and(|n| (1..=64).contains(&n))
+}
+
+impl FromStr for BarSignal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((signal, process)) if is_signal(signal) && !process.is_empty() => Ok(BarSignal {
+                signal: signal.to_string(),
+                process: process.to_string(),
+            }),
+            _ => Err(format!(
+                r#""{}" is not a signal and a process, e.g. RTMIN+8:waybar"#,
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for BarSignal {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// `--notify-bar`, which every subcommand takes.
+pub fn arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("notify-bar")
+        .long("notify-bar")
+        .value_name("SIGNAL:PROCESS")
+        .help("after a change, send the signal to the bar to refresh it, e.g. RTMIN+8:waybar for pkill -RTMIN+8 waybar")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .validator(|s| s.parse::<BarSignal>().map(|_| ()))
+        .global(true)
+}
+
+/// Sends each signal with pkill. A bar that isn't running isn't an error,
+/// and failing to signal one doesn't fail the change.
+pub fn send(signals: &[BarSignal]) {
+    for BarSignal { signal, process } in signals {
+        let flag = format!("-{}", signal);
+        let args = [flag.as_str(), process.as_str()];
+        if dry_run("pkill", &args) {
+            continue;
+        }
+        log::debug(1, format_args!("running {}", shell_words("pkill", &args)));
+        match tools::status(tools::command("pkill").args(args)) {
+            // 1 means no process matched
+            Ok(status) if status.success() || status.code() == Some(1) => (),
+            Ok(status) => log::debug(1, format_args!("pkill exited with {}", status)),
+            Err(e) => log::debug(1, format_args!("failed to execute pkill: {}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("RTMIN+8:waybar" => Some("-RTMIN+8 waybar".to_string()))]
+    #[test_case("SIGRTMIN+10:i3blocks" => Some("-SIGRTMIN+10 i3blocks".to_string()))]
+    #[test_case("USR1:dwmblocks" => Some("-USR1 dwmblocks".to_string()))]
+    #[test_case("42:bar" => Some("-42 bar".to_string()))]
+    #[test_case("RTMIN+8" => None)]
+    #[test_case("RTMIN+8:" => None)]
+    #[test_case("KILLALL:waybar" => None)]
+    #[test_case("RTMIN+99:waybar" => None)]
+    fn parse(s: &str) -> Option<String> {
+        let BarSignal { signal, process } = s.parse().ok()?;
+        Some(format!("-{} {}", signal, process))
+    }
+}
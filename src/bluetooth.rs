@@ -0,0 +1,245 @@
+//! `pw-volume bluetooth`: flips a Bluetooth headset between A2DP, which plays
+//! music in high quality but leaves the microphone off, and HFP or HSP, the
+//! headset profiles that turn the microphone on at phone quality, e.g. to
+//! join a call. Both are profiles of the headset's device, which `set-profile`
+//! switches too, but their names differ by codec, so this picks the best one.
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use crate::{
+    error::{Failure, Kind},
+    log, port, profile, pw_dump, unmarshal, DeviceProfile, PipeWireInterfaceDevice, PipeWireObject,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    /// A2DP.
+    Music,
+    /// HFP or HSP.
+    Headset,
+}
+
+impl Mode {
+    /// The start of the names of the mode's profiles, which go on with their
+    /// codec, e.g. `a2dp-sink-aac`.
+    fn prefix(self) -> &'static str {
+        match self {
+            Mode::Music => "a2dp-sink",
+            Mode::Headset => "headset-head-unit",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Mode::Music => "A2DP",
+            Mode::Headset => "HFP/HSP",
+        }
+    }
+
+    fn of(profile: &DeviceProfile<'_>) -> Option<Mode> {
+        [Mode::Music, Mode::Headset]
+            .iter()
+            .copied()
+            .find(|mode| mode.codec(profile).is_some())
+    }
+
+    /// The codec of a profile of this mode, which is empty for the profile
+    /// that leaves the codec to PipeWire.
+    fn codec<'a>(self, profile: &'a DeviceProfile<'_>) -> Option<&'a str> {
+        match profile.name.strip_prefix(self.prefix())? {
+            "" => Some(""),
+            rest => rest.strip_prefix('-'),
+        }
+    }
+}
+
+fn device_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("DEVICE")
+        .help("the headset's id, name, or description; the default sink's, or the only one, if left out")
+        .takes_value(true)
+}
+
+fn codec_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("codec")
+        .long("codec")
+        .value_name("CODEC")
+        .help("the codec to use, e.g. aac or msbc, rather than the best one")
+        .takes_value(true)
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("bluetooth")
+        .about("switches a Bluetooth headset between music (A2DP) and headset (HFP/HSP) profiles")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("music")
+                .alias("a2dp")
+                .about("plays in high quality, with the microphone off")
+                .arg(device_arg())
+                .arg(codec_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("headset")
+                .alias("hfp")
+                .about("turns the microphone on, e.g. for a call, at phone quality")
+                .arg(device_arg())
+                .arg(codec_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("toggle")
+                .about("switches to headset from music, and to music otherwise")
+                .arg(device_arg()),
+        )
+}
+
+/// The Bluetooth device that `arg` is, or else the default sink's, or else
+/// the only one connected.
+fn device<'a>(
+    obj: &'a [PipeWireObject<'a>],
+    arg: Option<&str>,
+) -> anyhow::Result<&'a PipeWireInterfaceDevice<'a>> {
+    let is_bluetooth = |device: &PipeWireInterfaceDevice<'_>| {
+        device
+            .info
+            .props
+            .as_ref()
+            .and_then(|props| props.device_api)
+            == Some("bluez5")
+    };
+    if let Some(arg) = arg {
+        let device = port::device(obj, Some(arg))?;
+        if !is_bluetooth(device) {
+            return Err(Failure::new(
+                Kind::Other,
+                format!("{} isn't a Bluetooth device", port::describe(device)),
+            )
+            .into());
+        }
+        return Ok(device);
+    }
+    if let Ok(device) = port::device(obj, None) {
+        if is_bluetooth(device) {
+            return Ok(device);
+        }
+    }
+    let devices: Vec<_> = obj
+        .iter()
+        .filter_map(|o| match o {
+            PipeWireObject::Device(d)
+                if d.typ == "PipeWire:Interface:Device" && is_bluetooth(d) =>
+            {
+                Some(d)
+            }
+            _ => None,
+        })
+        .collect();
+    match devices.as_slice() {
+        [device] => Ok(device),
+        [] => Err(Failure::new(Kind::NoDevice, "no Bluetooth audio device is connected").into()),
+        devices => {
+            let names: Vec<_> = devices.iter().map(|d| port::describe(d)).collect();
+            Err(Failure::new(
+                Kind::Other,
+                format!(
+                    "several Bluetooth devices are connected; name one of {}",
+                    names.join(", ")
+                ),
+            )
+            .into())
+        }
+    }
+}
+
+/// The profile of the mode to switch to: the one with the codec, or else the
+/// highest priority one that is available.
+fn choose<'a>(
+    device: &'a PipeWireInterfaceDevice<'a>,
+    mode: Mode,
+    codec: Option<&str>,
+) -> anyhow::Result<&'a DeviceProfile<'a>> {
+    let profiles = &device.info.params.enum_profile;
+    let chosen = profiles
+        .iter()
+        .filter(|profile| match (mode.codec(profile), codec) {
+            (Some(found), Some(codec)) => found == codec,
+            (Some(_), None) => profile.available != Some("no"),
+            (None, _) => false,
+        })
+        .max_by_key(|profile| profile.priority);
+    if let Some(profile) = chosen {
+        return Ok(profile);
+    }
+    let message = match codec {
+        Some(codec) => {
+            let codecs: Vec<_> = profiles
+                .iter()
+                .filter_map(|profile| mode.codec(profile))
+                .filter(|codec| !codec.is_empty())
+                .collect();
+            format!(
+                "{} has no {} profile with the codec {}; its codecs are {}",
+                port::describe(device),
+                mode.name(),
+                codec,
+                codecs.join(", ")
+            )
+        }
+        None => format!("{} has no {} profile", port::describe(device), mode.name()),
+    };
+    Err(Failure::new(Kind::Other, message).into())
+}
+
+pub fn run(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let (name, arg) = match matches.subcommand() {
+        (name, Some(arg)) => (name, arg),
+        _ => unreachable!("a subcommand is required"),
+    };
+    let buf = pw_dump()?;
+    let obj = unmarshal(&buf)?;
+    let device = device(&obj, arg.value_of("DEVICE"))?;
+    let mode = match name {
+        "music" => Mode::Music,
+        "headset" => Mode::Headset,
+        _ => match device.info.params.profile.first().and_then(Mode::of) {
+            Some(Mode::Music) => Mode::Headset,
+            _ => Mode::Music,
+        },
+    };
+    let profile = choose(device, mode, arg.value_of("codec"))?;
+    log::debug(
+        1,
+        format_args!("switching {} to {}", port::describe(device), profile.name),
+    );
+    profile::switch(device, profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(Mode::Music, None => Ok(7); "best music profile")]
+    #[test_case(Mode::Music, Some("sbc") => Ok(4))]
+    #[test_case(Mode::Headset, None => Ok(260); "best headset profile")]
+    #[test_case(Mode::Headset, Some("cvsd") => Ok(259))]
+    #[test_case(Mode::Music, Some("ldac") => Err("Galaxy Buds+ (8CFA) has no A2DP profile with the codec ldac; its codecs are sbc, sbc_xq, aac".to_string()))]
+    fn profiles(mode: Mode, codec: Option<&str>) -> Result<i64, String> {
+        let path: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "testdata",
+            "dump_aria_16.txt",
+        ]
+        .iter()
+        .collect();
+        let buf = fs::read(path).unwrap();
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf).unwrap();
+        let device = device(&obj, None).map_err(|e| e.to_string())?;
+        choose(device, mode, codec)
+            .map(|profile| profile.index)
+            .map_err(|e| e.to_string())
+    }
+}
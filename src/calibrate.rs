@@ -0,0 +1,215 @@
+//! `pw-volume calibrate`: per-channel trims, found by ear with a test tone and
+//! applied to every volume change afterwards. They are stored in
+//! `$XDG_STATE_HOME/pw-volume/trims.toml`.
+
+use std::{
+    collections::BTreeMap,
+    f64::consts::PI,
+    fs,
+    io::{self, BufRead, Write},
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, ensure, Context};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::state_dir, set_route, tools, volume_command, DeviceRoute, PipeWireInterfaceNode,
+};
+
+pub const SAMPLE_RATE: u32 = 48_000;
+
+/// Each answer moves the trim by this many dB.
+const STEP_DB: f64 = 1.0;
+
+/// Trims in dB for each channel, relative to the first, by node name.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(transparent)]
+pub struct Trims(BTreeMap<String, Vec<f64>>);
+
+fn ratio(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+impl Trims {
+    fn path() -> anyhow::Result<PathBuf> {
+        state_dir()
+            .map(|dir| dir.join("trims.toml"))
+            .ok_or_else(|| anyhow!("failed to determine state directory"))
+    }
+
+    pub fn load() -> anyhow::Result<Trims> {
+        let path = Trims::path()?;
+        match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents)
+                .with_context(|| format!("failed to parse {}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Trims::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let path = Trims::path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, toml::to_string(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Derives every channel's volume from the first one's, if the node has
+    /// been calibrated for this many channels.
+    pub fn apply(&self, node: &str, channel_volumes: &mut [f64]) {
+        let trims = match self.0.get(node) {
+            Some(trims) if trims.len() == channel_volumes.len() => trims,
+            _ => return,
+        };
+        let base = channel_volumes[0];
+        for (vol, db) in channel_volumes.iter_mut().zip(trims) {
+            *vol = (base * ratio(*db)).clamp(0.0, 1.0);
+        }
+    }
+}
+
+/// A 16-bit WAV file of interleaved samples from -1.0 to 1.0.
+pub fn wav(channels: u16, samples: &[f64]) -> Vec<u8> {
+    let data_len = samples.len() as u32 * 2;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * u32::from(channels) * 2).to_le_bytes());
+    wav.extend_from_slice(&(channels * 2).to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        let value = (sample * f64::from(i16::MAX)) as i16;
+        wav.extend_from_slice(&value.to_le_bytes());
+    }
+    wav
+}
+
+/// A WAV file with a 440 Hz tone at the given amplitude on each channel.
+fn tone(amplitudes: &[f64], seconds: f64) -> Vec<u8> {
+    let frames = (f64::from(SAMPLE_RATE) * seconds) as u32;
+    let mut samples = Vec::with_capacity(frames as usize * amplitudes.len());
+    for frame in 0..frames {
+        let sample = (2.0 * PI * 440.0 * f64::from(frame) / f64::from(SAMPLE_RATE)).sin();
+        samples.extend(amplitudes.iter().map(|amplitude| sample * amplitude));
+    }
+    wav(amplitudes.len() as u16, &samples)
+}
+
+/// Plays a tone on one channel of the node, blocking until it finishes.
+fn play(node: &str, channels: usize, channel: usize, db: f64) -> anyhow::Result<()> {
+    let mut amplitudes = vec![0.0; channels];
+    // leave headroom so that positive trims don't clip
+    amplitudes[channel] = 0.25 * ratio(db);
+    let path = std::env::temp_dir().join(format!("pw-volume-tone-{}.wav", std::process::id()));
+    fs::write(&path, tone(&amplitudes, 1.0))?;
+    let status = tools::status(
+        tools::command("pw-play")
+            .arg("--target")
+            .arg(node)
+            .arg(&path),
+    )
+    .context("failed to execute pw-play");
+    let _ = fs::remove_file(&path);
+    ensure!(status?.success(), "pw-play did not exit successfully");
+    Ok(())
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("calibrate")
+        .about("balances a device's channels by ear with a test tone")
+        .arg(
+            Arg::with_name("reset")
+                .long("reset")
+                .help("forget the device's trims"),
+        )
+}
+
+pub fn run(
+    matches: &ArgMatches<'_>,
+    node: &PipeWireInterfaceNode<'_>,
+    route: &DeviceRoute<'_>,
+) -> anyhow::Result<i32> {
+    let name = node.info.props.node_name;
+    let channels = route.props.channel_volumes.len();
+    let mut trims = Trims::load()?;
+    if matches.is_present("reset") {
+        trims.0.remove(name);
+        trims.save()?;
+        return Ok(0);
+    }
+    ensure!(channels > 1, "{} has only one channel", name);
+
+    let mut db = vec![0.0; channels];
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    for (channel, trim) in db.iter_mut().enumerate().skip(1) {
+        loop {
+            play(name, channels, 0, 0.0)?;
+            play(name, channels, channel, *trim)?;
+            print!(
+                "Is channel {} [l]ouder, [q]uieter, or the [s]ame as channel 1? ",
+                channel + 1
+            );
+            io::stdout().flush()?;
+            let answer = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(1),
+            };
+            match answer.trim() {
+                "l" => *trim -= STEP_DB,
+                "q" => *trim += STEP_DB,
+                "s" | "" => break,
+                _ => println!("please answer l, q, or s"),
+            }
+        }
+    }
+    trims.0.insert(name.to_string(), db);
+    trims.save()?;
+
+    // reapply the volume so the trims take effect right away
+    let channel_volumes = route.props.channel_volumes.clone();
+    set_route(node, &volume_command(node, route, channel_volumes))?;
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(&[0.5, 0.5] => vec![0.5, 0.25])]
+    #[test_case(&[0.5, 0.5, 0.5] => vec![0.5, 0.5, 0.5]; "uncalibrated channel count")]
+    #[test_case(&[1.0, 0.2] => vec![1.0, 0.5])]
+    fn apply_trims(channel_volumes: &[f64]) -> Vec<f64> {
+        let trims = Trims(
+            vec![("sink".to_string(), vec![0.0, -6.0206])]
+                .into_iter()
+                .collect(),
+        );
+        let mut channel_volumes = channel_volumes.to_vec();
+        trims.apply("sink", &mut channel_volumes);
+        channel_volumes
+            .into_iter()
+            .map(|vol| (vol * 1000.0).round() / 1000.0)
+            .collect()
+    }
+
+    #[test]
+    fn tone_header() {
+        let wav = tone(&[0.5, 0.0], 0.5);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(wav.len(), 44 + 24_000 * 2 * 2);
+    }
+}
@@ -0,0 +1,47 @@
+//! `pw-volume upmix`: PipeWire's channel mixer settings for spreading stereo
+//! over surround speakers.
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde_json::Value;
+
+use crate::{node_param, set_node_params, PipeWireInterfaceNode};
+
+const UPMIX: &str = "channelmix.upmix";
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("upmix")
+        .about("upmixes stereo to all of a surround sink's channels, or stops doing so")
+        .setting(AppSettings::ArgRequiredElseHelp)
+        .arg(
+            Arg::with_name("TRANSITION")
+                .possible_values(&["on", "off", "toggle", "status"])
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("method")
+                .long("method")
+                .help("upmixing algorithm")
+                .takes_value(true)
+                .possible_values(&["simple", "psd"]),
+        )
+}
+
+pub fn run(matches: &ArgMatches<'_>, node: &PipeWireInterfaceNode<'_>) -> anyhow::Result<i32> {
+    let enabled = node_param(node, UPMIX).and_then(Value::as_bool);
+    let upmix = match matches.value_of("TRANSITION") {
+        Some("on") => true,
+        Some("off") => false,
+        Some("toggle") => !enabled.unwrap_or(false),
+        _ => {
+            // like is-muted, the exit code answers the question
+            println!("{}", if enabled == Some(true) { "on" } else { "off" });
+            return Ok(if enabled == Some(true) { 0 } else { 1 });
+        }
+    };
+    let mut params = vec![(UPMIX, Value::from(upmix))];
+    if let Some(method) = matches.value_of("method") {
+        params.push(("channelmix.upmix-method", Value::from(method)));
+    }
+    set_node_params(node, &params)?;
+    Ok(0)
+}
@@ -0,0 +1,902 @@
+//! The command line: its arguments, and the subcommands that work on a single
+//! node, which the daemon also answers.
+
+use anyhow::{anyhow, ensure};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, Write},
+    path::Path,
+    sync::atomic::Ordering,
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    agc, apply, backend, bar, battery, bluetooth, calibrate, channelmix, commands, compress,
+    config,
+    config::Config,
+    cork, daemon, delay, device_description, doctor, endpoints, error,
+    error::{Failure, Kind},
+    fade, feedback, filter,
+    format::{Format, Icons, Status, Style},
+    group, help, hook, is_decimal_percentage, log, mic_active, mixer, monitor, node_id, notify,
+    output::Output,
+    pamixer, parse_dump, port, preset, profile, ptt, pw_dump, record, render, reset, route_command,
+    scene, service, set_route, stats, tools, unmarshal, update, wait, watch, DeviceRoute,
+    PipeWireInterfaceNode, PipeWireObject, Target, VolumeOp, DEVICES, DRY_RUN, VOLUME,
+};
+
+/// What `status` and `watch` print in place of a failure with
+/// `--never-fail`: a degraded status in JSON, or an empty line for the bars
+/// that take a plain label, which hides the module rather than garbling it.
+pub(crate) fn degraded_status(arg: &ArgMatches<'_>, err: &anyhow::Error) -> String {
+    let plain = (arg.is_present("output") || arg.is_present("format"))
+        && arg.value_of("format") != Some("waybar")
+        && arg.value_of("output") != Some("verbose-json");
+    if plain {
+        String::new()
+    } else {
+        error::degraded(err)
+    }
+}
+
+/// The statuses that `status` reports: the node's, or with `--all`, every
+/// sink's and source's.
+pub(crate) fn statuses(
+    arg: &ArgMatches<'_>,
+    obj: &[PipeWireObject<'_>],
+    node: &PipeWireInterfaceNode<'_>,
+    route: &DeviceRoute<'_>,
+) -> Vec<Status> {
+    let targets = if arg.is_present("all") {
+        endpoints(obj)
+    } else {
+        vec![(node, route)]
+    };
+    targets
+        .into_iter()
+        .map(|(node, route)| Status {
+            device: device_description(obj, node),
+            compress: filter::COMPRESS.running(obj),
+            agc: filter::AGC.running(obj),
+            mic_active: mic_active(obj),
+            fade: fade::running(node.id),
+            battery: battery(obj, node),
+            ..Status::new(node, route)
+        })
+        .collect()
+}
+
+/// Renders the `status` subcommand's output and whether the node (any of
+/// them, with `--all`) is muted.
+pub(crate) fn status_report(
+    arg: &ArgMatches<'_>,
+    config: &Config,
+    obj: &[PipeWireObject<'_>],
+    node: &PipeWireInterfaceNode<'_>,
+    route: &DeviceRoute<'_>,
+) -> anyhow::Result<(String, bool)> {
+    render_statuses(arg, config, &statuses(arg, obj, node, route))
+}
+
+/// A `--also-write` destination: what to render, and the file to write it to.
+struct AlsoWrite<'a> {
+    format: Option<Format>,
+    output: Option<Output>,
+    path: &'a Path,
+}
+
+/// Parses `--also-write`'s `MODE:PATH`, where the mode is an `--output` mode
+/// or one of the `--format` names.
+fn also_write(s: &str) -> Result<AlsoWrite<'_>, String> {
+    let (mode, path) = s
+        .split_once(':')
+        .filter(|(_, path)| !path.is_empty())
+        .ok_or_else(|| format!(r#""{}" is not MODE:PATH, e.g. plain:/tmp/volume.txt"#, s))?;
+    let (format, output) = match mode {
+        "waybar" | "text" | "a11y" => (Some(mode.parse()?), None),
+        _ => (None, Some(mode.parse()?)),
+    };
+    Ok(AlsoWrite {
+        format,
+        output,
+        path: Path::new(path),
+    })
+}
+
+/// Renders each status, wrapped for the output if there is one, as one
+/// report: lines, or a JSON array of the objects with `--all`.
+fn render(
+    statuses: &[Status],
+    format: &Format,
+    output: Option<Output>,
+    style: &Style,
+    config: &Config,
+    all: bool,
+) -> String {
+    let lines: Vec<_> = statuses
+        .iter()
+        .map(|status| {
+            let label = format.render(status, style);
+            match output {
+                Some(output) => output.render(status, &label, config),
+                None => label,
+            }
+        })
+        .collect();
+    let json = match output {
+        Some(output) => output == Output::VerboseJson,
+        None => *format == Format::Waybar,
+    };
+    if all && json {
+        format!("[{}]", lines.join(", "))
+    } else {
+        lines.join("\n")
+    }
+}
+
+/// Writes a file whole, through a temporary one beside it, so that whatever
+/// reads it never sees half of it.
+fn write_whole(path: &Path, contents: &str) -> anyhow::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Renders statuses in the format and for the output that `status` was asked
+/// for, and whether any of them is muted. The renderings that `--also-write`
+/// asks for are written to their files as well, for widgets that read a file
+/// rather than run pw-volume again.
+pub(crate) fn render_statuses(
+    arg: &ArgMatches<'_>,
+    config: &Config,
+    statuses: &[Status],
+) -> anyhow::Result<(String, bool)> {
+    let output = if arg.is_present("verbose-json") {
+        Some(Output::VerboseJson)
+    } else {
+        arg.value_of("output")
+            .or(config.status.output.as_deref())
+            .map(str::parse::<Output>)
+            .transpose()
+            .map_err(anyhow::Error::msg)?
+    };
+    // bars other than Waybar take a plain label
+    let default_format = if output.is_some() {
+        Format::Text
+    } else {
+        Format::Waybar
+    };
+    let format = arg
+        .value_of("format")
+        .or(config.status.format.as_deref())
+        .map_or(Ok(default_format), str::parse)
+        .map_err(anyhow::Error::msg)?;
+    ensure!(
+        output.is_none() || format != Format::Waybar,
+        "--format waybar can't be combined with --output"
+    );
+    let mut icons = config.icons.icons();
+    if let Some(preset) = arg.value_of("icons") {
+        icons = Icons::preset(preset.parse().map_err(anyhow::Error::msg)?);
+    }
+    let style = Style {
+        icons,
+        thresholds: config.thresholds,
+    };
+    let all = arg.is_present("all");
+    for also in arg.values_of("also-write").into_iter().flatten() {
+        let also = also_write(also).map_err(anyhow::Error::msg)?;
+        // a bar's output takes the label that stdout gets, unless that's JSON
+        let format = match also.format {
+            Some(format) => format,
+            None if format == Format::Waybar => Format::Text,
+            None => format.clone(),
+        };
+        let report = render(statuses, &format, also.output, &style, config, all);
+        if let Err(e) = write_whole(also.path, &format!("{}\n", report)) {
+            eprintln!("pw-volume: not writing {}: {:#}", also.path.display(), e);
+        }
+    }
+    let report = render(statuses, &format, output, &style, config, all);
+    Ok((report, statuses.iter().any(|status| status.mute)))
+}
+
+/// The fraction `change` was asked to move the volume by.
+pub(crate) fn change_delta(arg: &ArgMatches<'_>, config: &Config) -> anyhow::Result<f64> {
+    let delta = arg
+        .value_of("DELTA")
+        .ok_or_else(|| anyhow!("DELTA argument not found"))?;
+    let percent = match delta {
+        "+" => config.volume.step,
+        "-" => -config.volume.step,
+        _ => delta[..delta.len() - 1].parse::<f64>()?,
+    };
+    Ok(percent * 0.01)
+}
+
+/// Fails with a conflict unless the node is at the volume and mute state that
+/// `--if-volume` and `--if-mute` expect. The volume may be off by half a
+/// percent, so that the whole percentage `status` reports will do.
+pub(crate) fn check_expected(arg: &ArgMatches<'_>, status: &Status) -> anyhow::Result<()> {
+    if let Some(expected) = arg.value_of("if-volume") {
+        let expected = expected.trim_end_matches('%').parse::<f64>()?;
+        if (status.percentage() - expected).abs() > 0.5 {
+            let message = format!(
+                "the volume is {:.1}%, not {}%",
+                status.percentage(),
+                expected
+            );
+            return Err(Failure::new(Kind::Conflict, message).into());
+        }
+    }
+    if let Some(expected) = arg.value_of("if-mute") {
+        if status.mute.to_string() != expected {
+            let message = format!("mute is {}, not {}", status.mute, expected);
+            return Err(Failure::new(Kind::Conflict, message).into());
+        }
+    }
+    Ok(())
+}
+
+/// Answers `get-volume`, `get-mute`, `is-muted`, and `is-over` about a node.
+pub(crate) fn query(
+    name: &str,
+    arg: &ArgMatches<'_>,
+    status: &Status,
+    out: &mut dyn Write,
+) -> anyhow::Result<i32> {
+    match name {
+        "get-volume" => {
+            if arg.is_present("precise") {
+                writeln!(out, "{:.1}", status.percentage())?;
+            } else {
+                writeln!(out, "{:.0}", status.percentage())?;
+            }
+            Ok(0)
+        }
+        "get-mute" => {
+            let answer = match (status.mute, arg.is_present("yes-no")) {
+                (true, false) => "true",
+                (false, false) => "false",
+                (true, true) => "yes",
+                (false, true) => "no",
+            };
+            writeln!(out, "{}", answer)?;
+            Ok(0)
+        }
+        "is-muted" => Ok(if status.mute { 0 } else { 1 }),
+        "is-over" => {
+            let threshold = arg
+                .value_of("PERCENT")
+                .ok_or_else(|| anyhow!("PERCENT argument not found"))?;
+            let percent = threshold.trim_end_matches('%').parse::<f64>()?;
+            Ok(if status.percentage() > percent { 0 } else { 1 })
+        }
+        _ => unreachable!("{} is not a query", name),
+    }
+}
+
+/// Runs the requested subcommand, returning the process exit code.
+pub(crate) fn pw_cli<'a>(
+    matches: &ArgMatches<'_>,
+    config: &Config,
+    obj: &[PipeWireObject<'_>],
+    node: &'a PipeWireInterfaceNode<'a>,
+    route: &'a DeviceRoute<'a>,
+    out: &mut dyn Write,
+) -> anyhow::Result<i32> {
+    if let ("mute" | "change" | "fade-to", Some(arg)) = matches.subcommand() {
+        check_expected(arg, &Status::new(node, route))?;
+    }
+    // build and send a command to pw-cli to update audio state
+    let op = match matches.subcommand() {
+        (name @ ("change" | "fade-to"), Some(arg)) if arg.is_present("over") => {
+            let to = fade::target(name, arg, config, Status::new(node, route).volume)?;
+            let over = fade::duration(arg.value_of("over").unwrap_or_default())
+                .map_err(anyhow::Error::msg)?;
+            fade::run(&fade::Route::spawn(node.id), to, over, config.fade)?;
+            return Ok(0);
+        }
+        ("mute", Some(arg)) => match arg.value_of("TRANSITION") {
+            Some("on") => VolumeOp::Mute(Some(true)),
+            Some("toggle") => VolumeOp::Mute(None),
+            _ => VolumeOp::Mute(Some(false)),
+        },
+        ("ptt", Some(arg)) => VolumeOp::Mute(Some(arg.value_of("TRANSITION") == Some("release"))),
+        ("change", Some(arg)) => VolumeOp::Change(change_delta(arg, config)?),
+        ("status", Some(arg)) => {
+            let (report, muted) = status_report(arg, config, obj, node, route)?;
+            writeln!(out, "{}", report)?;
+            if arg.is_present("exit-status") && muted {
+                return Ok(1);
+            }
+            return Ok(0);
+        }
+        (name @ ("get-volume" | "get-mute" | "is-muted" | "is-over"), Some(arg)) => {
+            return query(name, arg, &Status::new(node, route), out)
+        }
+        ("pamixer", Some(arg)) => return pamixer::run(arg, node, route),
+        ("calibrate", Some(arg)) => return calibrate::run(arg, node, route),
+        ("upmix", Some(arg)) => return channelmix::run(arg, node),
+        ("render", Some(arg)) => {
+            return render::run(arg, &config.svg, &Status::new(node, route), out)
+        }
+        ("monitor", Some(arg)) => return monitor::run(arg, node, route),
+        (_, _) => unreachable!("argument parsing should have failed by now"),
+    };
+    let cmd = route_command(op, node, route);
+    set_route(node, &cmd)?;
+    if let (name @ ("change" | "mute"), Some(arg)) = matches.subcommand() {
+        notify::volume(notify::config(config.notify, arg), node, route, &cmd);
+        let status = Status::after(node, route, &cmd);
+        let hooks = hook::commands(config.on_change.as_deref(), arg);
+        hook::run(&hooks, Some(&Status::new(node, route)), &status);
+        if name == "change" {
+            stats::volume(&status);
+        }
+    }
+    if let ("change", Some(arg)) = matches.subcommand() {
+        feedback::play(
+            &feedback::config(&config.feedback, arg),
+            node.info.props.node_name,
+        );
+    }
+    Ok(0)
+}
+
+/// Options for the subcommands that write, `mute`, `change`, and `fade-to`:
+/// `--if-volume` and `--if-mute`, which make the write conditional on what the
+/// writer last read, and `--after`, which puts it off.
+fn write_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.arg(
+        Arg::with_name("if-volume")
+            .long("if-volume")
+            .value_name("PERCENT")
+            .help("only if the volume is still PERCENT, e.g. as read with get-volume; fails with exit code 9 otherwise")
+            .takes_value(true)
+            .validator(|s| {
+                s.trim_end_matches('%')
+                    .parse::<f64>()
+                    .map(|_| ())
+                    .map_err(|_| format!(r#""{}" is not a percentage"#, s))
+            }),
+    )
+    .arg(
+        Arg::with_name("if-mute")
+            .long("if-mute")
+            .value_name("MUTE")
+            .help("only if the mute state is still MUTE, as read with get-mute; fails with exit code 9 otherwise")
+            .takes_value(true)
+            .possible_values(&["true", "false"]),
+    )
+    .arg(
+        Arg::with_name("after")
+            .long("after")
+            .value_name("DURATION")
+            .help("wait a duration, e.g. 30m or 1h, and then look up the node and write, e.g. as a sleep timer")
+            .takes_value(true)
+            .validator(|s| fade::duration(&s).map(|_| ())),
+    )
+}
+
+/// Options shared by `status` and `watch`.
+pub(crate) fn status_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.arg(
+        Arg::with_name("format")
+            .long("format")
+            .help(
+                "output format: 'waybar' (default), 'text', 'a11y' for screen \
+                     readers, or a template such as '{percentage}% {mute:muted|}'",
+            )
+            .takes_value(true)
+            .validator(|s| s.parse::<Format>().map(|_| ())),
+    )
+    .arg(
+        Arg::with_name("output")
+            .long("output")
+            .help("wrap the --format label (default 'text') for a status bar")
+            .takes_value(true)
+            .possible_values(&[
+                "polybar",
+                "i3blocks",
+                "xmobar",
+                "conky",
+                "genmon",
+                "plain",
+                "csv",
+                "tsv",
+                "verbose-json",
+            ]),
+    )
+    .arg(
+        Arg::with_name("verbose-json")
+            .long("verbose-json")
+            .help("print all of the node's details as JSON, same as --output verbose-json")
+            .conflicts_with_all(&["output", "format"]),
+    )
+    .arg(
+        Arg::with_name("icons")
+            .long("icons")
+            .help("icon set, overriding the config file")
+            .takes_value(true)
+            .possible_values(&["nerd-font", "emoji", "ascii"]),
+    )
+    .arg(
+        Arg::with_name("all")
+            .long("all")
+            .help("report every sink and source, as a JSON array for JSON output"),
+    )
+    .arg(
+        Arg::with_name("also-write")
+            .long("also-write")
+            .value_name("MODE:PATH")
+            .help("also write the status to PATH as an --output mode or --format name renders it, e.g. plain:/tmp/volume.txt")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|s| also_write(&s).map(|_| ())),
+    )
+}
+
+/// The `--target` node, which may be one of the config file's aliases, or the
+/// default node for the subcommand.
+pub(crate) fn target(
+    matches: &ArgMatches<'_>,
+    config: &Config,
+    obj: &[PipeWireObject<'_>],
+) -> anyhow::Result<Target> {
+    let default = match matches.subcommand_name() {
+        Some("ptt") => Target::DefaultSource,
+        _ => Target::default(),
+    };
+    match matches.value_of("target") {
+        None => Ok(default),
+        Some(alias) if config.aliases.contains_key(alias) => {
+            let name = &config.aliases[alias];
+            log::debug(1, format_args!("{} is an alias for {}", alias, name));
+            node_id(obj, name).map(Target::Id)
+        }
+        Some(s) => s.parse().map_err(anyhow::Error::msg),
+    }
+}
+
+/// The command line interface, which the daemon also accepts over its socket.
+pub(crate) fn app<'a, 'b>() -> App<'a, 'b> {
+    App::new("pw-volume")
+    .about("Basic interface to PipeWire volume controls")
+    .settings(&[
+        AppSettings::SubcommandRequiredElseHelp,
+        AppSettings::DisableVersion,
+        AppSettings::VersionlessSubcommands,
+        AppSettings::UnifiedHelpMessage,
+        AppSettings::DisableHelpSubcommand,
+    ])
+    .arg(
+        Arg::with_name("target")
+            .long("target")
+            .value_name("TARGET")
+            .help("node to operate on: @DEFAULT_AUDIO_SINK@ (default), @DEFAULT_AUDIO_SOURCE@, a node id, or an alias from the config file")
+            .takes_value(true)
+            .global(true),
+    )
+    .arg(
+        Arg::with_name("remote")
+            .long("remote")
+            .value_name("NAME")
+            .help("PipeWire instance to control, e.g. pipewire-1 or a socket path, instead of the session's")
+            .takes_value(true)
+            .global(true),
+    )
+    .arg(
+        Arg::with_name("verbose")
+            .short("v")
+            .long("verbose")
+            .multiple(true)
+            .help("explain which devices were picked and what was run on stderr; -vv for more")
+            .global(true),
+    )
+    .arg(
+        Arg::with_name("wait-for-pipewire")
+            .long("wait-for-pipewire")
+            .value_name("SECONDS")
+            .help("wait up to 30s, or SECONDS, for PipeWire and a default sink before running the command")
+            .takes_value(true)
+            .min_values(0)
+            .require_equals(true)
+            .global(true)
+            .validator(|s| timeout(&s).map(|_| ())),
+    )
+    .arg(
+        Arg::with_name("backend")
+            .long("backend")
+            .value_name("BACKEND")
+            .help("tools to run mute, change, status, and the queries with; auto (default) uses PipeWire's, or else wpctl, or else pactl; native needs a build with --features native")
+            .possible_values(&["auto", "pipewire", "wpctl", "pulse", "native"])
+            .takes_value(true)
+            .global(true),
+    )
+    .arg(bar::arg())
+    .arg(
+        Arg::with_name("dry-run")
+            .long("dry-run")
+            .help("print the commands that would change the graph instead of running them")
+            .global(true),
+    )
+    .arg(
+        Arg::with_name("never-fail")
+            .long("never-fail")
+            .help("print a degraded status and exit with 0 on failure, so that bar widgets stay up")
+            .global(true),
+    )
+    .arg(
+        Arg::with_name("json-errors")
+            .long("json-errors")
+            .help("print failures as JSON with an error kind on stdout, e.g. for bar widgets")
+            .global(true),
+    )
+    .subcommand(
+        write_args(SubCommand::with_name("mute"))
+            .about("mutes audio [possible values: on, off, toggle]")
+            .setting(AppSettings::ArgRequiredElseHelp)
+            .arg(notify::arg())
+            .arg(hook::arg())
+            .arg(
+                Arg::with_name("TRANSITION")
+                    .takes_value(true)
+                    .required(true)
+                    .possible_values(&["on", "off", "toggle"]),
+            ),
+    )
+    .subcommand(ptt::subcommand())
+    .subcommand(
+        write_args(SubCommand::with_name("change"))
+            .about("adjusts volume by decimal percentage, e.g. '+1%', '-0.5%', or by the configured step with '+' or '-'")
+            .setting(AppSettings::ArgRequiredElseHelp)
+            .setting(AppSettings::AllowLeadingHyphen)
+            .arg(notify::arg())
+            .arg(hook::arg())
+            .arg(feedback::arg())
+            .arg(
+                Arg::with_name("DELTA")
+                    .help("decimal percentage, e.g. '+1%', '-0.5%', or '+' or '-' for a step")
+                    .takes_value(true)
+                    .required(true)
+                    .allow_hyphen_values(true)
+                    .validator(move |s| {
+                        if s == "+" || s == "-" || is_decimal_percentage(&s) {
+                            Ok(())
+                        } else {
+                            Err(format!(r#""{}" is not a decimal percentage"#, s))
+                        }
+                    }),
+            )
+            .arg(
+                Arg::with_name("group")
+                    .long("group")
+                    .help("change every member of a group from the config file together")
+                    .takes_value(true),
+            )
+            .arg(fade::over_arg().conflicts_with("group")),
+    )
+    .subcommand(
+        write_args(SubCommand::with_name("fade-to"))
+            .about("fades the volume to a decimal percentage over a duration, e.g. 'fade-to 0% --over 5s'")
+            .setting(AppSettings::ArgRequiredElseHelp)
+            .arg(
+                Arg::with_name("PERCENT")
+                    .help("decimal percentage, e.g. '40%'")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(move |s| {
+                        if is_decimal_percentage(&s) {
+                            Ok(())
+                        } else {
+                            Err(format!(r#""{}" is not a decimal percentage"#, s))
+                        }
+                    }),
+            )
+            .arg(fade::over_arg().required(true)),
+    )
+    .subcommand(
+        status_args(SubCommand::with_name("status"))
+            .about("get volume and mute information")
+            .arg(
+                Arg::with_name("exit-status")
+                    .long("exit-status")
+                    .help("exit with 1 if muted (any of them, with --all), 0 otherwise"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("get-volume")
+            .about("prints the volume percentage as a bare number, e.g. '37'")
+            .arg(
+                Arg::with_name("precise")
+                    .long("precise")
+                    .help("include one decimal place, e.g. '37.5'"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("get-mute")
+            .about("prints 'true' if muted, 'false' otherwise")
+            .arg(
+                Arg::with_name("yes-no")
+                    .long("yes-no")
+                    .help("print 'yes' or 'no' instead"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("is-muted")
+            .about("exits with 0 if muted, 1 otherwise; prints nothing"),
+    )
+    .subcommand(
+        SubCommand::with_name("is-over")
+            .about("exits with 0 if volume is above a percentage, 1 otherwise; prints nothing")
+            .setting(AppSettings::ArgRequiredElseHelp)
+            .arg(
+                Arg::with_name("PERCENT")
+                    .help("decimal percentage, e.g. '80%'")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(move |s| {
+                        if is_decimal_percentage(&s) {
+                            Ok(())
+                        } else {
+                            Err(format!(r#""{}" is not a decimal percentage"#, s))
+                        }
+                    }),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("mixer")
+            .about("interactive terminal mixer for all sinks and sources"),
+    )
+    .subcommand(pamixer::subcommand())
+    .subcommand(calibrate::subcommand())
+    .subcommand(channelmix::subcommand())
+    .subcommand(render::subcommand())
+    .subcommand(service::subcommand())
+    .subcommand(update::subcommand())
+    .subcommand(compress::subcommand())
+    .subcommand(agc::subcommand())
+    .subcommand(monitor::subcommand())
+    .subcommand(scene::subcommand())
+    .subcommand(preset::subcommand())
+    .subcommand(cork::subcommand())
+    .subcommand(reset::subcommand())
+    .subcommand(stats::subcommand())
+    .subcommand(doctor::subcommand())
+    .subcommand(port::subcommand())
+    .subcommand(port::list_subcommand())
+    .subcommand(profile::subcommand())
+    .subcommand(profile::list_subcommand())
+    .subcommand(bluetooth::subcommand())
+    .subcommand(config::subcommand())
+    .subcommand(apply::subcommand())
+    .subcommand(apply::export_subcommand())
+    .subcommand(delay::subcommand())
+    .subcommand(daemon::subcommand())
+    .subcommand(record::subcommand())
+    .subcommand(watch::subcommand())
+    .subcommand(help::subcommand())
+}
+
+/// Parses the timeout of `--wait-for-pipewire`, in seconds.
+fn timeout(s: &str) -> Result<Duration, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| format!("\"{}\" is not a number of seconds", s))
+}
+
+/// Runs the command, returning its exit code.
+fn run(args: &[OsString], matches: &ArgMatches<'_>) -> anyhow::Result<i32> {
+    let config = Config::load()?;
+    let _ = VOLUME.set(config.volume);
+    let _ = DEVICES.set(config.devices.clone());
+    tools::set(config.tools.clone());
+    hook::set(config.hooks.clone());
+    DRY_RUN.store(matches.is_present("dry-run"), Ordering::Relaxed);
+    log::set_level(matches.occurrences_of("verbose"));
+    if let Some(after) = matches.subcommand().1.and_then(|arg| arg.value_of("after")) {
+        let after = fade::duration(after).map_err(anyhow::Error::msg)?;
+        log::debug(1, format_args!("waiting {:?} before running", after));
+        thread::sleep(after);
+    }
+    if matches.is_present("wait-for-pipewire") {
+        let timeout = match matches.value_of("wait-for-pipewire") {
+            Some(s) => timeout(s).map_err(anyhow::Error::msg)?,
+            None => wait::DEFAULT_TIMEOUT,
+        };
+        wait::until_ready(timeout)?;
+    }
+    stats::set(config.stats);
+    let code = dispatch(args, matches, &config)?;
+    if let Some(name) = matches
+        .subcommand_name()
+        .filter(|name| code == 0 && bar::WRITES.contains(name))
+    {
+        stats::command(name);
+        let mut signals = config.status.notify_bar.clone();
+        for s in matches.values_of("notify-bar").into_iter().flatten() {
+            signals.push(s.parse().map_err(anyhow::Error::msg)?);
+        }
+        bar::send(&signals);
+    }
+    Ok(code)
+}
+
+/// Runs the subcommand, returning its exit code.
+fn dispatch(args: &[OsString], matches: &ArgMatches<'_>, config: &Config) -> anyhow::Result<i32> {
+    let backend = matches.value_of("backend").unwrap_or("auto");
+    let basic = matches
+        .subcommand_name()
+        .is_some_and(|name| backend::COMMANDS.contains(&name));
+    if basic && (backend::FALLBACKS.contains(&backend) || backend == "native") {
+        return backend::run(backend, matches, config, &mut io::stdout());
+    }
+    match matches.subcommand() {
+        ("mixer", _) => return mixer::run(&config.theme, &config.mixer).map(|()| 0),
+        ("scene", Some(arg)) => return scene::run(arg).map(|()| 0),
+        ("preset", Some(arg)) => return preset::run(arg, config).map(|()| 0),
+        ("app", Some(arg)) => return cork::run(arg).map(|()| 0),
+        ("reset", Some(arg)) => return reset::run(arg, config).map(|()| 0),
+        ("stats", Some(arg)) => return stats::run(arg, config.stats).map(|()| 0),
+        ("doctor", Some(arg)) => return doctor::run(arg),
+        ("set-port", Some(arg)) => return port::run(arg).map(|()| 0),
+        ("list-ports", Some(arg)) => return port::run_list(arg).map(|()| 0),
+        ("set-profile", Some(arg)) => return profile::run(arg).map(|()| 0),
+        ("list-profiles", Some(arg)) => return profile::run_list(arg).map(|()| 0),
+        ("bluetooth", Some(arg)) => return bluetooth::run(arg).map(|()| 0),
+        ("delay", Some(arg)) => return delay::run(arg).map(|()| 0),
+        ("service", Some(arg)) => return service::run(arg).map(|()| 0),
+        ("config", Some(arg)) => return config::run(arg).map(|()| 0),
+        ("help", Some(arg)) => return help::run(arg, config, &mut io::stdout()).map(|()| 0),
+        ("apply", Some(arg)) => return apply::run(arg, config),
+        ("export-state", _) => return apply::run_export(config).map(|()| 0),
+        ("check-update", _) => return update::run(),
+        ("daemon", Some(arg)) => return daemon::run(arg, config).map(|()| 0),
+        ("watch", Some(arg)) => return watch::run(arg, config).map(|()| 0),
+        ("record", Some(arg)) => return record::run(arg).map(|()| 0),
+        ("compress", Some(arg)) => return compress::run(arg, &config.compress),
+        ("mic-agc", Some(arg)) => return agc::run(arg),
+        ("change", Some(arg)) if arg.is_present("group") => {
+            return group::run(arg, config).map(|()| 0)
+        }
+        // let a running daemon answer from its copy of the graph, which
+        // saves running pw-dump, and lets its ptt failsafe see presses
+        (name, _)
+            if daemon::COMMANDS.contains(&name)
+                && !matches
+                    .subcommand()
+                    .1
+                    .is_some_and(|arg| arg.is_present("over") || arg.is_present("after"))
+                && !matches.is_present("dry-run")
+                && !config::env_overrides() =>
+        {
+            if let Some(response) = daemon::request(matches, &args[1..])? {
+                return Ok(response.print());
+            }
+        }
+        _ => (),
+    }
+
+    // call pw-dump and unmarshal its output, then act on it, starting over if
+    // the graph changed in between
+    let result = config.retry.run(|| {
+        let output = pw_dump()?;
+        let obj = unmarshal(&output)?;
+        let (node, route) = parse_dump(&obj, target(matches, config, &obj)?)?;
+        pw_cli(matches, config, &obj, node, route, &mut io::stdout())
+    });
+    match result {
+        // some distributions ship WirePlumber's wpctl without PipeWire's
+        // tools, and sandboxes may only reach pipewire-pulse
+        Err(e) if error::kind(&e) == Kind::MissingTool && basic && backend == "auto" => {
+            backend::fallback(matches, config, e, &mut io::stdout())
+        }
+        result => result,
+    }
+}
+
+/// The exit code for arguments that didn't parse: 0 after printing help or
+/// the version, and otherwise 2, as test(1) does, rather than clap's 1, so
+/// that a typo isn't taken for a predicate's "no".
+fn usage_code(e: &clap::Error) -> i32 {
+    if e.use_stderr() {
+        Kind::Other.code()
+    } else {
+        0
+    }
+}
+
+/// Prints clap's message for arguments that didn't parse, and exits.
+fn usage(e: clap::Error) -> ! {
+    if e.use_stderr() {
+        eprintln!("{}", e.message);
+    } else {
+        println!("{}", e.message);
+    }
+    std::process::exit(usage_code(&e))
+}
+
+/// Runs pw-volume with the process's arguments, and exits.
+pub fn main() {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    if pamixer::invoked_as_pamixer(&args) {
+        args.insert(1, "pamixer".into());
+    }
+
+    // parse cli flags, expanding the user's own commands if that fails
+    let matches = match app().get_matches_from_safe(&args) {
+        Ok(matches) => matches,
+        Err(e) => {
+            let commands = Config::load()
+                .map(|config| config.commands)
+                .unwrap_or_default();
+            match commands::expand(&args, &commands) {
+                Ok(Some(expanded)) => {
+                    args = expanded;
+                    app()
+                        .get_matches_from_safe(&args)
+                        .unwrap_or_else(|e| usage(e))
+                }
+                Ok(None) => usage(e),
+                Err(msg) => usage(clap::Error::with_description(
+                    &msg,
+                    clap::ErrorKind::TooFewValues,
+                )),
+            }
+        }
+    };
+    // pw-dump, pw-cli, and the other tools all connect to this instance, as
+    // does the daemon, whose socket is named after it
+    if let Some(remote) = matches.value_of("remote") {
+        std::env::set_var("PIPEWIRE_REMOTE", remote);
+    }
+    tools::handle_signals();
+    let code = match run(&args, &matches) {
+        Ok(code) => code,
+        Err(e) if matches.is_present("never-fail") => {
+            let degraded = match matches.subcommand() {
+                ("status", Some(arg)) | ("watch", Some(arg)) => degraded_status(arg, &e),
+                _ => error::degraded(&e),
+            };
+            println!("{}", degraded);
+            eprintln!("{}", error::report(&e));
+            0
+        }
+        Err(e) => {
+            if matches.is_present("json-errors") {
+                println!("{}", error::json(&e));
+            } else {
+                eprintln!("{}", error::report(&e));
+            }
+            error::kind(&e).code()
+        }
+    };
+    std::process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(&["is-muted", "--bogus"] => 2)]
+    #[test_case(&["is-over", "abc"] => 2)]
+    #[test_case(&["is-over"] => 2; "missing argument")]
+    #[test_case(&["--help"] => 0)]
+    fn usage_codes(args: &[&str]) -> i32 {
+        let e = app()
+            .get_matches_from_safe(std::iter::once("pw-volume").chain(args.iter().copied()))
+            .unwrap_err();
+        usage_code(&e)
+    }
+}
@@ -0,0 +1,137 @@
+//! The `[commands]` section: subcommands of the user's own, e.g.
+//! `louder = "change +5%"`, which `pw-volume louder` runs. They are
+//! expanded before the command line is parsed, so they can't stand in for the
+//! built-in subcommands.
+
+use std::{collections::BTreeMap, ffi::OsString};
+
+/// Global options that take a value in the next argument, which can come
+/// before the command's name.
+const VALUE_OPTIONS: [&str; 3] = ["--target", "--remote", "--backend"];
+
+/// Splits a command into words at whitespace, keeping what is in single or
+/// double quotes together, e.g. `status --format '{icon} {volume}'`.
+fn split(command: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = None::<String>;
+    let mut quote = None;
+    for c in command.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(format!("unterminated quote in \"{}\"", command));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Substitutes `args` into `words`: `$1` to `$9` for one argument, and a
+/// `$@` word for all of them. Without either, the arguments are appended.
+fn substitute(name: &str, words: Vec<String>, args: &[OsString]) -> Result<Vec<OsString>, String> {
+    let placeholders = words
+        .iter()
+        .any(|word| word.contains("$@") || (1..=9).any(|n| word.contains(&format!("${}", n))));
+    if !placeholders {
+        return Ok(words
+            .into_iter()
+            .map(OsString::from)
+            .chain(args.iter().cloned())
+            .collect());
+    }
+    let mut expanded = Vec::new();
+    for word in words {
+        if word == "$@" {
+            expanded.extend(args.iter().cloned());
+            continue;
+        }
+        let mut word = word;
+        for n in (1..=9).rev() {
+            let placeholder = format!("${}", n);
+            if !word.contains(&placeholder) {
+                continue;
+            }
+            let arg = args.get(n - 1).ok_or_else(|| {
+                format!(
+                    "{} needs at least {} argument{}",
+                    name,
+                    n,
+                    if n == 1 { "" } else { "s" }
+                )
+            })?;
+            word = word.replace(&placeholder, &arg.to_string_lossy());
+        }
+        expanded.push(word.into());
+    }
+    Ok(expanded)
+}
+
+/// The command line with the user's command in it expanded, or `None` when
+/// its first word isn't one of `commands`.
+pub fn expand(
+    args: &[OsString],
+    commands: &BTreeMap<String, String>,
+) -> Result<Option<Vec<OsString>>, String> {
+    let mut i = 1;
+    while let Some(arg) = args.get(i) {
+        let arg = arg.to_string_lossy();
+        if !arg.starts_with('-') {
+            break;
+        }
+        i += if VALUE_OPTIONS.contains(&&*arg) { 2 } else { 1 };
+    }
+    let name = match args.get(i) {
+        Some(name) => name.to_string_lossy(),
+        None => return Ok(None),
+    };
+    let command = match commands.get(&*name) {
+        Some(command) => command,
+        None => return Ok(None),
+    };
+    let words = split(command).map_err(|e| format!("[commands] {}: {}", name, e))?;
+    let mut expanded = args[..i].to_vec();
+    expanded.extend(substitute(&name, words, &args[i + 1..])?);
+    Ok(Some(expanded))
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("pw-volume louder" => Ok(Some("pw-volume change +5%".to_string())))]
+    #[test_case("pw-volume louder --dry-run" => Ok(Some("pw-volume change +5% --dry-run".to_string())))]
+    #[test_case("pw-volume --target phones louder" => Ok(Some("pw-volume --target phones change +5%".to_string())))]
+    #[test_case("pw-volume duck 20" => Ok(Some("pw-volume change -20%".to_string())))]
+    #[test_case("pw-volume duck" => Err("duck needs at least 1 argument".to_string()))]
+    #[test_case("pw-volume on phones" => Ok(Some("pw-volume --target phones mute off".to_string())))]
+    #[test_case("pw-volume bar" => Ok(Some("pw-volume status --format {icon}|{volume}".to_string())))]
+    #[test_case("pw-volume status" => Ok(None))]
+    #[test_case("pw-volume --target louder" => Ok(None))]
+    fn expand_command(line: &str) -> Result<Option<String>, String> {
+        let commands = BTreeMap::from([
+            ("louder".to_string(), "change +5%".to_string()),
+            ("duck".to_string(), "change -$1%".to_string()),
+            ("on".to_string(), "--target $1 mute off".to_string()),
+            (
+                "bar".to_string(),
+                "status --format '{icon}|{volume}'".to_string(),
+            ),
+        ]);
+        let args: Vec<OsString> = line.split(' ').map(OsString::from).collect();
+        let expanded = expand(&args, &commands)?;
+        Ok(expanded.map(|args| {
+            let words: Vec<_> = args.iter().map(|arg| arg.to_string_lossy()).collect();
+            words.join(" ")
+        }))
+    }
+}
@@ -0,0 +1,109 @@
+//! `pw-volume compress`: a dynamic range compressor in front of the default
+//! sink, so that dialogue stays audible at night without explosions waking
+//! the neighbours.
+//!
+//! The compressor is a filter-chain sink, run as a [`filter`].
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::filter;
+
+/// The `[compress]` section of the config file.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CompressConfig {
+    /// Level in dB above which the signal is compressed.
+    pub threshold: f64,
+    /// Compression ratio, e.g. 4 for 4:1.
+    pub ratio: f64,
+    pub attack_ms: f64,
+    pub release_ms: f64,
+    /// Gain in dB applied after compression, to make up for the lost level.
+    pub makeup: f64,
+}
+
+impl Default for CompressConfig {
+    fn default() -> Self {
+        CompressConfig {
+            threshold: -20.0,
+            ratio: 4.0,
+            attack_ms: 10.0,
+            release_ms: 300.0,
+            makeup: 8.0,
+        }
+    }
+}
+
+/// A filter-chain that runs the `sc4m` compressor from the LADSPA
+/// swh-plugins, playing into `sink`.
+fn filter_chain(sink: &str, config: &CompressConfig) -> Value {
+    serde_json::json!({
+        "name": "libpipewire-module-filter-chain",
+        "args": {
+            "node.description": "Night compression",
+            "media.name": "Night compression",
+            "audio.channels": 2,
+            "audio.position": ["FL", "FR"],
+            // a mono graph is duplicated for each channel
+            "filter.graph": {
+                "nodes": [{
+                    "type": "ladspa",
+                    "name": "compressor",
+                    "plugin": "sc4m_1916",
+                    "label": "sc4m",
+                    "control": {
+                        "Threshold level (dB)": config.threshold,
+                        "Ratio (1:n)": config.ratio,
+                        "Attack time (ms)": config.attack_ms,
+                        "Release time (ms)": config.release_ms,
+                        "Makeup gain (dB)": config.makeup,
+                    },
+                }],
+            },
+            "capture.props": {
+                "node.name": "pw-volume-compress",
+                "media.class": "Audio/Sink",
+            },
+            "playback.props": {
+                "node.name": "pw-volume-compress.output",
+                "node.passive": true,
+                "target.object": sink,
+            },
+        },
+    })
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("compress")
+        .about("compresses the default sink's dynamic range, e.g. for watching at night")
+        .setting(AppSettings::ArgRequiredElseHelp)
+        .arg(
+            Arg::with_name("TRANSITION")
+                .possible_values(&["on", "off", "toggle", "status"])
+                .required(true),
+        )
+}
+
+pub fn run(matches: &ArgMatches<'_>, config: &CompressConfig) -> anyhow::Result<i32> {
+    filter::COMPRESS.run(matches, |sink| filter_chain(sink, config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressor_module() {
+        let module = filter_chain(r#"odd "sink""#, &CompressConfig::default());
+        assert_eq!(
+            module["args"]["playback.props"]["target.object"],
+            r#"odd "sink""#
+        );
+        assert_eq!(
+            module["args"]["filter.graph"]["nodes"][0]["control"]["Ratio (1:n)"],
+            4.0
+        );
+    }
+}
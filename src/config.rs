@@ -0,0 +1,637 @@
+//! Settings read from `$XDG_CONFIG_HOME/pw-volume/config.toml`.
+
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    env, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Context};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde::Deserialize;
+use toml::{value::Table, Value};
+
+use crate::{
+    bar::BarSignal,
+    compress::CompressConfig,
+    daemon::{MicLock, Offset, RestoreConfig},
+    fade::FadeConfig,
+    feedback::FeedbackConfig,
+    focus::FocusSink,
+    format::{IconPreset, Icons, Thresholds},
+    hook::HooksConfig,
+    limiter::LimiterConfig,
+    mixer::MixerConfig,
+    output::StateColors,
+    privacy::PrivacyConfig,
+    ptt::PttConfig,
+    render::SvgConfig,
+    retry::RetryConfig,
+    stats::StatsConfig,
+    theme::Theme,
+    tools::ToolsConfig,
+    update::UpdateConfig,
+};
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub icons: IconConfig,
+    pub thresholds: Thresholds,
+    pub theme: Theme,
+    pub mixer: MixerConfig,
+    pub ptt: PttConfig,
+    pub compress: CompressConfig,
+    pub polybar: StateColors,
+    pub i3blocks: StateColors,
+    pub xmobar: StateColors,
+    pub conky: StateColors,
+    pub genmon: StateColors,
+    pub svg: SvgConfig,
+    /// Sync groups: node names whose volumes are changed together.
+    pub groups: BTreeMap<String, Vec<String>>,
+    pub offsets: Vec<Offset>,
+    pub focus: Vec<FocusSink>,
+    #[serde(rename = "mic-lock")]
+    pub mic_lock: Option<MicLock>,
+    pub privacy: Option<PrivacyConfig>,
+    pub restore: Option<RestoreConfig>,
+    pub limiter: Option<LimiterConfig>,
+    pub retry: RetryConfig,
+    pub update: UpdateConfig,
+    pub volume: VolumeConfig,
+    pub status: StatusConfig,
+    pub notify: NotifyConfig,
+    pub feedback: FeedbackConfig,
+    pub stats: StatsConfig,
+    pub fade: FadeConfig,
+    /// Short names for nodes, usable with `--target`, e.g.
+    /// `speakers = "alsa_output.pci-0000_00_1f.3.analog-stereo"`.
+    pub aliases: BTreeMap<String, String>,
+    /// Subcommands of the user's own, e.g. `louder = "change +5%"`.
+    pub commands: BTreeMap<String, String>,
+    /// A command run with `sh -c` after each `change` and `mute`, with the
+    /// new state in `PWV_VOLUME`, `PWV_MUTED`, and `PWV_NODE`.
+    #[serde(rename = "on-change")]
+    pub on_change: Option<String>,
+    /// How `on-change` and the other hooks are run.
+    pub hooks: HooksConfig,
+    /// Settings for the nodes whose names contain the key, e.g.
+    /// `[devices."usb-Topping"]`.
+    pub devices: BTreeMap<String, DeviceConfig>,
+    pub tools: ToolsConfig,
+}
+
+/// How volume percentages map onto PipeWire's channel volumes.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(try_from = "String")]
+pub enum Scale {
+    /// The channel volume itself, as pw-volume has always shown it.
+    #[default]
+    Linear,
+    /// The cube root of the channel volume, as pavucontrol and wpctl show it,
+    /// which makes steps sound more even.
+    Cubic,
+    /// The channel volume's root of this degree, `power:2.2` in the config
+    /// file, for devices whose analog stage makes neither of the others even.
+    Power(f64),
+}
+
+impl Scale {
+    /// A channel volume as a fraction on this scale.
+    pub fn scaled(self, volume: f64) -> f64 {
+        match self {
+            Scale::Linear => volume,
+            Scale::Cubic => volume.max(0.0).cbrt(),
+            Scale::Power(exponent) => volume.max(0.0).powf(exponent.recip()),
+        }
+    }
+
+    /// A fraction on this scale as a channel volume.
+    pub fn linear(self, volume: f64) -> f64 {
+        match self {
+            Scale::Linear => volume,
+            Scale::Cubic => volume.max(0.0).powi(3),
+            Scale::Power(exponent) => volume.max(0.0).powf(exponent),
+        }
+    }
+}
+
+impl FromStr for Scale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Scale::Linear),
+            "cubic" => Ok(Scale::Cubic),
+            _ => s
+                .strip_prefix("power:")
+                .and_then(|exponent| exponent.parse::<f64>().ok())
+                .filter(|exponent| exponent.is_finite() && *exponent > 0.0)
+                .map(Scale::Power)
+                .ok_or_else(|| format!(r#""{}" is not a scale"#, s)),
+        }
+    }
+}
+
+impl TryFrom<String> for Scale {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// A `[devices]` entry: settings for particular nodes that override the
+/// general ones.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceConfig {
+    pub scale: Option<Scale>,
+}
+
+/// The scale `devices` sets for the node named `node`. When several keys are
+/// part of its name, the longest, most particular one wins.
+pub fn device_scale(devices: &BTreeMap<String, DeviceConfig>, node: &str) -> Option<Scale> {
+    devices
+        .iter()
+        .filter(|(key, device)| node.contains(key.as_str()) && device.scale.is_some())
+        .max_by_key(|(key, _)| key.len())
+        .and_then(|(_, device)| device.scale)
+}
+
+/// The `[volume]` section: how `change` moves the volume.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct VolumeConfig {
+    /// Percentage that `change +` and `change -` move by.
+    pub step: f64,
+    /// Percentage that changes stop at, which may be over 100 to boost.
+    pub max: f64,
+    pub scale: Scale,
+}
+
+impl Default for VolumeConfig {
+    fn default() -> Self {
+        VolumeConfig {
+            step: 5.0,
+            max: 100.0,
+            scale: Scale::Linear,
+        }
+    }
+}
+
+/// The `[status]` section: what `status` and `watch` print when not told.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatusConfig {
+    pub format: Option<String>,
+    pub output: Option<String>,
+    /// Signals sent to the bar after each change, e.g. `["RTMIN+8:waybar"]`.
+    #[serde(rename = "notify-bar")]
+    pub notify_bar: Vec<BarSignal>,
+}
+
+/// The `[notify]` section: a desktop notification showing the volume after
+/// each `change` and `mute`.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct NotifyConfig {
+    pub enabled: bool,
+    /// How long the notification stays up, or the server's default if unset.
+    pub timeout_ms: Option<u32>,
+}
+
+/// Icon set used by the formats; individual glyphs override the preset.
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct IconConfig {
+    pub preset: IconPreset,
+    pub mute: Option<String>,
+    pub levels: Option<Vec<String>>,
+}
+
+impl IconConfig {
+    pub fn icons(&self) -> Icons {
+        let mut icons = Icons::preset(self.preset);
+        if let Some(mute) = &self.mute {
+            icons.mute = mute.clone();
+        }
+        if let Some(levels) = &self.levels {
+            icons.levels = levels.clone();
+        }
+        icons
+    }
+}
+
+/// `$XDG_CONFIG_HOME` (`~/.config` if unset).
+pub fn config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+/// Directory for persistent state such as scenes, following
+/// `$XDG_STATE_HOME` (`~/.local/state` if unset).
+pub fn state_dir() -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })
+        .map(|dir| dir.join("pw-volume"))
+}
+
+/// Directory for files that only live as long as the session, such as pid
+/// files, following `$XDG_RUNTIME_DIR`.
+pub fn runtime_dir() -> Option<PathBuf> {
+    env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| PathBuf::from(dir).join("pw-volume"))
+}
+
+/// The config file every user shares, which their own overrides.
+const SYSTEM_PATH: &str = "/etc/pw-volume/config.toml";
+
+/// The variables read by [`Config::override_with`], and the settings they
+/// override.
+const ENV_OVERRIDES: [(&str, &str); 15] = [
+    ("PW_VOLUME_STEP", "volume.step"),
+    ("PW_VOLUME_LIMIT", "volume.max"),
+    ("PW_VOLUME_SCALE", "volume.scale"),
+    ("PW_VOLUME_FORMAT", "status.format"),
+    ("PW_VOLUME_OUTPUT", "status.output"),
+    ("PW_VOLUME_ICONS", "icons.preset"),
+    ("PW_VOLUME_NOTIFY", "notify.enabled"),
+    ("PW_VOLUME_PW_CLI", "tools.pw-cli"),
+    ("PW_VOLUME_PW_DUMP", "tools.pw-dump"),
+    ("PW_VOLUME_PW_METADATA", "tools.pw-metadata"),
+    ("PW_VOLUME_PW_PLAY", "tools.pw-play"),
+    ("PW_VOLUME_PW_RECORD", "tools.pw-record"),
+    ("PW_VOLUME_PIPEWIRE", "tools.pipewire"),
+    ("PW_VOLUME_WPCTL", "tools.wpctl"),
+    ("PW_VOLUME_PACTL", "tools.pactl"),
+];
+
+/// Whether any setting is overridden from the environment, which a running
+/// daemon, having its own environment, wouldn't see.
+pub fn env_overrides() -> bool {
+    ENV_OVERRIDES
+        .iter()
+        .any(|(name, _)| env::var_os(name).is_some_and(|value| !value.is_empty()))
+}
+
+/// The settings from every layer merged, and where each came from, keyed by
+/// dotted path, e.g. `volume.step`.
+#[derive(Debug, Default, PartialEq)]
+struct Layers {
+    table: Table,
+    origins: BTreeMap<String, String>,
+}
+
+impl Layers {
+    /// Lays `table` over what's there. Tables are merged key by key, and
+    /// anything else, arrays of tables included, replaces what was there.
+    fn add(&mut self, table: Table, origin: &str) {
+        fn merge(
+            into: &mut Table,
+            from: Table,
+            prefix: &str,
+            origin: &str,
+            origins: &mut BTreeMap<String, String>,
+        ) {
+            for (key, value) in from {
+                let path = format!("{}{}", prefix, key);
+                match (into.get_mut(&key), value) {
+                    (Some(Value::Table(into)), Value::Table(from)) => {
+                        merge(into, from, &format!("{}.", path), origin, origins)
+                    }
+                    (_, value) => {
+                        let nested = format!("{}.", path);
+                        origins.retain(|key, _| *key != path && !key.starts_with(&nested));
+                        record(&value, &path, origin, origins);
+                        into.insert(key, value);
+                    }
+                }
+            }
+        }
+        fn record(value: &Value, path: &str, origin: &str, origins: &mut BTreeMap<String, String>) {
+            match value {
+                Value::Table(table) => {
+                    for (key, value) in table {
+                        record(value, &format!("{}.{}", path, key), origin, origins);
+                    }
+                }
+                _ => {
+                    origins.insert(path.to_string(), origin.to_string());
+                }
+            }
+        }
+        merge(&mut self.table, table, "", origin, &mut self.origins);
+    }
+
+    /// Reads a layer from a file, if it exists. Each file has to make sense
+    /// on its own, so that a mistake is blamed on the file it's in.
+    fn read(&mut self, path: &Path) -> anyhow::Result<()> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        let parse = || -> anyhow::Result<Table> {
+            let table: Table = toml::from_str(&contents)?;
+            Value::Table(table.clone()).try_into::<Config>()?;
+            Ok(table)
+        };
+        let table = parse().with_context(|| format!("failed to parse {}", path.display()))?;
+        self.add(table, &path.display().to_string());
+        Ok(())
+    }
+
+    /// The system-wide file, then the user's.
+    fn files() -> anyhow::Result<Layers> {
+        let mut layers = Layers::default();
+        layers.read(Path::new(SYSTEM_PATH))?;
+        if let Some(path) = Config::path() {
+            layers.read(&path)?;
+        }
+        Ok(layers)
+    }
+
+    /// Adds the environment variables that are set, as close to the type of
+    /// the setting as they can be shown. Only used for showing the config;
+    /// [`Config::override_with`] is what applies them.
+    fn add_env(&mut self, var: impl Fn(&str) -> Option<String>) {
+        for (name, key) in ENV_OVERRIDES {
+            let value = match var(name).filter(|value| !value.is_empty()) {
+                Some(value) => value,
+                None => continue,
+            };
+            let value = match key {
+                "volume.step" | "volume.max" => value
+                    .trim_end_matches('%')
+                    .parse()
+                    .map_or(Value::String(value), Value::Float),
+                "notify.enabled" => {
+                    Value::Boolean(matches!(value.as_str(), "1" | "true" | "yes" | "on"))
+                }
+                _ => Value::String(value),
+            };
+            let (section, field) = key.split_once('.').unwrap_or(("", key));
+            let mut table = Table::new();
+            table.insert(field.to_string(), value);
+            let mut outer = Table::new();
+            outer.insert(section.to_string(), Value::Table(table));
+            self.add(outer, name);
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("pw-volume").join("config.toml"))
+    }
+
+    /// Loads `/etc/pw-volume/config.toml` and then the user's config file
+    /// over it, falling back to defaults for what neither sets, and then
+    /// applies the `PW_VOLUME_*` environment variables over both.
+    pub fn load() -> anyhow::Result<Config> {
+        let mut config: Config = Value::Table(Layers::files()?.table)
+            .try_into()
+            .context("failed to combine the config files")?;
+        config.override_with(|name| env::var(name).ok())?;
+        Ok(config)
+    }
+
+    /// Overrides settings with environment variables, for tweaks that only
+    /// last a session, e.g. `PW_VOLUME_STEP=2` on a sway `exec` line. Empty
+    /// variables are ignored.
+    fn override_with(&mut self, var: impl Fn(&str) -> Option<String>) -> anyhow::Result<()> {
+        fn parse<T: FromStr>(name: &str, value: &str) -> anyhow::Result<T>
+        where
+            T::Err: std::fmt::Display,
+        {
+            value
+                .parse()
+                .map_err(|e| anyhow!("{} is set to \"{}\": {}", name, value, e))
+        }
+        let var = |name: &str| var(name).filter(|value| !value.is_empty());
+        if let Some(step) = var("PW_VOLUME_STEP") {
+            self.volume.step = parse("PW_VOLUME_STEP", step.trim_end_matches('%'))?;
+        }
+        if let Some(limit) = var("PW_VOLUME_LIMIT") {
+            self.volume.max = parse("PW_VOLUME_LIMIT", limit.trim_end_matches('%'))?;
+        }
+        if let Some(scale) = var("PW_VOLUME_SCALE") {
+            self.volume.scale = parse("PW_VOLUME_SCALE", &scale)?;
+        }
+        if let Some(format) = var("PW_VOLUME_FORMAT") {
+            self.status.format = Some(format);
+        }
+        if let Some(output) = var("PW_VOLUME_OUTPUT") {
+            self.status.output = Some(output);
+        }
+        if let Some(preset) = var("PW_VOLUME_ICONS") {
+            self.icons.preset = parse("PW_VOLUME_ICONS", &preset)?;
+        }
+        if let Some(notify) = var("PW_VOLUME_NOTIFY") {
+            self.notify.enabled = match notify.as_str() {
+                "1" | "true" | "yes" | "on" => true,
+                "0" | "false" | "no" | "off" => false,
+                _ => parse::<bool>("PW_VOLUME_NOTIFY", &notify)?,
+            };
+        }
+        for (name, tool) in self.tools.overrides() {
+            if let Some(command) = var(name) {
+                *tool = Some(command);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A value on one line, with tables inline, e.g. arrays of tables such as
+/// `[[focus]]`.
+fn inline(value: &Value) -> String {
+    match value {
+        Value::Table(table) => {
+            let entries: Vec<String> = table
+                .iter()
+                .map(|(key, value)| format!("{} = {}", key, inline(value)))
+                .collect();
+            format!("{{ {} }}", entries.join(", "))
+        }
+        Value::Array(values) => {
+            let values: Vec<String> = values.iter().map(inline).collect();
+            format!("[{}]", values.join(", "))
+        }
+        value => value.to_string(),
+    }
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("config")
+        .about("shows the settings from the config files and environment")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("show")
+                .about("prints the settings in effect, leaving out defaults")
+                .arg(Arg::with_name("origin").long("origin").help(
+                    "print each setting on its own line with the file or variable it came from",
+                )),
+        )
+}
+
+pub fn run(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    match matches.subcommand() {
+        ("show", Some(arg)) => {
+            let mut layers = Layers::files()?;
+            layers.add_env(|name| env::var(name).ok());
+            if arg.is_present("origin") {
+                for (key, origin) in &layers.origins {
+                    let value = key.split('.').try_fold(&layers.table, |table, part| {
+                        match table.get(part) {
+                            Some(Value::Table(table)) => Ok(table),
+                            value => Err(value),
+                        }
+                    });
+                    if let Err(Some(value)) = value {
+                        println!("{} = {}  # {}", key, inline(value), origin);
+                    }
+                }
+            } else {
+                print!("{}", toml::to_string(&layers.table)?);
+            }
+            Ok(())
+        }
+        (_, _) => unreachable!("argument parsing should have failed by now"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn icon_overrides() -> anyhow::Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            [icons]
+            preset = "ascii"
+            mute = "M"
+            "#,
+        )?;
+        let icons = config.icons.icons();
+        assert_eq!(icons.mute, "M");
+        assert_eq!(icons.levels, Icons::preset(IconPreset::Ascii).levels);
+        Ok(())
+    }
+
+    #[test]
+    fn environment_overrides() -> anyhow::Result<()> {
+        let mut config: Config = toml::from_str("[volume]\nstep = 2.5\nmax = 150")?;
+        config.override_with(|name| match name {
+            "PW_VOLUME_STEP" => Some("1%".to_string()),
+            "PW_VOLUME_SCALE" => Some("cubic".to_string()),
+            "PW_VOLUME_NOTIFY" => Some("yes".to_string()),
+            "PW_VOLUME_FORMAT" => Some(String::new()),
+            _ => None,
+        })?;
+        assert_eq!(
+            config.volume,
+            VolumeConfig {
+                step: 1.0,
+                max: 150.0,
+                scale: Scale::Cubic,
+            }
+        );
+        assert!(config.notify.enabled);
+        assert_eq!(config.status.format, None);
+
+        let err = config
+            .override_with(|name| (name == "PW_VOLUME_LIMIT").then(|| "loud".to_string()))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"PW_VOLUME_LIMIT is set to "loud": invalid float literal"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn layered_origins() -> anyhow::Result<()> {
+        let mut layers = Layers::default();
+        layers.add(
+            toml::from_str(
+                "[volume]\nstep = 2.0\nmax = 120.0\n[[focus]]\noutput = \"DP-1\"\nsink = \"a\"",
+            )?,
+            "/etc/pw-volume/config.toml",
+        );
+        layers.add(
+            toml::from_str("[volume]\nstep = 5.0\n[[focus]]\nworkspace = \"1\"\nsink = \"b\"")?,
+            "~/.config/pw-volume/config.toml",
+        );
+        layers.add_env(|name| (name == "PW_VOLUME_LIMIT").then(|| "150%".to_string()));
+        let origins: Vec<(&str, &str)> = layers
+            .origins
+            .iter()
+            .map(|(key, origin)| (key.as_str(), origin.as_str()))
+            .collect();
+        assert_eq!(
+            origins,
+            [
+                ("focus", "~/.config/pw-volume/config.toml"),
+                ("volume.max", "PW_VOLUME_LIMIT"),
+                ("volume.step", "~/.config/pw-volume/config.toml"),
+            ]
+        );
+        let config: Config = Value::Table(layers.table).try_into()?;
+        assert_eq!(config.volume.step, 5.0);
+        assert_eq!(config.volume.max, 150.0);
+        assert_eq!(config.focus.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn cubic_scale() {
+        assert!((Scale::Cubic.scaled(0.125) - 0.5).abs() < 1e-12);
+        assert!((Scale::Cubic.linear(0.5) - 0.125).abs() < 1e-12);
+        assert_eq!(Scale::Linear.linear(0.3), 0.3);
+    }
+
+    #[test]
+    fn device_scales() -> anyhow::Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            [volume]
+            scale = "cubic"
+            [devices."usb-Topping"]
+            scale = "power:2.2"
+            [devices."usb-Topping_D10s"]
+            scale = "linear"
+            [devices.hdmi]
+            "#,
+        )?;
+        let scale = |node| device_scale(&config.devices, node);
+        assert_eq!(
+            scale("alsa_output.usb-Topping_E30-00.analog-stereo"),
+            Some(Scale::Power(2.2))
+        );
+        assert_eq!(
+            scale("alsa_output.usb-Topping_D10s-00.analog-stereo"),
+            Some(Scale::Linear)
+        );
+        assert_eq!(scale("alsa_output.pci-0000_00_1f.3.hdmi-stereo"), None);
+        assert!((Scale::Power(2.0).scaled(0.25) - 0.5).abs() < 1e-12);
+        assert!((Scale::Power(2.0).linear(0.5) - 0.25).abs() < 1e-12);
+        assert_eq!(
+            "power:0".parse::<Scale>(),
+            Err(r#""power:0" is not a scale"#.to_string())
+        );
+        Ok(())
+    }
+}
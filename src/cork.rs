@@ -0,0 +1,241 @@
+//! `pw-volume app NAME cork`: asks an application to pause, as PulseAudio's
+//! corking does, for players that are better paused than muted, since they
+//! pick up where they left off rather than playing on in silence. The request
+//! goes to the application's MPRIS player on the session bus, so it works for
+//! those that have one, such as browsers and media players. `app NAME uncork`
+//! resumes only what `cork` paused, which is remembered in
+//! `$XDG_RUNTIME_DIR/pw-volume/corked.json`.
+
+use std::{collections::BTreeMap, fs, path::PathBuf, sync::atomic::Ordering};
+
+use anyhow::{anyhow, ensure, Context};
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use crate::{
+    config::runtime_dir,
+    dry_run,
+    error::{Failure, Kind},
+    log, pw_dump, shell_words, streams, tools, unmarshal, PipeWireObject, DRY_RUN,
+};
+
+const PREFIX: &str = "org.mpris.MediaPlayer2.";
+
+/// The MPRIS players that `cork` paused, by application name.
+pub type Corked = BTreeMap<String, Vec<String>>;
+
+fn path() -> Option<PathBuf> {
+    runtime_dir().map(|dir| dir.join("corked.json"))
+}
+
+pub fn load() -> Corked {
+    path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn save(corked: &Corked) -> anyhow::Result<()> {
+    let path = path().ok_or_else(|| anyhow!("failed to determine runtime directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, serde_json::to_string(corked)?)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// The bus names in dbus-send's reply to `ListNames`.
+fn bus_names(reply: &str) -> Vec<&str> {
+    reply
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("string \"")?.strip_suffix('"'))
+        .collect()
+}
+
+/// Whether `bus` is the MPRIS player of the application `name`. Players are
+/// named after the program, e.g. `org.mpris.MediaPlayer2.firefox.instance_1_84`
+/// for Firefox, or `org.mpris.MediaPlayer2.vlc` for VLC media player.
+fn plays(bus: &str, name: &str) -> bool {
+    let player = match bus.strip_prefix(PREFIX) {
+        Some(rest) => rest.split('.').next().unwrap_or_default(),
+        None => return false,
+    };
+    let name = name.to_lowercase();
+    !player.is_empty() && (player == name || name.split_whitespace().next() == Some(player))
+}
+
+/// The bus names of the application's MPRIS players.
+fn players(name: &str) -> anyhow::Result<Vec<String>> {
+    let output = tools::output(tools::command("dbus-send").args([
+        "--session",
+        "--print-reply",
+        "--dest=org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        "org.freedesktop.DBus.ListNames",
+    ]))
+    .context("failed to execute dbus-send")?;
+    ensure!(
+        output.status.success(),
+        "dbus-send did not exit successfully"
+    );
+    let reply = String::from_utf8_lossy(&output.stdout);
+    Ok(bus_names(&reply)
+        .into_iter()
+        .filter(|bus| plays(bus, name))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Calls a player's `Pause` or `Play`.
+fn call(bus: &str, method: &str) -> anyhow::Result<()> {
+    let dest = format!("--dest={}", bus);
+    let method = format!("org.mpris.MediaPlayer2.Player.{}", method);
+    let args = [
+        "--session",
+        "--type=method_call",
+        &dest,
+        "/org/mpris/MediaPlayer2",
+        &method,
+    ];
+    if dry_run("dbus-send", &args) {
+        return Ok(());
+    }
+    log::debug(
+        1,
+        format_args!("running {}", shell_words("dbus-send", &args)),
+    );
+    let status = tools::status(tools::command("dbus-send").args(args))
+        .context("failed to execute dbus-send")?;
+    ensure!(status.success(), "dbus-send did not exit successfully");
+    Ok(())
+}
+
+/// Whether any of the application's playback streams is playing.
+pub fn playing(obj: &[PipeWireObject<'_>], name: &str) -> bool {
+    streams(obj).iter().any(|(s, _)| {
+        s.info.props.application_name.as_deref() == Some(name)
+            && s.info.props.media_class.starts_with("Stream/Output/")
+            && s.info.state == Some("running")
+    })
+}
+
+/// `corked` while paused by `cork`, `playing`, or `idle`.
+fn state(corked: &Corked, obj: &[PipeWireObject<'_>], name: &str) -> &'static str {
+    if playing(obj, name) {
+        "playing"
+    } else if corked.contains_key(name) {
+        "corked"
+    } else {
+        "idle"
+    }
+}
+
+/// Pauses the application's players, or resumes the ones that it paused.
+pub fn set(name: &str, cork: bool) -> anyhow::Result<()> {
+    let mut corked = load();
+    if cork {
+        let players = players(name)?;
+        if players.is_empty() {
+            return Err(Failure::new(
+                Kind::Other,
+                format!("{} has no MPRIS player to cork; mute it instead", name),
+            )
+            .into());
+        }
+        for bus in &players {
+            call(bus, "Pause")?;
+        }
+        corked.insert(name.to_string(), players);
+    } else {
+        let players = match corked.remove(name) {
+            Some(players) => players,
+            None => return Ok(()),
+        };
+        for bus in players {
+            // a player that quit meanwhile has nothing to resume
+            if let Err(e) = call(&bus, "Play") {
+                log::debug(1, format_args!("failed to resume {}: {:#}", bus, e));
+            }
+        }
+    }
+    if DRY_RUN.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    save(&corked)
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("app")
+        .about("pauses an application through its media player, a gentler mute")
+        .arg(
+            Arg::with_name("NAME")
+                .help("application name, as export-state lists it")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("ACTION")
+                .help("cork pauses it, uncork resumes what cork paused, and status prints corked, playing, or idle")
+                .possible_values(&["cork", "uncork", "status"])
+                .required(true),
+        )
+}
+
+pub fn run(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let name = matches.value_of("NAME").unwrap_or_default();
+    let buf = pw_dump()?;
+    let obj = unmarshal(&buf)?;
+    let corked = load();
+    // some players close their streams while paused
+    if !corked.contains_key(name)
+        && !streams(&obj)
+            .iter()
+            .any(|(s, _)| s.info.props.application_name.as_deref() == Some(name))
+    {
+        return Err(Failure::new(Kind::Other, format!("no application {:?}", name)).into());
+    }
+    match matches.value_of("ACTION") {
+        // only a playing application has anything to pause
+        Some("cork") if playing(&obj, name) => set(name, true),
+        Some("cork") => Ok(()),
+        Some("uncork") => set(name, false),
+        _ => {
+            println!("{}", state(&corked, &obj, name));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("org.mpris.MediaPlayer2.firefox.instance_1_84", "Firefox" => true)]
+    #[test_case("org.mpris.MediaPlayer2.vlc", "VLC media player" => true)]
+    #[test_case("org.mpris.MediaPlayer2.mpv", "mpv" => true)]
+    #[test_case("org.mpris.MediaPlayer2.mpv", "Firefox" => false)]
+    #[test_case("org.freedesktop.Notifications", "Notifications" => false)]
+    #[test_case("org.mpris.MediaPlayer2.", "" => false)]
+    fn player_names(bus: &str, name: &str) -> bool {
+        plays(bus, name)
+    }
+
+    #[test]
+    fn list_names() {
+        let reply = r#"method return time=1700000000.000000 sender=org.freedesktop.DBus -> destination=:1.90 serial=3 reply_serial=2
+   array [
+      string "org.freedesktop.DBus"
+      string ":1.7"
+      string "org.mpris.MediaPlayer2.spotify"
+   ]
+"#;
+        assert_eq!(
+            bus_names(reply),
+            [
+                "org.freedesktop.DBus",
+                ":1.7",
+                "org.mpris.MediaPlayer2.spotify"
+            ]
+        );
+    }
+}
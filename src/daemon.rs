@@ -0,0 +1,1265 @@
+//! `pw-volume daemon`: a long-running process that keeps rules from the config
+//! file in force as the PipeWire graph changes, and answers the same commands
+//! as the command line over a Unix socket without running pw-dump each time.
+//!
+//! A client may open with a [`Message::Hello`], which the daemon answers with
+//! the protocol versions and commands it supports, and then sends one
+//! [`Message::Command`]. The reply to that is one line holding a
+//! [`Response`]. Clients from before the hello send a bare JSON array of
+//! arguments, such as `["change", "+5%"]`, which is still answered as
+//! version 1. Since version 3, a client may instead send a
+//! [`Message::Subscribe`] and keep the connection open, to be sent the
+//! default devices' state whenever it changes.
+//!
+//! Requests are handled one at a time, and the daemon's own writes show in
+//! its copy of the graph right away, so commands from clients arriving
+//! together never interleave. `--if-volume` and `--if-mute` make a command
+//! fail instead if another client got there first.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    ffi::OsString,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::Ordering,
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app, battery,
+    config::{runtime_dir, state_dir, Config},
+    dbus::Signal,
+    degraded_status, error, fade, focus,
+    focus::Focus,
+    format::Status,
+    graph::Graph,
+    hook,
+    limiter::Limiter,
+    log, node_id,
+    output::Output,
+    parse_dump,
+    privacy::Guard,
+    pw_cli,
+    record::{self, Record},
+    route_command, set_default_name, set_route, target, unmarshal, update, volume_command,
+    DeviceRoute, PipeWireInterfaceNode, PipeWireObject, Target, VolumeOp, DRY_RUN,
+};
+
+/// How often to check the rules when `pw-dump --monitor` isn't available.
+const POLL: Duration = Duration::from_secs(2);
+
+/// How long a client may take to send its request.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a client waits for the daemon to answer its hello before running
+/// the command itself, as when no daemon is running, e.g. because it was
+/// stopped with `SIGSTOP` or is wedged.
+const HELLO_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long a client waits for the reply to a command. The daemon may have
+/// run it by then, so running it again could apply it twice, and it fails
+/// instead.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Subcommands that the daemon answers. The others either run for a long
+/// time or talk to the user.
+pub const COMMANDS: [&str; 8] = [
+    "mute",
+    "ptt",
+    "change",
+    "status",
+    "get-volume",
+    "get-mute",
+    "is-muted",
+    "is-over",
+];
+
+/// The protocol version the daemon speaks, and the oldest it still answers.
+pub const VERSION: u32 = 3;
+const MIN_VERSION: u32 = 1;
+
+/// Volumes closer than this are considered equal, to absorb rounding in
+/// PipeWire's reported volumes.
+const EPSILON: f64 = 1e-4;
+
+/// An `[[offsets]]` entry: keeps `follower` at a fixed level relative to
+/// `leader`, e.g. rear speakers 6 dB below the fronts.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Offset {
+    pub leader: String,
+    pub follower: String,
+    pub db: f64,
+}
+
+impl Offset {
+    /// The follower's volume as a multiple of the leader's.
+    fn ratio(&self) -> f64 {
+        10f64.powf(self.db / 20.0)
+    }
+}
+
+/// The `[mic-lock]` section: holds the default source at `volume` percent,
+/// undoing changes unless a client in `allow` is connected.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MicLock {
+    pub volume: f64,
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+/// The `[restore]` section: puts a sink back at the volume it had when it
+/// was last the default, when it becomes the default again, e.g. Bluetooth
+/// headphones that reconnect at full volume.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RestoreConfig {
+    /// Parts of the node names of the sinks to restore; all of them if empty.
+    pub devices: Vec<String>,
+}
+
+impl RestoreConfig {
+    fn restores(&self, node: &str) -> bool {
+        self.devices.is_empty() || self.devices.iter().any(|part| node.contains(part.as_str()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Adjust {
+    Leader(f64),
+    Follower(f64),
+}
+
+/// Works out which side of an offset moved since the volumes were `last`
+/// seen, and what the other side should be set to. The leader wins if both
+/// moved, or on the first pass.
+fn adjust(ratio: f64, leader: f64, follower: f64, last: Option<(f64, f64)>) -> Option<Adjust> {
+    let leader_moved = last.is_none_or(|(l, _)| (l - leader).abs() > EPSILON);
+    let follower_moved = last.is_some_and(|(_, f)| (f - follower).abs() > EPSILON);
+    if leader_moved {
+        let want = (leader * ratio).clamp(0.0, 1.0);
+        ((want - follower).abs() > EPSILON).then_some(Adjust::Follower(want))
+    } else if follower_moved {
+        let want = (follower / ratio).clamp(0.0, 1.0);
+        ((want - leader).abs() > EPSILON).then_some(Adjust::Leader(want))
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Lock {
+    /// Hold the source at its new volume from now on.
+    Keep(f64),
+    /// Put the source back at the locked volume.
+    Revert(f64),
+}
+
+/// What to do about the default source's volume, given the level it is held
+/// at: keep a change as the new level if it was allowed, or undo it.
+fn lock(locked: f64, current: f64, allowed: bool) -> Option<Lock> {
+    if (current - locked).abs() <= EPSILON {
+        None
+    } else if allowed {
+        Some(Lock::Keep(current))
+    } else {
+        Some(Lock::Revert(locked))
+    }
+}
+
+/// The channel volumes to put the default sink back at, if it has just
+/// become the default and was last at `saved`.
+fn restore(saved: Option<&[f64]>, current: &[f64], became_default: bool) -> Option<Vec<f64>> {
+    let saved = saved.filter(|_| became_default)?;
+    let differs = saved.len() != current.len()
+        || saved
+            .iter()
+            .zip(current)
+            .any(|(s, c)| (s - c).abs() > EPSILON);
+    differs.then(|| saved.to_vec())
+}
+
+/// `$XDG_STATE_HOME/pw-volume/volumes.toml`, where the volumes that
+/// `[restore]` puts back are kept between runs of the daemon.
+fn volumes_path() -> Option<PathBuf> {
+    state_dir().map(|dir| dir.join("volumes.toml"))
+}
+
+fn load_volumes() -> BTreeMap<String, Vec<f64>> {
+    volumes_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| toml::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn save_volumes(volumes: &BTreeMap<String, Vec<f64>>) -> anyhow::Result<()> {
+    // a simulation leaves the volumes of the real daemon alone
+    if DRY_RUN.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let path = volumes_path().ok_or_else(|| anyhow!("failed to determine state directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, toml::to_string(volumes)?)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Application names of the clients connected to PipeWire.
+fn clients<'a>(obj: &'a [PipeWireObject<'_>]) -> impl Iterator<Item = &'a str> {
+    obj.iter().filter_map(|o| match o {
+        PipeWireObject::Value(v) if v["type"] == "PipeWire:Interface:Client" => {
+            v["info"]["props"]["application.name"].as_str()
+        }
+        _ => None,
+    })
+}
+
+/// What the rules remember between passes.
+#[derive(Debug, Default)]
+struct Memory {
+    /// The volumes of each offset's devices, by index.
+    offsets: HashMap<usize, (f64, f64)>,
+    /// The level the default source is held at, once it differs from the
+    /// config file's.
+    mic: Option<f64>,
+    /// Keep the default source's next change, which came over the socket.
+    mic_allowed: bool,
+    /// The node held open by `ptt press`, and when to give up on its release.
+    ptt: Option<(i64, Instant)>,
+    /// The default sink's and source's signals, with `--dbus`.
+    signals: Option<(Signal, Signal)>,
+    guard: Guard,
+    /// The sink last made the default for the focused workspace or output.
+    focus: Option<String>,
+    /// The default sink when the rules last ran, to tell when it changes.
+    sink: Option<String>,
+    /// Each sink's channel volumes when it was last the default, by node
+    /// name, with `[restore]`.
+    volumes: BTreeMap<String, Vec<f64>>,
+    limiter: Limiter,
+    /// Clients that subscribed to the default devices' state.
+    subscribers: Vec<UnixStream>,
+    /// The state they were last sent.
+    state: Option<String>,
+}
+
+impl Memory {
+    fn new(config: &Config) -> Memory {
+        Memory {
+            volumes: if config.restore.is_some() {
+                load_volumes()
+            } else {
+                BTreeMap::new()
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// Sets a route's volume, keeping the balance between its channels.
+pub fn set_volume(
+    node: &PipeWireInterfaceNode<'_>,
+    route: &DeviceRoute<'_>,
+    volume: f64,
+) -> anyhow::Result<()> {
+    let current = route.props.channel_volumes[0];
+    let channel_volumes = route
+        .props
+        .channel_volumes
+        .iter()
+        .map(|vol| {
+            if current > 0.0 {
+                (vol * volume / current).clamp(0.0, 1.0)
+            } else {
+                volume
+            }
+        })
+        .collect();
+    set_route(node, &volume_command(node, route, channel_volumes))
+}
+
+/// Holds the default source at its locked level.
+fn enforce_mic_lock(
+    mic_lock: &MicLock,
+    obj: &[PipeWireObject<'_>],
+    memory: &mut Memory,
+) -> anyhow::Result<()> {
+    let (node, route) = match parse_dump(obj, Target::DefaultSource) {
+        Ok(source) => source,
+        Err(_) => return Ok(()),
+    };
+    let locked = memory.mic.unwrap_or(mic_lock.volume * 0.01);
+    let allowed = memory.mic_allowed
+        || clients(obj).any(|name| mic_lock.allow.iter().any(|allow| allow == name));
+    match lock(locked, route.props.channel_volumes[0], allowed) {
+        Some(Lock::Keep(volume)) => {
+            memory.mic = Some(volume);
+            memory.mic_allowed = false;
+        }
+        Some(Lock::Revert(volume)) => set_volume(node, route, volume)?,
+        None => (),
+    }
+    Ok(())
+}
+
+/// Puts the default sink back at its last volume when it has just become the
+/// default, and otherwise remembers its volume.
+fn enforce_restore(
+    config: &RestoreConfig,
+    obj: &[PipeWireObject<'_>],
+    memory: &mut Memory,
+) -> anyhow::Result<()> {
+    let (node, route) = match parse_dump(obj, Target::DefaultSink) {
+        Ok(sink) => sink,
+        Err(_) => {
+            memory.sink = None;
+            return Ok(());
+        }
+    };
+    let name = node.info.props.node_name;
+    let became_default = memory.sink.as_deref() != Some(name);
+    memory.sink = Some(name.to_string());
+    if !config.restores(name) {
+        return Ok(());
+    }
+    let current = &route.props.channel_volumes;
+    let saved = memory.volumes.get(name).map(Vec::as_slice);
+    if let Some(volumes) = restore(saved, current, became_default) {
+        log::debug(
+            1,
+            format_args!("restoring {} to {:.0}%", name, volumes[0] * 100.0),
+        );
+        return set_route(node, &volume_command(node, route, volumes));
+    }
+    if saved != Some(current.as_slice()) {
+        memory.volumes.insert(name.to_string(), current.clone());
+        save_volumes(&memory.volumes)?;
+    }
+    Ok(())
+}
+
+/// Mutes the node held open by `ptt press` if the release is overdue, e.g.
+/// because the key-up event was lost. A node that has gone, e.g. a headset
+/// unplugged while held, counts as released.
+fn enforce_ptt(obj: &[PipeWireObject<'_>], memory: &mut Memory) -> anyhow::Result<()> {
+    let (id, deadline) = match memory.ptt {
+        Some(held) => held,
+        None => return Ok(()),
+    };
+    let (node, route) = match parse_dump(obj, Target::Id(id)) {
+        Ok(found) => found,
+        Err(_) => {
+            memory.ptt = None;
+            return Ok(());
+        }
+    };
+    if Instant::now() < deadline {
+        return Ok(());
+    }
+    memory.ptt = None;
+    set_route(
+        node,
+        &route_command(VolumeOp::Mute(Some(true)), node, route),
+    )
+}
+
+/// Prints why a rule failed, so that the other rules, and the daemon, keep
+/// going.
+fn report(rule: &str, result: anyhow::Result<()>) {
+    if let Err(e) = result {
+        eprintln!("{}: {:#}", rule, e);
+    }
+}
+
+/// Applies every rule once.
+fn enforce(config: &Config, graph: &Graph, memory: &mut Memory) -> anyhow::Result<()> {
+    let buf = graph.dump()?;
+    let obj = unmarshal(&buf)?;
+    report("ptt-failsafe", enforce_ptt(&obj, memory));
+    if let Some(privacy) = &config.privacy {
+        report("privacy", memory.guard.check(privacy, &obj));
+    }
+    if let Some((sink, source)) = &mut memory.signals {
+        for (signal, target) in [(sink, Target::DefaultSink), (source, Target::DefaultSource)] {
+            if let Ok((node, route)) = parse_dump(&obj, target) {
+                report("dbus", signal.update(node, route));
+            }
+        }
+    }
+    if let Some(mic_lock) = &config.mic_lock {
+        report("mic-lock", enforce_mic_lock(mic_lock, &obj, memory));
+    }
+    if let Some(restore) = &config.restore {
+        report("restore", enforce_restore(restore, &obj, memory));
+    }
+    if let Some(limiter) = &config.limiter {
+        report("limiter", memory.limiter.check(limiter, &obj));
+    }
+    report(
+        "offsets",
+        enforce_offsets(config, &obj, &mut memory.offsets),
+    );
+    Ok(())
+}
+
+/// Applies every offset once, recording the volumes each one ends up at.
+fn enforce_offsets(
+    config: &Config,
+    obj: &[PipeWireObject<'_>],
+    last: &mut HashMap<usize, (f64, f64)>,
+) -> anyhow::Result<()> {
+    let find = |name: &str| node_id(obj, name).and_then(|id| parse_dump(obj, Target::Id(id)));
+    for (i, offset) in config.offsets.iter().enumerate() {
+        // either device may come and go, e.g. over Bluetooth
+        let ((leader_node, leader_route), (follower_node, follower_route)) =
+            match (find(&offset.leader), find(&offset.follower)) {
+                (Ok(leader), Ok(follower)) => (leader, follower),
+                _ => {
+                    last.remove(&i);
+                    continue;
+                }
+            };
+        let mut leader = leader_route.props.channel_volumes[0];
+        let mut follower = follower_route.props.channel_volumes[0];
+        match adjust(offset.ratio(), leader, follower, last.get(&i).copied()) {
+            Some(Adjust::Leader(volume)) => {
+                set_volume(leader_node, leader_route, volume)?;
+                leader = volume;
+            }
+            Some(Adjust::Follower(volume)) => {
+                set_volume(follower_node, follower_route, volume)?;
+                follower = volume;
+            }
+            None => (),
+        }
+        last.insert(i, (leader, follower));
+    }
+    Ok(())
+}
+
+/// Makes the sink for a newly focused workspace or output the default, if
+/// it's there. The choice is only made when focus moves, so that picking
+/// another default by hand sticks until then.
+fn follow_focus(
+    config: &Config,
+    graph: &Graph,
+    focus: &Focus,
+    memory: &mut Memory,
+) -> anyhow::Result<()> {
+    let sink = match focus::sink(&config.focus, focus) {
+        Some(sink) => sink,
+        None => return Ok(()),
+    };
+    if memory.focus.as_deref() == Some(sink) {
+        return Ok(());
+    }
+    let buf = graph.dump()?;
+    let obj = unmarshal(&buf)?;
+    if node_id(&obj, sink).is_ok() {
+        set_default_name("default.configured.audio.sink", sink)?;
+        memory.focus = Some(sink.to_string());
+    }
+    Ok(())
+}
+
+/// `$XDG_RUNTIME_DIR/pw-volume.sock`, or `pw-volume-<remote>.sock` for
+/// another PipeWire instance chosen with `--remote`, so that its daemon
+/// doesn't take the local one's place.
+pub fn socket_path() -> Option<PathBuf> {
+    let dir = runtime_dir()?;
+    let remote = env::var_os("PIPEWIRE_REMOTE")
+        .filter(|remote| !remote.is_empty() && remote != "pipewire-0")
+        .map(PathBuf::from);
+    // a remote may be the path of a socket
+    match remote.as_deref().and_then(Path::file_name) {
+        Some(name) => {
+            Some(dir.with_file_name(format!("pw-volume-{}.sock", name.to_string_lossy())))
+        }
+        None => Some(dir.with_extension("sock")),
+    }
+}
+
+/// A line sent to the daemon.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+enum Request {
+    /// Version 1: a bare array of arguments.
+    Args(Vec<String>),
+    Message(Message),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum Message {
+    /// Asks what the daemon supports, before sending a command.
+    Hello { version: u32 },
+    /// Runs a command, given as its arguments, in a version of the protocol
+    /// that both sides speak.
+    Command { version: u32, args: Vec<String> },
+    /// Asks for the default devices' state now and after every change.
+    Subscribe { version: u32 },
+    /// A message from a newer client.
+    #[serde(other)]
+    Unknown,
+}
+
+/// The rules the daemon keeps in force, by name, and whether the config file
+/// turns each one on.
+pub fn rules(config: &Config) -> [(&'static str, bool); 7] {
+    [
+        ("ptt-failsafe", true),
+        ("offsets", !config.offsets.is_empty()),
+        ("mic-lock", config.mic_lock.is_some()),
+        ("focus", !config.focus.is_empty()),
+        ("privacy", config.privacy.is_some()),
+        ("restore", config.restore.is_some()),
+        ("limiter", config.limiter.is_some()),
+    ]
+}
+
+/// The reply to a hello.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+struct Hello {
+    version: u32,
+    min_version: u32,
+    /// The subcommands the daemon answers.
+    commands: Vec<String>,
+    /// The rules it is keeping in force, e.g. `mic-lock`.
+    #[serde(default)]
+    features: Vec<String>,
+    /// How many hooks have failed since it started, and how the last one
+    /// did.
+    #[serde(default)]
+    hook_failures: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_hook_failure: Option<String>,
+}
+
+impl Hello {
+    fn new(config: &Config, memory: &Memory) -> Hello {
+        let mut features = rules(config).to_vec();
+        features.push(("dbus", memory.signals.is_some()));
+        let failures = hook::failures();
+        Hello {
+            version: VERSION,
+            min_version: MIN_VERSION,
+            commands: COMMANDS.iter().map(|name| name.to_string()).collect(),
+            features: features
+                .iter()
+                .filter(|(_, on)| *on)
+                .map(|(name, _)| name.to_string())
+                .collect(),
+            hook_failures: failures.count,
+            last_hook_failure: failures.last,
+        }
+    }
+
+    /// The version to send `name` in, which must be at least `needed`, or
+    /// `None` if this daemon can't run it for us.
+    fn agree(&self, name: &str, needed: u32) -> Option<u32> {
+        let version = self.version.min(VERSION);
+        (version >= self.min_version.max(needed)
+            && self.commands.iter().any(|command| command == name))
+        .then_some(version)
+    }
+}
+
+/// The reply to a command: what it would have printed and its exit code, or
+/// why it failed.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Response {
+    /// The protocol version of the reply, left out for version 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+    pub code: i32,
+    pub output: String,
+    pub error: Option<String>,
+}
+
+impl Response {
+    /// Prints the reply as if the command had run in this process, and
+    /// returns its exit code.
+    pub fn print(self) -> i32 {
+        print!("{}", self.output);
+        if let Some(error) = self.error {
+            eprintln!("{}", error);
+        }
+        self.code
+    }
+}
+
+/// Writes a line to the daemon and reads its reply, giving up after
+/// `timeout` for each.
+fn exchange(stream: &UnixStream, request: &str, timeout: Duration) -> io::Result<String> {
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let mut writer = stream;
+    writeln!(writer, "{}", request)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(line)
+}
+
+/// Sends the subcommand, with its arguments, to the daemon, or returns `None`
+/// if it isn't running or can't run it.
+pub fn request(matches: &ArgMatches<'_>, args: &[OsString]) -> anyhow::Result<Option<Response>> {
+    // the daemon checks conditional writes since version 3
+    let (name, needed) = match matches.subcommand() {
+        (name, Some(arg)) if arg.is_present("if-volume") || arg.is_present("if-mute") => (name, 3),
+        (name, _) => (name, MIN_VERSION),
+    };
+    let connect = || socket_path().map(UnixStream::connect)?.ok();
+    let stream = match connect() {
+        Some(stream) => stream,
+        None => return Ok(None),
+    };
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let hello = match exchange(
+        &stream,
+        &serde_json::to_string(&Message::Hello { version: VERSION })?,
+        HELLO_TIMEOUT,
+    ) {
+        Ok(hello) => hello,
+        Err(e) => {
+            log::debug(1, format_args!("the daemon didn't answer: {}", e));
+            return Ok(None);
+        }
+    };
+    let reply = |stream: &UnixStream, request: String| {
+        exchange(stream, &request, REPLY_TIMEOUT)
+            .context("the daemon didn't reply; the command may or may not have run")
+    };
+    let line = match serde_json::from_str::<Hello>(&hello) {
+        Ok(hello) => match hello.agree(name, needed) {
+            Some(version) => {
+                let command = Message::Command { version, args };
+                reply(&stream, serde_json::to_string(&command)?)?
+            }
+            None => return Ok(None),
+        },
+        // a daemon from before the hello answers it with an error and hangs
+        // up, but still takes a bare array of arguments
+        Err(_) if needed > MIN_VERSION => return Ok(None),
+        Err(_) => match connect() {
+            Some(stream) => reply(&stream, serde_json::to_string(&args)?)?,
+            None => return Ok(None),
+        },
+    };
+    let response = serde_json::from_str(&line).context("failed to parse the daemon's reply")?;
+    Ok(Some(response))
+}
+
+/// Runs a command against the daemon's copy of the graph.
+fn execute(
+    matches: &ArgMatches<'_>,
+    config: &Config,
+    graph: &Graph,
+    memory: &mut Memory,
+) -> anyhow::Result<Response> {
+    match matches.subcommand() {
+        ("change", Some(arg)) if arg.is_present("group") => {
+            bail!("change --group is not available through the daemon")
+        }
+        ("change", Some(arg)) if arg.is_present("over") => {
+            bail!("change --over is not available through the daemon")
+        }
+        (name, Some(arg)) if arg.is_present("after") => {
+            bail!("{} --after is not available through the daemon", name)
+        }
+        (name, _) if !COMMANDS.contains(&name) => {
+            bail!("{} is not available through the daemon", name)
+        }
+        _ => (),
+    }
+    let buf = graph.dump()?;
+    let obj = unmarshal(&buf)?;
+    let (node, route) = parse_dump(&obj, target(matches, config, &obj)?)?;
+    let mut output = Vec::new();
+    let code = pw_cli(matches, config, &obj, node, route, &mut output)?;
+    match matches.subcommand() {
+        ("change", _) => {
+            // changes made with pw-volume are never undone by the mic lock
+            // or the limiter
+            memory.mic_allowed |= parse_dump(&obj, Target::DefaultSource)
+                .is_ok_and(|(source, _)| source.id == node.id);
+            memory.limiter.accept(node.info.props.node_name);
+        }
+        ("ptt", Some(arg)) => {
+            memory.ptt = match arg.value_of("TRANSITION") {
+                Some("press") => Some((node.id, Instant::now() + config.ptt.timeout())),
+                _ => None,
+            };
+        }
+        _ => (),
+    }
+    Ok(Response {
+        code,
+        output: String::from_utf8(output)?,
+        ..Default::default()
+    })
+}
+
+/// A reply saying why a request couldn't be run.
+fn failure(error: String) -> Response {
+    Response {
+        code: 1,
+        error: Some(error),
+        ..Default::default()
+    }
+}
+
+fn respond(args: &[String], config: &Config, graph: &Graph, memory: &mut Memory) -> Response {
+    let matches = match app()
+        .get_matches_from_safe(std::iter::once("pw-volume").chain(args.iter().map(String::as_str)))
+    {
+        Ok(matches) => matches,
+        Err(e) if e.kind == clap::ErrorKind::HelpDisplayed => {
+            return Response {
+                output: e.message,
+                ..Default::default()
+            }
+        }
+        Err(e) => return failure(e.message),
+    };
+    execute(&matches, config, graph, memory).unwrap_or_else(|e| {
+        let code = error::kind(&e).code();
+        if matches.is_present("never-fail") {
+            let degraded = match matches.subcommand() {
+                ("status", Some(arg)) => degraded_status(arg, &e),
+                _ => error::degraded(&e),
+            };
+            Response {
+                output: format!("{}\n", degraded),
+                error: Some(error::report(&e)),
+                ..Default::default()
+            }
+        } else if matches.is_present("json-errors") {
+            Response {
+                code,
+                output: format!("{}\n", error::json(&e)),
+                ..Default::default()
+            }
+        } else {
+            Response {
+                code,
+                error: Some(error::report(&e)),
+                ..Default::default()
+            }
+        }
+    })
+}
+
+/// Answers a request from a client, and whether that was the command that
+/// ends the conversation.
+fn answer(
+    request: serde_json::Result<Request>,
+    config: &Config,
+    graph: &Graph,
+    memory: &mut Memory,
+) -> (String, bool) {
+    let response = match request {
+        Ok(Request::Message(Message::Hello { .. })) => {
+            let hello = Hello::new(config, memory);
+            return (serde_json::to_string(&hello).unwrap_or_default(), false);
+        }
+        Ok(Request::Args(args)) => respond(&args, config, graph, memory),
+        Ok(Request::Message(Message::Command { version, args })) => {
+            let mut response = if (MIN_VERSION..=VERSION).contains(&version) {
+                respond(&args, config, graph, memory)
+            } else {
+                failure(format!(
+                    "the daemon speaks protocol versions {} to {}, not {}; restart it after updating pw-volume",
+                    MIN_VERSION, VERSION, version
+                ))
+            };
+            response.version = Some(version.clamp(MIN_VERSION, VERSION));
+            response
+        }
+        Ok(Request::Message(Message::Subscribe { .. } | Message::Unknown)) => {
+            failure("unknown request type".to_string())
+        }
+        Err(e) => failure(format!("failed to parse request: {}", e)),
+    };
+    (serde_json::to_string(&response).unwrap_or_default(), true)
+}
+
+/// Reads a client's requests, a hello and then a command, and writes the
+/// replies.
+fn serve(
+    stream: UnixStream,
+    config: &Config,
+    graph: &Graph,
+    memory: &mut Memory,
+) -> anyhow::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut writer = &stream;
+    for _ in 0..2 {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let request = serde_json::from_str::<Request>(&line);
+        if let Ok(Request::Message(Message::Subscribe { .. })) = request {
+            stream.set_write_timeout(Some(TIMEOUT))?;
+            // the others were sent the same state last
+            let state = state(config, graph)?;
+            writeln!(writer, "{}", state)?;
+            memory.subscribers.push(stream.try_clone()?);
+            memory.state = Some(state);
+            return Ok(());
+        }
+        let (reply, done) = answer(request, config, graph, memory);
+        writeln!(writer, "{}", reply)?;
+        if done {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The default sink's and source's status, as `status --output verbose-json`
+/// prints it, or `null` while there is none.
+fn state(config: &Config, graph: &Graph) -> anyhow::Result<String> {
+    let buf = graph.dump()?;
+    let obj = unmarshal(&buf)?;
+    let status = |target| -> anyhow::Result<serde_json::Value> {
+        match parse_dump(&obj, target) {
+            Ok((node, route)) => {
+                let status = Status {
+                    fade: fade::running(node.id),
+                    battery: battery(&obj, node),
+                    ..Status::new(node, route)
+                };
+                let rendered = Output::VerboseJson.render(&status, "", config);
+                Ok(serde_json::from_str(&rendered)?)
+            }
+            Err(_) => Ok(serde_json::Value::Null),
+        }
+    };
+    Ok(serde_json::json!({
+        "type": "state",
+        "sink": status(Target::DefaultSink)?,
+        "source": status(Target::DefaultSource)?,
+    })
+    .to_string())
+}
+
+/// Sends the state to every subscriber if it changed since they were last
+/// sent it, dropping those that have hung up.
+fn broadcast(config: &Config, graph: &Graph, memory: &mut Memory) -> anyhow::Result<()> {
+    if memory.subscribers.is_empty() {
+        return Ok(());
+    }
+    let state = state(config, graph)?;
+    if memory.state.as_ref() == Some(&state) {
+        return Ok(());
+    }
+    memory
+        .subscribers
+        .retain(|mut subscriber| writeln!(subscriber, "{}", state).is_ok());
+    memory.state = Some(state);
+    Ok(())
+}
+
+/// Binds the socket, replacing one left behind by a daemon that has exited.
+fn listen() -> anyhow::Result<UnixListener> {
+    let path = socket_path().ok_or_else(|| anyhow!("failed to determine runtime directory"))?;
+    if UnixStream::connect(&path).is_ok() {
+        bail!("a daemon is already listening on {}", path.display());
+    }
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => (),
+    }
+    UnixListener::bind(&path).with_context(|| format!("failed to bind {}", path.display()))
+}
+
+enum Event {
+    Changed,
+    Request(UnixStream),
+    Focused(Focus),
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("daemon")
+        .about("keeps rules from the config file in force and answers commands on a socket")
+        .arg(
+            Arg::with_name("dbus")
+                .long("dbus")
+                .help("send a D-Bus signal when the default devices' volume or mute state changes"),
+        )
+        .arg(
+            Arg::with_name("status")
+                .long("status")
+                .help("print what the running daemon answers, the rules it keeps, and its failed hooks")
+                .conflicts_with("dbus"),
+        )
+        .arg(
+            Arg::with_name("simulate")
+                .long("simulate")
+                .value_name("FILE")
+                .help("replay updates recorded with `pw-dump --monitor` through the rules, printing what they would do")
+                .takes_value(true)
+                .conflicts_with_all(&["dbus", "status"]),
+        )
+}
+
+/// `daemon --status`: asks the running daemon about itself.
+fn status() -> anyhow::Result<()> {
+    let path = socket_path().ok_or_else(|| anyhow!("failed to determine runtime directory"))?;
+    // not an error of its own, which would be taken for a missing tool
+    let stream = UnixStream::connect(&path)
+        .map_err(|_| anyhow!("no daemon is listening on {}", path.display()))?;
+    let reply = exchange(
+        &stream,
+        &serde_json::to_string(&Message::Hello { version: VERSION })?,
+        HELLO_TIMEOUT,
+    )
+    .context("the daemon didn't answer")?;
+    let hello: Hello = serde_json::from_str(&reply)
+        .context("the daemon is from a release before its status could be asked for")?;
+    print!("{}", describe(&hello));
+    Ok(())
+}
+
+/// The lines `daemon --status` prints.
+fn describe(hello: &Hello) -> String {
+    let list = |items: &[String]| {
+        if items.is_empty() {
+            "none".to_string()
+        } else {
+            items.join(", ")
+        }
+    };
+    let hooks = match &hello.last_hook_failure {
+        Some(last) => format!("{}, the last: {}", hello.hook_failures, last),
+        None => "none".to_string(),
+    };
+    format!(
+        "protocol: {} (answers {} to {})\ncommands: {}\nrules: {}\nfailed hooks: {}\n",
+        hello.version,
+        hello.min_version,
+        hello.version,
+        list(&hello.commands),
+        list(&hello.features),
+        hooks
+    )
+}
+
+/// The comment `daemon --simulate` prints before an update: when it came,
+/// if recorded, and what it changed, or else which objects it changed and
+/// which went away.
+fn summary(n: usize, record: &Record) -> String {
+    let mut summary = format!("# event {}", n);
+    if let Some(at_ms) = record.at_ms {
+        summary = format!("{} at {:.3}s", summary, at_ms as f64 / 1000.0);
+    }
+    if !record.events.is_empty() {
+        let events: Vec<_> = record.events.iter().map(ToString::to_string).collect();
+        return format!("{}: {}", summary, events.join("; "));
+    }
+    let (removed, changed): (Vec<_>, Vec<_>) = record
+        .update
+        .iter()
+        .filter(|object| object.get("id").is_some_and(serde_json::Value::is_i64))
+        .partition(|object| object.get("info") == Some(&serde_json::Value::Null));
+    let mut parts = Vec::new();
+    for (what, objects) in [("changed", changed), ("removed", removed)] {
+        // the first update is the whole graph
+        if objects.len() > 8 {
+            parts.push(format!("{} {} objects", what, objects.len()));
+        } else if !objects.is_empty() {
+            let ids: Vec<_> = objects.iter().map(|o| o["id"].to_string()).collect();
+            parts.push(format!("{} {}", what, ids.join(", ")));
+        }
+    }
+    if parts.is_empty() {
+        parts.push("nothing changed".to_string());
+    }
+    format!("{}: {}", summary, parts.join("; "))
+}
+
+/// `daemon --simulate`: runs the rules after each update in the file, from
+/// `pw-volume record` or `pw-dump --monitor`, as the daemon would have, printing the commands they would run
+/// instead of running them.
+fn simulate(path: &str, config: &Config) -> anyhow::Result<()> {
+    let buf = fs::read(path).with_context(|| format!("failed to read {}", path))?;
+    DRY_RUN.store(true, Ordering::Relaxed);
+    let graph = Graph::replay();
+    let mut memory = Memory::new(config);
+    let records = record::read(&buf).with_context(|| format!("failed to read {}", path))?;
+    for (i, record) in records.into_iter().enumerate() {
+        println!("{}", summary(i + 1, &record));
+        graph.update(record.update);
+        // the daemon prints this and goes on with the next update
+        if let Err(e) = enforce(config, &graph, &mut memory) {
+            println!("# failed to read the graph: {:#}", e);
+        }
+    }
+    Ok(())
+}
+
+pub fn run(matches: &ArgMatches<'_>, config: &Config) -> anyhow::Result<()> {
+    if matches.is_present("status") {
+        return status();
+    }
+    if let Some(path) = matches.value_of("simulate") {
+        return simulate(path, config);
+    }
+    let listener = listen()?;
+    let (tx, rx) = mpsc::channel();
+    let changed = tx.clone();
+    let graph = Graph::spawn(move || {
+        let _ = changed.send(Event::Changed);
+    });
+    update::spawn(config.update);
+    if !config.focus.is_empty() {
+        let focused = tx.clone();
+        focus::spawn(move |focus| {
+            let _ = focused.send(Event::Focused(focus));
+        });
+    }
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if tx.send(Event::Request(stream)).is_err() {
+                break;
+            }
+        }
+    });
+    // requests and rules are handled one at a time, so that commands never
+    // race each other
+    let mut memory = Memory {
+        signals: matches.is_present("dbus").then(Default::default),
+        ..Memory::new(config)
+    };
+    loop {
+        // a limiter bringing a volume back takes steps between changes
+        let wait = if memory.limiter.pulling() {
+            Duration::from_millis(config.fade.interval_ms.max(1))
+        } else {
+            POLL
+        };
+        match rx.recv_timeout(wait) {
+            Ok(Event::Request(stream)) => {
+                if let Err(e) = serve(stream, config, &graph, &mut memory) {
+                    eprintln!("{:#}", e);
+                }
+            }
+            Ok(Event::Focused(focus)) => {
+                if let Err(e) = follow_focus(config, &graph, &focus, &mut memory) {
+                    eprintln!("{:#}", e);
+                }
+            }
+            Ok(Event::Changed) | Err(RecvTimeoutError::Timeout) => {
+                if let Err(e) = enforce(config, &graph, &mut memory) {
+                    eprintln!("{:#}", e);
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => unreachable!("the listener holds a sender"),
+        }
+        if let Err(e) = broadcast(config, &graph, &mut memory) {
+            eprintln!("{:#}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(0.5, 0.5, 0.4, None => Some(Adjust::Follower(0.25)); "first pass")]
+    #[test_case(0.5, 0.6, 0.25, Some((0.5, 0.25)) => Some(Adjust::Follower(0.3)); "leader moved")]
+    #[test_case(0.5, 0.5, 0.3, Some((0.5, 0.25)) => Some(Adjust::Leader(0.6)); "follower moved")]
+    #[test_case(0.5, 0.5, 0.25, Some((0.5, 0.25)) => None; "nothing moved")]
+    #[test_case(0.5, 0.5, 0.6, Some((0.5, 0.25)) => Some(Adjust::Leader(1.0)); "clamped")]
+    fn adjust_offset(
+        ratio: f64,
+        leader: f64,
+        follower: f64,
+        last: Option<(f64, f64)>,
+    ) -> Option<Adjust> {
+        adjust(ratio, leader, follower, last)
+    }
+
+    #[test_case(0.5, 0.5, false => None; "unchanged")]
+    #[test_case(0.5, 0.9, false => Some(Lock::Revert(0.5)); "undone")]
+    #[test_case(0.5, 0.7, true => Some(Lock::Keep(0.7)); "allowed")]
+    fn mic_lock(locked: f64, current: f64, allowed: bool) -> Option<Lock> {
+        lock(locked, current, allowed)
+    }
+
+    #[test_case(Some(&[0.3, 0.3]), &[1.0, 1.0], true => Some(vec![0.3, 0.3]); "reconnected")]
+    #[test_case(Some(&[0.3, 0.3]), &[1.0, 1.0], false => None; "changed while the default")]
+    #[test_case(Some(&[0.3, 0.3]), &[0.3, 0.3], true => None; "already there")]
+    #[test_case(None, &[1.0, 1.0], true => None; "never seen")]
+    fn restore_volume(
+        saved: Option<&[f64]>,
+        current: &[f64],
+        became_default: bool,
+    ) -> Option<Vec<f64>> {
+        restore(saved, current, became_default)
+    }
+
+    #[test]
+    fn restored_devices() {
+        let restore = RestoreConfig {
+            devices: vec!["bluez_output".to_string()],
+        };
+        assert!(restore.restores("bluez_output.18_54_CF_71_8C_FA.a2dp-sink"));
+        assert!(!restore.restores("alsa_output.pci-0000_00_1f.3.analog-stereo"));
+        assert!(RestoreConfig::default().restores("alsa_output.pci-0000_00_1f.3.analog-stereo"));
+    }
+
+    #[test]
+    fn connected_clients() -> anyhow::Result<()> {
+        let path: std::path::PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "testdata",
+            "dump_aria_16.txt",
+        ]
+        .iter()
+        .collect();
+        let buf = fs::read(path)?;
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf)?;
+        assert!(clients(&obj).any(|name| name == "telegram-desktop"));
+        Ok(())
+    }
+
+    #[test]
+    fn silent_daemon() -> io::Result<()> {
+        // a daemon that was stopped still accepts the connection
+        let (client, _daemon) = UnixStream::pair()?;
+        let start = Instant::now();
+        let err = exchange(&client, "{}", Duration::from_millis(100)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
+
+    #[test]
+    fn vanished_ptt_node() -> anyhow::Result<()> {
+        let path: std::path::PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "testdata",
+            "dump_aria_16.txt",
+        ]
+        .iter()
+        .collect();
+        let buf = fs::read(path)?;
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf)?;
+        let mut memory = Memory {
+            ptt: Some((9999, Instant::now())),
+            ..Default::default()
+        };
+        enforce_ptt(&obj, &mut memory)?;
+        assert_eq!(memory.ptt, None);
+        Ok(())
+    }
+
+    #[test_case(r#"["get-volume", "--precise"]"# => Request::Args(vec!["get-volume".into(), "--precise".into()]); "bare arguments")]
+    #[test_case(r#"{"type": "hello", "version": 3}"# => Request::Message(Message::Hello { version: 3 }); "hello")]
+    #[test_case(r#"{"type": "command", "version": 2, "args": ["mute", "on"]}"# => Request::Message(Message::Command { version: 2, args: vec!["mute".into(), "on".into()] }); "command")]
+    #[test_case(r#"{"type": "subscribe", "version": 3}"# => Request::Message(Message::Subscribe { version: 3 }); "subscribe")]
+    #[test_case(r#"{"type": "meter", "version": 4}"# => Request::Message(Message::Unknown); "newer message")]
+    fn parse_request(line: &str) -> Request {
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test_case(3, 1, "status", 1 => Some(3); "same version")]
+    #[test_case(4, 1, "status", 1 => Some(3); "newer daemon")]
+    #[test_case(2, 1, "status", 1 => Some(2); "older daemon")]
+    #[test_case(4, 4, "status", 1 => None; "daemon too new")]
+    #[test_case(3, 1, "mixer", 1 => None; "not answered")]
+    #[test_case(3, 1, "change", 3 => Some(3); "conditional write")]
+    #[test_case(2, 1, "change", 3 => None; "too old for a conditional write")]
+    fn agree_version(version: u32, min_version: u32, name: &str, needed: u32) -> Option<u32> {
+        let hello = Hello {
+            version,
+            min_version,
+            commands: vec!["status".to_string(), "change".to_string()],
+            features: Vec::new(),
+            hook_failures: 0,
+            last_hook_failure: None,
+        };
+        hello.agree(name, needed)
+    }
+
+    #[test]
+    fn status_lines() -> anyhow::Result<()> {
+        let hello: Hello = serde_json::from_str(
+            r#"{"version":3,"min-version":1,"commands":["mute","status"],"features":[],"hook-failures":2,"last-hook-failure":"the hook `osd` exited with exit status: 1"}"#,
+        )?;
+        assert_eq!(
+            describe(&hello),
+            "protocol: 3 (answers 1 to 3)\n\
+             commands: mute, status\n\
+             rules: none\n\
+             failed hooks: 2, the last: the hook `osd` exited with exit status: 1\n"
+        );
+        Ok(())
+    }
+
+    #[test_case(r#"[{ "id": 53, "info": {} }]"# => "# event 2: changed 53")]
+    #[test_case(r#"[{ "id": 69, "info": null }, { "id": 53, "info": null }]"# => "# event 2: removed 69, 53")]
+    #[test_case(r#"[{ "id": 53, "info": {} }, { "id": 69, "info": null }]"# => "# event 2: changed 53; removed 69")]
+    #[test_case(r#"[{ "id": 0 }, { "id": 1 }, { "id": 2 }, { "id": 3 }, { "id": 4 }, { "id": 5 }, { "id": 6 }, { "id": 7 }, { "id": 8 }]"# => "# event 2: changed 9 objects"; "whole graph")]
+    #[test_case("[]" => "# event 2: nothing changed")]
+    fn event_summary(update: &str) -> String {
+        let record = Record {
+            at_ms: None,
+            events: Vec::new(),
+            update: serde_json::from_str(update).unwrap(),
+        };
+        summary(2, &record)
+    }
+
+    #[test]
+    fn recorded_event_summary() {
+        let record = Record {
+            at_ms: Some(1520),
+            events: vec![record::Event::Mute {
+                id: 69,
+                name: "bluez_output".to_string(),
+                mute: true,
+            }],
+            update: Vec::new(),
+        };
+        assert_eq!(
+            summary(3, &record),
+            "# event 3 at 1.520s: bluez_output (69) is muted"
+        );
+    }
+
+    #[test]
+    fn offset_ratio() {
+        let offset = Offset {
+            leader: String::new(),
+            follower: String::new(),
+            db: -6.0,
+        };
+        assert!((offset.ratio() - 0.501).abs() < 0.001);
+    }
+}
@@ -0,0 +1,79 @@
+//! `Changed` signals on the session bus, sent by `watch --dbus` and
+//! `daemon --dbus` so that OSDs can react without polling.
+
+use anyhow::{ensure, Context};
+
+use crate::{format::Status, tools, DeviceRoute, PipeWireInterfaceNode};
+
+const PATH: &str = "/io/github/smasher164/PwVolume";
+const INTERFACE: &str = "io.github.smasher164.PwVolume";
+
+/// Arguments to dbus-send for `Changed(kind, node, percentage, mute)`, where
+/// `kind` is `sink` or `source`.
+fn args(kind: &str, status: &Status) -> Vec<String> {
+    vec![
+        "--session".to_string(),
+        "--type=signal".to_string(),
+        PATH.to_string(),
+        format!("{}.Changed", INTERFACE),
+        format!("string:{}", kind),
+        format!("string:{}", status.node),
+        format!("uint32:{:.0}", status.percentage()),
+        format!("boolean:{}", status.mute),
+    ]
+}
+
+/// Announces one node's volume and mute state, remembering what was last
+/// sent so that only changes are announced.
+#[derive(Debug, Default)]
+pub struct Signal {
+    last: Option<(String, f64, bool)>,
+}
+
+impl Signal {
+    pub fn update(
+        &mut self,
+        node: &PipeWireInterfaceNode<'_>,
+        route: &DeviceRoute<'_>,
+    ) -> anyhow::Result<()> {
+        let status = Status::new(node, route);
+        let current = (status.node.clone(), status.volume, status.mute);
+        if self.last.as_ref() == Some(&current) {
+            return Ok(());
+        }
+        self.last = Some(current);
+        let kind = match node.info.props.media_class {
+            Some("Audio/Source") => "source",
+            _ => "sink",
+        };
+        let status = tools::status(tools::command("dbus-send").args(args(kind, &status)))
+            .context("failed to execute dbus-send")?;
+        ensure!(status.success(), "dbus-send did not exit successfully");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_args() {
+        let status = Status {
+            volume: 0.42,
+            mute: true,
+            node: "alsa_output.analog-stereo".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            args("sink", &status)[3..],
+            [
+                "io.github.smasher164.PwVolume.Changed",
+                "string:sink",
+                "string:alsa_output.analog-stereo",
+                "uint32:42",
+                "boolean:true",
+            ]
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! `pw-volume delay`: latency offsets for lining up audio with video, e.g. on
+//! Bluetooth speakers.
+
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use crate::{pw_dump, set_param, unmarshal, PipeWireObject};
+
+/// A latency offset in nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Delay(pub u64);
+
+impl FromStr for Delay {
+    type Err = String;
+
+    /// Parses a duration such as `120ms`, `1.5s`, or `800us`; a bare number is
+    /// in milliseconds.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let (value, unit) = s.split_at(split);
+        let scale = match unit {
+            "ns" => 1.0,
+            "us" | "µs" => 1e3,
+            "ms" | "" => 1e6,
+            "s" => 1e9,
+            _ => return Err(format!(r#""{}" is not a delay such as "120ms""#, s)),
+        };
+        value
+            .parse::<f64>()
+            .map(|value| Delay((value * scale).round() as u64))
+            .map_err(|_| format!(r#""{}" is not a delay such as "120ms""#, s))
+    }
+}
+
+/// Finds a node or stream by id, node name, or application name.
+fn find(obj: &[PipeWireObject<'_>], name: &str) -> Option<i64> {
+    let id = name.parse::<i64>().ok();
+    obj.iter().find_map(|o| match o {
+        PipeWireObject::Node(n) if Some(n.id) == id || n.info.props.node_name == name => Some(n.id),
+        PipeWireObject::Stream(s)
+            if Some(s.id) == id
+                || s.info.props.node_name == name
+                || s.info.props.application_name.as_deref() == Some(name) =>
+        {
+            Some(s.id)
+        }
+        _ => None,
+    })
+}
+
+fn set_latency(id: i64, delay: Delay) -> anyhow::Result<()> {
+    let param = serde_json::json!({ "ns": delay.0 }).to_string();
+    set_param(id, "ProcessLatency", &param)
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("delay")
+        .about("sets the latency offset of a stream or sink, e.g. for lip-sync")
+        .arg(
+            Arg::with_name("TARGET")
+                .help("application name, node name, or id")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("DELAY")
+                .help("offset such as '120ms' or '0' to reset")
+                .required(true)
+                .validator(|s| s.parse::<Delay>().map(|_| ())),
+        )
+}
+
+pub fn run(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let name = matches.value_of("TARGET").unwrap_or_default();
+    let delay: Delay = matches
+        .value_of("DELAY")
+        .unwrap_or_default()
+        .parse()
+        .map_err(anyhow::Error::msg)?;
+    let buf = pw_dump()?;
+    let obj = unmarshal(&buf)?;
+    let id = find(&obj, name).ok_or_else(|| anyhow!("failed to find node: {}", name))?;
+    set_latency(id, delay)
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("120ms" => Ok(Delay(120_000_000)))]
+    #[test_case("120" => Ok(Delay(120_000_000)))]
+    #[test_case("1.5s" => Ok(Delay(1_500_000_000)))]
+    #[test_case("800us" => Ok(Delay(800_000)))]
+    #[test_case("0" => Ok(Delay(0)))]
+    #[test_case("-5ms" => Err(r#""-5ms" is not a delay such as "120ms""#.to_string()))]
+    #[test_case("fast" => Err(r#""fast" is not a delay such as "120ms""#.to_string()))]
+    fn parse_delay(s: &str) -> Result<Delay, String> {
+        s.parse()
+    }
+}
@@ -0,0 +1,387 @@
+//! `pw-volume doctor`: looks for the usual reasons there's no sound, a sound
+//! card switched off, a default sink that isn't there or is unplugged, a
+//! muted or silent sink or application, or a sink that failed, and says how
+//! each would be fixed. With `--interactive` it walks through them, offering
+//! to make each fix, and looks again after every one it makes.
+
+use std::{
+    fmt,
+    io::{self, BufRead, Write},
+};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde_json::json;
+
+use crate::{
+    default_name, endpoints, pw_dump, route_command, set_default_name, set_param, set_route,
+    streams, unmarshal, volume_config, DeviceRoute, PipeWireCommand, PipeWireInterfaceNode,
+    PipeWireObject, VolumeOp,
+};
+
+/// Volumes below this are taken to be silent.
+const SILENT: f64 = 0.005;
+
+/// The volume a silent sink is turned up to.
+const VOLUME: f64 = 0.4;
+
+/// How a problem is fixed.
+#[derive(Debug)]
+enum Fix<'a> {
+    Profile {
+        device: i64,
+        index: i64,
+    },
+    Default(&'a str),
+    Route {
+        node: &'a PipeWireInterfaceNode<'a>,
+        cmd: PipeWireCommand,
+    },
+    Stream {
+        id: i64,
+        props: serde_json::Value,
+    },
+}
+
+/// Something that keeps sound from being heard, and what would fix it.
+#[derive(Debug)]
+struct Problem<'a> {
+    found: String,
+    fix: Option<(String, Fix<'a>)>,
+}
+
+impl fmt::Display for Problem<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "! {}", self.found)?;
+        match &self.fix {
+            Some((what, _)) => write!(f, "\n  fix: {}", what),
+            None => write!(f, "\n  no fix is known"),
+        }
+    }
+}
+
+fn make(fix: &Fix<'_>) -> anyhow::Result<()> {
+    match fix {
+        Fix::Profile { device, index } => set_param(
+            *device,
+            "Profile",
+            &json!({ "index": index, "save": true }).to_string(),
+        ),
+        Fix::Default(name) => set_default_name("default.configured.audio.sink", name),
+        Fix::Route { node, cmd } => set_route(node, cmd),
+        Fix::Stream { id, props } => set_param(*id, "Props", &props.to_string()),
+    }
+}
+
+fn describe(node: &PipeWireInterfaceNode<'_>) -> String {
+    let props = &node.info.props;
+    props
+        .node_description
+        .as_deref()
+        .unwrap_or(props.node_name)
+        .to_string()
+}
+
+/// Whether the route's port is known to be unplugged.
+fn unplugged(route: &DeviceRoute<'_>) -> bool {
+    route.available == Some("no")
+}
+
+/// The problems in the graph, in the order they are best fixed in, since
+/// switching a card on brings its sinks, which the rest are about.
+fn diagnose<'a>(obj: &'a [PipeWireObject<'a>]) -> Vec<Problem<'a>> {
+    let mut problems = Vec::new();
+    for device in obj.iter().filter_map(|o| match o {
+        PipeWireObject::Device(d) if d.typ == "PipeWire:Interface:Device" => Some(d),
+        _ => None,
+    }) {
+        let params = &device.info.params;
+        if !params.profile.iter().any(|profile| profile.name == "off") {
+            continue;
+        }
+        let best = params
+            .enum_profile
+            .iter()
+            .filter(|profile| profile.name != "off" && profile.available != Some("no"))
+            .max_by_key(|profile| profile.priority);
+        let name = device
+            .info
+            .props
+            .as_ref()
+            .and_then(|props| props.device_description.as_deref())
+            .map_or_else(|| format!("device {}", device.id), str::to_string);
+        problems.push(Problem {
+            found: format!("{} is switched off", name),
+            fix: best.map(|profile| {
+                (
+                    format!(
+                        "switch it to {}",
+                        profile.description.as_deref().unwrap_or(&profile.name)
+                    ),
+                    Fix::Profile {
+                        device: device.id,
+                        index: profile.index,
+                    },
+                )
+            }),
+        });
+    }
+
+    let sinks: Vec<_> = endpoints(obj)
+        .into_iter()
+        .filter(|(node, _)| node.info.props.media_class == Some("Audio/Sink"))
+        .collect();
+    let fallback = sinks
+        .iter()
+        .find(|(_, route)| !unplugged(route))
+        .map(|(node, _)| *node);
+    let switch = |found: String, current: Option<&str>| Problem {
+        found,
+        fix: fallback
+            .filter(|node| Some(node.info.props.node_name) != current)
+            .map(|node| {
+                (
+                    format!("make {} the default", describe(node)),
+                    Fix::Default(node.info.props.node_name),
+                )
+            }),
+    };
+    let name = default_name(obj, "default.audio.sink");
+    let default = sinks
+        .iter()
+        .find(|(node, _)| Some(node.info.props.node_name) == name);
+    match (name, default) {
+        (None, _) => problems.push(switch("no default sink is set".to_string(), None)),
+        (Some(name), None) => problems.push(switch(
+            format!("the default sink, {}, isn't there", name),
+            Some(name),
+        )),
+        (Some(name), Some((node, route))) => {
+            let sink = describe(node);
+            if unplugged(route) {
+                let port = route.description.as_deref().unwrap_or("port");
+                problems.push(switch(
+                    format!("{}'s {} is unplugged", sink, port),
+                    Some(name),
+                ));
+            }
+            if node.info.state == Some("error") {
+                problems.push(Problem {
+                    found: format!(
+                        "{} failed: {}",
+                        sink,
+                        node.info.error.as_deref().unwrap_or("unknown error")
+                    ),
+                    fix: None,
+                });
+            }
+            let scale = crate::scale(name);
+            let silent = route
+                .props
+                .channel_volumes
+                .iter()
+                .all(|v| scale.scaled(*v) < SILENT);
+            if route.props.mute || silent {
+                let (found, op, what) = match (route.props.mute, silent) {
+                    (true, true) => ("muted and at 0%", VolumeOp::Set(VOLUME), "unmute it at 40%"),
+                    (true, false) => ("muted", VolumeOp::Mute(Some(false)), "unmute it"),
+                    _ => ("at 0%", VolumeOp::Set(VOLUME), "turn it up to 40%"),
+                };
+                let cmd = route_command(op, node, route);
+                problems.push(Problem {
+                    found: format!("{} is {}", sink, found),
+                    fix: Some((what.to_string(), Fix::Route { node, cmd })),
+                });
+            }
+        }
+    }
+
+    // streams aren't devices, so `[devices]` doesn't apply to them
+    let stream_scale = volume_config().scale;
+    for (stream, _) in streams(obj) {
+        let props = &stream.info.props;
+        let level = match stream.info.params.volume() {
+            Some(level) if props.media_class.starts_with("Stream/Output/") => level,
+            _ => continue,
+        };
+        let silent = level
+            .channel_volumes
+            .iter()
+            .all(|v| stream_scale.scaled(*v) < SILENT);
+        if !level.mute && !silent {
+            continue;
+        }
+        let name = props
+            .application_name
+            .as_deref()
+            .unwrap_or(props.node_name.as_ref());
+        let (found, what) = match (level.mute, silent) {
+            (true, true) => ("muted and at 0%", "unmute it at 100%"),
+            (true, false) => ("muted", "unmute it"),
+            _ => ("at 0%", "turn it up to 100%"),
+        };
+        let mut props = json!({ "mute": false });
+        if silent {
+            props["channelVolumes"] = vec![1.0; level.channel_volumes.len()].into();
+        }
+        problems.push(Problem {
+            found: format!("{} ({}) is {}", name, stream.id, found),
+            fix: Some((
+                what.to_string(),
+                Fix::Stream {
+                    id: stream.id,
+                    props,
+                },
+            )),
+        });
+    }
+    problems
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("doctor")
+        .about("looks for the usual reasons there's no sound, and how to fix them")
+        .arg(
+            Arg::with_name("interactive")
+                .short("i")
+                .long("interactive")
+                .help("offer to fix each problem found, one at a time"),
+        )
+}
+
+/// Asks a yes or no question, taking an empty answer as yes and the end of
+/// input as no.
+fn ask(
+    question: &str,
+    lines: &mut impl Iterator<Item = io::Result<String>>,
+) -> anyhow::Result<bool> {
+    loop {
+        print!("{} [Y/n] ", question);
+        io::stdout().flush()?;
+        let answer = match lines.next() {
+            Some(line) => line?,
+            None => return Ok(false),
+        };
+        match answer.trim() {
+            "y" | "Y" | "yes" | "" => return Ok(true),
+            "n" | "N" | "no" => return Ok(false),
+            _ => println!("please answer y or n"),
+        }
+    }
+}
+
+/// Lists the problems found, or offers to fix them, exiting with 1 while any
+/// are left.
+pub fn run(matches: &ArgMatches<'_>) -> anyhow::Result<i32> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    // what has been offered already, fixed or not
+    let mut seen: Vec<String> = Vec::new();
+    loop {
+        let buf = pw_dump()?;
+        let obj = unmarshal(&buf)?;
+        let problems = diagnose(&obj);
+        if !matches.is_present("interactive") {
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            if problems.is_empty() {
+                println!("no problems found");
+                return Ok(0);
+            }
+            println!("run `pw-volume doctor --interactive` to fix them one at a time");
+            return Ok(1);
+        }
+        let problem = match problems.iter().find(|p| !seen.contains(&p.found)) {
+            Some(problem) => problem,
+            None if problems.is_empty() => {
+                println!("no problems found");
+                return Ok(0);
+            }
+            None => {
+                println!("{} left unfixed", problems.len());
+                return Ok(1);
+            }
+        };
+        seen.push(problem.found.clone());
+        println!("{}", problem.found);
+        if let Some((what, fix)) = &problem.fix {
+            let mut question = what.clone();
+            if let Some(first) = question.get_mut(..1) {
+                first.make_ascii_uppercase();
+            }
+            if ask(&format!("{}?", question), &mut lines)? {
+                make(fix)?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use serde_json::Value;
+
+    use super::*;
+
+    fn dump(edit: impl Fn(&mut Value)) -> anyhow::Result<Vec<u8>> {
+        let path: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "testdata",
+            "dump_aria_16.txt",
+        ]
+        .iter()
+        .collect();
+        let mut dump: Value = serde_json::from_slice(&fs::read(path)?)?;
+        for o in dump.as_array_mut().into_iter().flatten() {
+            edit(o);
+        }
+        Ok(serde_json::to_vec(&dump)?)
+    }
+
+    fn found(buf: &[u8]) -> anyhow::Result<Vec<String>> {
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(buf)?;
+        Ok(diagnose(&obj).iter().map(ToString::to_string).collect())
+    }
+
+    #[test]
+    fn healthy() -> anyhow::Result<()> {
+        assert_eq!(found(&dump(|_| ())?)?, Vec::<String>::new());
+        Ok(())
+    }
+
+    #[test]
+    fn switched_off_card() -> anyhow::Result<()> {
+        let buf = dump(|o| {
+            if o["id"] == 42 {
+                o["info"]["params"]["Profile"][0]["index"] = 0.into();
+                o["info"]["params"]["Profile"][0]["name"] = "off".into();
+            }
+        })?;
+        assert_eq!(
+            found(&buf)?,
+            ["! Built-in Audio is switched off\n  fix: switch it to Analog Stereo Duplex"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn muted_unplugged_sink() -> anyhow::Result<()> {
+        let buf = dump(|o| {
+            if o["id"] == 53 {
+                let route = &mut o["info"]["params"]["Route"][0];
+                route["available"] = "no".into();
+                route["props"]["mute"] = true.into();
+            }
+        })?;
+        assert_eq!(
+            found(&buf)?,
+            [
+                "! Galaxy Buds+ (8CFA)'s Headset is unplugged\n  fix: make Built-in Audio Analog Stereo the default",
+                "! Galaxy Buds+ (8CFA) is muted\n  fix: unmute it",
+            ]
+        );
+        Ok(())
+    }
+}
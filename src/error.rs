@@ -0,0 +1,205 @@
+//! Failures tagged with a kind, which picks pw-volume's exit code and a hint
+//! for fixing it, and which widgets using `--json-errors` can match on to
+//! show a sensible state, e.g. "no audio" for `no-default-sink`.
+
+use std::{fmt, io};
+
+/// What went wrong, as far as a widget is concerned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// pw-dump couldn't connect to PipeWire.
+    PipewireNotRunning,
+    NoDefaultSink,
+    NoDefaultSource,
+    /// The node asked for, or the default's node, isn't in the graph.
+    NoNode,
+    NoDevice,
+    NoRoute,
+    /// pw-dump's output couldn't be parsed.
+    BadDump,
+    /// pw-dump, pw-cli, or another tool isn't installed.
+    MissingTool,
+    PwCliFailed,
+    /// The node wasn't at the volume or mute state that `--if-volume` or
+    /// `--if-mute` expected.
+    Conflict,
+    Other,
+}
+
+impl Kind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::PipewireNotRunning => "pipewire-not-running",
+            Kind::NoDefaultSink => "no-default-sink",
+            Kind::NoDefaultSource => "no-default-source",
+            Kind::NoNode => "no-node",
+            Kind::NoDevice => "no-device",
+            Kind::NoRoute => "no-route",
+            Kind::BadDump => "bad-dump",
+            Kind::MissingTool => "missing-tool",
+            Kind::PwCliFailed => "pw-cli-failed",
+            Kind::Conflict => "conflict",
+            Kind::Other => "other",
+        }
+    }
+
+    /// The exit code, kept clear of the 0 and 1 that predicates such as
+    /// `is-muted` answer with.
+    pub fn code(self) -> i32 {
+        match self {
+            Kind::Other => 2,
+            Kind::PipewireNotRunning => 3,
+            Kind::NoDefaultSink | Kind::NoDefaultSource => 4,
+            Kind::NoNode | Kind::NoDevice | Kind::NoRoute => 5,
+            Kind::BadDump => 6,
+            Kind::PwCliFailed => 7,
+            Kind::MissingTool => 8,
+            Kind::Conflict => 9,
+        }
+    }
+
+    fn hint(self) -> Option<&'static str> {
+        match self {
+            Kind::PipewireNotRunning => {
+                Some("is PipeWire running? `systemctl --user status pipewire` will tell")
+            }
+            Kind::NoDefaultSink | Kind::NoDefaultSource => {
+                Some("no device is selected; plug one in or pick one in `pw-volume mixer`")
+            }
+            Kind::NoNode | Kind::NoDevice | Kind::NoRoute => {
+                Some("the device may have gone away; `pw-volume status --all` lists what's there")
+            }
+            Kind::BadDump => Some("pw-dump's output didn't make sense; try again"),
+            Kind::PwCliFailed => Some("the graph may have changed in the meantime; try again"),
+            Kind::MissingTool => {
+                Some("pw-volume runs pw-dump and pw-cli, which come with PipeWire's tools")
+            }
+            Kind::Conflict => Some("the node changed since it was read; read it again and retry"),
+            Kind::Other => None,
+        }
+    }
+}
+
+/// An error whose kind is known where it happens.
+#[derive(Debug)]
+pub struct Failure {
+    kind: Kind,
+    message: String,
+}
+
+impl Failure {
+    pub fn new(kind: Kind, message: impl Into<String>) -> Failure {
+        Failure {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// The kind of an error, from a [`Failure`] anywhere in its chain, either as
+/// an error or as the context added to one.
+pub fn kind(err: &anyhow::Error) -> Kind {
+    err.downcast_ref::<Failure>()
+        .or_else(|| {
+            err.chain()
+                .find_map(|cause| cause.downcast_ref::<Failure>())
+        })
+        .map_or(Kind::Other, |failure| failure.kind)
+}
+
+/// Fails to run one of PipeWire's tools, or wpctl or pactl, as a missing tool
+/// if it isn't installed. The I/O error stays in the chain, so that a retry
+/// can tell that the tool timed out.
+pub fn tool(tool: &str, err: io::Error) -> anyhow::Error {
+    let kind = match err.kind() {
+        io::ErrorKind::NotFound => Kind::MissingTool,
+        // a tool that hangs is waiting on a PipeWire that has wedged
+        io::ErrorKind::TimedOut => Kind::PipewireNotRunning,
+        _ => Kind::Other,
+    };
+    anyhow::Error::new(err).context(Failure::new(kind, format!("failed to execute {}", tool)))
+}
+
+/// A message for the terminal, e.g.
+///
+/// ```text
+/// pw-volume: failed to execute pw-dump: No such file or directory (os error 2)
+/// hint: pw-volume runs pw-dump and pw-cli, which come with PipeWire's tools
+/// ```
+pub fn report(err: &anyhow::Error) -> String {
+    let message = format!("pw-volume: {:#}", err);
+    match kind(err).hint() {
+        Some(hint) => format!("{}\nhint: {}", message, hint),
+        None => message,
+    }
+}
+
+/// A status in Waybar's fields standing in for the real one with
+/// `--never-fail`, so that the widget stays up and says why.
+pub fn degraded(err: &anyhow::Error) -> String {
+    serde_json::json!({
+        "text": "",
+        "percentage": 0,
+        "alt": "unavailable",
+        "tooltip": format!("{:#}", err),
+        "class": "unavailable",
+        "state": "unavailable",
+        "kind": kind(err).as_str(),
+    })
+    .to_string()
+}
+
+/// E.g. `{"error":"failed to determine default audio sink","kind":"no-default-sink"}`.
+pub fn json(err: &anyhow::Error) -> String {
+    serde_json::json!({
+        "error": format!("{:#}", err),
+        "kind": kind(err).as_str(),
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn error_kinds() {
+        let err = anyhow::Error::new(Failure::new(Kind::NoDefaultSink, "no sink"))
+            .context("while changing the volume");
+        assert_eq!(kind(&err), Kind::NoDefaultSink);
+        assert_eq!(
+            json(&err),
+            r#"{"error":"while changing the volume: no sink","kind":"no-default-sink"}"#
+        );
+
+        let err = tool("pw-dump", io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(kind(&err), Kind::MissingTool);
+        assert_eq!(
+            report(&err),
+            "pw-volume: failed to execute pw-dump: entity not found\n\
+             hint: pw-volume runs pw-dump and pw-cli, which come with PipeWire's tools"
+        );
+        let err = tool("pw-cli", io::Error::from(io::ErrorKind::TimedOut));
+        assert_eq!(kind(&err), Kind::PipewireNotRunning);
+
+        // a missing file or bad JSON elsewhere is no missing tool or bad dump
+        let err = Err::<(), _>(io::Error::from(io::ErrorKind::NotFound))
+            .context("failed to read scene nosuch")
+            .unwrap_err();
+        assert_eq!(kind(&err), Kind::Other);
+        let err = anyhow::Error::new(serde_json::from_str::<Vec<u8>>("[").unwrap_err());
+        assert_eq!(kind(&err), Kind::Other);
+
+        assert_eq!(kind(&anyhow::anyhow!("something else")), Kind::Other);
+    }
+}
@@ -0,0 +1,66 @@
+//! Notification of changes to the PipeWire graph, for the long-running
+//! subcommands.
+
+use std::{
+    io::{BufRead, BufReader},
+    process::Stdio,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::tools;
+
+/// Updates arriving this close together are reported as one change.
+const SETTLE: Duration = Duration::from_millis(50);
+
+/// Follows `pw-dump --monitor`, which prints the objects that changed as they
+/// change. If it can't be started or exits, callers fall back to polling.
+pub struct Changes {
+    rx: Receiver<()>,
+}
+
+impl Changes {
+    pub fn spawn() -> Changes {
+        let (tx, rx) = mpsc::channel();
+        let child = tools::spawn(
+            tools::command("pw-dump")
+                .args(["--monitor", "--no-colors"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null()),
+        );
+        if let Ok(mut child) = child {
+            if let Some(stdout) = child.stdout.take() {
+                thread::spawn(move || {
+                    for line in BufReader::new(stdout).lines() {
+                        if line.is_err() || tx.send(()).is_err() {
+                            break;
+                        }
+                    }
+                    tools::kill(&mut child);
+                });
+            }
+        }
+        Changes { rx }
+    }
+
+    /// Blocks until something changes, or at most `timeout`, returning
+    /// whether it saw a change. Without `pw-dump --monitor` it never does,
+    /// though anything may have changed.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        match self.rx.recv_timeout(timeout) {
+            Ok(()) => {
+                // pw-dump prints an update over many lines, but don't wait
+                // forever if the graph keeps changing
+                let deadline = Instant::now() + timeout;
+                while Instant::now() < deadline && self.rx.recv_timeout(SETTLE).is_ok() {}
+                true
+            }
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => {
+                thread::sleep(timeout);
+                false
+            }
+        }
+    }
+}
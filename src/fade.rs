@@ -0,0 +1,396 @@
+//! Fades: `change DELTA --over DURATION` and `fade-to PERCENT --over DURATION`
+//! move the volume in small steps rather than at once, e.g. for an alarm that
+//! gets louder or a smooth mute-out. The command keeps running until the fade
+//! is done, and while it runs, `$XDG_RUNTIME_DIR/pw-volume/fade-<id>.json`
+//! tells `status` where the fade is going and how far along it is.
+//!
+//! When something else changes the volume meanwhile, such as a volume key,
+//! the fade stops rather than fighting it, or with `on-change = "rebase"`
+//! carries on from the new volume.
+
+use std::{
+    env,
+    ffi::OsStr,
+    fs, io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::atomic::Ordering,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context};
+use clap::{Arg, ArgMatches};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::Node,
+    change_delta,
+    config::{runtime_dir, Config},
+    format::{Fade, Status},
+    graph::Graph,
+    log, parse_dump, route_command, scale, set_route, unmarshal, volume_command, volume_config,
+    DeviceRoute, PipeWireInterfaceNode, Target, VolumeOp, DRY_RUN,
+};
+
+/// A volume this close to one the fade set is taken to be that one, as
+/// PipeWire and the other tools round what they are given.
+const TOLERANCE: f64 = 0.005;
+
+/// How many of the volumes it set a fade recognizes as its own, since
+/// pw-dump reports each a moment after it was set.
+const RECENT: usize = 8;
+
+/// The `[fade]` section of the config file.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FadeConfig {
+    /// Milliseconds between steps.
+    pub interval_ms: u64,
+    pub on_change: OnChange,
+}
+
+impl Default for FadeConfig {
+    fn default() -> Self {
+        FadeConfig {
+            interval_ms: 50,
+            on_change: OnChange::default(),
+        }
+    }
+}
+
+/// What a fade does when something else changes the volume.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnChange {
+    /// Stop where the volume was left.
+    #[default]
+    Cancel,
+    /// Fade from the new volume to the same target in the time that is left.
+    Rebase,
+}
+
+/// `--over`, shared by `change` and `fade-to`.
+pub fn over_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("over")
+        .long("over")
+        .value_name("DURATION")
+        .help("fade there over a duration, e.g. 2s, 500ms, or 1m, instead of at once")
+        .takes_value(true)
+        .validator(|s| duration(&s).map(|_| ()))
+}
+
+/// Parses a duration such as `2s`, `500ms`, `1.5m`, or `1h`; a bare number is
+/// in seconds.
+pub fn duration(s: &str) -> Result<Duration, String> {
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let scale = match unit {
+        "ms" => 1e-3,
+        "s" | "" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!(r#""{}" is not a duration such as "2s""#, s)),
+    };
+    value
+        .parse::<f64>()
+        .ok()
+        .map(|value| value * scale)
+        .filter(|secs| secs.is_finite())
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| format!(r#""{}" is not a duration such as "2s""#, s))
+}
+
+/// The volume `change --over` or `fade-to` fades to, from `current`.
+pub fn target(
+    name: &str,
+    arg: &ArgMatches<'_>,
+    config: &Config,
+    current: f64,
+) -> anyhow::Result<f64> {
+    if name == "change" {
+        return Ok(current + change_delta(arg, config)?);
+    }
+    let percent = arg
+        .value_of("PERCENT")
+        .ok_or_else(|| anyhow!("PERCENT argument not found"))?;
+    Ok(percent.trim_end_matches('%').parse::<f64>()? * 0.01)
+}
+
+/// A running fade as its file records it.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct State {
+    pid: u32,
+    /// The volumes it fades between, on the node's scale.
+    from: f64,
+    to: f64,
+    /// When it started, in milliseconds since the epoch, and how long it
+    /// takes.
+    started_ms: u64,
+    duration_ms: u64,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+impl State {
+    fn new(from: f64, to: f64, duration: Duration) -> State {
+        State {
+            pid: std::process::id(),
+            from,
+            to,
+            started_ms: now_ms(),
+            duration_ms: duration.as_millis() as u64,
+        }
+    }
+
+    /// Where the fade is at `now_ms`.
+    fn fade(&self, now_ms: u64) -> Fade {
+        let elapsed = now_ms.saturating_sub(self.started_ms).min(self.duration_ms);
+        Fade {
+            target: self.to,
+            progress: if self.duration_ms == 0 {
+                1.0
+            } else {
+                elapsed as f64 / self.duration_ms as f64
+            },
+            remaining: (self.duration_ms - elapsed) as f64 / 1000.0,
+        }
+    }
+
+    /// Whether the fade's process is still running. A fade that was killed
+    /// leaves its file behind, and its pid may since have gone to another
+    /// program, so the process must be a pw-volume too.
+    fn alive(&self) -> bool {
+        let exe = env::current_exe().ok();
+        let own = exe.as_deref().and_then(Path::file_name);
+        fs::read(
+            Path::new("/proc")
+                .join(self.pid.to_string())
+                .join("cmdline"),
+        )
+        .is_ok_and(|cmdline| runs(&cmdline, own))
+    }
+}
+
+/// Whether a `/proc/<pid>/cmdline` runs pw-volume, or `own`, the name this
+/// process was installed under.
+fn runs(cmdline: &[u8], own: Option<&OsStr>) -> bool {
+    let program = cmdline.split(|&b| b == 0).next().unwrap_or_default();
+    match Path::new(OsStr::from_bytes(program)).file_name() {
+        Some(name) => name == "pw-volume" || Some(name) == own,
+        None => false,
+    }
+}
+
+fn path(id: i64) -> Option<PathBuf> {
+    runtime_dir().map(|dir| dir.join(format!("fade-{}.json", id)))
+}
+
+fn load(id: i64) -> Option<State> {
+    let contents = fs::read_to_string(path(id)?).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// The fade running on node `id`, if any.
+pub fn running(id: i64) -> Option<Fade> {
+    load(id)
+        .filter(State::alive)
+        .map(|state| state.fade(now_ms()))
+}
+
+/// The file that announces a fade, removed when the fade ends.
+struct Announcement(PathBuf);
+
+impl Announcement {
+    fn save(&self, state: &State) -> anyhow::Result<()> {
+        if let Some(dir) = self.0.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.0, serde_json::to_string(state)?)
+            .with_context(|| format!("failed to write {}", self.0.display()))
+    }
+}
+
+impl Drop for Announcement {
+    fn drop(&mut self) {
+        match fs::remove_file(&self.0) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => log::debug(
+                1,
+                format_args!("failed to remove {}: {}", self.0.display(), e),
+            ),
+            _ => (),
+        }
+    }
+}
+
+/// Ends the fade already running on node `id`, which the new one replaces.
+pub fn stop(id: i64) {
+    if let Some(state) = load(id).filter(State::alive) {
+        log::debug(
+            1,
+            format_args!("stopping the fade in process {}", state.pid),
+        );
+        let _ = Command::new("kill")
+            .arg(state.pid.to_string())
+            .stderr(Stdio::null())
+            .status();
+    }
+}
+
+/// A node's route, read from a copy of the graph that follows pw-dump, so
+/// that a step doesn't take running pw-dump. The fade's own writes aren't
+/// patched into it, or they would hide what something else set.
+pub struct Route {
+    graph: Graph,
+    id: i64,
+}
+
+impl Route {
+    pub fn spawn(id: i64) -> Route {
+        Route {
+            graph: Graph::follow(|| ()),
+            id,
+        }
+    }
+
+    fn with<T>(
+        &self,
+        f: impl FnOnce(&PipeWireInterfaceNode<'_>, &DeviceRoute<'_>) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let buf = self.graph.dump()?;
+        let obj = unmarshal(&buf)?;
+        let (node, route) = parse_dump(&obj, Target::Id(self.id))?;
+        f(node, route)
+    }
+}
+
+impl Node for Route {
+    fn status(&self) -> anyhow::Result<Status> {
+        self.with(|node, route| Ok(Status::new(node, route)))
+    }
+
+    fn set_volume(&self, volume: f64) -> anyhow::Result<()> {
+        self.with(|node, route| {
+            let volumes = vec![volume; route.props.channel_volumes.len()];
+            set_route(node, &volume_command(node, route, volumes))
+        })
+    }
+
+    fn set_mute(&self, mute: Option<bool>) -> anyhow::Result<()> {
+        self.with(|node, route| set_route(node, &route_command(VolumeOp::Mute(mute), node, route)))
+    }
+}
+
+/// Fades the node to `to`, a volume on its scale, over
+/// `duration`. Fading up unmutes the node, as `change` does; fading down
+/// leaves it muted.
+pub fn run(node: &dyn Node, to: f64, duration: Duration, config: FadeConfig) -> anyhow::Result<()> {
+    let status = node.status()?;
+    let scale = scale(&status.node);
+    let to = to.clamp(0.0, volume_config().max / 100.0);
+    let dry_run = DRY_RUN.load(Ordering::Relaxed);
+    stop(status.id);
+    if status.mute && to > status.volume {
+        node.set_mute(Some(false))?;
+    }
+    let announcement = match path(status.id) {
+        Some(path) if !dry_run => Some(Announcement(path)),
+        _ => None,
+    };
+    let (mut from, mut duration, mut start) = (status.volume, duration, Instant::now());
+    if let Some(announcement) = &announcement {
+        announcement.save(&State::new(from, to, duration))?;
+    }
+    let interval = Duration::from_millis(config.interval_ms.max(1));
+    let mut recent: Vec<f64> = Vec::with_capacity(RECENT);
+    loop {
+        if !recent.is_empty() && !dry_run {
+            let (current, _) = node.volume()?;
+            if recent
+                .iter()
+                .all(|volume| (volume - current).abs() > TOLERANCE)
+            {
+                log::debug(
+                    1,
+                    format_args!("the volume was changed to {:.1}%", current * 100.0),
+                );
+                if config.on_change == OnChange::Cancel {
+                    return Ok(());
+                }
+                from = current;
+                duration = duration.saturating_sub(start.elapsed());
+                start = Instant::now();
+                recent.clear();
+                if let Some(announcement) = &announcement {
+                    announcement.save(&State::new(from, to, duration))?;
+                }
+            }
+        }
+        let elapsed = start.elapsed();
+        if elapsed >= duration {
+            break;
+        }
+        let volume = from + (to - from) * elapsed.as_secs_f64() / duration.as_secs_f64();
+        node.set_volume(scale.linear(volume))?;
+        if recent.len() == RECENT {
+            recent.remove(0);
+        }
+        recent.push(volume);
+        thread::sleep(interval);
+    }
+    // gone before the last step, so that whatever reads the status after it
+    // sees the fade over
+    drop(announcement);
+    node.set_volume(scale.linear(to))
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("2s" => Ok(Duration::from_secs(2)))]
+    #[test_case("500ms" => Ok(Duration::from_millis(500)))]
+    #[test_case("1.5m" => Ok(Duration::from_secs(90)))]
+    #[test_case("3" => Ok(Duration::from_secs(3)))]
+    #[test_case("1h" => Ok(Duration::from_secs(3600)))]
+    #[test_case("2d" => Err(r#""2d" is not a duration such as "2s""#.to_string()))]
+    #[test_case("s" => Err(r#""s" is not a duration such as "2s""#.to_string()))]
+    fn parse_duration(s: &str) -> Result<Duration, String> {
+        duration(s)
+    }
+
+    #[test]
+    fn progress() {
+        let state = State {
+            pid: 1,
+            from: 0.2,
+            to: 0.6,
+            started_ms: 10_000,
+            duration_ms: 4000,
+        };
+        let fade = state.fade(11_000);
+        assert_eq!(fade.target, 0.6);
+        assert_eq!(fade.progress, 0.25);
+        assert_eq!(fade.remaining, 3.0);
+        assert_eq!(state.fade(20_000).progress, 1.0);
+        assert_eq!(state.fade(20_000).remaining, 0.0);
+    }
+
+    #[test_case(b"/usr/bin/pw-volume\x00fade-to\x0030\x00--over\x002s\x00", None => true; "installed")]
+    #[test_case(b"pw-volume\x00change\x00+5%\x00", None => true; "from path")]
+    #[test_case(b"./volume\x00change\x00", Some("volume") => true; "renamed")]
+    #[test_case(b"/usr/bin/sleep\x0010\x00", None => false; "reused pid")]
+    #[test_case(b"", None => false; "zombie")]
+    fn pid_runs_pw_volume(cmdline: &[u8], own: Option<&str>) -> bool {
+        runs(cmdline, own.map(OsStr::new))
+    }
+}
@@ -0,0 +1,111 @@
+//! Audible feedback: a short click played on the node after `change`, as
+//! GNOME plays one, so that the new volume can be heard with nothing else
+//! playing. `[feedback]` turns it on for every change and `change --feedback`
+//! for one. It is played with pw-play, which the change doesn't wait for.
+
+use std::{f64::consts::PI, fs, path::PathBuf, process::Stdio, sync::atomic::Ordering};
+
+use anyhow::{anyhow, Context};
+use clap::{Arg, ArgMatches};
+use serde::Deserialize;
+
+use crate::{
+    calibrate::{wav, SAMPLE_RATE},
+    config::runtime_dir,
+    log, tools, DRY_RUN,
+};
+
+/// How long the click lasts, in seconds.
+const CLICK: f64 = 0.04;
+
+/// The `[feedback]` section of the config file.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FeedbackConfig {
+    pub enabled: bool,
+    /// A sound file to play instead of the click, e.g. the sound theme's
+    /// `audio-volume-change.oga`.
+    pub sound: Option<PathBuf>,
+}
+
+/// `--feedback`, taken by `change`.
+pub fn arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("feedback")
+        .long("feedback")
+        .help("play a short click at the new volume, as [feedback] in the config file does")
+}
+
+/// The `[feedback]` section, turned on if the subcommand was given
+/// `--feedback`.
+pub fn config(config: &FeedbackConfig, arg: &ArgMatches<'_>) -> FeedbackConfig {
+    FeedbackConfig {
+        enabled: config.enabled || arg.is_present("feedback"),
+        ..config.clone()
+    }
+}
+
+/// A 1 kHz tone that dies away within [`CLICK`], quiet enough not to startle
+/// at full volume.
+fn click() -> Vec<f64> {
+    let frames = (f64::from(SAMPLE_RATE) * CLICK) as u32;
+    (0..frames)
+        .map(|frame| {
+            let t = f64::from(frame) / f64::from(SAMPLE_RATE);
+            0.3 * (2.0 * PI * 1000.0 * t).sin() * (-t / (CLICK / 5.0)).exp()
+        })
+        .collect()
+}
+
+/// The click's file, written the first time it's needed.
+fn click_path() -> anyhow::Result<PathBuf> {
+    let dir = runtime_dir().ok_or_else(|| anyhow!("failed to determine runtime directory"))?;
+    let path = dir.join("click.wav");
+    if !path.exists() {
+        fs::create_dir_all(&dir)?;
+        fs::write(&path, wav(1, &click()))
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    Ok(path)
+}
+
+/// Plays the click on the node named `node`. Failing to play it doesn't fail
+/// the change.
+pub fn play(config: &FeedbackConfig, node: &str) {
+    if !config.enabled || DRY_RUN.load(Ordering::Relaxed) {
+        return;
+    }
+    let path = match config.sound.clone().map_or_else(click_path, Ok) {
+        Ok(path) => path,
+        Err(e) => {
+            log::debug(1, format_args!("not playing feedback: {:#}", e));
+            return;
+        }
+    };
+    let child = tools::spawn(
+        tools::command("pw-play")
+            .arg("--target")
+            .arg(node)
+            .arg(&path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null()),
+    );
+    match child {
+        // reaped in the background, as the daemon plays it too and lives on
+        Ok(child) => tools::reap(child),
+        Err(e) => log::debug(1, format_args!("failed to execute pw-play: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn click_dies_away() {
+        let click = click();
+        assert_eq!(click.len(), 1920);
+        let peak = |samples: &[f64]| samples.iter().fold(0.0, |max: f64, s| max.max(s.abs()));
+        assert!(peak(&click) <= 0.3);
+        assert!(peak(&click[click.len() - 100..]) < 0.01);
+    }
+}
@@ -0,0 +1,243 @@
+//! Filters that pw-volume puts in front of the default sink or source. Each
+//! runs in its own `pipewire` process, as a virtual device that becomes the
+//! default and passes audio to or from the device that was the default before.
+//! While one runs, `$XDG_RUNTIME_DIR/pw-volume/<name>.state` holds its pid and
+//! the previous default.
+
+use std::{
+    fs, io,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+use anyhow::{anyhow, ensure, Context};
+use clap::ArgMatches;
+use serde_json::Value;
+
+use crate::{
+    config::runtime_dir, default_name, pw_dump, set_default_name, tools, unmarshal, PipeWireObject,
+};
+
+pub struct Filter {
+    /// Names the state and config files.
+    name: &'static str,
+    /// Node name of the virtual sink or source.
+    node: &'static str,
+    /// Node name of the stream between it and the real device.
+    stream: &'static str,
+    /// Metadata key of the default in effect, which the virtual device
+    /// replaces.
+    default: &'static str,
+    /// Metadata key of the configured default, which is set to take over.
+    key: &'static str,
+}
+
+/// `pw-volume compress`.
+pub const COMPRESS: Filter = Filter {
+    name: "compress",
+    node: "pw-volume-compress",
+    stream: "pw-volume-compress.output",
+    default: "default.audio.sink",
+    key: "default.configured.audio.sink",
+};
+
+/// `pw-volume mic-agc`.
+pub const AGC: Filter = Filter {
+    name: "agc",
+    node: "pw-volume-agc",
+    stream: "pw-volume-agc.capture",
+    default: "default.audio.source",
+    key: "default.configured.audio.source",
+};
+
+const FILTERS: [Filter; 2] = [COMPRESS, AGC];
+
+/// The real device behind `name`, if it is one of the filters, so that
+/// commands keep acting on the hardware while a filter is the default.
+pub fn device_behind<'a>(obj: &'a [PipeWireObject<'_>], name: &str) -> Option<&'a str> {
+    let filter = FILTERS.iter().find(|f| f.node == name)?;
+    obj.iter().find_map(|o| match o {
+        PipeWireObject::Stream(s) if s.info.props.node_name == filter.stream => {
+            s.info.props.target_object.as_deref()
+        }
+        _ => None,
+    })
+}
+
+/// A `pipewire -c` config that runs nothing but `module`. SPA JSON accepts
+/// plain JSON, which takes care of quoting.
+fn pipewire_config(module: Value) -> String {
+    let modules = serde_json::json!([
+        { "name": "libpipewire-module-rt", "flags": ["ifexists", "nofail"] },
+        { "name": "libpipewire-module-protocol-native" },
+        { "name": "libpipewire-module-client-node" },
+        { "name": "libpipewire-module-adapter" },
+        module,
+    ]);
+    format!(
+        "context.spa-libs = {{\n    \
+             audio.convert.* = audioconvert/libspa-audioconvert\n    \
+             support.* = support/libspa-support\n\
+         }}\n\
+         context.modules = {:#}\n",
+        modules
+    )
+}
+
+/// A running filter's process and the default to restore when it stops.
+#[derive(Debug, PartialEq)]
+struct State {
+    pid: u32,
+    device: String,
+}
+
+impl Filter {
+    /// Whether the virtual device exists.
+    pub fn running(&self, obj: &[PipeWireObject<'_>]) -> bool {
+        obj.iter().any(|o| match o {
+            PipeWireObject::Node(n) => n.info.props.node_name == self.node,
+            PipeWireObject::Stream(s) => s.info.props.node_name == self.node,
+            _ => false,
+        })
+    }
+
+    fn path(&self, extension: &str) -> anyhow::Result<PathBuf> {
+        runtime_dir()
+            .map(|dir| dir.join(format!("{}.{}", self.name, extension)))
+            .ok_or_else(|| anyhow!("failed to determine runtime directory"))
+    }
+
+    fn load(&self) -> anyhow::Result<Option<State>> {
+        let path = self.path("state")?;
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        let (pid, device) = contents
+            .split_once('\n')
+            .ok_or_else(|| anyhow!("failed to parse {}", path.display()))?;
+        Ok(Some(State {
+            pid: pid.parse()?,
+            device: device.to_string(),
+        }))
+    }
+
+    fn save(&self, state: &State) -> anyhow::Result<()> {
+        let path = self.path("state")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, format!("{}\n{}", state.pid, state.device))
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    fn start(&self, device: &str, module: Value) -> anyhow::Result<()> {
+        let conf = self.path("conf")?;
+        if let Some(dir) = conf.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&conf, pipewire_config(module))
+            .with_context(|| format!("failed to write {}", conf.display()))?;
+        let child = tools::command("pipewire")
+            .arg("-c")
+            .arg(&conf)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("failed to execute pipewire")?;
+        self.save(&State {
+            pid: child.id(),
+            device: device.to_string(),
+        })?;
+        // the configured default is stored by name, so this holds even if the
+        // virtual device hasn't appeared yet
+        set_default_name(self.key, self.node)
+    }
+
+    fn stop(&self, state: &State) -> anyhow::Result<()> {
+        set_default_name(self.key, &state.device)?;
+        kill(state);
+        let _ = fs::remove_file(self.path("conf")?);
+        fs::remove_file(self.path("state")?)?;
+        Ok(())
+    }
+
+    /// Handles `on`, `off`, `toggle`, and `status`, where `module` builds the
+    /// filter's module for the device it sits in front of.
+    pub fn run(
+        &self,
+        matches: &ArgMatches<'_>,
+        module: impl Fn(&str) -> Value,
+    ) -> anyhow::Result<i32> {
+        let buf = pw_dump()?;
+        let obj = unmarshal(&buf)?;
+        let state = self.load()?;
+        let enabled = self.running(&obj);
+        let enable = match matches.value_of("TRANSITION") {
+            Some("on") => true,
+            Some("off") => false,
+            Some("toggle") => !enabled,
+            _ => {
+                // like is-muted, the exit code answers the question
+                println!("{}", if enabled { "on" } else { "off" });
+                return Ok(if enabled { 0 } else { 1 });
+            }
+        };
+        match (enable, state) {
+            (true, _) if enabled => (),
+            // left over from a filter that has since exited, so the default
+            // still names it
+            (true, Some(state)) => {
+                kill(&state);
+                self.start(&state.device, module(&state.device))?;
+            }
+            (true, None) => {
+                let device = default_name(&obj, self.default)
+                    .ok_or_else(|| anyhow!("failed to find default device"))?;
+                self.start(device, module(device))?;
+            }
+            (false, Some(state)) => self.stop(&state)?,
+            (false, None) => ensure!(!enabled, "{} was not started by pw-volume", self.node),
+        }
+        Ok(0)
+    }
+}
+
+/// Ends a filter's process, which may already have exited on its own.
+fn kill(state: &State) {
+    let _ = Command::new("kill")
+        .arg(state.pid.to_string())
+        .stderr(Stdio::null())
+        .status();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_device() -> anyhow::Result<()> {
+        let dump = serde_json::json!([
+            {
+                "id": 90,
+                "type": "PipeWire:Interface:Node",
+                "info": { "props": {
+                    "node.name": "pw-volume-compress.output",
+                    "media.class": "Stream/Output/Audio",
+                    "target.object": "alsa_output.analog-stereo",
+                } },
+            },
+        ])
+        .to_string();
+        let obj: Vec<PipeWireObject> = serde_json::from_str(&dump)?;
+        assert_eq!(
+            device_behind(&obj, "pw-volume-compress"),
+            Some("alsa_output.analog-stereo")
+        );
+        assert_eq!(device_behind(&obj, "pw-volume-agc"), None);
+        assert_eq!(device_behind(&obj, "alsa_output.analog-stereo"), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+//! Audio that follows the focused workspace or output in sway or i3, e.g. to
+//! the USB speakers attached to whichever monitor is in use. The daemon
+//! follows focus changes with `swaymsg` (or `i3-msg`) subscribed to workspace
+//! events, and makes the matching `[[focus]]` entry's sink the default.
+
+use std::{env, io::BufReader, process::Stdio, thread};
+
+use serde::Deserialize;
+
+use crate::tools;
+
+/// A `[[focus]]` entry: `sink` becomes the default while `workspace` (by
+/// name) or `output` is focused.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FocusSink {
+    pub workspace: Option<String>,
+    pub output: Option<String>,
+    pub sink: String,
+}
+
+/// The focused workspace and the output it's on.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Focus {
+    #[serde(rename = "name")]
+    pub workspace: String,
+    pub output: String,
+}
+
+/// The subset of a workspace event that matters here. The reply to the
+/// subscription, `{"success": true}`, comes first and has no `change`.
+#[derive(Deserialize)]
+struct WorkspaceEvent {
+    #[serde(default)]
+    change: String,
+    current: Option<Focus>,
+}
+
+/// The sink for a focus. An entry for the workspace wins over one for its
+/// output, so that a single workspace can be sent elsewhere.
+pub fn sink<'a>(entries: &'a [FocusSink], focus: &Focus) -> Option<&'a str> {
+    entries
+        .iter()
+        .find(|e| e.workspace.as_deref() == Some(focus.workspace.as_str()))
+        .or_else(|| {
+            entries.iter().find(|e| {
+                e.workspace.is_none() && e.output.as_deref() == Some(focus.output.as_str())
+            })
+        })
+        .map(|e| e.sink.as_str())
+}
+
+/// Follows workspace focus, calling `focused` each time it moves. Does
+/// nothing outside of sway and i3.
+pub fn spawn(focused: impl Fn(Focus) + Send + 'static) {
+    let program = if env::var_os("SWAYSOCK").is_some() {
+        "swaymsg"
+    } else if env::var_os("I3SOCK").is_some() {
+        "i3-msg"
+    } else {
+        return;
+    };
+    let child = tools::spawn(
+        tools::command(program)
+            .args(["-t", "subscribe", "-m", r#"["workspace"]"#])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null()),
+    );
+    let mut child = match child {
+        Ok(child) => child,
+        Err(_) => return,
+    };
+    let stdout = match child.stdout.take() {
+        Some(stdout) => stdout,
+        None => return,
+    };
+    thread::spawn(move || {
+        let events = serde_json::Deserializer::from_reader(BufReader::new(stdout))
+            .into_iter::<WorkspaceEvent>();
+        for event in events {
+            match event {
+                Ok(WorkspaceEvent {
+                    change,
+                    current: Some(focus),
+                }) if change == "focus" => focused(focus),
+                Ok(_) => (),
+                Err(_) => break,
+            }
+        }
+        tools::kill(&mut child);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("1", "DP-1" => Some("desk".to_string()))]
+    #[test_case("9", "DP-1" => Some("headphones".to_string()); "workspace wins")]
+    #[test_case("2", "HDMI-A-1" => None)]
+    fn focus_sink(workspace: &str, output: &str) -> Option<String> {
+        let entries = vec![
+            FocusSink {
+                workspace: None,
+                output: Some("DP-1".to_string()),
+                sink: "desk".to_string(),
+            },
+            FocusSink {
+                workspace: Some("9".to_string()),
+                output: None,
+                sink: "headphones".to_string(),
+            },
+        ];
+        let focus = Focus {
+            workspace: workspace.to_string(),
+            output: output.to_string(),
+        };
+        sink(&entries, &focus).map(str::to_string)
+    }
+
+    #[test]
+    fn workspace_event() -> anyhow::Result<()> {
+        let event: WorkspaceEvent = serde_json::from_str(
+            r#"{ "change": "focus",
+                 "current": { "id": 12, "type": "workspace", "name": "3", "output": "DP-2",
+                              "focused": true, "nodes": [] },
+                 "old": { "id": 8, "type": "workspace", "name": "1", "output": "eDP-1" } }"#,
+        )?;
+        assert_eq!(event.change, "focus");
+        assert_eq!(
+            event.current,
+            Some(Focus {
+                workspace: "3".to_string(),
+                output: "DP-2".to_string(),
+            })
+        );
+        Ok(())
+    }
+}
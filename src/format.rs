@@ -0,0 +1,520 @@
+//! Rendering of the output's volume and mute state for `status`.
+
+use std::{fmt::Write as _, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::{scale, template::Template, DeviceRoute, PipeWireCommand, PipeWireInterfaceNode};
+
+/// Volume (as a fraction of 1.0) at or above which the state becomes a warning.
+const WARNING_VOLUME: f64 = 0.9;
+
+/// Volume and mute state of a node's route, along with what identifies it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Status {
+    pub id: i64,
+    /// Volume as a fraction, where 1.0 is 100%.
+    pub volume: f64,
+    pub mute: bool,
+    /// Human-readable name, e.g. "Built-in Audio Analog Stereo".
+    pub name: String,
+    /// PipeWire node name, e.g. "alsa_output.pci-0000_00_1f.3.analog-stereo".
+    pub node: String,
+    /// Description of the active route, e.g. "Speakers".
+    pub port: Option<String>,
+    /// Name of the active route, e.g. "analog-output-headphones".
+    pub port_name: Option<String>,
+    pub channels: usize,
+    /// Description of the device the node belongs to, e.g. "Built-in Audio".
+    /// Filled in separately, since it comes from the device object.
+    pub device: Option<String>,
+    /// Channel positions, e.g. `FL`, `FR`.
+    pub channel_map: Vec<String>,
+    pub channel_volumes: Vec<f64>,
+    pub volume_base: Option<f64>,
+    /// Whether `pw-volume compress` is on. Filled in separately, like `device`.
+    pub compress: bool,
+    /// Whether `pw-volume mic-agc` is on, likewise.
+    pub agc: bool,
+    /// Whether an application is recording from the default source, likewise.
+    pub mic_active: bool,
+    /// The fade running on the node, likewise.
+    pub fade: Option<Fade>,
+    /// The battery level of the Bluetooth device the node belongs to, in
+    /// percent, where it reports one, likewise.
+    pub battery: Option<u8>,
+}
+
+/// Where a fade started by `change --over` or `fade-to` is going, and how far
+/// along it is.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Fade {
+    /// The volume it ends at, as a fraction.
+    pub target: f64,
+    /// From 0.0 when it starts to 1.0 when it's done.
+    pub progress: f64,
+    /// Seconds left.
+    pub remaining: f64,
+}
+
+impl Status {
+    pub fn new(node: &PipeWireInterfaceNode<'_>, route: &DeviceRoute<'_>) -> Status {
+        let props = &node.info.props;
+        Status {
+            id: node.id,
+            // assumes that all channels have the same volume.
+            volume: scale(props.node_name).scaled(route.props.channel_volumes[0]),
+            mute: route.props.mute,
+            name: props
+                .node_description
+                .as_deref()
+                .unwrap_or(props.node_name)
+                .to_string(),
+            node: props.node_name.to_string(),
+            port: route.description.as_deref().map(str::to_string),
+            port_name: route.name.as_deref().map(str::to_string),
+            channels: route.props.channel_volumes.len(),
+            device: None,
+            channel_map: route.props.channel_map.clone(),
+            channel_volumes: route.props.channel_volumes.clone(),
+            volume_base: route.props.volume_base,
+            compress: false,
+            agc: false,
+            mic_active: false,
+            fade: None,
+            battery: None,
+        }
+    }
+
+    /// The status once `cmd` has been applied to the route.
+    pub fn after(
+        node: &PipeWireInterfaceNode<'_>,
+        route: &DeviceRoute<'_>,
+        cmd: &PipeWireCommand,
+    ) -> Status {
+        let mut status = Status::new(node, route);
+        status.mute = cmd.props.mute;
+        // a mute leaves the volumes out
+        if let Some(volume) = cmd.props.channel_volumes.first() {
+            status.volume = scale(&status.node).scaled(*volume);
+            status.channel_volumes = cmd.props.channel_volumes.clone();
+        }
+        status
+    }
+
+    pub fn percentage(&self) -> f64 {
+        self.volume * 100.0
+    }
+
+    /// What kind of device the sound goes to, going by the node and port
+    /// names, or `None` if neither tells, as for most sources.
+    pub fn device_kind(&self) -> Option<DeviceKind> {
+        if self.node.starts_with("bluez_") {
+            return Some(DeviceKind::Bluetooth);
+        }
+        let port = self.port_name.as_deref()?.to_lowercase();
+        let has = |words: &[&str]| words.iter().any(|word| port.contains(word));
+        // a headset's microphone isn't headphones
+        if has(&["input"]) {
+            None
+        } else if has(&["headphone", "headset"]) {
+            Some(DeviceKind::Headphones)
+        } else if has(&["hdmi", "displayport", "iec958", "spdif"]) {
+            Some(DeviceKind::Hdmi)
+        } else if has(&["speaker", "lineout", "analog-output"]) {
+            Some(DeviceKind::Speakers)
+        } else {
+            None
+        }
+    }
+
+    pub fn state(&self) -> State {
+        if self.mute {
+            State::Muted
+        } else if self.volume > 1.0 {
+            State::Boosted
+        } else if self.volume >= WARNING_VOLUME {
+            State::Warning
+        } else {
+            State::Ok
+        }
+    }
+}
+
+/// Semantic state of the audio output, independent of any color or styling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Ok,
+    Warning,
+    Muted,
+    Boosted,
+}
+
+impl State {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            State::Ok => "ok",
+            State::Warning => "warning",
+            State::Muted => "muted",
+            State::Boosted => "boosted",
+        }
+    }
+}
+
+/// The kind of device a sink's port leads to, for bars to pick a glyph by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceKind {
+    Headphones,
+    Speakers,
+    /// HDMI, DisplayPort, or S/PDIF, which go to a monitor or receiver.
+    Hdmi,
+    Bluetooth,
+}
+
+impl DeviceKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeviceKind::Headphones => "headphones",
+            DeviceKind::Speakers => "speakers",
+            DeviceKind::Hdmi => "hdmi",
+            DeviceKind::Bluetooth => "bluetooth",
+        }
+    }
+}
+
+/// Built-in icon sets.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum IconPreset {
+    /// Glyphs from a patched Nerd Font.
+    #[default]
+    NerdFont,
+    Emoji,
+    /// Plain ASCII, for terminals and bars without special fonts.
+    Ascii,
+}
+
+impl FromStr for IconPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nerd-font" => Ok(IconPreset::NerdFont),
+            "emoji" => Ok(IconPreset::Emoji),
+            "ascii" => Ok(IconPreset::Ascii),
+            _ => Err(format!(r#""{}" is not an icon preset"#, s)),
+        }
+    }
+}
+
+/// Glyphs shown for the mute state and for increasing volume levels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Icons {
+    pub mute: String,
+    pub levels: Vec<String>,
+}
+
+impl Icons {
+    pub fn preset(preset: IconPreset) -> Icons {
+        let (mute, levels): (&str, &[&str]) = match preset {
+            IconPreset::NerdFont => ("\u{f075f}", &["\u{f057f}", "\u{f0580}", "\u{f057e}"]),
+            IconPreset::Emoji => ("\u{1f507}", &["\u{1f508}", "\u{1f509}", "\u{1f50a}"]),
+            IconPreset::Ascii => ("<x", &["<", "<)", "<))"]),
+        };
+        Icons {
+            mute: mute.to_string(),
+            levels: levels.iter().map(|l| l.to_string()).collect(),
+        }
+    }
+
+    pub fn icon(&self, status: &Status) -> &str {
+        if status.mute || self.levels.is_empty() {
+            return &self.mute;
+        }
+        let n = self.levels.len();
+        let tier = (status.volume.clamp(0.0, 1.0) * n as f64) as usize;
+        &self.levels[tier.min(n - 1)]
+    }
+}
+
+impl Default for Icons {
+    fn default() -> Self {
+        Icons::preset(IconPreset::default())
+    }
+}
+
+/// Volume tier, used for Waybar's `class` and `alt` fields.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tier {
+    Low,
+    Medium,
+    High,
+}
+
+impl Tier {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Tier::Low => "low",
+            Tier::Medium => "medium",
+            Tier::High => "high",
+        }
+    }
+}
+
+/// Volume percentages at which the medium and high tiers begin.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Thresholds {
+    pub medium: f64,
+    pub high: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            medium: 34.0,
+            high: 67.0,
+        }
+    }
+}
+
+impl Thresholds {
+    pub fn tier(&self, status: &Status) -> Tier {
+        let percentage = status.percentage();
+        if percentage >= self.high {
+            Tier::High
+        } else if percentage >= self.medium {
+            Tier::Medium
+        } else {
+            Tier::Low
+        }
+    }
+}
+
+/// Presentation settings shared by all formats.
+#[derive(Debug, Clone, Default)]
+pub struct Style {
+    pub icons: Icons,
+    pub thresholds: Thresholds,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Format {
+    /// JSON for Waybar's custom module.
+    Waybar,
+    /// Short, punctuation-free sentences for braille displays and screen readers.
+    A11y,
+    /// An icon followed by the volume, e.g. `<)) 42%`.
+    Text,
+    /// A user-supplied template, e.g. `{percentage}% {mute:muted|}`.
+    Template(Template),
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "waybar" => Ok(Format::Waybar),
+            "a11y" => Ok(Format::A11y),
+            "text" => Ok(Format::Text),
+            _ if s.contains('{') => s.parse().map(Format::Template),
+            _ => Err(format!(
+                r#""{}" is neither a format name nor a template with {{placeholders}}"#,
+                s
+            )),
+        }
+    }
+}
+
+impl Format {
+    pub fn render(&self, status: &Status, style: &Style) -> String {
+        match self {
+            Format::Waybar => waybar(status, style),
+            Format::A11y => a11y(status),
+            Format::Text => text(status, style),
+            Format::Template(template) => template.render(status, style),
+        }
+    }
+}
+
+/// Waybar's custom module fields. `class` and `alt` name the volume tier
+/// (`low`, `medium`, `high`) or `muted`/`mute`, so that both CSS and
+/// `format-icons` can key off them. Where the kind of device is known, `class`
+/// names it as well, e.g. `["low", "headphones"]`, and `device_kind` follows
+/// the route's `port` name.
+fn waybar(status: &Status, style: &Style) -> String {
+    let state = status.state();
+    let percentage = status.percentage();
+    let (alt, tooltip) = if status.mute {
+        ("mute", "muted".to_string())
+    } else {
+        (
+            style.thresholds.tier(status).as_str(),
+            format!("{}%", percentage),
+        )
+    };
+    let class = if status.mute { "muted" } else { alt };
+    let kind = status.device_kind();
+    let class = match kind {
+        Some(kind) => format!(r#"["{}", "{}"]"#, class, kind.as_str()),
+        None => format!(r#""{}""#, class),
+    };
+    let mut json = format!(
+        r#"{{"percentage":{:.0}, "alt":"{}", "tooltip":"{}", "class":{}, "state":"{}""#,
+        percentage,
+        alt,
+        tooltip,
+        class,
+        state.as_str()
+    );
+    if let Some(port) = &status.port_name {
+        let _ = write!(
+            json,
+            r#", "port":{}"#,
+            serde_json::Value::from(port.as_str())
+        );
+    }
+    if let Some(kind) = kind {
+        let _ = write!(json, r#", "device_kind":"{}""#, kind.as_str());
+    }
+    if let Some(battery) = status.battery {
+        let _ = write!(json, r#", "battery":{}"#, battery);
+    }
+    json.push('}');
+    json
+}
+
+/// Names the state unless it is `ok`, e.g. `volume 95 percent warning`.
+fn a11y(status: &Status) -> String {
+    match status.state() {
+        State::Muted => "output muted".to_string(),
+        State::Ok => format!("volume {:.0} percent", status.percentage()),
+        state => format!(
+            "volume {:.0} percent {}",
+            status.percentage(),
+            state.as_str()
+        ),
+    }
+}
+
+/// Like [`a11y`], names the state unless it is `ok`, e.g. `<)) 120% boosted`.
+fn text(status: &Status, style: &Style) -> String {
+    let icon = style.icons.icon(status);
+    match status.state() {
+        State::Muted => format!("{} muted", icon),
+        State::Ok => format!("{} {:.0}%", icon, status.percentage()),
+        state => format!("{} {:.0}% {}", icon, status.percentage(), state.as_str()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(false, 0.4 => State::Ok)]
+    #[test_case(false, 0.95 => State::Warning)]
+    #[test_case(false, 1.2 => State::Boosted)]
+    #[test_case(true, 1.2 => State::Muted)]
+    fn state(mute: bool, volume: f64) -> State {
+        Status {
+            volume,
+            mute,
+            ..Default::default()
+        }
+        .state()
+    }
+
+    #[test_case(false, 0.4 => "volume 40 percent")]
+    #[test_case(false, 0.95 => "volume 95 percent warning")]
+    #[test_case(false, 1.5 => "volume 150 percent boosted")]
+    #[test_case(true, 0.4 => "output muted")]
+    fn a11y_sentence(mute: bool, volume: f64) -> String {
+        let status = Status {
+            volume,
+            mute,
+            ..Default::default()
+        };
+        Format::A11y.render(&status, &Style::default())
+    }
+
+    #[test_case(false, 0.4 => "<) 40%")]
+    #[test_case(false, 0.95 => "<)) 95% warning")]
+    #[test_case(false, 1.2 => "<)) 120% boosted")]
+    #[test_case(true, 0.4 => "<x muted")]
+    fn text_label(mute: bool, volume: f64) -> String {
+        let status = Status {
+            volume,
+            mute,
+            ..Default::default()
+        };
+        let style = Style {
+            icons: Icons::preset(IconPreset::Ascii),
+            ..Default::default()
+        };
+        Format::Text.render(&status, &style)
+    }
+
+    #[test_case(false, 0.2 => r#"{"percentage":20, "alt":"low", "tooltip":"20%", "class":"low", "state":"ok"}"#)]
+    #[test_case(false, 0.5 => r#"{"percentage":50, "alt":"medium", "tooltip":"50%", "class":"medium", "state":"ok"}"#)]
+    #[test_case(true, 0.5 => r#"{"percentage":50, "alt":"mute", "tooltip":"muted", "class":"muted", "state":"muted"}"#)]
+    fn waybar_json(mute: bool, volume: f64) -> String {
+        let status = Status {
+            volume,
+            mute,
+            ..Default::default()
+        };
+        let rendered = Format::Waybar.render(&status, &Style::default());
+        serde_json::from_str::<serde_json::Value>(&rendered).unwrap();
+        rendered
+    }
+
+    #[test_case("alsa_output.pci-0000_00_1f.3.analog-stereo", Some("analog-output-headphones") => Some("headphones"))]
+    #[test_case("alsa_output.pci-0000_00_1f.3.analog-stereo", Some("analog-output-speaker") => Some("speakers"))]
+    #[test_case("alsa_output.pci-0000_01_00.1.hdmi-stereo", Some("hdmi-output-0") => Some("hdmi"))]
+    #[test_case("alsa_output.usb-Topping-00.iec958-stereo", Some("iec958-stereo-output") => Some("hdmi"))]
+    #[test_case("bluez_output.18_54_CF_71_8C_FA.a2dp-sink", Some("headset-output") => Some("bluetooth"))]
+    #[test_case("alsa_output.usb-FiiO_K5-00.analog-stereo", Some("analog-output") => Some("speakers"))]
+    #[test_case("alsa_input.pci-0000_00_1f.3.analog-stereo", Some("analog-input-headset-mic") => None)]
+    #[test_case("v4l2_output", None => None)]
+    fn device_kinds(node: &str, port: Option<&str>) -> Option<&'static str> {
+        let status = Status {
+            node: node.to_string(),
+            port_name: port.map(str::to_string),
+            ..Default::default()
+        };
+        status.device_kind().map(DeviceKind::as_str)
+    }
+
+    #[test]
+    fn waybar_port() {
+        let status = Status {
+            volume: 0.2,
+            node: "alsa_output.pci-0000_00_1f.3.analog-stereo".to_string(),
+            port_name: Some("analog-output-headphones".to_string()),
+            ..Default::default()
+        };
+        let rendered = Format::Waybar.render(&status, &Style::default());
+        assert_eq!(
+            rendered,
+            r#"{"percentage":20, "alt":"low", "tooltip":"20%", "class":["low", "headphones"], "state":"ok", "port":"analog-output-headphones", "device_kind":"headphones"}"#
+        );
+        serde_json::from_str::<serde_json::Value>(&rendered).unwrap();
+    }
+
+    #[test_case(false, 0.0 => "<")]
+    #[test_case(false, 0.5 => "<)")]
+    #[test_case(false, 1.0 => "<))")]
+    #[test_case(false, 1.5 => "<))")]
+    #[test_case(true, 0.5 => "<x")]
+    fn ascii_icon(mute: bool, volume: f64) -> String {
+        let status = Status {
+            volume,
+            mute,
+            ..Default::default()
+        };
+        Icons::preset(IconPreset::Ascii).icon(&status).to_string()
+    }
+}
@@ -0,0 +1,296 @@
+//! A copy of the PipeWire graph that follows `pw-dump --monitor`, so that the
+//! daemon can answer requests without running pw-dump for each one.
+//!
+//! The daemon's own writes are patched into the copy right away, since
+//! pw-dump only reports them a moment later: two `change +5%` arriving
+//! together then add up, where the second would otherwise start from the
+//! volume before the first.
+
+use std::{
+    collections::BTreeMap,
+    io::BufReader,
+    process::{Child, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use serde_json::Value;
+
+use crate::{pw_dump, tools};
+
+/// Objects by id, or `None` while pw-dump isn't being followed.
+type Objects = Arc<Mutex<Option<BTreeMap<i64, Value>>>>;
+
+/// Params set with pw-cli that the copy may not show yet, by object id, or
+/// `None` when no copy is being kept.
+static WRITES: Mutex<Option<Vec<(i64, String, Value)>>> = Mutex::new(None);
+
+/// Notes a param that was just set, such as a device's `Route`.
+pub fn record(id: i64, param: &str, value: &str) {
+    if let Some(writes) = WRITES.lock().unwrap().as_mut() {
+        if let Ok(value) = serde_json::from_str(value) {
+            writes.push((id, param.to_string(), value));
+        }
+    }
+}
+
+/// Patches a `Route` that was set into the route it was set on, which has
+/// the same index and device. Other params are left for pw-dump to report.
+fn apply_write(objects: &mut BTreeMap<i64, Value>, id: i64, param: &str, value: &Value) {
+    let routes = match objects.get_mut(&id) {
+        Some(object) if param == "Route" => &mut object["info"]["params"]["Route"],
+        _ => return,
+    };
+    let routes = match routes.as_array_mut() {
+        Some(routes) => routes,
+        None => return,
+    };
+    for route in routes {
+        if route["index"] != value["index"] || route["device"] != value["device"] {
+            continue;
+        }
+        route["props"]["mute"] = value["props"]["mute"].clone();
+        // a mute leaves the volumes out
+        match &value["props"]["channelVolumes"] {
+            Value::Array(volumes) if volumes.is_empty() => (),
+            volumes => route["props"]["channelVolumes"] = volumes.clone(),
+        }
+    }
+}
+
+pub struct Graph {
+    objects: Objects,
+    /// The pw-dump being followed, killed and reaped when the copy is
+    /// dropped, so that it doesn't outlive a fade.
+    child: Option<Arc<Mutex<Child>>>,
+}
+
+/// Applies one of pw-dump's updates, which list the objects that changed in
+/// full, and objects that went away as `{ "id": N, "info": null }`.
+fn apply(objects: &mut BTreeMap<i64, Value>, update: Vec<Value>) {
+    for object in update {
+        let id = match object.get("id").and_then(Value::as_i64) {
+            Some(id) => id,
+            None => continue,
+        };
+        if object.get("info") == Some(&Value::Null) {
+            objects.remove(&id);
+        } else {
+            objects.insert(id, object);
+        }
+    }
+}
+
+/// Collapses pw-dump's output into a single array. pw-dump prints an update
+/// after the first array when the graph changes while it runs, and output
+/// that ends partway through a document is cut short; updates are merged
+/// into the first array and anything incomplete after it is ignored.
+pub fn merge(buf: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    let mut documents = serde_json::Deserializer::from_slice(&buf).into_iter::<Vec<Value>>();
+    let first = match documents.next() {
+        Some(first) => first?,
+        None => return Ok(buf),
+    };
+    let end = documents.byte_offset();
+    if buf[end..].iter().all(u8::is_ascii_whitespace) {
+        return Ok(buf);
+    }
+    let mut objects = BTreeMap::new();
+    apply(&mut objects, first);
+    for update in documents {
+        match update {
+            Ok(update) => apply(&mut objects, update),
+            Err(_) => break,
+        }
+    }
+    Ok(serde_json::to_vec(&objects.values().collect::<Vec<_>>())?)
+}
+
+impl Graph {
+    /// Starts following the graph, calling `changed` after every update.
+    pub fn spawn(changed: impl Fn() + Send + 'static) -> Graph {
+        *WRITES.lock().unwrap() = Some(Vec::new());
+        Graph::follow(changed)
+    }
+
+    /// Starts following the graph without patching this process's writes
+    /// into it, so that it shows only what pw-dump reports.
+    pub fn follow(changed: impl Fn() + Send + 'static) -> Graph {
+        let objects: Objects = Arc::new(Mutex::new(None));
+        let child = tools::spawn(
+            tools::command("pw-dump")
+                .args(["--monitor", "--no-colors"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null()),
+        );
+        let mut child = match child {
+            Ok(child) => child,
+            Err(_) => {
+                return Graph {
+                    objects,
+                    child: None,
+                }
+            }
+        };
+        let stdout = child.stdout.take();
+        let child = Arc::new(Mutex::new(child));
+        if let Some(stdout) = stdout {
+            let shared = Arc::clone(&objects);
+            let child = Arc::clone(&child);
+            thread::spawn(move || {
+                let updates = serde_json::Deserializer::from_reader(BufReader::new(stdout))
+                    .into_iter::<Vec<Value>>();
+                for update in updates {
+                    let update = match update {
+                        Ok(update) => update,
+                        Err(_) => break,
+                    };
+                    apply(
+                        shared.lock().unwrap().get_or_insert_with(BTreeMap::new),
+                        update,
+                    );
+                    changed();
+                }
+                // the copy would go stale from here on
+                *shared.lock().unwrap() = None;
+                tools::kill(&mut child.lock().unwrap());
+            });
+        }
+        Graph {
+            objects,
+            child: Some(child),
+        }
+    }
+
+    /// A copy that only changes with [`Graph::update`], for updates read
+    /// from a recording, or from a pw-dump started elsewhere.
+    pub fn replay() -> Graph {
+        *WRITES.lock().unwrap() = Some(Vec::new());
+        Graph {
+            objects: Arc::new(Mutex::new(Some(BTreeMap::new()))),
+            child: None,
+        }
+    }
+
+    /// Applies a recorded update. Writes made since the last one are patched
+    /// in first, so that the update shows what came after them.
+    pub fn update(&self, update: Vec<Value>) {
+        let writes = WRITES
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default();
+        let mut objects = self.objects.lock().unwrap();
+        let objects = objects.get_or_insert_with(BTreeMap::new);
+        for (id, param, value) in &writes {
+            apply_write(objects, *id, param, value);
+        }
+        apply(objects, update);
+    }
+
+    /// The graph in pw-dump's format, running pw-dump when the graph isn't
+    /// being followed.
+    pub fn dump(&self) -> anyhow::Result<Vec<u8>> {
+        let writes = WRITES
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default();
+        if let Some(objects) = &mut *self.objects.lock().unwrap() {
+            for (id, param, value) in &writes {
+                apply_write(objects, *id, param, value);
+            }
+            return Ok(serde_json::to_vec(&objects.values().collect::<Vec<_>>())?);
+        }
+        pw_dump()
+    }
+}
+
+impl Drop for Graph {
+    fn drop(&mut self) {
+        if let Some(child) = &self.child {
+            tools::kill(&mut child.lock().unwrap());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_updates() {
+        let mut objects = BTreeMap::new();
+        apply(
+            &mut objects,
+            serde_json::from_str(r#"[{ "id": 1, "info": {} }, { "id": 2, "metadata": [] }]"#)
+                .unwrap(),
+        );
+        apply(
+            &mut objects,
+            serde_json::from_str(r#"[{ "id": 1, "info": null }, { "id": 2, "metadata": [1] }]"#)
+                .unwrap(),
+        );
+        assert_eq!(
+            objects.into_iter().collect::<Vec<_>>(),
+            vec![(2, serde_json::json!({ "id": 2, "metadata": [1] }))]
+        );
+    }
+
+    #[test]
+    fn patch_route() {
+        let route = |mute, volume| serde_json::json!({ "index": 1, "device": 2, "props": { "mute": mute, "channelVolumes": [volume, volume] } });
+        let mut objects = BTreeMap::new();
+        objects.insert(
+            40,
+            serde_json::json!({ "id": 40, "info": { "params": { "Route": [route(false, 0.5)] } } }),
+        );
+        let change = serde_json::json!({ "index": 1, "device": 2, "props": { "mute": false, "channelVolumes": [0.6, 0.6] } });
+        apply_write(&mut objects, 40, "Route", &change);
+        assert_eq!(
+            objects[&40]["info"]["params"]["Route"][0],
+            route(false, 0.6)
+        );
+        let mute = serde_json::json!({ "index": 1, "device": 2, "props": { "mute": true, "channelVolumes": [] } });
+        apply_write(&mut objects, 40, "Route", &mute);
+        assert_eq!(objects[&40]["info"]["params"]["Route"][0], route(true, 0.6));
+        let other = serde_json::json!({ "index": 0, "device": 2, "props": { "mute": false, "channelVolumes": [0.1] } });
+        apply_write(&mut objects, 40, "Route", &other);
+        assert_eq!(objects[&40]["info"]["params"]["Route"][0], route(true, 0.6));
+    }
+
+    #[test]
+    fn replay_writes() {
+        let route = |volume| serde_json::json!({ "index": 1, "device": 2, "props": { "mute": false, "channelVolumes": [volume] } });
+        let device = |volume| serde_json::json!({ "id": 40, "info": { "params": { "Route": [route(volume)] } } });
+        let graph = Graph::replay();
+        graph.update(vec![device(0.5)]);
+        record(40, "Route", &route(0.3).to_string());
+        graph.update(Vec::new());
+        let dumped: Value = serde_json::from_slice(&graph.dump().unwrap()).unwrap();
+        assert_eq!(dumped, serde_json::json!([device(0.3)]));
+        // an update after the write wins over it
+        record(40, "Route", &route(0.2).to_string());
+        graph.update(vec![device(0.8)]);
+        let dumped: Value = serde_json::from_slice(&graph.dump().unwrap()).unwrap();
+        assert_eq!(dumped, serde_json::json!([device(0.8)]));
+    }
+
+    #[test]
+    fn merge_documents() -> anyhow::Result<()> {
+        let whole = br#"[{ "id": 1, "info": {} }]
+"#;
+        assert_eq!(merge(whole.to_vec())?, whole.to_vec());
+        let updated = br#"[{ "id": 1, "info": {} }, { "id": 2, "info": {} }]
+[{ "id": 2, "info": null }, { "id": 3, "info": {} }]
+[{ "id": 1, "info": nu"#;
+        let merged: Value = serde_json::from_slice(&merge(updated.to_vec())?)?;
+        assert_eq!(
+            merged,
+            serde_json::json!([{ "id": 1, "info": {} }, { "id": 3, "info": {} }])
+        );
+        Ok(())
+    }
+}
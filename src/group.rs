@@ -0,0 +1,83 @@
+//! Sync groups: sinks or sources listed together under `[groups]` in the
+//! config file, whose volumes move together with `change --group`.
+
+use anyhow::anyhow;
+use clap::ArgMatches;
+
+use crate::{
+    config::Config, node_id, parse_dump, pw_dump, set_route, unmarshal, volume_command, Target,
+};
+
+/// Changes every member's channel volumes by the same factor, chosen so the
+/// loudest channel moves by `delta`. Members keep their relative levels,
+/// except when the group is brought down to silence.
+fn scale(volumes: &[Vec<f64>], delta: f64) -> Vec<Vec<f64>> {
+    let loudest = volumes.iter().flatten().copied().fold(0.0, f64::max);
+    let target = (loudest + delta).clamp(0.0, 1.0);
+    volumes
+        .iter()
+        .map(|channels| {
+            channels
+                .iter()
+                .map(|vol| {
+                    if loudest > 0.0 {
+                        vol * target / loudest
+                    } else {
+                        // nothing to scale from, so start everyone level
+                        target
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub fn run(matches: &ArgMatches<'_>, config: &Config) -> anyhow::Result<()> {
+    let name = matches.value_of("group").unwrap_or_default();
+    let members = config
+        .groups
+        .get(name)
+        .ok_or_else(|| anyhow!("no group named {:?} in the config file", name))?;
+    let delta = matches
+        .value_of("DELTA")
+        .ok_or_else(|| anyhow!("DELTA argument not found"))?;
+    let delta = delta[..delta.len() - 1].parse::<f64>()? * 0.01;
+
+    let buf = pw_dump()?;
+    let obj = unmarshal(&buf)?;
+    let mut targets = Vec::with_capacity(members.len());
+    for member in members {
+        targets.push(parse_dump(&obj, Target::Id(node_id(&obj, member)?))?);
+    }
+    let volumes: Vec<Vec<f64>> = targets
+        .iter()
+        .map(|(_, route)| route.props.channel_volumes.clone())
+        .collect();
+    for ((node, route), channel_volumes) in targets.iter().zip(scale(&volumes, delta)) {
+        set_route(node, &volume_command(node, route, channel_volumes))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(&[vec![0.5, 0.5], vec![0.25]], 0.1 => vec![vec![0.6, 0.6], vec![0.3]])]
+    #[test_case(&[vec![0.8], vec![0.4]], 0.5 => vec![vec![1.0], vec![0.5]]; "clamped at full volume")]
+    #[test_case(&[vec![0.2], vec![0.1]], -0.5 => vec![vec![0.0], vec![0.0]]; "silenced")]
+    #[test_case(&[vec![0.0], vec![0.0]], 0.1 => vec![vec![0.1], vec![0.1]]; "from silence")]
+    fn scale_volumes(volumes: &[Vec<f64>], delta: f64) -> Vec<Vec<f64>> {
+        scale(volumes, delta)
+            .into_iter()
+            .map(|channels| {
+                channels
+                    .into_iter()
+                    .map(|vol| (vol * 1000.0).round() / 1000.0)
+                    .collect()
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,270 @@
+//! `pw-volume help <topic>`: worked examples for the parts of pw-volume that
+//! take more than a flag to learn. They are rendered by the same code that
+//! `status` and the daemon run, with the settings from the config file, so
+//! they show what this binary does rather than what a manual once said.
+
+use std::io::Write;
+
+use clap::{App, AppSettings, ArgMatches, SubCommand};
+
+use crate::{
+    config::Config,
+    daemon,
+    format::{Fade, Format, IconPreset, Icons, Status, Style},
+    output::Output,
+    template::PLACEHOLDERS,
+};
+
+/// The rules the daemon can keep in force, by the name its hello reports
+/// them under, with what each does and a config file entry that turns it on.
+const RULES: [(&str, &str, &str); 7] = [
+    (
+        "ptt-failsafe",
+        "mutes the microphone again if `ptt release` never arrives",
+        "[ptt]\ntimeout = 60  # seconds",
+    ),
+    (
+        "offsets",
+        "holds a follower device at a fixed level in dB relative to a leader",
+        "[[offsets]]\nleader = \"alsa_output.pci-0000_00_1f.3.analog-surround-40\"\nfollower = \"alsa_output.usb-Generic_USB_Audio-00.analog-stereo\"\ndb = -6",
+    ),
+    (
+        "mic-lock",
+        "holds the default source at a volume, undoing changes made by applications",
+        "[mic-lock]\nvolume = 80  # percent\nallow = [\"pavucontrol\"]",
+    ),
+    (
+        "focus",
+        "makes the default sink follow the focused sway or i3 workspace or output",
+        "[[focus]]\noutput = \"DP-2\"\nsink = \"alsa_output.usb-Generic_USB_Audio-00.analog-stereo\"",
+    ),
+    (
+        "privacy",
+        "announces, and can mute, applications that start recording",
+        "[privacy]\nnotify = true\nallow = [\"obs\", \"Mumble\"]",
+    ),
+    (
+        "restore",
+        "puts a sink back at its last volume when it becomes the default again",
+        "[restore]\ndevices = [\"bluez_output\"]  # every sink if left out",
+    ),
+    (
+        "limiter",
+        "brings a sink that jumps past a threshold at once smoothly back down",
+        "[limiter]\nthreshold = 80  # percent\njump = 20\nwindow-ms = 500\nfade-ms = 1000",
+    ),
+];
+
+/// The names `--output` takes.
+const OUTPUTS: [&str; 9] = [
+    "polybar",
+    "i3blocks",
+    "xmobar",
+    "conky",
+    "genmon",
+    "plain",
+    "csv",
+    "tsv",
+    "verbose-json",
+];
+
+/// The names `--icons` takes.
+const PRESETS: [&str; 3] = ["nerd-font", "emoji", "ascii"];
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("help")
+        .about("explains the daemon's rules, the icon presets, or the status formats with examples")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::DisableHelpSubcommand)
+        .subcommand(
+            SubCommand::with_name("rules")
+                .about("the rules the daemon keeps in force, and which of them are on"),
+        )
+        .subcommand(
+            SubCommand::with_name("presets")
+                .about("the icon presets, and how to override their glyphs"),
+        )
+        .subcommand(
+            SubCommand::with_name("formats")
+                .about("status formats, outputs, and template placeholders"),
+        )
+}
+
+/// What the examples show the status of: a sink at 42%, a quarter of the
+/// way through a fade to 60%.
+fn sample() -> Status {
+    Status {
+        id: 53,
+        volume: 0.42,
+        name: "Built-in Audio Analog Stereo".to_string(),
+        node: "alsa_output.pci-0000_00_1f.3.analog-stereo".to_string(),
+        port: Some("Speakers".to_string()),
+        channels: 2,
+        device: Some("Built-in Audio".to_string()),
+        channel_map: vec!["FL".to_string(), "FR".to_string()],
+        channel_volumes: vec![0.42, 0.42],
+        volume_base: Some(1.0),
+        fade: Some(Fade {
+            target: 0.6,
+            progress: 0.25,
+            remaining: 1.5,
+        }),
+        ..Status::default()
+    }
+}
+
+/// Indents a block of text under a heading.
+fn indent(text: &str) -> String {
+    text.lines().map(|line| format!("    {}\n", line)).collect()
+}
+
+fn rules(config: &Config, out: &mut dyn Write) -> anyhow::Result<()> {
+    writeln!(
+        out,
+        "`pw-volume daemon` keeps these rules in force while it runs, each turned on\n\
+         by an entry in the config file; restart it after editing.\n"
+    )?;
+    let configured = daemon::rules(config);
+    for (name, about, example) in RULES {
+        let on = configured.iter().any(|(rule, on)| *rule == name && *on);
+        writeln!(
+            out,
+            "{} ({}): {}",
+            name,
+            if on { "on" } else { "off" },
+            about
+        )?;
+        writeln!(out, "{}", indent(example))?;
+    }
+    Ok(())
+}
+
+fn presets(config: &Config, out: &mut dyn Write) -> anyhow::Result<()> {
+    writeln!(
+        out,
+        "`--icons`, or `preset` under `[icons]`, picks the glyphs for muted and for\n\
+         each volume tier, from quiet to loud:\n"
+    )?;
+    let mut status = sample();
+    for name in PRESETS {
+        let icons = Icons::preset(name.parse().map_err(anyhow::Error::msg)?);
+        let marker = if name.parse::<IconPreset>().ok() == Some(config.icons.preset) {
+            " (configured)"
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "    {:<10} muted {}  levels {}{}",
+            name,
+            icons.mute,
+            icons.levels.join(" "),
+            marker
+        )?;
+    }
+    writeln!(
+        out,
+        "\n`mute` and `levels` replace a preset's glyphs; there may be any number of\n\
+         levels, which split the volume evenly:\n"
+    )?;
+    let example =
+        "[icons]\npreset = \"ascii\"\nmute = \"x\"\nlevels = [\".\", \"o\", \"O\", \"@\"]";
+    writeln!(out, "{}", indent(example))?;
+    let icons: Config = toml::from_str(example)?;
+    let icons = icons.icons.icons();
+    write!(out, "which shows")?;
+    for volume in [0.1, 0.3, 0.6, 0.9] {
+        status.volume = volume;
+        write!(out, " {} at {:.0}%,", icons.icon(&status), volume * 100.0)?;
+    }
+    status.mute = true;
+    writeln!(out, " and {} while muted.", icons.icon(&status))?;
+    Ok(())
+}
+
+fn formats(config: &Config, out: &mut dyn Write) -> anyhow::Result<()> {
+    let status = sample();
+    let style = Style {
+        icons: config.icons.icons(),
+        thresholds: config.thresholds,
+    };
+    writeln!(out, "`status --format` takes a format by name:\n")?;
+    for name in ["waybar", "text", "a11y"] {
+        let format: Format = name.parse().map_err(anyhow::Error::msg)?;
+        writeln!(out, "    {:<8} {}", name, format.render(&status, &style))?;
+    }
+    writeln!(
+        out,
+        "\nor a template, whose placeholders are replaced by the node's values, here\n\
+         for a sink fading from 42% to 60%, and where {{key:a|b}} prints a when the\n\
+         value is set and b otherwise. The fade_ values are empty while no fade runs,\n\
+         and battery for devices that don't report one:\n"
+    )?;
+    for key in PLACEHOLDERS {
+        let template: Format = format!("{{{}}}", key).parse().map_err(anyhow::Error::msg)?;
+        let key = format!("{{{}}}", key);
+        writeln!(out, "    {:<16} {}", key, template.render(&status, &style))?;
+    }
+    let example = "{icon} {percentage}%{mute: (muted)|}";
+    let template: Format = example.parse().map_err(anyhow::Error::msg)?;
+    writeln!(
+        out,
+        "\n    --format '{}'\n    {}\n",
+        example,
+        template.render(&status, &style)
+    )?;
+    writeln!(
+        out,
+        "`--output` wraps the text format, or a template, for a bar other than Waybar:\n"
+    )?;
+    let label = Format::Text.render(&status, &style);
+    for name in OUTPUTS {
+        let output: Output = name.parse().map_err(anyhow::Error::msg)?;
+        let rendered = output.render(&status, &label, config);
+        let mut lines = rendered.lines();
+        writeln!(out, "    {:<12} {}", name, lines.next().unwrap_or_default())?;
+        for line in lines {
+            writeln!(out, "    {:<12} {}", "", line)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn run(matches: &ArgMatches<'_>, config: &Config, out: &mut dyn Write) -> anyhow::Result<()> {
+    match matches.subcommand_name() {
+        Some("rules") => rules(config, out),
+        Some("presets") => presets(config, out),
+        Some("formats") => formats(config, out),
+        _ => unreachable!("argument parsing should have failed by now"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rule_examples() -> anyhow::Result<()> {
+        for (name, _, example) in RULES {
+            let config: Config = toml::from_str(example)?;
+            let on = daemon::rules(&config)
+                .iter()
+                .any(|(rule, on)| *rule == name && *on);
+            assert!(on, "the example for {} doesn't turn it on", name);
+        }
+        assert_eq!(daemon::rules(&Config::default()).len(), RULES.len());
+        Ok(())
+    }
+
+    #[test]
+    fn every_output() -> anyhow::Result<()> {
+        let mut out = Vec::new();
+        formats(&Config::default(), &mut out)?;
+        let out = String::from_utf8(out)?;
+        for name in OUTPUTS {
+            assert!(out.contains(name), "{} is missing", name);
+        }
+        assert!(out.contains("{fade_target}    60"));
+        Ok(())
+    }
+}
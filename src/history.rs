@@ -0,0 +1,201 @@
+//! The state history: for each change made in the mixer, what it altered
+//! was before, so that `u` can put it back, in the same session or a later
+//! one. It is kept in `$XDG_STATE_HOME/pw-volume/history.json`, and names
+//! nodes and devices rather than giving their ids, which change when
+//! PipeWire restarts.
+
+use std::{fs, io, path::PathBuf};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::state_dir, move_stream, node_id, parse_dump, port, profile, pw_dump, route_command,
+    set_default, set_route, unmarshal, PipeWireObject, Target, VolumeOp,
+};
+
+/// Number of changes that can be undone.
+const LIMIT: usize = 100;
+
+/// What a node or device was before a change.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Restore {
+    /// A device's volume, on its scale, and mute state.
+    Route {
+        node: String,
+        volume: f64,
+        mute: bool,
+    },
+    /// The device that was the default sink or source.
+    Default { node: String },
+    /// The device a stream was linked to. Streams of the same application
+    /// may share a name, so the id is tried first.
+    Stream {
+        stream: String,
+        id: i64,
+        device: String,
+    },
+    /// A sound card's active profile, by index.
+    Profile { device: String, index: i64 },
+}
+
+impl Restore {
+    /// Puts the node or device back the way it was.
+    pub fn apply(&self) -> anyhow::Result<()> {
+        let buf = pw_dump()?;
+        let obj = unmarshal(&buf)?;
+        match self {
+            Restore::Route { node, volume, mute } => {
+                let (node, route) = parse_dump(&obj, Target::Id(node_id(&obj, node)?))?;
+                let mut cmd = route_command(VolumeOp::Set(*volume), node, route);
+                cmd.props.mute = *mute;
+                set_route(node, &cmd)
+            }
+            Restore::Default { node } => {
+                let (node, _) = parse_dump(&obj, Target::Id(node_id(&obj, node)?))?;
+                set_default(node)
+            }
+            Restore::Stream { stream, id, device } => {
+                let ids: Vec<i64> = obj
+                    .iter()
+                    .filter_map(|o| match o {
+                        PipeWireObject::Stream(s) if s.info.props.node_name == stream.as_str() => {
+                            Some(s.id)
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let id = if ids.contains(id) {
+                    *id
+                } else {
+                    *ids.first().ok_or_else(|| anyhow!("{} has gone", stream))?
+                };
+                let (device, _) = parse_dump(&obj, Target::Id(node_id(&obj, device)?))?;
+                move_stream(id, device)
+            }
+            Restore::Profile { device, index } => {
+                let device = port::device(&obj, Some(device))?;
+                profile::switch(device, profile::profile(device, &index.to_string())?)
+            }
+        }
+    }
+}
+
+/// The changes that can be undone, oldest first, each with what it altered.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+struct History {
+    changes: Vec<Vec<Restore>>,
+}
+
+impl History {
+    fn path() -> Option<PathBuf> {
+        state_dir().map(|dir| dir.join("history.json"))
+    }
+
+    fn load() -> anyhow::Result<History> {
+        let path = match History::path() {
+            Some(path) => path,
+            None => return Ok(History::default()),
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("failed to parse {}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(History::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let path = match History::path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, serde_json::to_string(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Adds a change, forgetting the oldest past [`LIMIT`].
+    fn push(&mut self, change: Vec<Restore>) {
+        if self.changes.len() == LIMIT {
+            self.changes.remove(0);
+        }
+        self.changes.push(change);
+    }
+}
+
+/// Records what a change altered. The file is read again each time, so that
+/// two mixers open at once share one history.
+pub fn record(change: Vec<Restore>) -> anyhow::Result<()> {
+    if change.is_empty() {
+        return Ok(());
+    }
+    let mut history = History::load()?;
+    history.push(change);
+    history.save()
+}
+
+/// Undoes the most recent change, returning what it put back, or `None` if
+/// there was nothing to undo. The change is forgotten even if putting it
+/// back fails, e.g. because its device has gone, so that the one before can
+/// still be undone.
+pub fn undo() -> anyhow::Result<Option<Vec<Restore>>> {
+    let mut history = History::load()?;
+    let change = match history.changes.pop() {
+        Some(change) => change,
+        None => return Ok(None),
+    };
+    history.save()?;
+    for restore in change.iter().rev() {
+        restore.apply()?;
+    }
+    Ok(Some(change))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_round_trip() -> anyhow::Result<()> {
+        let mut history = History::default();
+        history.push(vec![Restore::Route {
+            node: "alsa_output.pci-0000_00_1f.3.analog-stereo".to_string(),
+            volume: 0.4,
+            mute: false,
+        }]);
+        history.push(vec![Restore::Profile {
+            device: "alsa_card.pci-0000_00_1f.3".to_string(),
+            index: 1,
+        }]);
+        let saved = serde_json::to_string(&history)?;
+        assert_eq!(
+            saved,
+            r#"{"changes":[[{"kind":"route","node":"alsa_output.pci-0000_00_1f.3.analog-stereo","volume":0.4,"mute":false}],[{"kind":"profile","device":"alsa_card.pci-0000_00_1f.3","index":1}]]}"#
+        );
+        assert_eq!(serde_json::from_str::<History>(&saved)?, history);
+        Ok(())
+    }
+
+    #[test]
+    fn forgets_the_oldest() {
+        let mut history = History::default();
+        for index in 0..=LIMIT as i64 {
+            history.push(vec![Restore::Profile {
+                device: "card".to_string(),
+                index,
+            }]);
+        }
+        assert_eq!(history.changes.len(), LIMIT);
+        assert_eq!(
+            history.changes[0],
+            [Restore::Profile {
+                device: "card".to_string(),
+                index: 1
+            }]
+        );
+    }
+}
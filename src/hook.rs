@@ -0,0 +1,514 @@
+//! The `on-change` hook: a command run with `sh -c` after `change` or `mute`
+//! succeeds, with the new state in its environment, for what pw-volume has no
+//! integration of its own for, such as an OSD, a keyboard's mute LED, or a
+//! log. `--exec` runs another for one change, after the configured one.
+//!
+//! Every hook, `[privacy]`'s included, is run as `[hooks]` says: with only
+//! the variables it needs from pw-volume's environment, killed if it outlasts
+//! its timeout, and optionally isolated by systemd-run or bubblewrap. The
+//! ones that fail are counted, and `daemon --status` shows them.
+//!
+//! `[[hooks.on-change]]` declares hooks with conditions instead, such as only
+//! when the mute state changed, or at most once a second, so that a
+//! notification script doesn't have to keep its own state to rate-limit
+//! itself. What each last ran for is kept in
+//! `$XDG_RUNTIME_DIR/pw-volume/hooks.json`, which the command line and the
+//! daemon share.
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::PathBuf,
+    process::Command,
+    sync::{atomic::Ordering, Mutex, OnceLock},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context};
+use clap::{Arg, ArgMatches};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::runtime_dir, display_percent, dry_run, format::Status, log, shell_words, tools, DRY_RUN,
+};
+
+/// Volumes closer than this, in percent, are taken to be the same.
+const EPSILON: f64 = 0.01;
+
+/// The variables a hook is given from pw-volume's environment, besides its
+/// own and those in `pass-env`: enough to find programs, and to reach the
+/// desktop and the session bus.
+const PASS: [&str; 9] = [
+    "PATH",
+    "HOME",
+    "USER",
+    "LANG",
+    "LC_ALL",
+    "XDG_RUNTIME_DIR",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "WAYLAND_DISPLAY",
+    "DISPLAY",
+];
+
+/// The `[hooks]` section of the config file.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct HooksConfig {
+    /// How long a hook may run before it's killed, 10 seconds if unset.
+    pub timeout_ms: Option<u64>,
+    pub sandbox: Sandbox,
+    /// More variables to pass on from pw-volume's environment.
+    pub pass_env: Vec<String>,
+    /// `on-change` hooks with conditions.
+    pub on_change: Vec<Hook>,
+}
+
+/// A `[[hooks.on-change]]` entry: a command run after `change` or `mute`
+/// when its conditions hold.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Hook {
+    pub run: String,
+    #[serde(default)]
+    pub when: When,
+    /// How far, in percent, the volume must have moved since the hook last
+    /// ran.
+    #[serde(default)]
+    pub min_delta: f64,
+    /// How long after running the hook skips changes.
+    #[serde(default)]
+    pub min_interval_ms: u64,
+}
+
+/// What must have changed for a hook to run.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum When {
+    /// The mute state or the volume.
+    #[default]
+    Any,
+    Mute,
+    Volume,
+}
+
+/// What a hook last ran for on one node: the volume in percent, the mute
+/// state, and when, in milliseconds since the epoch.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+struct Seen {
+    volume: f64,
+    mute: bool,
+    at_ms: u64,
+}
+
+/// What each hook last ran for, by its command and then by node name.
+type Ran = BTreeMap<String, BTreeMap<String, Seen>>;
+
+impl Hook {
+    /// Whether the hook runs for a change from `before`, if it is known, to
+    /// `after`, given what it last ran for.
+    fn due(&self, seen: Option<&Seen>, before: Option<(f64, bool)>, after: &Seen) -> bool {
+        if seen.is_some_and(|seen| after.at_ms.saturating_sub(seen.at_ms) < self.min_interval_ms) {
+            return false;
+        }
+        let baseline = seen.map(|seen| (seen.volume, seen.mute)).or(before);
+        let (volume, mute) = match baseline {
+            Some(baseline) => baseline,
+            None => return true,
+        };
+        // the change itself says whether it muted, even if the hook last ran
+        // before something else did
+        let muted = before.map_or(mute, |(_, mute)| mute) != after.mute;
+        let moved = (after.volume - volume).abs();
+        let moved = moved > EPSILON && moved + EPSILON >= self.min_delta;
+        match self.when {
+            When::Any => muted || moved,
+            When::Mute => muted,
+            When::Volume => moved,
+        }
+    }
+}
+
+fn ran_path() -> Option<PathBuf> {
+    runtime_dir().map(|dir| dir.join("hooks.json"))
+}
+
+fn load_ran() -> Ran {
+    ran_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn save_ran(ran: &Ran) -> anyhow::Result<()> {
+    let path = ran_path().ok_or_else(|| anyhow!("failed to determine runtime directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, serde_json::to_string(ran)?)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// What a hook is isolated with.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Sandbox {
+    /// Run as a child of pw-volume.
+    #[default]
+    None,
+    /// Run as a transient systemd user unit, with the system read-only and
+    /// home read-only, and stopped by systemd once the timeout is up.
+    SystemdRun,
+    /// Run in bubblewrap's namespaces, with the file system read-only, a
+    /// private `/tmp`, and no network.
+    Bubblewrap,
+}
+
+static HOOKS: OnceLock<HooksConfig> = OnceLock::new();
+
+/// Sets how hooks are run for the rest of the process.
+pub fn set(config: HooksConfig) {
+    let _ = HOOKS.set(config);
+}
+
+/// The hooks that failed in this process, and how the last one did.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Failures {
+    pub count: u64,
+    pub last: Option<String>,
+}
+
+static FAILURES: Mutex<Failures> = Mutex::new(Failures {
+    count: 0,
+    last: None,
+});
+
+pub fn failures() -> Failures {
+    FAILURES.lock().map(|f| f.clone()).unwrap_or_default()
+}
+
+fn fail(message: String) {
+    log::debug(1, format_args!("{}", message));
+    if let Ok(mut failures) = FAILURES.lock() {
+        failures.count += 1;
+        failures.last = Some(message);
+    }
+}
+
+/// `--exec`, shared by `change` and `mute`.
+pub fn arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("exec")
+        .long("exec")
+        .value_name("COMMAND")
+        .help("run COMMAND with sh after the change, with PWV_VOLUME, PWV_MUTED, and PWV_NODE set, as on-change in the config file does")
+        .takes_value(true)
+}
+
+/// The hooks to run: `on-change`, then `--exec`.
+pub fn commands<'a>(on_change: Option<&'a str>, arg: &'a ArgMatches<'_>) -> Vec<&'a str> {
+    on_change.into_iter().chain(arg.value_of("exec")).collect()
+}
+
+/// The variables an `on-change` hook is run with.
+fn env(status: &Status) -> [(&'static str, String); 5] {
+    [
+        ("PWV_VOLUME", display_percent(status.volume).to_string()),
+        ("PWV_MUTED", status.mute.to_string()),
+        ("PWV_NODE", status.node.clone()),
+        ("PWV_NODE_ID", status.id.to_string()),
+        ("PWV_DESCRIPTION", status.name.clone()),
+    ]
+}
+
+/// The environment a hook is run with: what it is passed from pw-volume's,
+/// then its own variables.
+fn restricted(
+    config: &HooksConfig,
+    lookup: impl Fn(&str) -> Option<String>,
+    vars: &[(&str, String)],
+) -> Vec<(String, String)> {
+    let passed = PASS
+        .iter()
+        .copied()
+        .chain(config.pass_env.iter().map(String::as_str))
+        .filter_map(|name| Some((name.to_string(), lookup(name)?)));
+    passed
+        .chain(vars.iter().map(|(k, v)| (k.to_string(), v.clone())))
+        .collect()
+}
+
+/// The command that runs `hook` in the configured sandbox, with only
+/// `environment` set.
+fn command(
+    config: &HooksConfig,
+    hook: &str,
+    environment: &[(String, String)],
+    timeout: Duration,
+) -> Command {
+    let mut command = match config.sandbox {
+        Sandbox::None => Command::new("sh"),
+        Sandbox::SystemdRun => {
+            let mut command = Command::new("systemd-run");
+            command.args([
+                "--user",
+                "--quiet",
+                "--wait",
+                "--collect",
+                "--property=NoNewPrivileges=yes",
+                "--property=ProtectSystem=strict",
+                "--property=ProtectHome=read-only",
+            ]);
+            // systemd-run hands its own environment to the manager, not the
+            // unit, and killing it would leave the unit running
+            command.arg(format!(
+                "--property=RuntimeMaxSec={}ms",
+                timeout.as_millis()
+            ));
+            command.args(
+                environment
+                    .iter()
+                    .map(|(k, v)| format!("--setenv={}={}", k, v)),
+            );
+            command.args(["--", "sh"]);
+            command
+        }
+        Sandbox::Bubblewrap => {
+            let mut command = Command::new("bwrap");
+            command.args([
+                "--ro-bind",
+                "/",
+                "/",
+                "--dev",
+                "/dev",
+                "--proc",
+                "/proc",
+                "--tmpfs",
+                "/tmp",
+                "--unshare-all",
+                "--die-with-parent",
+                "--new-session",
+                "--",
+                "sh",
+            ]);
+            command
+        }
+    };
+    command
+        .args(["-c", hook])
+        .env_clear()
+        .envs(environment.iter().map(|(k, v)| (k, v)));
+    command
+}
+
+/// Runs one hook and waits for it, counting it if it fails.
+fn run_one(hook: &str, vars: &[(&str, String)]) {
+    let config = HOOKS.get().cloned().unwrap_or_default();
+    let timeout = config
+        .timeout_ms
+        .map_or(Duration::from_secs(10), Duration::from_millis);
+    let environment = restricted(&config, |name| env::var(name).ok(), vars);
+    let mut command = command(&config, hook, &environment, timeout);
+    let program = command.get_program().to_string_lossy().into_owned();
+    let args: Vec<_> = command
+        .get_args()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let args: Vec<_> = args.iter().map(String::as_str).collect();
+    log::debug(1, format_args!("running {}", shell_words(&program, &args)));
+    // systemd stops the unit itself, a little before pw-volume gives up
+    let wait = match config.sandbox {
+        Sandbox::SystemdRun => timeout + Duration::from_secs(1),
+        _ => timeout,
+    };
+    match tools::status_within(&mut command, wait) {
+        Ok(status) if status.success() => (),
+        Ok(status) => fail(format!("the hook `{}` exited with {}", hook, status)),
+        Err(e) => fail(format!("the hook `{}` failed: {}", hook, e)),
+    }
+}
+
+/// Whether there are hooks to run after a change, so that its result has to
+/// be read back.
+pub fn any(commands: &[&str]) -> bool {
+    !commands.is_empty()
+        || HOOKS
+            .get()
+            .is_some_and(|config| !config.on_change.is_empty())
+}
+
+/// Runs each hook in turn, waiting for it, so that what it does is done by
+/// the time pw-volume exits, and then the `[[hooks.on-change]]` whose
+/// conditions hold for the change from `before`. A failing hook doesn't fail
+/// the change.
+pub fn run(commands: &[&str], before: Option<&Status>, status: &Status) {
+    if DRY_RUN.load(Ordering::Relaxed) {
+        return;
+    }
+    let vars = env(status);
+    for command in commands {
+        run_one(command, &vars);
+    }
+    let hooks = match HOOKS.get() {
+        Some(config) if !config.on_change.is_empty() => &config.on_change,
+        _ => return,
+    };
+    let after = Seen {
+        volume: display_percent(status.volume),
+        mute: status.mute,
+        at_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64),
+    };
+    let before = before.map(|before| (display_percent(before.volume), before.mute));
+    let mut ran = load_ran();
+    for hook in hooks {
+        let seen = ran.get(&hook.run).and_then(|nodes| nodes.get(&status.node));
+        if !hook.due(seen, before, &after) {
+            log::debug(1, format_args!("skipping the hook `{}`", hook.run));
+            // a volume that moves in small steps is measured from where it
+            // started, not from each step
+            if let (None, Some((volume, mute))) = (seen, before) {
+                let baseline = Seen {
+                    volume,
+                    mute,
+                    at_ms: 0,
+                };
+                ran.entry(hook.run.clone())
+                    .or_default()
+                    .insert(status.node.clone(), baseline);
+            }
+            continue;
+        }
+        run_one(&hook.run, &vars);
+        ran.entry(hook.run.clone())
+            .or_default()
+            .insert(status.node.clone(), after);
+    }
+    if let Err(e) = save_ran(&ran) {
+        log::debug(1, format_args!("{:#}", e));
+    }
+}
+
+/// Runs a hook on a thread of its own, for the daemon, which shouldn't wait
+/// for it.
+pub fn spawn(hook: String, vars: Vec<(&'static str, String)>) {
+    if dry_run("sh", &["-c", &hook]) {
+        return;
+    }
+    thread::spawn(move || run_one(&hook, &vars));
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test]
+    fn environment() {
+        let status = Status {
+            id: 69,
+            volume: 0.375,
+            mute: true,
+            name: "Galaxy Buds+ (8CFA)".to_string(),
+            node: "bluez_output.18_54_CF_71_8C_FA.a2dp-sink".to_string(),
+            ..Status::default()
+        };
+        let env: Vec<_> = env(&status)
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        assert_eq!(
+            env,
+            [
+                "PWV_VOLUME=37.5",
+                "PWV_MUTED=true",
+                "PWV_NODE=bluez_output.18_54_CF_71_8C_FA.a2dp-sink",
+                "PWV_NODE_ID=69",
+                "PWV_DESCRIPTION=Galaxy Buds+ (8CFA)",
+            ]
+        );
+    }
+
+    #[test_case(When::Any, 0.0, 0, None, Some((40.0, false)), 40.0, false => false; "nothing changed")]
+    #[test_case(When::Any, 0.0, 0, None, None, 40.0, false => true; "never ran, from the fallback")]
+    #[test_case(When::Mute, 0.0, 0, None, Some((40.0, false)), 45.0, false => false; "volume only")]
+    #[test_case(When::Mute, 0.0, 0, Some((45.0, false)), Some((40.0, false)), 40.0, true => true; "muted")]
+    #[test_case(When::Volume, 5.0, 0, Some((40.0, false)), Some((42.0, false)), 44.0, false => false; "too small")]
+    #[test_case(When::Volume, 5.0, 0, Some((40.0, false)), Some((44.0, false)), 46.0, false => true; "added up")]
+    #[test_case(When::Any, 0.0, 1000, Some((40.0, false)), Some((40.0, false)), 50.0, true => false; "too soon")]
+    fn conditions(
+        when: When,
+        min_delta: f64,
+        min_interval_ms: u64,
+        seen: Option<(f64, bool)>,
+        before: Option<(f64, bool)>,
+        volume: f64,
+        mute: bool,
+    ) -> bool {
+        let hook = Hook {
+            run: "osd.sh".to_string(),
+            when,
+            min_delta,
+            min_interval_ms,
+        };
+        // the hook last ran half a second ago
+        let seen = seen.map(|(volume, mute)| Seen {
+            volume,
+            mute,
+            at_ms: 10_000,
+        });
+        let after = Seen {
+            volume,
+            mute,
+            at_ms: 10_500,
+        };
+        hook.due(seen.as_ref(), before, &after)
+    }
+
+    #[test]
+    fn restricted_environment() {
+        let config = HooksConfig {
+            pass_env: vec!["SWAYSOCK".to_string()],
+            ..HooksConfig::default()
+        };
+        let lookup = |name: &str| match name {
+            "PATH" => Some("/usr/bin".to_string()),
+            "SWAYSOCK" => Some("/run/user/1000/sway.sock".to_string()),
+            "AWS_SECRET_ACCESS_KEY" => Some("hunter2".to_string()),
+            _ => None,
+        };
+        let environment = restricted(&config, lookup, &[("PWV_MUTED", "true".to_string())]);
+        let environment: Vec<_> = environment
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        assert_eq!(
+            environment,
+            [
+                "PATH=/usr/bin",
+                "SWAYSOCK=/run/user/1000/sway.sock",
+                "PWV_MUTED=true",
+            ]
+        );
+    }
+
+    #[test_case(Sandbox::None => "sh -c osd.sh")]
+    #[test_case(Sandbox::SystemdRun => "systemd-run --user --quiet --wait --collect --property=NoNewPrivileges=yes --property=ProtectSystem=strict --property=ProtectHome=read-only --property=RuntimeMaxSec=2500ms --setenv=PWV_MUTED=true -- sh -c osd.sh")]
+    #[test_case(Sandbox::Bubblewrap => "bwrap --ro-bind / / --dev /dev --proc /proc --tmpfs /tmp --unshare-all --die-with-parent --new-session -- sh -c osd.sh")]
+    fn sandboxes(sandbox: Sandbox) -> String {
+        let config = HooksConfig {
+            sandbox,
+            ..HooksConfig::default()
+        };
+        let environment = [("PWV_MUTED".to_string(), "true".to_string())];
+        let command = command(&config, "osd.sh", &environment, Duration::from_millis(2500));
+        let args: Vec<_> = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let mut words = vec![command.get_program().to_string_lossy().into_owned()];
+        words.extend(args);
+        words.join(" ")
+    }
+}
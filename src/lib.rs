@@ -0,0 +1,1310 @@
+//! The logic behind the `pw-volume` command, for programs that want to read
+//! or change PipeWire volumes without running it, such as a status bar
+//! written in Rust. It still talks to PipeWire through `pw-dump` and
+//! `pw-cli`.
+//!
+//! [`status`] reads a node's [`Status`], and [`execute`] applies a
+//! [`VolumeOp`] to it, each picking the node by [`Target`]:
+//!
+//! ```no_run
+//! use pw_volume::{Target, VolumeOp};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let status = pw_volume::status(Target::DefaultSink)?;
+//! println!("{} is at {:.0}%", status.name, status.percentage());
+//! pw_volume::execute(Target::DefaultSink, "+5%".parse().map_err(anyhow::Error::msg)?)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! For more than one read from the same graph, [`pw_dump`] returns the
+//! graph, which deserializes into [`PipeWireObject`]s, and [`parse_dump`]
+//! finds a node and its device's active route in it, from which
+//! [`route_command`] builds the command that [`set_route`] sends.
+
+use anyhow::{anyhow, ensure, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+mod agc;
+mod apply;
+mod backend;
+mod bar;
+mod bluetooth;
+mod calibrate;
+mod channelmix;
+#[doc(hidden)]
+pub mod cli;
+mod commands;
+mod compress;
+mod config;
+mod cork;
+mod daemon;
+mod dbus;
+mod delay;
+mod doctor;
+mod error;
+mod events;
+mod fade;
+mod feedback;
+mod filter;
+mod focus;
+mod format;
+mod graph;
+mod group;
+mod help;
+mod history;
+mod hook;
+mod limiter;
+mod log;
+mod mixer;
+mod monitor;
+#[cfg(feature = "native")]
+mod native;
+mod notify;
+mod output;
+mod pactl;
+mod pamixer;
+mod port;
+mod preset;
+mod privacy;
+mod profile;
+mod ptt;
+mod record;
+mod render;
+mod reset;
+mod retry;
+mod scene;
+mod service;
+mod stats;
+mod template;
+mod theme;
+mod tools;
+mod update;
+mod wait;
+mod watch;
+mod wpctl;
+
+use calibrate::Trims;
+use cli::{
+    app, change_delta, check_expected, degraded_status, pw_cli, query, render_statuses,
+    status_args, statuses, target,
+};
+use config::{DeviceConfig, Scale, VolumeConfig};
+use error::{Failure, Kind};
+pub use format::{Fade, Status};
+
+/// An object in pw-dump's output, borrowing from it. Objects of other kinds
+/// are left out.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum PipeWireObject<'a> {
+    #[serde(borrow)]
+    Metadata(PipeWireInterfaceMetadata<'a>),
+
+    #[serde(borrow)]
+    Node(PipeWireInterfaceNode<'a>),
+
+    #[serde(borrow)]
+    Device(PipeWireInterfaceDevice<'a>),
+
+    #[serde(borrow)]
+    Stream(PipeWireInterfaceStream<'a>),
+
+    #[serde(borrow)]
+    Link(PipeWireInterfaceLink<'a>),
+    Value(Value),
+}
+
+/// An application's playback or recording stream, i.e. a node whose media
+/// class starts with `Stream/`.
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct PipeWireInterfaceStream<'a> {
+    id: i64,
+
+    #[serde(rename = "type")]
+    typ: &'a str,
+
+    #[serde(borrow)]
+    info: StreamInfo<'a>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct StreamInfo<'a> {
+    /// `running` while audio flows, `idle` or `suspended` otherwise.
+    state: Option<&'a str>,
+
+    #[serde(borrow)]
+    props: StreamProps<'a>,
+
+    #[serde(default)]
+    params: StreamParams,
+}
+
+/// A stream's own volume, which applications and pavucontrol set.
+#[derive(Deserialize, Debug, Default, PartialEq)]
+struct StreamParams {
+    #[serde(rename = "Props", default)]
+    props: Vec<NodeProp>,
+}
+
+impl StreamParams {
+    fn volume(&self) -> Option<&NodePropVolume> {
+        self.props.iter().find_map(|prop| match prop {
+            NodeProp::Volume(v) => Some(v),
+            NodeProp::Value(_) => None,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct StreamProps<'a> {
+    #[serde(rename = "media.class")]
+    media_class: &'a str,
+
+    #[serde(borrow)]
+    #[serde(rename = "node.name")]
+    node_name: Cow<'a, str>,
+
+    #[serde(borrow)]
+    #[serde(rename = "application.name")]
+    application_name: Option<Cow<'a, str>>,
+
+    #[serde(borrow)]
+    #[serde(rename = "target.object")]
+    target_object: Option<Cow<'a, str>>,
+}
+
+/// A link from one node's output port to another's input.
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct PipeWireInterfaceLink<'a> {
+    id: i64,
+
+    #[serde(rename = "type")]
+    typ: &'a str,
+
+    info: LinkInfo,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct LinkInfo {
+    #[serde(rename = "output-node-id")]
+    output_node_id: i64,
+
+    #[serde(rename = "input-node-id")]
+    input_node_id: i64,
+}
+
+/// A device, e.g. a sound card, whose routes hold its sinks' and sources'
+/// volumes.
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct PipeWireInterfaceDevice<'a> {
+    id: i64,
+
+    #[serde(rename = "type")]
+    typ: &'a str,
+
+    #[serde(borrow)]
+    info: DeviceInfo<'a>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct DeviceInfo<'a> {
+    #[serde(borrow)]
+    props: Option<DeviceProps<'a>>,
+
+    #[serde(borrow)]
+    params: DeviceParams<'a>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct DeviceProps<'a> {
+    #[serde(borrow)]
+    #[serde(rename = "device.description")]
+    device_description: Option<Cow<'a, str>>,
+
+    /// e.g. `alsa_card.pci-0000_00_1f.3`.
+    #[serde(borrow)]
+    #[serde(rename = "device.name")]
+    device_name: Option<Cow<'a, str>>,
+
+    /// e.g. `alsa` or `bluez5`.
+    #[serde(rename = "device.api")]
+    device_api: Option<&'a str>,
+
+    /// A Bluetooth device's battery level in percent, where it reports one,
+    /// as a number or a string.
+    #[serde(rename = "api.bluez5.battery")]
+    battery: Option<Value>,
+}
+
+/// A device's params. A device switched off lists no routes.
+#[derive(Deserialize, Debug, PartialEq)]
+struct DeviceParams<'a> {
+    #[serde(borrow)]
+    #[serde(rename = "Route")]
+    route: Vec<DeviceRoute<'a>>,
+
+    /// The active profile, e.g. `output:analog-stereo+input:analog-stereo`.
+    #[serde(borrow)]
+    #[serde(rename = "Profile", default)]
+    profile: Vec<DeviceProfile<'a>>,
+
+    #[serde(borrow)]
+    #[serde(rename = "EnumProfile", default)]
+    enum_profile: Vec<DeviceProfile<'a>>,
+
+    /// Every port, whether or not its route is active.
+    #[serde(borrow)]
+    #[serde(rename = "EnumRoute", default)]
+    enum_route: Vec<DevicePort<'a>>,
+}
+
+/// One of a device's ports, which a route can be switched to while the
+/// active profile is one of its `profiles`.
+#[derive(Deserialize, Debug, PartialEq)]
+struct DevicePort<'a> {
+    index: i64,
+    direction: &'a str,
+
+    #[serde(borrow)]
+    name: Cow<'a, str>,
+
+    #[serde(borrow)]
+    description: Option<Cow<'a, str>>,
+
+    available: Option<&'a str>,
+
+    #[serde(default)]
+    profiles: Vec<i64>,
+
+    /// The profile devices, e.g. a card's sinks, that it can be the route of.
+    #[serde(default)]
+    devices: Vec<i64>,
+}
+
+/// One of a device's profiles, which decide the sinks and sources it has.
+#[derive(Deserialize, Debug, PartialEq)]
+struct DeviceProfile<'a> {
+    index: i64,
+
+    #[serde(borrow)]
+    name: Cow<'a, str>,
+
+    #[serde(borrow)]
+    description: Option<Cow<'a, str>>,
+
+    #[serde(default)]
+    priority: i64,
+
+    /// `yes`, `no`, or `unknown`.
+    available: Option<&'a str>,
+}
+
+/// A device's route to one of its ports, e.g. speakers or headphones, which
+/// holds the volume and mute state of the node it belongs to.
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct DeviceRoute<'a> {
+    index: i64,
+    direction: &'a str,
+
+    #[serde(borrow)]
+    name: Option<Cow<'a, str>>,
+
+    #[serde(borrow)]
+    description: Option<Cow<'a, str>>,
+
+    /// `no` while the port is unplugged, or else `yes` or `unknown`.
+    available: Option<&'a str>,
+
+    /// The profile device it is the route of.
+    device: Option<i64>,
+
+    props: DeviceRouteProp,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct DeviceRouteProp {
+    mute: bool,
+    #[serde(rename = "channelVolumes")]
+    channel_volumes: Vec<f64>,
+    #[serde(rename = "channelMap", default)]
+    channel_map: Vec<String>,
+    #[serde(rename = "volumeBase")]
+    volume_base: Option<f64>,
+}
+
+/// A node, such as a sink or source.
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct PipeWireInterfaceNode<'a> {
+    id: i64,
+
+    #[serde(rename = "type")]
+    typ: &'a str,
+
+    #[serde(borrow)]
+    info: NodeInfo<'a>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct NodeInfo<'a> {
+    /// `running`, `idle`, `suspended`, or `error`.
+    state: Option<&'a str>,
+
+    #[serde(borrow)]
+    error: Option<Cow<'a, str>>,
+
+    #[serde(borrow)]
+    props: NodeProps<'a>,
+
+    #[serde(borrow)]
+    params: NodeParams<'a>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct NodeProps<'a> {
+    #[serde(rename = "card.profile.device")]
+    card_profile_device: i64,
+
+    #[serde(rename = "device.id")]
+    device_id: i64,
+
+    #[serde(rename = "node.name")]
+    node_name: &'a str,
+
+    #[serde(rename = "media.class")]
+    media_class: Option<&'a str>,
+
+    #[serde(rename = "object.serial")]
+    object_serial: Option<i64>,
+
+    #[serde(borrow)]
+    #[serde(rename = "node.description")]
+    node_description: Option<Cow<'a, str>>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct NodeParams<'a> {
+    #[serde(rename = "EnumFormat")]
+    enum_format: Vec<NodeEnumFormat>,
+
+    #[serde(borrow)]
+    #[serde(rename = "PropInfo")]
+    prop_info: Vec<NodePropInfo<'a>>,
+
+    #[serde(rename = "Props")]
+    props: Vec<NodeProp>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct NodeEnumFormat {
+    channels: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+enum NodePropInfo<'a> {
+    #[serde(borrow)]
+    Volume(NodePropInfoVolume<'a>),
+    Value(Value),
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct NodePropInfoVolume<'a> {
+    id: &'a str,
+
+    #[serde(rename = "type")]
+    typ: NodePropInfoTypeVolume,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct NodePropInfoTypeVolume {
+    default: f64,
+    min: f64,
+    max: f64,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+enum NodeProp {
+    Volume(NodePropVolume),
+    Value(Value),
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct NodePropVolume {
+    volume: f64,
+    mute: bool,
+
+    #[serde(rename = "channelVolumes")]
+    channel_volumes: Vec<f64>,
+
+    /// Alternating keys and values of the node's adjustable parameters, e.g.
+    /// `channelmix.upmix`.
+    #[serde(default)]
+    params: Vec<Value>,
+}
+
+/// Metadata, such as the default sink and source.
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct PipeWireInterfaceMetadata<'a> {
+    #[serde(rename = "type")]
+    typ: &'a str,
+
+    #[serde(borrow)]
+    metadata: Vec<Metadata<'a>>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct Metadata<'a> {
+    key: &'a str,
+
+    #[serde(borrow)]
+    value: MetadataValue<'a>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+enum MetadataValue<'a> {
+    #[serde(borrow)]
+    Name(MetadataValueName<'a>),
+    Value(Value),
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct MetadataValueName<'a> {
+    name: &'a str,
+}
+
+/// A `Route` param for pw-cli, which sets a route's volume or mute state.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct PipeWireCommand {
+    index: i64,
+    device: i64,
+    props: CommandVolumeProps,
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+struct CommandVolumeProps {
+    mute: bool,
+
+    #[serde(rename = "channelVolumes")]
+    channel_volumes: Vec<f64>,
+}
+
+fn is_decimal_percentage(value: &str) -> bool {
+    value
+        .strip_suffix('%')
+        .and_then(|value| value.parse::<f32>().ok())
+        .is_some()
+}
+
+/// Selects the node a command operates on, using wpctl's target syntax.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Target {
+    /// `@DEFAULT_AUDIO_SINK@`
+    #[default]
+    DefaultSink,
+    /// `@DEFAULT_AUDIO_SOURCE@`
+    DefaultSource,
+    /// A numeric node id.
+    Id(i64),
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "@DEFAULT_AUDIO_SINK@" | "@DEFAULT_SINK@" => Ok(Target::DefaultSink),
+            "@DEFAULT_AUDIO_SOURCE@" | "@DEFAULT_SOURCE@" => Ok(Target::DefaultSource),
+            _ => s.parse::<i64>().map(Target::Id).map_err(|_| {
+                format!(
+                    r#""{}" is not a node id or @DEFAULT_AUDIO_SINK@/@DEFAULT_AUDIO_SOURCE@"#,
+                    s
+                )
+            }),
+        }
+    }
+}
+
+/// Looks up the name of a default node, e.g. `default.audio.sink`, in the
+/// dump's metadata.
+fn default_name<'a>(obj: &'a [PipeWireObject<'_>], key: &str) -> Option<&'a str> {
+    let name = obj
+        .iter()
+        .filter_map(|o| match o {
+            PipeWireObject::Metadata(md) if md.typ == "PipeWire:Interface:Metadata" => Some(md),
+            _ => None,
+        })
+        .flat_map(|md| &md.metadata)
+        .find_map(|md| match &md.value {
+            MetadataValue::Name(mv) if md.key == key => Some(mv.name),
+            _ => None,
+        })?;
+    log::debug(1, format_args!("{} is {}", key, name));
+    // while night compression is on, the device behind the compressor
+    match filter::device_behind(obj, name) {
+        Some(device) => {
+            log::debug(1, format_args!("{} is in front of {}", name, device));
+            Some(device)
+        }
+        None => Some(name),
+    }
+}
+
+/// Lists every audio sink and source that has a device route, in dump order.
+fn endpoints<'a>(
+    obj: &'a [PipeWireObject<'_>],
+) -> Vec<(&'a PipeWireInterfaceNode<'a>, &'a DeviceRoute<'a>)> {
+    obj.iter()
+        .filter_map(|o| match o {
+            PipeWireObject::Node(n)
+                if n.typ == "PipeWire:Interface:Node"
+                    && matches!(
+                        n.info.props.media_class,
+                        Some("Audio/Sink") | Some("Audio/Source")
+                    ) =>
+            {
+                parse_dump(obj, Target::Id(n.id)).ok()
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Lists application streams, in dump order, along with the ids of the nodes
+/// they are linked to, in ascending order.
+fn streams<'a>(obj: &'a [PipeWireObject<'_>]) -> Vec<(&'a PipeWireInterfaceStream<'a>, Vec<i64>)> {
+    obj.iter()
+        .filter_map(|o| match o {
+            PipeWireObject::Stream(s)
+                if s.typ == "PipeWire:Interface:Node"
+                    && s.info.props.media_class.starts_with("Stream/") =>
+            {
+                Some(s)
+            }
+            _ => None,
+        })
+        .map(|s| {
+            let mut peers: Vec<i64> = obj
+                .iter()
+                .filter_map(|o| match o {
+                    PipeWireObject::Link(l) if l.typ == "PipeWire:Interface:Link" => {
+                        if l.info.output_node_id == s.id {
+                            Some(l.info.input_node_id)
+                        } else if l.info.input_node_id == s.id {
+                            Some(l.info.output_node_id)
+                        } else {
+                            None
+                        }
+                    }
+                    _ => None,
+                })
+                .collect();
+            // a link per port, and the ports needn't be listed together
+            peers.sort_unstable();
+            peers.dedup();
+            (s, peers)
+        })
+        .collect()
+}
+
+/// Whether any application is recording from the default source right now.
+fn mic_active(obj: &[PipeWireObject<'_>]) -> bool {
+    let source = match default_name(obj, "default.audio.source").map(|name| node_id(obj, name)) {
+        Some(Ok(id)) => id,
+        _ => return false,
+    };
+    streams(obj).iter().any(|(s, peers)| {
+        s.info.props.media_class.starts_with("Stream/Input/")
+            && s.info.state == Some("running")
+            && peers.contains(&source)
+    })
+}
+
+/// Runs pw-dump and returns its JSON output.
+pub fn pw_dump() -> anyhow::Result<Vec<u8>> {
+    let output =
+        tools::output(&mut tools::command("pw-dump")).map_err(|e| error::tool("pw-dump", e))?;
+    log::debug(
+        2,
+        format_args!("pw-dump printed {} bytes", output.stdout.len()),
+    );
+    if !output.status.success() {
+        return Err(Failure::new(
+            Kind::PipewireNotRunning,
+            "pw-dump failed to connect to PipeWire",
+        )
+        .into());
+    }
+    graph::merge(output.stdout).context(Failure::new(
+        Kind::BadDump,
+        "failed to read pw-dump's output",
+    ))
+}
+
+/// Parses pw-dump's output, failing as a bad dump if it doesn't parse.
+fn unmarshal(buf: &[u8]) -> anyhow::Result<Vec<PipeWireObject<'_>>> {
+    serde_json::from_slice(buf).map_err(|e| {
+        anyhow::Error::new(e).context(Failure::new(
+            Kind::BadDump,
+            "failed to unmarshal PipeWireObject",
+        ))
+    })
+}
+
+/// Finds the target node in the dump, and the active route of its device in
+/// the node's direction.
+pub fn parse_dump<'a>(
+    obj: &'a [PipeWireObject<'_>],
+    target: Target,
+) -> anyhow::Result<(&'a PipeWireInterfaceNode<'a>, &'a DeviceRoute<'a>)> {
+    let nodes = || {
+        obj.iter().filter_map(|o| match o {
+            PipeWireObject::Node(n) if n.typ == "PipeWire:Interface:Node" => Some(n),
+            _ => None,
+        })
+    };
+
+    // find node for the target
+    let node = match target {
+        Target::DefaultSink | Target::DefaultSource => {
+            let (key, kind, missing) = if target == Target::DefaultSink {
+                ("default.audio.sink", "sink", Kind::NoDefaultSink)
+            } else {
+                ("default.audio.source", "source", Kind::NoDefaultSource)
+            };
+            let name = default_name(obj, key).ok_or_else(|| {
+                Failure::new(
+                    missing,
+                    format!("failed to determine default audio {}", kind),
+                )
+            })?;
+            nodes()
+                .find(|n| n.info.props.node_name == name)
+                .ok_or_else(|| {
+                    Failure::new(
+                        Kind::NoNode,
+                        format!("failed to find node for audio {}: {}", kind, name),
+                    )
+                })?
+        }
+        Target::Id(id) => nodes()
+            .find(|n| n.id == id)
+            .ok_or_else(|| Failure::new(Kind::NoNode, format!("failed to find node: {}", id)))?,
+    };
+    log::debug(
+        1,
+        format_args!("node {} is {}", node.id, node.info.props.node_name),
+    );
+    let direction = match (target, node.info.props.media_class) {
+        (Target::DefaultSource, _) | (Target::Id(_), Some("Audio/Source")) => "Input",
+        _ => "Output",
+    };
+
+    // get device corresponding to this node
+    let device = obj
+        .iter()
+        .find_map(|o| match o {
+            PipeWireObject::Device(d)
+                if d.typ == "PipeWire:Interface:Device" && d.id == node.info.props.device_id =>
+            {
+                Some(d)
+            }
+            _ => None,
+        })
+        .ok_or_else(|| {
+            Failure::new(
+                Kind::NoDevice,
+                format!("failed to find device: {}", node.info.props.device_id),
+            )
+        })?;
+
+    log::debug(1, format_args!("device {} has the node", device.id));
+    for route in &device.info.params.route {
+        log::debug(
+            2,
+            format_args!(
+                "device {} has {} route {} ({})",
+                device.id,
+                route.direction.to_lowercase(),
+                route.index,
+                route.description.as_deref().unwrap_or("no description")
+            ),
+        );
+    }
+
+    // get the node's active route: a device can have several routes in the
+    // same direction, e.g. analog and HDMI outputs, each for its own node
+    let route = device
+        .info
+        .params
+        .route
+        .iter()
+        .find(|r| r.direction == direction && r.device == Some(node.info.props.card_profile_device))
+        .ok_or_else(|| {
+            Failure::new(
+                Kind::NoRoute,
+                format!("failed to find {} route", direction.to_lowercase()),
+            )
+        })?;
+
+    log::debug(
+        1,
+        format_args!(
+            "{} route {} is active",
+            direction.to_lowercase(),
+            route.index
+        ),
+    );
+    ensure!(
+        !route.props.channel_volumes.is_empty(),
+        "no volume channels present"
+    );
+    Ok((node, route))
+}
+
+/// Looks up one of the node's adjustable parameters, such as
+/// `channelmix.upmix`.
+fn node_param<'a>(node: &'a PipeWireInterfaceNode<'_>, key: &str) -> Option<&'a Value> {
+    node.info.params.props.iter().find_map(|prop| match prop {
+        NodeProp::Volume(volume) => volume
+            .params
+            .chunks(2)
+            .find(|pair| pair[0] == key)
+            .and_then(|pair| pair.get(1)),
+        NodeProp::Value(_) => None,
+    })
+}
+
+/// The description of the device a node belongs to, e.g. "Built-in Audio".
+fn device_description(
+    obj: &[PipeWireObject<'_>],
+    node: &PipeWireInterfaceNode<'_>,
+) -> Option<String> {
+    obj.iter().find_map(|o| match o {
+        PipeWireObject::Device(d) if d.id == node.info.props.device_id => d
+            .info
+            .props
+            .as_ref()
+            .and_then(|props| props.device_description.as_deref())
+            .map(str::to_string),
+        _ => None,
+    })
+}
+
+/// The battery level of the Bluetooth device that the node belongs to, if it
+/// reports one.
+fn battery(obj: &[PipeWireObject<'_>], node: &PipeWireInterfaceNode<'_>) -> Option<u8> {
+    let props = obj.iter().find_map(|o| match o {
+        PipeWireObject::Device(d) if d.id == node.info.props.device_id => d.info.props.as_ref(),
+        _ => None,
+    })?;
+    if props.device_api != Some("bluez5") {
+        return None;
+    }
+    let level = match props.battery.as_ref()? {
+        Value::Number(n) => n.as_f64()?,
+        Value::String(s) => s.trim().trim_end_matches('%').parse().ok()?,
+        _ => return None,
+    };
+    Some(level.round().clamp(0.0, 100.0) as u8)
+}
+
+/// Looks up a sink or source by its `node.name`.
+pub fn node_id(obj: &[PipeWireObject<'_>], name: &str) -> anyhow::Result<i64> {
+    obj.iter()
+        .find_map(|o| match o {
+            PipeWireObject::Node(n) if n.info.props.node_name == name => Some(n.id),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("failed to find node: {}", name))
+}
+
+/// A modification of the output route's mute or volume state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VolumeOp {
+    /// Mute on (`Some(true)`), off (`Some(false)`), or toggle (`None`).
+    Mute(Option<bool>),
+    /// Adjust volume by a fraction, e.g. `0.05` for +5%.
+    Change(f64),
+    /// Set volume to an absolute fraction.
+    Set(f64),
+}
+
+/// Percentage with at most three decimal places, e.g. `0.05` as `5`.
+fn display_percent(fraction: f64) -> f64 {
+    (fraction * 100_000.0).round() / 1000.0
+}
+
+/// Formats an operation the way `FromStr` reads it: `mute on|off|toggle`,
+/// `+5%`/`-5%` to change, and `=40%` to set.
+impl fmt::Display for VolumeOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VolumeOp::Mute(Some(true)) => write!(f, "mute on"),
+            VolumeOp::Mute(Some(false)) => write!(f, "mute off"),
+            VolumeOp::Mute(None) => write!(f, "mute toggle"),
+            VolumeOp::Change(delta) => write!(f, "{:+}%", display_percent(*delta)),
+            VolumeOp::Set(vol) => write!(f, "={}%", display_percent(*vol)),
+        }
+    }
+}
+
+impl FromStr for VolumeOp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let percent = |value: &str| {
+            value
+                .strip_suffix('%')
+                .and_then(|value| value.parse::<f64>().ok())
+                .map(|percent| percent * 0.01)
+        };
+        let op = match s {
+            "mute on" => Some(VolumeOp::Mute(Some(true))),
+            "mute off" => Some(VolumeOp::Mute(Some(false))),
+            "mute toggle" => Some(VolumeOp::Mute(None)),
+            _ => match s.strip_prefix('=') {
+                Some(value) => percent(value).map(VolumeOp::Set),
+                None if s.starts_with('+') || s.starts_with('-') => {
+                    percent(s).map(VolumeOp::Change)
+                }
+                None => None,
+            },
+        };
+        op.ok_or_else(|| format!(r#""{}" is not a volume operation"#, s))
+    }
+}
+
+/// Builds the command that applies `op` to the node's route, on the node's
+/// scale and within the configured limit.
+pub fn route_command(
+    op: VolumeOp,
+    node: &PipeWireInterfaceNode<'_>,
+    route: &DeviceRoute<'_>,
+) -> PipeWireCommand {
+    let mut cmd = PipeWireCommand {
+        index: route.index,
+        device: node.info.props.card_profile_device,
+        ..Default::default()
+    };
+    let scale = scale(node.info.props.node_name);
+    let max = volume_config().max / 100.0;
+    match op {
+        VolumeOp::Mute(Some(mute)) => cmd.props.mute = mute,
+        VolumeOp::Mute(None) => cmd.props.mute = !route.props.mute,
+        VolumeOp::Change(increment) => {
+            let mut vols = Vec::with_capacity(route.props.channel_volumes.len());
+            for vol in route.props.channel_volumes.iter() {
+                let new_vol = (scale.scaled(*vol) + increment).clamp(0.0, max);
+                vols.push(scale.linear(new_vol));
+            }
+            cmd.props.channel_volumes = vols;
+        }
+        VolumeOp::Set(vol) => {
+            cmd.props.channel_volumes =
+                vec![scale.linear(vol.clamp(0.0, max)); route.props.channel_volumes.len()];
+        }
+    }
+    cmd
+}
+
+/// Sets each channel's volume, leaving the mute state alone.
+fn volume_command(
+    node: &PipeWireInterfaceNode<'_>,
+    route: &DeviceRoute<'_>,
+    channel_volumes: Vec<f64>,
+) -> PipeWireCommand {
+    PipeWireCommand {
+        index: route.index,
+        device: node.info.props.card_profile_device,
+        props: CommandVolumeProps {
+            mute: route.props.mute,
+            channel_volumes,
+        },
+    }
+}
+
+/// Set by `--dry-run`.
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// The config file's `[volume]` section, which every volume change and
+/// [`Status`] follows.
+static VOLUME: OnceLock<VolumeConfig> = OnceLock::new();
+
+fn volume_config() -> VolumeConfig {
+    VOLUME.get().copied().unwrap_or_default()
+}
+
+/// The config file's `[devices]` section.
+static DEVICES: OnceLock<BTreeMap<String, DeviceConfig>> = OnceLock::new();
+
+/// The scale of the node named `node`: its own from `[devices]`, or else the
+/// one in `[volume]`.
+fn scale(node: &str) -> Scale {
+    DEVICES
+        .get()
+        .and_then(|devices| config::device_scale(devices, node))
+        .unwrap_or(volume_config().scale)
+}
+
+/// A command line quoted for a shell, e.g. `pw-cli set-param 53 Route '{...}'`.
+fn shell_words(program: &str, args: &[&str]) -> String {
+    let quote = |arg: &str| {
+        if !arg.is_empty()
+            && arg
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "/-_.=+:,@".contains(c))
+        {
+            arg.to_string()
+        } else {
+            format!("'{}'", arg.replace('\'', r"'\''"))
+        }
+    };
+    std::iter::once(program)
+        .chain(args.iter().copied())
+        .map(quote)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// With `--dry-run`, prints a command that would change the graph and
+/// returns true instead of letting it run.
+fn dry_run(program: &str, args: &[&str]) -> bool {
+    if !DRY_RUN.load(Ordering::Relaxed) {
+        return false;
+    }
+    println!("{}", shell_words(program, args));
+    true
+}
+
+/// Sets a parameter of a node or device through pw-cli, e.g. a device's
+/// `Route`.
+fn set_param(id: i64, param: &str, value: &str) -> anyhow::Result<()> {
+    let object = id.to_string();
+    let args = ["set-param", &object, param, value];
+    if dry_run("pw-cli", &args) {
+        // `daemon --simulate` goes on from what it would have set
+        graph::record(id, param, value);
+        return Ok(());
+    }
+    log::debug(1, format_args!("running {}", shell_words("pw-cli", &args)));
+    let code = tools::status(tools::command("pw-cli").args(args))
+        .map_err(|e| error::tool("pw-cli", e))?
+        .code()
+        .ok_or_else(|| anyhow!("pw-cli terminated by signal"))?;
+    if code != 0 {
+        return Err(Failure::new(Kind::PwCliFailed, "pw-cli did not exit successfully").into());
+    }
+    graph::record(id, param, value);
+    Ok(())
+}
+
+/// Sends a command to the node's device.
+pub fn set_route(node: &PipeWireInterfaceNode<'_>, cmd: &PipeWireCommand) -> anyhow::Result<()> {
+    let mut cmd = cmd.clone();
+    if !cmd.props.channel_volumes.is_empty() {
+        Trims::load()?.apply(node.info.props.node_name, &mut cmd.props.channel_volumes);
+    }
+    set_param(
+        node.info.props.device_id,
+        "Route",
+        &serde_json::to_string(&cmd)?,
+    )
+}
+
+/// Reads the target node's status from a fresh pw-dump.
+pub fn status(target: Target) -> anyhow::Result<Status> {
+    let dump = pw_dump()?;
+    let obj = unmarshal(&dump)?;
+    let (node, route) = parse_dump(&obj, target)?;
+    Ok(Status {
+        fade: fade::running(node.id),
+        ..Status::new(node, route)
+    })
+}
+
+/// Applies an operation to the target node, as `pw-volume mute` and
+/// `pw-volume change` do.
+pub fn execute(target: Target, op: VolumeOp) -> anyhow::Result<()> {
+    let dump = pw_dump()?;
+    let obj = unmarshal(&dump)?;
+    let (node, route) = parse_dump(&obj, target)?;
+    set_route(node, &route_command(op, node, route))
+}
+
+/// Sets adjustable parameters on a node, e.g. `{"channelmix.upmix": true}`.
+fn set_node_params(
+    node: &PipeWireInterfaceNode<'_>,
+    params: &[(&str, Value)],
+) -> anyhow::Result<()> {
+    let params: Vec<Value> = params
+        .iter()
+        .flat_map(|(key, value)| [Value::from(*key), value.clone()])
+        .collect();
+    let props = serde_json::json!({ "params": params }).to_string();
+    set_param(node.id, "Props", &props)
+}
+
+fn pw_metadata(args: &[&str]) -> anyhow::Result<()> {
+    if dry_run("pw-metadata", args) {
+        return Ok(());
+    }
+    log::debug(
+        1,
+        format_args!("running {}", shell_words("pw-metadata", args)),
+    );
+    let output = tools::output(tools::command("pw-metadata").args(args))
+        .map_err(|e| error::tool("pw-metadata", e))?;
+    ensure!(
+        output.status.success(),
+        "pw-metadata did not exit successfully"
+    );
+    Ok(())
+}
+
+/// Makes a sink or source the configured default, as pavucontrol does.
+fn set_default(node: &PipeWireInterfaceNode<'_>) -> anyhow::Result<()> {
+    let key = match node.info.props.media_class {
+        Some("Audio/Source") => "default.configured.audio.source",
+        _ => "default.configured.audio.sink",
+    };
+    set_default_name(key, node.info.props.node_name)
+}
+
+fn set_default_name(key: &str, name: &str) -> anyhow::Result<()> {
+    let value = serde_json::json!({ "name": name }).to_string();
+    pw_metadata(&["0", key, &value, "Spa:String:JSON"])?;
+    stats::switch();
+    Ok(())
+}
+
+/// Asks the session manager to move a stream to another sink or source.
+fn move_stream(stream_id: i64, node: &PipeWireInterfaceNode<'_>) -> anyhow::Result<()> {
+    let serial = node
+        .info
+        .props
+        .object_serial
+        .ok_or_else(|| anyhow!("node {} has no object.serial", node.id))?;
+    pw_metadata(&[
+        &stream_id.to_string(),
+        "target.object",
+        &serial.to_string(),
+        "Spa:Id",
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Read, path::PathBuf};
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("without_discord.txt")]
+    #[test_case("with_discord.txt")]
+    #[test_case("dump_aria_16.txt")]
+    fn parse_output(filename: &str) -> anyhow::Result<()> {
+        let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "src", "testdata", filename]
+            .iter()
+            .collect();
+        let mut f = File::open(path)?;
+        let mut buf = Vec::new();
+        f.read_to_end(&mut buf)?;
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf)?;
+        parse_dump(&obj, Target::DefaultSink)?;
+        parse_dump(&obj, Target::DefaultSource)?;
+        ensure!(endpoints(&obj).len() >= 2, "sink and source not listed");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_node_params() -> anyhow::Result<()> {
+        let path: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "testdata",
+            "dump_aria_16.txt",
+        ]
+        .iter()
+        .collect();
+        let buf = std::fs::read(path)?;
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf)?;
+        let (node, _) = parse_dump(&obj, Target::Id(49))?;
+        assert_eq!(
+            node_param(node, "channelmix.upmix"),
+            Some(&Value::Bool(true))
+        );
+        assert_eq!(
+            node_param(node, "channelmix.upmix-method"),
+            Some(&Value::from("psd"))
+        );
+        assert_eq!(node_param(node, "no.such.param"), None);
+        Ok(())
+    }
+
+    #[test_case(53, serde_json::json!(80) => Some(80))]
+    #[test_case(53, serde_json::json!("75%") => Some(75); "as a string")]
+    #[test_case(53, serde_json::json!(null) => None; "not reported")]
+    #[test_case(42, serde_json::json!(80) => None; "not bluetooth")]
+    fn battery_level(device: i64, level: Value) -> Option<u8> {
+        let path: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "testdata",
+            "dump_aria_16.txt",
+        ]
+        .iter()
+        .collect();
+        let mut dump: Value = serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+        for o in dump.as_array_mut().into_iter().flatten() {
+            if o["id"] == device && !level.is_null() {
+                o["info"]["props"]["api.bluez5.battery"] = level.clone();
+            }
+        }
+        let buf = serde_json::to_vec(&dump).unwrap();
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf).unwrap();
+        // the default sink is on device 53, the built-in sink on 42
+        let target = if device == 53 {
+            Target::DefaultSink
+        } else {
+            Target::Id(node_id(&obj, "alsa_output.pci-0000_00_1f.3.analog-stereo").unwrap())
+        };
+        let (node, _) = parse_dump(&obj, target).unwrap();
+        battery(&obj, node)
+    }
+
+    #[test]
+    fn parse_streams() -> anyhow::Result<()> {
+        let path: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "testdata",
+            "with_discord.txt",
+        ]
+        .iter()
+        .collect();
+        let buf = std::fs::read(path)?;
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf)?;
+        let streams: Vec<(i64, Vec<i64>)> = streams(&obj)
+            .into_iter()
+            .map(|(s, peers)| (s.id, peers))
+            .collect();
+        assert_eq!(
+            streams,
+            [
+                (65, vec![47]),
+                (78, vec![47]),
+                (77, vec![47]),
+                (85, vec![48])
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn stream_peers_interleaved() -> anyhow::Result<()> {
+        let dump = r#"[
+            {"id": 90, "type": "PipeWire:Interface:Node", "info": {"props":
+                {"media.class": "Stream/Output/Audio", "node.name": "mpv"}}},
+            {"id": 91, "type": "PipeWire:Interface:Link", "info":
+                {"output-node-id": 90, "input-node-id": 48}},
+            {"id": 92, "type": "PipeWire:Interface:Link", "info":
+                {"output-node-id": 90, "input-node-id": 47}},
+            {"id": 93, "type": "PipeWire:Interface:Link", "info":
+                {"output-node-id": 90, "input-node-id": 48}}
+        ]"#;
+        let obj: Vec<PipeWireObject> = serde_json::from_str(dump)?;
+        let streams: Vec<(i64, Vec<i64>)> = streams(&obj)
+            .into_iter()
+            .map(|(s, peers)| (s.id, peers))
+            .collect();
+        assert_eq!(streams, [(90, vec![47, 48])]);
+        Ok(())
+    }
+
+    #[test_case("with_discord.txt" => true)]
+    #[test_case("without_discord.txt" => false)]
+    fn recording(filename: &str) -> bool {
+        let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "src", "testdata", filename]
+            .iter()
+            .collect();
+        let buf = std::fs::read(path).unwrap();
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf).unwrap();
+        mic_active(&obj)
+    }
+
+    #[test_case("dump_aria_16.txt", 69 => "Output")]
+    #[test_case("dump_aria_16.txt", 50 => "Input")]
+    fn parse_output_by_id(filename: &str, id: i64) -> String {
+        let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "src", "testdata", filename]
+            .iter()
+            .collect();
+        let buf = std::fs::read(path).unwrap();
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf).unwrap();
+        let (node, route) = parse_dump(&obj, Target::Id(id)).unwrap();
+        assert_eq!(node.id, id);
+        route.direction.to_string()
+    }
+
+    #[test_case(Target::DefaultSink => "Speakers")]
+    #[test_case(Target::Id(49) => "HDMI / DisplayPort")]
+    #[test_case(Target::DefaultSource => "Microphone")]
+    fn route_of_node(target: Target) -> String {
+        let path: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "testdata",
+            "hdmi_and_analog.txt",
+        ]
+        .iter()
+        .collect();
+        let buf = std::fs::read(path).unwrap();
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf).unwrap();
+        let (_, route) = parse_dump(&obj, target).unwrap();
+        route.description.as_deref().unwrap_or_default().to_string()
+    }
+
+    #[test_case("mute toggle" => Ok(VolumeOp::Mute(None)))]
+    #[test_case("+5%" => Ok(VolumeOp::Change(0.05)))]
+    #[test_case("-0.5%" => Ok(VolumeOp::Change(-0.005)))]
+    #[test_case("=40%" => Ok(VolumeOp::Set(0.4)))]
+    #[test_case("40%" => matches Err(_))]
+    fn parse_volume_op(s: &str) -> Result<VolumeOp, String> {
+        s.parse()
+    }
+
+    #[test_case(VolumeOp::Mute(Some(true)))]
+    #[test_case(VolumeOp::Change(0.05))]
+    #[test_case(VolumeOp::Change(-0.025))]
+    #[test_case(VolumeOp::Set(0.4))]
+    fn volume_op_round_trip(op: VolumeOp) {
+        assert_eq!(op.to_string().parse(), Ok(op));
+    }
+
+    #[test_case("@DEFAULT_AUDIO_SINK@" => Ok(Target::DefaultSink))]
+    #[test_case("@DEFAULT_AUDIO_SOURCE@" => Ok(Target::DefaultSource))]
+    #[test_case("42" => Ok(Target::Id(42)))]
+    #[test_case("speakers" => matches Err(_))]
+    fn parse_target(s: &str) -> Result<Target, String> {
+        s.parse()
+    }
+
+    #[test_case(&["set-param", "53", "Props", r#"{"mute":true}"#] => r#"pw-cli set-param 53 Props '{"mute":true}'"#)]
+    #[test_case(&["0", "it's"] => r"pw-cli 0 'it'\''s'")]
+    fn dry_run_words(args: &[&str]) -> String {
+        shell_words("pw-cli", args)
+    }
+}
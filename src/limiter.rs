@@ -0,0 +1,228 @@
+//! The daemon's limiter: when a sink's volume jumps up past a threshold all
+//! at once, such as an application setting itself to 100%, it is brought
+//! back down to where it was, smoothly rather than with a click. Rises that
+//! take longer than a moment, such as holding a volume key, are left alone,
+//! as are changes sent through the daemon's socket.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::{daemon, endpoints, log, scale, PipeWireObject};
+
+/// Volumes closer than this are taken to be equal, as PipeWire rounds them.
+const EPSILON: f64 = 1e-3;
+
+/// The `[limiter]` section of the config file, which turns the limiter on.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct LimiterConfig {
+    /// Percentage that a sink must jump past to be brought back.
+    pub threshold: f64,
+    /// Percentage points that it must jump by.
+    pub jump: f64,
+    /// Milliseconds that the jump must happen within.
+    pub window_ms: u64,
+    /// Milliseconds taken to bring the volume back.
+    pub fade_ms: u64,
+}
+
+impl Default for LimiterConfig {
+    fn default() -> Self {
+        LimiterConfig {
+            threshold: 80.0,
+            jump: 20.0,
+            window_ms: 500,
+            fade_ms: 1000,
+        }
+    }
+}
+
+/// Bringing a sink's volume back down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Pull {
+    from: f64,
+    to: f64,
+    start: Instant,
+}
+
+/// What the limiter knows of one sink's volume, on the sink's scale.
+#[derive(Debug, Clone, PartialEq)]
+struct Level {
+    /// The volume it was at before it last started moving.
+    settled: f64,
+    /// The volume it was last seen at, or last set to.
+    last: f64,
+    /// When it started moving away from `settled`.
+    moved: Option<Instant>,
+    pull: Option<Pull>,
+}
+
+impl Level {
+    fn new(volume: f64) -> Level {
+        Level {
+            settled: volume,
+            last: volume,
+            moved: None,
+            pull: None,
+        }
+    }
+
+    /// Takes in the sink's volume at `now`, returning the volume to set it
+    /// to, if any.
+    fn step(&mut self, volume: f64, now: Instant, config: &LimiterConfig) -> Option<f64> {
+        if let Some(pull) = self.pull {
+            if (volume - self.last).abs() > EPSILON {
+                // someone else changed it meanwhile, which wins
+                *self = Level::new(volume);
+                return None;
+            }
+            let fade = Duration::from_millis(config.fade_ms);
+            let elapsed = now.saturating_duration_since(pull.start);
+            if elapsed >= fade {
+                *self = Level::new(pull.to);
+            } else {
+                let progress = elapsed.as_secs_f64() / fade.as_secs_f64();
+                self.last = pull.from + (pull.to - pull.from) * progress;
+            }
+            return Some(self.last);
+        }
+        let window = Duration::from_millis(config.window_ms);
+        if self
+            .moved
+            .is_some_and(|moved| now.saturating_duration_since(moved) >= window)
+        {
+            self.settled = self.last;
+            self.moved = None;
+        }
+        if (volume - self.last).abs() > EPSILON {
+            self.moved.get_or_insert(now);
+            self.last = volume;
+        }
+        if self.moved.is_some()
+            && volume * 100.0 > config.threshold
+            && (volume - self.settled) * 100.0 >= config.jump
+        {
+            self.pull = Some(Pull {
+                from: volume,
+                to: self.settled,
+                start: now,
+            });
+        }
+        None
+    }
+}
+
+/// The sinks the limiter watches, by node name.
+#[derive(Debug, Default)]
+pub struct Limiter {
+    sinks: HashMap<String, Level>,
+}
+
+impl Limiter {
+    /// Whether a sink is being brought back, which takes checking again
+    /// sooner than the graph changes by itself.
+    pub fn pulling(&self) -> bool {
+        self.sinks.values().any(|level| level.pull.is_some())
+    }
+
+    /// Takes the node's volume as it will be next as intended, e.g. after a
+    /// change sent through the daemon's socket.
+    pub fn accept(&mut self, node: &str) {
+        self.sinks.remove(node);
+    }
+
+    pub fn check(
+        &mut self,
+        config: &LimiterConfig,
+        obj: &[PipeWireObject<'_>],
+    ) -> anyhow::Result<()> {
+        let now = Instant::now();
+        let sinks: Vec<_> = endpoints(obj)
+            .into_iter()
+            .filter(|(node, _)| node.info.props.media_class == Some("Audio/Sink"))
+            .collect();
+        // sinks that went away start over if they come back
+        self.sinks.retain(|name, _| {
+            sinks
+                .iter()
+                .any(|(node, _)| node.info.props.node_name == name)
+        });
+        for (node, route) in sinks {
+            let name = node.info.props.node_name;
+            let scale = scale(name);
+            let volume = scale.scaled(route.props.channel_volumes[0]);
+            let level = self
+                .sinks
+                .entry(name.to_string())
+                .or_insert_with(|| Level::new(volume));
+            let was_pulling = level.pull.is_some();
+            if let Some(volume) = level.step(volume, now, config) {
+                daemon::set_volume(node, route, scale.linear(volume))?;
+            }
+            if !was_pulling && level.pull.is_some() {
+                log::debug(
+                    1,
+                    format_args!(
+                        "{} jumped to {:.0}%, bringing it back to {:.0}%",
+                        name,
+                        level.last * 100.0,
+                        level.settled * 100.0
+                    ),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pulls_back_jumps() {
+        let config = LimiterConfig::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut level = Level::new(0.5);
+        assert_eq!(level.step(0.5, at(0), &config), None);
+        // an application grabs 100%
+        assert_eq!(level.step(1.0, at(100), &config), None);
+        assert!(level.pull.is_some());
+        assert_eq!(level.step(1.0, at(600), &config), Some(0.75));
+        assert_eq!(level.step(0.75, at(1100), &config), Some(0.5));
+        assert_eq!(level, Level::new(0.5));
+    }
+
+    #[test]
+    fn leaves_slow_rises() {
+        let config = LimiterConfig::default();
+        let start = Instant::now();
+        let mut level = Level::new(0.5);
+        for (i, volume) in [0.55, 0.6, 0.65, 0.7, 0.75, 0.8, 0.85, 0.9]
+            .iter()
+            .enumerate()
+        {
+            let now = start + Duration::from_millis(300 * i as u64);
+            assert_eq!(level.step(*volume, now, &config), None);
+        }
+        assert_eq!(level.pull, None);
+    }
+
+    #[test]
+    fn yields_to_other_changes() {
+        let config = LimiterConfig::default();
+        let start = Instant::now();
+        let mut level = Level::new(0.3);
+        level.step(1.0, start, &config);
+        assert_eq!(
+            level.step(0.5, start + Duration::from_millis(100), &config),
+            None
+        );
+        assert_eq!(level, Level::new(0.5));
+    }
+}
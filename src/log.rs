@@ -0,0 +1,22 @@
+//! Diagnostics on stderr for `-v` and `-vv`: which default was resolved,
+//! which node, device, and route were picked, and what was sent to pw-cli.
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// How many `-v` were given.
+static LEVEL: AtomicU64 = AtomicU64::new(0);
+
+pub fn set_level(level: u64) {
+    LEVEL.store(level, Ordering::Relaxed);
+}
+
+/// Prints the message if at least `level` `-v` were given. 1 is for the
+/// choices pw-volume makes, 2 for the details behind them.
+pub fn debug(level: u64, message: fmt::Arguments<'_>) {
+    if LEVEL.load(Ordering::Relaxed) >= level {
+        eprintln!("pw-volume: {}", message);
+    }
+}
@@ -0,0 +1,1106 @@
+//! Interactive terminal mixer listing every sink, source, and stream.
+
+use std::{
+    cmp::Ordering,
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use anyhow::Context;
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, MouseButton, MouseEventKind},
+    execute, queue,
+    style::{Attribute, Print, SetAttribute, SetForegroundColor},
+    terminal::{self, ClearType},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::state_dir,
+    default_name, endpoints,
+    format::Status,
+    history::{self, Restore},
+    move_stream, parse_dump, port, profile, pw_dump, route_command, scale,
+    scene::{Scene, Step},
+    set_default, set_route, streams,
+    theme::Theme,
+    unmarshal, PipeWireInterfaceDevice, PipeWireObject, Target, VolumeOp,
+};
+
+/// Volume change for a single key press.
+const STEP: f64 = 0.05;
+
+/// How often the view is refreshed to pick up changes made elsewhere.
+const REFRESH: Duration = Duration::from_secs(1);
+
+/// The `[mixer]` section of the config file.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MixerConfig {
+    /// Ask before muting everything, switching the default device, or
+    /// switching a card's profile.
+    pub confirm: bool,
+}
+
+impl Default for MixerConfig {
+    fn default() -> Self {
+        MixerConfig { confirm: true }
+    }
+}
+
+/// Groups of rows, in display order.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+enum Section {
+    Output,
+    Input,
+    Playback,
+    Recording,
+}
+
+/// Sections in display order, numbered 1-4 for collapsing.
+const SECTIONS: [Section; 4] = [
+    Section::Output,
+    Section::Input,
+    Section::Playback,
+    Section::Recording,
+];
+
+impl Section {
+    fn heading(self) -> &'static str {
+        match self {
+            Section::Output => "Output",
+            Section::Input => "Input",
+            Section::Playback => "Playback",
+            Section::Recording => "Recording",
+        }
+    }
+
+    fn is_stream(self) -> bool {
+        matches!(self, Section::Playback | Section::Recording)
+    }
+
+    /// The section holding the devices that streams of this section play to
+    /// or record from.
+    fn devices(self) -> Section {
+        match self {
+            Section::Playback => Section::Output,
+            Section::Recording => Section::Input,
+            device => device,
+        }
+    }
+}
+
+/// Order of the rows within each section, cycled with `s`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+enum Sort {
+    /// By node id, which is roughly the order devices appeared in.
+    #[default]
+    Id,
+    Name,
+    /// Loudest first.
+    Volume,
+}
+
+impl Sort {
+    fn as_str(self) -> &'static str {
+        match self {
+            Sort::Id => "id",
+            Sort::Name => "name",
+            Sort::Volume => "volume",
+        }
+    }
+
+    fn next(self) -> Sort {
+        match self {
+            Sort::Id => Sort::Name,
+            Sort::Name => Sort::Volume,
+            Sort::Volume => Sort::Id,
+        }
+    }
+
+    fn compare(self, a: &Row, b: &Row) -> Ordering {
+        let order = match self {
+            Sort::Id => a.id.cmp(&b.id),
+            Sort::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            Sort::Volume => b.volume.total_cmp(&a.volume),
+        };
+        a.section.cmp(&b.section).then(order)
+    }
+}
+
+/// How the mixer was last arranged, restored on the next run from
+/// `$XDG_STATE_HOME/pw-volume/mixer.toml`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+struct Layout {
+    /// Width of the name column, as a percentage of the window.
+    name_width: u16,
+    sort: Sort,
+    collapsed: Vec<Section>,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            name_width: 33,
+            sort: Sort::default(),
+            collapsed: Vec::new(),
+        }
+    }
+}
+
+impl Layout {
+    fn path() -> Option<PathBuf> {
+        state_dir().map(|dir| dir.join("mixer.toml"))
+    }
+
+    fn load() -> anyhow::Result<Layout> {
+        let path = match Layout::path() {
+            Some(path) => path,
+            None => return Ok(Layout::default()),
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents)
+                .with_context(|| format!("failed to parse {}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Layout::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let path = match Layout::path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, toml::to_string(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    fn is_collapsed(&self, section: Section) -> bool {
+        self.collapsed.contains(&section)
+    }
+
+    fn toggle(&mut self, section: Section) {
+        match self.collapsed.iter().position(|s| *s == section) {
+            Some(i) => {
+                self.collapsed.remove(i);
+            }
+            None => self.collapsed.push(section),
+        }
+    }
+
+    fn resize(&mut self, delta: i32) {
+        self.name_width = (i32::from(self.name_width) + delta).clamp(10, 70) as u16;
+    }
+}
+
+/// A sink, source, or stream as shown in the mixer.
+struct Row {
+    id: i64,
+    node: String,
+    name: String,
+    section: Section,
+    volume: f64,
+    mute: bool,
+    default: bool,
+    /// For streams, the id of the device they are linked to.
+    target: Option<i64>,
+}
+
+fn snapshot(layout: &Layout) -> anyhow::Result<Vec<Row>> {
+    let buf = pw_dump()?;
+    let obj = unmarshal(&buf)?;
+    let default_sink = default_name(&obj, "default.audio.sink");
+    let default_source = default_name(&obj, "default.audio.source");
+    let mut rows: Vec<Row> = endpoints(&obj)
+        .into_iter()
+        .map(|(node, route)| {
+            let props = &node.info.props;
+            let (section, default) = if route.direction == "Input" {
+                (Section::Input, default_source == Some(props.node_name))
+            } else {
+                (Section::Output, default_sink == Some(props.node_name))
+            };
+            Row {
+                id: node.id,
+                node: props.node_name.to_string(),
+                name: props
+                    .node_description
+                    .as_deref()
+                    .unwrap_or(props.node_name)
+                    .to_string(),
+                section,
+                // assumes that all channels have the same volume.
+                volume: scale(props.node_name).scaled(route.props.channel_volumes[0]),
+                mute: route.props.mute,
+                default,
+                target: None,
+            }
+        })
+        .collect();
+    for (stream, peers) in streams(&obj) {
+        let props = &stream.info.props;
+        let section = match props.media_class {
+            "Stream/Output/Audio" => Section::Playback,
+            "Stream/Input/Audio" => Section::Recording,
+            _ => continue,
+        };
+        let target = peers
+            .into_iter()
+            .find(|peer| rows.iter().any(|row| row.id == *peer));
+        rows.push(Row {
+            id: stream.id,
+            node: props.node_name.to_string(),
+            name: props
+                .application_name
+                .as_deref()
+                .unwrap_or(&props.node_name)
+                .to_string(),
+            section,
+            volume: 0.0,
+            mute: false,
+            default: false,
+            target,
+        });
+    }
+    rows.sort_by(|a, b| layout.sort.compare(a, b));
+    Ok(rows)
+}
+
+/// Re-reads the rows, keeping the same row selected even if it moved.
+fn refresh(rows: &mut Vec<Row>, selected: &mut usize, layout: &Layout) -> anyhow::Result<()> {
+    let id = rows.get(*selected).map(|row| row.id);
+    *rows = snapshot(layout)?;
+    if let Some(i) = rows.iter().position(|row| Some(row.id) == id) {
+        *selected = i;
+    }
+    Ok(())
+}
+
+/// `selected` if it is visible, otherwise the nearest visible row.
+fn visible(rows: &[Row], layout: &Layout, selected: usize) -> usize {
+    match rows.get(selected) {
+        Some(row) if layout.is_collapsed(row.section) => {
+            let next = step(rows, layout, selected, 1);
+            if next != selected {
+                next
+            } else {
+                step(rows, layout, selected, -1)
+            }
+        }
+        _ => selected.min(rows.len().saturating_sub(1)),
+    }
+}
+
+/// The visible row after (or before, with a negative `step`) `selected`,
+/// skipping collapsed sections.
+fn step(rows: &[Row], layout: &Layout, selected: usize, step: isize) -> usize {
+    let mut i = selected as isize;
+    loop {
+        i += step;
+        match rows.get(i as usize) {
+            _ if i < 0 => return selected,
+            None => return selected,
+            Some(row) if !layout.is_collapsed(row.section) => return i as usize,
+            Some(_) => (),
+        }
+    }
+}
+
+/// Something the user asked to do with a row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Volume(VolumeOp),
+    /// Make the device the default sink or source.
+    SetDefault,
+    /// Move the stream to the device with this id.
+    MoveTo(i64),
+    /// Mute every device.
+    MuteAll,
+    /// Switch the device's card to the profile with this index.
+    Profile(i64),
+}
+
+impl Action {
+    /// The question to ask before an action that is disruptive to undo by
+    /// hand, if any.
+    fn prompt(self) -> Option<&'static str> {
+        match self {
+            Action::MuteAll => Some("mute every device?"),
+            Action::SetDefault => Some("make this the default device?"),
+            _ => None,
+        }
+    }
+}
+
+/// Applies `action` to the row at `target`, recording in the state history
+/// how to undo it.
+fn perform(
+    rows: &[Row],
+    target: usize,
+    action: Action,
+    recording: &mut Recording,
+) -> anyhow::Result<()> {
+    // what was changed before a failure can still be undone
+    let mut change = Vec::new();
+    let mut result = Ok(());
+    for (row, action) in changes(rows, target, action) {
+        match apply(row, action) {
+            Ok(restore) => change.extend(restore),
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+        if let (Recording::On(scene), Action::Volume(op)) = (&mut *recording, action) {
+            scene.record(Step::new(&row.node, op));
+        }
+    }
+    history::record(change)?;
+    result
+}
+
+/// The rows that `action` on the row at `target` changes, and what it does to
+/// each.
+fn changes(rows: &[Row], target: usize, action: Action) -> Vec<(&Row, Action)> {
+    let changes: Vec<(&Row, Action)> = match action {
+        Action::MuteAll => rows
+            .iter()
+            .map(|row| (row, Action::Volume(VolumeOp::Mute(Some(true)))))
+            .collect(),
+        _ => rows
+            .get(target)
+            .map(|row| (row, action))
+            .into_iter()
+            .collect(),
+    };
+    // streams have no route volume, and only devices can be defaults or have
+    // profiles
+    changes
+        .into_iter()
+        .filter(|(row, action)| match action {
+            Action::Volume(_) | Action::SetDefault | Action::Profile(_) => !row.section.is_stream(),
+            Action::MoveTo(_) => row.section.is_stream(),
+            Action::MuteAll => false,
+        })
+        .collect()
+}
+
+/// Undoes the most recent change in the state history, returning false if
+/// there was none.
+fn undo(recording: &mut Recording) -> anyhow::Result<bool> {
+    let change = match history::undo()? {
+        Some(change) => change,
+        None => return Ok(false),
+    };
+    if let Recording::On(scene) = recording {
+        for restore in change.iter().rev() {
+            if let Restore::Route { node, volume, mute } = restore {
+                scene.record(Step::new(node, VolumeOp::Set(*volume)));
+                scene.record(Step::new(node, VolumeOp::Mute(Some(*mute))));
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Applies `action` to the row, returning what it changed was before.
+fn apply(row: &Row, action: Action) -> anyhow::Result<Option<Restore>> {
+    let buf = pw_dump()?;
+    let obj = unmarshal(&buf)?;
+    match action {
+        Action::Volume(op) => {
+            let (node, route) = parse_dump(&obj, Target::Id(row.id))?;
+            let restore = Restore::Route {
+                node: row.node.clone(),
+                volume: Status::new(node, route).volume,
+                mute: route.props.mute,
+            };
+            set_route(node, &route_command(op, node, route))?;
+            Ok(Some(restore))
+        }
+        Action::SetDefault => {
+            let (node, _) = parse_dump(&obj, Target::Id(row.id))?;
+            let key = match row.section {
+                Section::Input => "default.audio.source",
+                _ => "default.audio.sink",
+            };
+            let restore = default_name(&obj, key).map(|name| Restore::Default {
+                node: name.to_string(),
+            });
+            set_default(node)?;
+            Ok(restore)
+        }
+        Action::MoveTo(device) => {
+            let (node, _) = parse_dump(&obj, Target::Id(device))?;
+            let restore = row
+                .target
+                .and_then(|id| parse_dump(&obj, Target::Id(id)).ok())
+                .map(|(from, _)| Restore::Stream {
+                    stream: row.node.clone(),
+                    id: row.id,
+                    device: from.info.props.node_name.to_string(),
+                });
+            move_stream(row.id, node)?;
+            Ok(restore)
+        }
+        Action::Profile(index) => {
+            let device = card(&obj, row)?;
+            let restore = device
+                .info
+                .params
+                .profile
+                .first()
+                .map(|active| Restore::Profile {
+                    device: device
+                        .info
+                        .props
+                        .as_ref()
+                        .and_then(|props| props.device_name.as_deref())
+                        .map_or_else(|| device.id.to_string(), str::to_string),
+                    index: active.index,
+                });
+            profile::switch(device, profile::profile(device, &index.to_string())?)?;
+            Ok(restore)
+        }
+        Action::MuteAll => Ok(None),
+    }
+}
+
+/// The sound card that a device row's node belongs to.
+fn card<'a>(
+    obj: &'a [PipeWireObject<'a>],
+    row: &Row,
+) -> anyhow::Result<&'a PipeWireInterfaceDevice<'a>> {
+    let (node, _) = parse_dump(obj, Target::Id(row.id))?;
+    port::device(obj, Some(&node.info.props.device_id.to_string()))
+}
+
+/// The index and description of the profile that `p` switches the row's
+/// card to, if it has another.
+fn next_profile(row: &Row) -> anyhow::Result<Option<(i64, String)>> {
+    let buf = pw_dump()?;
+    let obj = unmarshal(&buf)?;
+    let device = card(&obj, row)?;
+    Ok(profile::next(device).map(|profile| {
+        let name = profile.description.as_deref().unwrap_or(&profile.name);
+        (profile.index, name.to_string())
+    }))
+}
+
+/// The device after a stream's current one, wrapping around, for cycling
+/// through targets with the keyboard.
+fn next_target(rows: &[Row], stream: &Row) -> Option<i64> {
+    let devices: Vec<i64> = rows
+        .iter()
+        .filter(|row| row.section == stream.section.devices())
+        .map(|row| row.id)
+        .collect();
+    let current = devices.iter().position(|id| Some(*id) == stream.target);
+    let next = current.map_or(0, |i| (i + 1) % devices.len().max(1));
+    devices.get(next).copied()
+}
+
+/// Runs the mixer until the user quits, restoring the terminal afterwards.
+pub fn run(theme: &Theme, config: &MixerConfig) -> anyhow::Result<()> {
+    let mut stdout = io::stdout();
+    terminal::enable_raw_mode()?;
+    execute!(
+        stdout,
+        terminal::EnterAlternateScreen,
+        cursor::Hide,
+        event::EnableMouseCapture
+    )?;
+    let result = event_loop(&mut stdout, theme, config);
+    execute!(
+        stdout,
+        event::DisableMouseCapture,
+        cursor::Show,
+        terminal::LeaveAlternateScreen
+    )?;
+    terminal::disable_raw_mode()?;
+    result
+}
+
+/// Screen position of a row and its volume meter, for mouse handling.
+struct Hit {
+    y: u16,
+    row: usize,
+    meter_start: u16,
+    meter_width: u16,
+}
+
+impl Hit {
+    fn on_meter(&self, x: u16) -> bool {
+        x >= self.meter_start && x < self.meter_start + self.meter_width
+    }
+
+    fn after_meter(&self, x: u16) -> bool {
+        x >= self.meter_start + self.meter_width
+    }
+
+    /// Volume for a click or drag at column `x`, clamped to the meter.
+    fn fraction(&self, x: u16) -> f64 {
+        let cells = (x + 1)
+            .saturating_sub(self.meter_start)
+            .min(self.meter_width);
+        f64::from(cells) / f64::from(self.meter_width.max(1))
+    }
+}
+
+/// What was drawn where, for mouse handling.
+struct Screen {
+    hits: Vec<Hit>,
+    /// Lines showing section headings.
+    headings: Vec<(u16, Section)>,
+}
+
+/// What a key press asks the mixer to do, other than while a scene is being
+/// named or an action confirmed.
+#[derive(Debug, PartialEq)]
+enum Command {
+    Quit,
+    /// Start or stop recording a scene.
+    Record,
+    /// Move the selection by this many rows.
+    Select(isize),
+    Sort,
+    Collapse(Section),
+    /// Widen the name column by this many percent, or narrow it.
+    Resize(i32),
+    /// Apply the action to the selected row.
+    Act(Action),
+    /// Switch the selected device's card to its next profile.
+    Profile,
+    /// Move the selected stream to the next device.
+    MoveStream,
+    Undo,
+}
+
+/// The command a key stands for, if any.
+fn command(code: KeyCode) -> Option<Command> {
+    Some(match code {
+        KeyCode::Char('q') | KeyCode::Esc => Command::Quit,
+        KeyCode::Char('r') => Command::Record,
+        KeyCode::Up | KeyCode::Char('k') => Command::Select(-1),
+        KeyCode::Down | KeyCode::Char('j') => Command::Select(1),
+        KeyCode::Char('s') => Command::Sort,
+        KeyCode::Char(c @ '1'..='4') => Command::Collapse(SECTIONS[c as usize - '1' as usize]),
+        KeyCode::Char('<') => Command::Resize(-5),
+        KeyCode::Char('>') => Command::Resize(5),
+        KeyCode::Left | KeyCode::Char('h') => Command::Act(Action::Volume(VolumeOp::Change(-STEP))),
+        KeyCode::Right | KeyCode::Char('l') => Command::Act(Action::Volume(VolumeOp::Change(STEP))),
+        KeyCode::Char('m') => Command::Act(Action::Volume(VolumeOp::Mute(None))),
+        KeyCode::Char('M') => Command::Act(Action::MuteAll),
+        KeyCode::Char('d') => Command::Act(Action::SetDefault),
+        KeyCode::Char('p') => Command::Profile,
+        KeyCode::Char('t') => Command::MoveStream,
+        KeyCode::Char('u') => Command::Undo,
+        _ => return None,
+    })
+}
+
+/// Recording of adjustments into a scene, toggled with `r`.
+enum Recording {
+    Off,
+    On(Scene),
+    /// Recording stopped; the user is typing a name to save it under.
+    Naming(Scene, String),
+}
+
+const HELP: &str = " ↑/↓ select  ←/→ volume  m mute  M mute all  d default  p profile  \
+                    t move stream  u undo  r record  s sort  1-4 collapse  </> resize  q quit  \
+                    (wheel, click, and drag also work)";
+
+fn event_loop(out: &mut impl Write, theme: &Theme, config: &MixerConfig) -> anyhow::Result<()> {
+    let mut layout = Layout::load()?;
+    let mut rows = snapshot(&layout)?;
+    let mut selected = 0;
+    // row whose meter, or stream, is being dragged with the left button
+    let mut dragging: Option<usize> = None;
+    let mut recording = Recording::Off;
+    // action waiting for the user to confirm it, with the question asked
+    let mut pending: Option<(usize, Action, String)> = None;
+    // shown in place of the help line until the next key press
+    let mut message: Option<String> = None;
+    loop {
+        selected = visible(&rows, &layout, selected);
+        let footer = match (&recording, &pending, &message) {
+            (_, Some((_, _, question)), _) => format!(" {} (y/n)", question),
+            (Recording::Naming(_, name), ..) => {
+                format!(" save scene as: {}_  (enter to save, esc to cancel)", name)
+            }
+            (_, _, Some(message)) => message.clone(),
+            (Recording::On(scene), ..) => format!(
+                " ● recording, {} steps (r to stop)  {}",
+                scene.steps.len(),
+                HELP
+            ),
+            (Recording::Off, ..) => HELP.to_string(),
+        };
+        let screen = draw(out, theme, &layout, &rows, selected, &footer)?;
+        let hits = screen.hits;
+        if !event::poll(REFRESH)? {
+            report(&mut message, refresh(&mut rows, &mut selected, &layout));
+            continue;
+        }
+        let event = event::read()?;
+        if let Event::Key(key) = &event {
+            if key.kind == KeyEventKind::Press {
+                message = None;
+            }
+        }
+        if let Recording::Naming(scene, name) = &mut recording {
+            match event {
+                Event::Key(key) if key.kind == KeyEventKind::Press => match key.code {
+                    KeyCode::Char(c) => name.push(c),
+                    KeyCode::Backspace => {
+                        name.pop();
+                    }
+                    KeyCode::Enter => {
+                        message = Some(match scene.save(name) {
+                            Ok(_) => format!(" saved scene {:?}", name),
+                            Err(e) => format!(" {:#}", e),
+                        });
+                        recording = Recording::Off;
+                    }
+                    KeyCode::Esc => recording = Recording::Off,
+                    _ => (),
+                },
+                _ => (),
+            }
+            continue;
+        }
+        if let Some((target, action, _)) = pending {
+            match event {
+                Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    pending = None;
+                    if key.code == KeyCode::Char('y') {
+                        let result = perform(&rows, target, action, &mut recording);
+                        report(&mut message, refresh(&mut rows, &mut selected, &layout));
+                        report(&mut message, result);
+                    }
+                }
+                _ => (),
+            }
+            continue;
+        }
+        // the question to confirm an action with, where the action doesn't
+        // have a fixed one
+        let mut question = None;
+        let (target, action) = match event {
+            Event::Key(key) if key.kind == KeyEventKind::Press => match command(key.code) {
+                Some(Command::Quit) => return Ok(()),
+                Some(Command::Record) => {
+                    recording = match std::mem::replace(&mut recording, Recording::Off) {
+                        Recording::Off => Recording::On(Scene::default()),
+                        Recording::On(scene) if scene.steps.is_empty() => Recording::Off,
+                        Recording::On(scene) => Recording::Naming(scene, String::new()),
+                        naming => naming,
+                    };
+                    continue;
+                }
+                Some(Command::Select(by)) => {
+                    selected = step(&rows, &layout, selected, by);
+                    continue;
+                }
+                Some(Command::Sort) => {
+                    layout.sort = layout.sort.next();
+                    message = Some(format!(" sorted by {}", layout.sort.as_str()));
+                    report(&mut message, layout.save());
+                    report(&mut message, refresh(&mut rows, &mut selected, &layout));
+                    continue;
+                }
+                Some(Command::Collapse(section)) => {
+                    layout.toggle(section);
+                    report(&mut message, layout.save());
+                    continue;
+                }
+                Some(Command::Resize(by)) => {
+                    layout.resize(by);
+                    report(&mut message, layout.save());
+                    continue;
+                }
+                Some(Command::Act(action)) => (selected, action),
+                Some(Command::Profile) => {
+                    let next = match rows.get(selected) {
+                        Some(row) if !row.section.is_stream() => next_profile(row),
+                        _ => continue,
+                    };
+                    match next {
+                        Ok(Some((index, name))) => {
+                            question = Some(format!("switch the card to {}?", name));
+                            (selected, Action::Profile(index))
+                        }
+                        Ok(None) => {
+                            message = Some(" no other profile to switch to".to_string());
+                            continue;
+                        }
+                        Err(e) => {
+                            report(&mut message, Err(e));
+                            continue;
+                        }
+                    }
+                }
+                Some(Command::Undo) => {
+                    match undo(&mut recording) {
+                        Ok(true) => (),
+                        Ok(false) => message = Some(" nothing to undo".to_string()),
+                        Err(e) => report(&mut message, Err(e)),
+                    }
+                    report(&mut message, refresh(&mut rows, &mut selected, &layout));
+                    continue;
+                }
+                Some(Command::MoveStream) => {
+                    match rows.get(selected).and_then(|row| next_target(&rows, row)) {
+                        Some(id) => (selected, Action::MoveTo(id)),
+                        None => continue,
+                    }
+                }
+                None => continue,
+            },
+            Event::Mouse(mouse) => {
+                // clicking a heading collapses or expands its section
+                if let MouseEventKind::Down(MouseButton::Left) = mouse.kind {
+                    if let Some((_, section)) = screen.headings.iter().find(|h| h.0 == mouse.row) {
+                        layout.toggle(*section);
+                        report(&mut message, layout.save());
+                        continue;
+                    }
+                }
+                let hover = hits.iter().find(|h| h.y == mouse.row);
+                let dragged = dragging.and_then(|row| hits.iter().find(|h| h.row == row));
+                // a stream dropped onto a device moves it there
+                if let (MouseEventKind::Up(MouseButton::Left), Some(from), Some(to)) =
+                    (mouse.kind, dragged, hover)
+                {
+                    dragging = None;
+                    let (stream, device) = (&rows[from.row], &rows[to.row]);
+                    if stream.section.is_stream()
+                        && device.section == stream.section.devices()
+                        && stream.target != Some(device.id)
+                    {
+                        (from.row, Action::MoveTo(device.id))
+                    } else {
+                        continue;
+                    }
+                } else {
+                    let hit = match dragged.or(hover) {
+                        Some(hit) => hit,
+                        None => continue,
+                    };
+                    let is_stream = rows[hit.row].section.is_stream();
+                    match mouse.kind {
+                        MouseEventKind::ScrollUp => {
+                            (hit.row, Action::Volume(VolumeOp::Change(STEP)))
+                        }
+                        MouseEventKind::ScrollDown => {
+                            (hit.row, Action::Volume(VolumeOp::Change(-STEP)))
+                        }
+                        MouseEventKind::Down(MouseButton::Left) if is_stream => {
+                            selected = hit.row;
+                            dragging = Some(hit.row);
+                            continue;
+                        }
+                        MouseEventKind::Down(MouseButton::Left) if hit.on_meter(mouse.column) => {
+                            selected = hit.row;
+                            dragging = Some(hit.row);
+                            (
+                                hit.row,
+                                Action::Volume(VolumeOp::Set(hit.fraction(mouse.column))),
+                            )
+                        }
+                        MouseEventKind::Drag(MouseButton::Left)
+                            if dragging.is_some() && !is_stream =>
+                        {
+                            let volume = hit.fraction(mouse.column);
+                            // skip drag events that stay within the same cell
+                            if (rows[hit.row].volume - volume).abs() < f64::EPSILON {
+                                continue;
+                            }
+                            (hit.row, Action::Volume(VolumeOp::Set(volume)))
+                        }
+                        MouseEventKind::Down(MouseButton::Left)
+                            if hit.after_meter(mouse.column) =>
+                        {
+                            selected = hit.row;
+                            (hit.row, Action::Volume(VolumeOp::Mute(None)))
+                        }
+                        MouseEventKind::Down(MouseButton::Left) => {
+                            selected = hit.row;
+                            continue;
+                        }
+                        MouseEventKind::Down(MouseButton::Right) => {
+                            (hit.row, Action::Volume(VolumeOp::Mute(None)))
+                        }
+                        MouseEventKind::Up(_) => {
+                            dragging = None;
+                            continue;
+                        }
+                        _ => continue,
+                    }
+                }
+            }
+            _ => continue,
+        };
+        let question = question.or_else(|| action.prompt().map(str::to_string));
+        if let (true, Some(question)) = (config.confirm, question) {
+            pending = Some((target, action, question));
+            continue;
+        }
+        let result = perform(&rows, target, action, &mut recording);
+        report(&mut message, refresh(&mut rows, &mut selected, &layout));
+        report(&mut message, result);
+    }
+}
+
+/// Shows what went wrong in the status line, in place of the help, so that
+/// an action that fails, e.g. on a device unplugged a moment before, leaves
+/// the mixer open.
+fn report(message: &mut Option<String>, result: anyhow::Result<()>) {
+    if let Err(e) = result {
+        *message = Some(format!(" {:#}", e));
+    }
+}
+
+fn draw(
+    out: &mut impl Write,
+    theme: &Theme,
+    layout: &Layout,
+    rows: &[Row],
+    selected: usize,
+    footer: &str,
+) -> io::Result<Screen> {
+    let (width, height) = terminal::size()?;
+    let (width, height) = (width as usize, height as usize);
+    let border = theme.border_style.chars();
+    let inset = if border.is_some() { 1 } else { 0 };
+    let inner = width.saturating_sub(2 * inset);
+    queue!(out, terminal::Clear(ClearType::All))?;
+
+    // content lines, with the index of the row they show
+    let mut lines: Vec<(Option<usize>, String)> = Vec::new();
+    let mut hits = Vec::new();
+    let mut headings = Vec::new();
+    let name_width = (inner * usize::from(layout.name_width) / 100).max(8);
+    for (i, row) in rows.iter().enumerate() {
+        let collapsed = layout.is_collapsed(row.section);
+        if i == 0 || rows[i - 1].section != row.section {
+            headings.push(((lines.len() + inset) as u16, row.section));
+            let heading = if collapsed {
+                let hidden = rows.iter().filter(|r| r.section == row.section).count();
+                format!("▸ {} ({})", row.section.heading(), hidden)
+            } else {
+                format!("▾ {}", row.section.heading())
+            };
+            lines.push((None, heading));
+        }
+        if collapsed {
+            continue;
+        }
+        let marker = if row.default { '*' } else { ' ' };
+        let name: String = row.name.chars().take(name_width).collect();
+        let y = (lines.len() + inset) as u16;
+        let meter_start = (inset + name_width + 4) as u16;
+        if row.section.is_stream() {
+            let target = row
+                .target
+                .and_then(|id| rows.iter().find(|r| r.id == id))
+                .map_or("", |r| r.name.as_str());
+            hits.push(Hit {
+                y,
+                row: i,
+                meter_start,
+                meter_width: 0,
+            });
+            lines.push((
+                Some(i),
+                format!(
+                    " {} {:<name_width$} → {}",
+                    marker,
+                    name,
+                    target,
+                    name_width = name_width
+                ),
+            ));
+            continue;
+        }
+        let percent = format!("{:>4.0}%", row.volume * 100.0);
+        let mute = if row.mute { " muted" } else { "      " };
+        let meter_width = inner.saturating_sub(name_width + percent.len() + mute.len() + 6);
+        hits.push(Hit {
+            y,
+            row: i,
+            meter_start,
+            meter_width: meter_width as u16,
+        });
+        lines.push((
+            Some(i),
+            format!(
+                " {} {:<name_width$} {} {}{}",
+                marker,
+                name,
+                theme.meter(row.volume, meter_width),
+                percent,
+                mute,
+                name_width = name_width
+            ),
+        ));
+    }
+
+    if let Some(b) = &border {
+        let horizontal = b.horizontal.to_string().repeat(inner);
+        queue!(
+            out,
+            SetForegroundColor(theme.border.0),
+            cursor::MoveTo(0, 0),
+            Print(b.top_left),
+            Print(&horizontal),
+            Print(b.top_right),
+        )?;
+        for y in 1..height.saturating_sub(2) {
+            queue!(
+                out,
+                cursor::MoveTo(0, y as u16),
+                Print(b.vertical),
+                cursor::MoveTo((width - 1) as u16, y as u16),
+                Print(b.vertical),
+            )?;
+        }
+        queue!(
+            out,
+            cursor::MoveTo(0, height.saturating_sub(2) as u16),
+            Print(b.bottom_left),
+            Print(&horizontal),
+            Print(b.bottom_right),
+        )?;
+    }
+
+    let visible = height.saturating_sub(1 + 2 * inset);
+    hits.retain(|hit| usize::from(hit.y) < visible + inset);
+    headings.retain(|heading| usize::from(heading.0) < visible + inset);
+    for (y, (row, line)) in lines.iter().take(visible).enumerate() {
+        let line: String = line.chars().take(inner).collect();
+        let color = match row.map(|i| &rows[i]) {
+            Some(r) if r.mute => theme.muted.0,
+            Some(_) => theme.meter.0,
+            None => theme.text.0,
+        };
+        queue!(out, cursor::MoveTo(inset as u16, (y + inset) as u16))?;
+        if *row == Some(selected) {
+            queue!(
+                out,
+                SetForegroundColor(theme.selected.0),
+                SetAttribute(Attribute::Reverse),
+                Print(format!("{:<inner$}", line, inner = inner)),
+                SetAttribute(Attribute::Reset),
+            )?;
+        } else {
+            queue!(out, SetForegroundColor(color), Print(line))?;
+        }
+    }
+    queue!(
+        out,
+        SetForegroundColor(theme.text.0),
+        cursor::MoveTo(0, height.saturating_sub(1) as u16),
+        Print(footer.chars().take(width).collect::<String>()),
+        SetAttribute(Attribute::Reset),
+    )?;
+    out.flush()?;
+    Ok(Screen { hits, headings })
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(KeyCode::Char('q') => Some(Command::Quit))]
+    #[test_case(KeyCode::Esc => Some(Command::Quit))]
+    #[test_case(KeyCode::Char('k') => Some(Command::Select(-1)))]
+    #[test_case(KeyCode::Down => Some(Command::Select(1)))]
+    #[test_case(KeyCode::Char('3') => Some(Command::Collapse(Section::Playback)))]
+    #[test_case(KeyCode::Char('<') => Some(Command::Resize(-5)))]
+    #[test_case(KeyCode::Left => Some(Command::Act(Action::Volume(VolumeOp::Change(-STEP)))))]
+    #[test_case(KeyCode::Char('l') => Some(Command::Act(Action::Volume(VolumeOp::Change(STEP)))))]
+    #[test_case(KeyCode::Char('m') => Some(Command::Act(Action::Volume(VolumeOp::Mute(None)))))]
+    #[test_case(KeyCode::Char('M') => Some(Command::Act(Action::MuteAll)))]
+    #[test_case(KeyCode::Char('d') => Some(Command::Act(Action::SetDefault)))]
+    #[test_case(KeyCode::Char('p') => Some(Command::Profile))]
+    #[test_case(KeyCode::Char('t') => Some(Command::MoveStream))]
+    #[test_case(KeyCode::Char('u') => Some(Command::Undo))]
+    #[test_case(KeyCode::Char('5') => None)]
+    #[test_case(KeyCode::Enter => None)]
+    fn keys(code: KeyCode) -> Option<Command> {
+        command(code)
+    }
+
+    fn row(id: i64, section: Section) -> Row {
+        Row {
+            id,
+            node: format!("node-{}", id),
+            name: format!("Node {}", id),
+            section,
+            volume: 0.4,
+            mute: false,
+            default: false,
+            target: None,
+        }
+    }
+
+    #[test_case(0, Action::MuteAll => vec![(56, Action::Volume(VolumeOp::Mute(Some(true)))), (57, Action::Volume(VolumeOp::Mute(Some(true))))]; "mute all mutes only devices")]
+    #[test_case(1, Action::Volume(VolumeOp::Change(STEP)) => vec![(57, Action::Volume(VolumeOp::Change(STEP)))])]
+    #[test_case(2, Action::Volume(VolumeOp::Change(STEP)) => Vec::<(i64, Action)>::new(); "streams have no volume")]
+    #[test_case(2, Action::MoveTo(56) => vec![(80, Action::MoveTo(56))])]
+    #[test_case(0, Action::MoveTo(57) => Vec::<(i64, Action)>::new(); "devices don't move")]
+    #[test_case(1, Action::Profile(2) => vec![(57, Action::Profile(2))])]
+    #[test_case(9, Action::SetDefault => Vec::<(i64, Action)>::new(); "row gone")]
+    fn performed(target: usize, action: Action) -> Vec<(i64, Action)> {
+        let rows = [
+            row(56, Section::Output),
+            row(57, Section::Input),
+            row(80, Section::Playback),
+        ];
+        changes(&rows, target, action)
+            .into_iter()
+            .map(|(row, action)| (row.id, action))
+            .collect()
+    }
+
+    #[test]
+    fn reported() {
+        let mut message = None;
+        report(&mut message, Ok(()));
+        assert_eq!(message, None);
+        let err = anyhow::anyhow!("node 56 is gone").context("failed to mute");
+        report(&mut message, Err(err));
+        assert_eq!(message.as_deref(), Some(" failed to mute: node 56 is gone"));
+    }
+
+    #[test]
+    fn layout_round_trip() -> anyhow::Result<()> {
+        let layout = Layout {
+            name_width: 40,
+            sort: Sort::Volume,
+            collapsed: vec![Section::Playback],
+        };
+        let saved = toml::to_string(&layout)?;
+        assert_eq!(
+            saved,
+            "name-width = 40\nsort = \"volume\"\ncollapsed = [\"playback\"]\n"
+        );
+        assert_eq!(toml::from_str::<Layout>(&saved)?, layout);
+        assert_eq!(toml::from_str::<Layout>("")?, Layout::default());
+        Ok(())
+    }
+}
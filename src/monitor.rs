@@ -0,0 +1,135 @@
+//! `pw-volume monitor`: peak levels of what a sink is playing, or of what a
+//! source is recording, for a VU meter in a bar or OSD.
+
+use std::{
+    io::{self, BufReader, Read, Write},
+    process::Stdio,
+};
+
+use anyhow::{anyhow, Context};
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use crate::{tools, DeviceRoute, PipeWireInterfaceNode};
+
+const SAMPLE_RATE: u64 = 48_000;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("monitor")
+        .about("prints peak levels as percentages, one line per interval")
+        .arg(
+            Arg::with_name("interval")
+                .long("interval")
+                .value_name("MS")
+                .help("milliseconds covered by each line (default 100)")
+                .takes_value(true)
+                .validator(|s| match s.parse::<u64>() {
+                    Ok(ms) if ms > 0 => Ok(()),
+                    _ => Err(format!(r#""{}" is not a number of milliseconds"#, s)),
+                }),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help(r#"print a JSON object such as {"peak":[0.42,0.4]} instead"#),
+        )
+}
+
+/// The peak of each channel in interleaved signed 16-bit samples, as a
+/// fraction of full scale.
+fn peaks(buf: &[u8], channels: usize) -> Vec<f64> {
+    let mut peaks = vec![0.0; channels];
+    for (i, sample) in buf.chunks_exact(2).enumerate() {
+        let value = f64::from(i16::from_le_bytes([sample[0], sample[1]])).abs();
+        let peak = &mut peaks[i % channels];
+        *peak = f64::max(*peak, value / 32768.0);
+    }
+    peaks
+}
+
+fn line(peaks: &[f64], json: bool) -> String {
+    if json {
+        let peaks: Vec<f64> = peaks
+            .iter()
+            .map(|p| (p * 1000.0).round() / 1000.0)
+            .collect();
+        serde_json::json!({ "peak": peaks }).to_string()
+    } else {
+        peaks
+            .iter()
+            .map(|p| format!("{:.0}", p * 100.0))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+pub fn run(
+    matches: &ArgMatches<'_>,
+    node: &PipeWireInterfaceNode<'_>,
+    route: &DeviceRoute<'_>,
+) -> anyhow::Result<i32> {
+    let interval = matches
+        .value_of("interval")
+        .map_or(Ok(100), str::parse::<u64>)?;
+    let json = matches.is_present("json");
+    let channels = route.props.channel_volumes.len();
+    let mut record = tools::command("pw-record");
+    record
+        .arg("--target")
+        .arg(node.info.props.node_name)
+        .args(["--format", "s16", "--rate", &SAMPLE_RATE.to_string()])
+        .args(["--channels", &channels.to_string()]);
+    if node.info.props.media_class != Some("Audio/Source") {
+        // record what the sink plays rather than a source of the same name
+        record.args(["--properties", "{ stream.capture.sink = true }"]);
+    }
+    let mut child = tools::spawn(record.arg("-").stdout(Stdio::piped()))
+        .context("failed to execute pw-record")?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("failed to read from pw-record"))?;
+    let mut reader = BufReader::new(stdout);
+    let mut buf = vec![0; (SAMPLE_RATE * interval / 1000) as usize * channels * 2];
+    let out = io::stdout();
+    let result = loop {
+        match reader.read_exact(&mut buf) {
+            Ok(()) => (),
+            // pw-record stopped, e.g. because the node went away
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break Ok(1),
+            Err(e) => break Err(e.into()),
+        }
+        let mut out = out.lock();
+        match writeln!(out, "{}", line(&peaks(&buf, channels), json)).and_then(|()| out.flush()) {
+            Ok(()) => (),
+            // the bar went away
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => break Ok(0),
+            Err(e) => break Err(e.into()),
+        }
+    };
+    tools::kill(&mut child);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    fn samples(values: &[i16]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test_case(&[0, 0, 0, 0], 2 => vec![0.0, 0.0]; "silence")]
+    #[test_case(&[16384, -8192, -16384, 4096], 2 => vec![0.5, 0.25])]
+    #[test_case(&[-32768, 0], 1 => vec![1.0])]
+    fn channel_peaks(values: &[i16], channels: usize) -> Vec<f64> {
+        peaks(&samples(values), channels)
+    }
+
+    #[test_case(false => "50 25")]
+    #[test_case(true => r#"{"peak":[0.5,0.25]}"#)]
+    fn peak_line(json: bool) -> String {
+        line(&[0.5, 0.25], json)
+    }
+}
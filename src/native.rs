@@ -0,0 +1,505 @@
+//! The native backend, which talks to PipeWire through libpipewire instead of
+//! running pw-dump and pw-cli: `--backend native`, in a pw-volume built with
+//! the `native` cargo feature.
+//!
+//! Like pw-cli, it sets the `Route` param of the node's device, so it changes
+//! the same volume as PipeWire's tools and the default backend, where wpctl
+//! and pactl change the node's.
+
+use std::{
+    cell::{Cell, RefCell},
+    convert::TryFrom,
+    io::Cursor,
+    rc::Rc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context as _};
+use clap::ArgMatches;
+use pipewire::{
+    context::Context,
+    core::{Core, PW_ID_CORE},
+    device::Device,
+    main_loop::MainLoop,
+    metadata::Metadata,
+    node::Node as PwNode,
+    properties::Properties,
+    registry::{GlobalObject, Registry},
+    spa::{
+        self,
+        param::ParamType,
+        pod::{
+            deserialize::PodDeserializer, serialize::PodSerializer, Object, Pod, Property, Value,
+            ValueArray,
+        },
+        utils::Id,
+    },
+    types::ObjectType,
+};
+
+use crate::{
+    backend::Node,
+    calibrate::Trims,
+    config::Config,
+    dry_run,
+    error::{Failure, Kind},
+    format::Status,
+    log, scale, Target,
+};
+
+/// How long PipeWire has to answer before the command fails.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A connection to PipeWire. The fields are dropped in order, the registry
+/// before the core, and the core before its context.
+struct Connection {
+    registry: Registry,
+    core: Core,
+    _context: Context,
+    mainloop: MainLoop,
+}
+
+impl Connection {
+    fn new() -> anyhow::Result<Connection> {
+        pipewire::init();
+        let mainloop = MainLoop::new(None).context("failed to create a PipeWire main loop")?;
+        let context = Context::new(&mainloop).context("failed to create a PipeWire context")?;
+        let core = context.connect(None).map_err(|e| {
+            Failure::new(
+                Kind::PipewireNotRunning,
+                format!("failed to connect to PipeWire: {}", e),
+            )
+        })?;
+        let registry = core
+            .get_registry()
+            .context("failed to get PipeWire's registry")?;
+        Ok(Connection {
+            registry,
+            core,
+            _context: context,
+            mainloop,
+        })
+    }
+
+    /// Runs the main loop until PipeWire has answered everything sent so
+    /// far, so that the events it sent back have been seen.
+    fn roundtrip(&self) -> anyhow::Result<()> {
+        let pending = self.core.sync(0).context("failed to sync with PipeWire")?;
+        let done = Rc::new(Cell::new(false));
+        let failure = Rc::new(RefCell::new(None));
+        let timed_out = Rc::new(Cell::new(false));
+        let _listener = self
+            .core
+            .add_listener_local()
+            .done({
+                let (done, mainloop) = (done.clone(), self.mainloop.clone());
+                move |id, seq| {
+                    if id == PW_ID_CORE && seq == pending {
+                        done.set(true);
+                        mainloop.quit();
+                    }
+                }
+            })
+            .error({
+                let (failure, mainloop) = (failure.clone(), self.mainloop.clone());
+                move |id, _, res, message| {
+                    *failure.borrow_mut() = Some(format!("object {}: {} ({})", id, message, res));
+                    mainloop.quit();
+                }
+            })
+            .register();
+        let timer = self.mainloop.loop_().add_timer({
+            let (timed_out, mainloop) = (timed_out.clone(), self.mainloop.clone());
+            move |_| {
+                timed_out.set(true);
+                mainloop.quit();
+            }
+        });
+        timer
+            .update_timer(Some(TIMEOUT), None)
+            .into_result()
+            .context("failed to arm a timer")?;
+        while !done.get() {
+            self.mainloop.run();
+            if let Some(message) = failure.take() {
+                return Err(
+                    Failure::new(Kind::PwCliFailed, format!("PipeWire: {}", message)).into(),
+                );
+            }
+            if timed_out.get() {
+                return Err(anyhow!("PipeWire didn't answer within {:?}", TIMEOUT));
+            }
+        }
+        Ok(())
+    }
+
+    /// Every global object in the registry.
+    fn globals(&self) -> anyhow::Result<Vec<GlobalObject<Properties>>> {
+        let globals = Rc::new(RefCell::new(Vec::new()));
+        let _listener = self
+            .registry
+            .add_listener_local()
+            .global({
+                let globals = globals.clone();
+                move |global| globals.borrow_mut().push(global.to_owned())
+            })
+            .register();
+        self.roundtrip()?;
+        Ok(globals.take())
+    }
+
+    /// The name of the default node for `key`, e.g. `default.audio.sink`,
+    /// which the `default` metadata holds as `{"name": "…"}`.
+    fn default(
+        &self,
+        globals: &[GlobalObject<Properties>],
+        key: &'static str,
+    ) -> anyhow::Result<Option<String>> {
+        let global = match globals.iter().find(|g| {
+            g.type_ == ObjectType::Metadata && prop(g, "metadata.name") == Some("default")
+        }) {
+            Some(global) => global,
+            None => return Ok(None),
+        };
+        let metadata: Metadata = self.registry.bind(global)?;
+        let value = Rc::new(RefCell::new(None));
+        let _listener = metadata
+            .add_listener_local()
+            .property({
+                let value = value.clone();
+                move |subject, k, _, v| {
+                    if subject == PW_ID_CORE && k == Some(key) {
+                        *value.borrow_mut() = v.map(str::to_string);
+                    }
+                    0
+                }
+            })
+            .register();
+        self.roundtrip()?;
+        let value = value.take();
+        Ok(value.and_then(|value| {
+            let value: serde_json::Value = serde_json::from_str(&value).ok()?;
+            value["name"].as_str().map(str::to_string)
+        }))
+    }
+}
+
+/// A property of a global object.
+fn prop<'a>(global: &'a GlobalObject<Properties>, key: &str) -> Option<&'a str> {
+    global.props.as_ref()?.get(key)
+}
+
+/// A device's route, as the `Route` param describes it.
+#[derive(Debug, Default, PartialEq)]
+struct Route {
+    index: i32,
+    /// Whether the route is an output rather than an input.
+    output: bool,
+    /// The device the route belongs to, which `card.profile.device` names.
+    device: i32,
+    name: Option<String>,
+    description: Option<String>,
+    mute: bool,
+    channel_volumes: Vec<f32>,
+}
+
+impl Route {
+    /// Reads a `Route` param, or `None` if it isn't one.
+    fn parse(value: &Value) -> Option<Route> {
+        let object = match value {
+            Value::Object(object) if object.type_ == spa::sys::SPA_TYPE_OBJECT_ParamRoute => object,
+            _ => return None,
+        };
+        let mut route = Route::default();
+        for property in &object.properties {
+            match (property.key, &property.value) {
+                (spa::sys::SPA_PARAM_ROUTE_index, Value::Int(index)) => route.index = *index,
+                (spa::sys::SPA_PARAM_ROUTE_direction, Value::Id(Id(direction))) => {
+                    route.output = *direction == spa::sys::SPA_DIRECTION_OUTPUT
+                }
+                (spa::sys::SPA_PARAM_ROUTE_device, Value::Int(device)) => route.device = *device,
+                (spa::sys::SPA_PARAM_ROUTE_name, Value::String(name)) => {
+                    route.name = Some(name.clone())
+                }
+                (spa::sys::SPA_PARAM_ROUTE_description, Value::String(description)) => {
+                    route.description = Some(description.clone())
+                }
+                (spa::sys::SPA_PARAM_ROUTE_props, Value::Object(props)) => {
+                    for property in &props.properties {
+                        match (property.key, &property.value) {
+                            (spa::sys::SPA_PROP_mute, Value::Bool(mute)) => route.mute = *mute,
+                            (
+                                spa::sys::SPA_PROP_channelVolumes,
+                                Value::ValueArray(ValueArray::Float(volumes)),
+                            ) => route.channel_volumes = volumes.clone(),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Some(route)
+    }
+
+    /// The `Route` param that sets this route to `mute` and `channel_volumes`,
+    /// and saves them, as pw-cli's `{ "save": true }` would.
+    fn command(&self, mute: bool, channel_volumes: Vec<f32>) -> Value {
+        Value::Object(Object {
+            type_: spa::sys::SPA_TYPE_OBJECT_ParamRoute,
+            id: spa::sys::SPA_PARAM_Route,
+            properties: vec![
+                Property::new(spa::sys::SPA_PARAM_ROUTE_index, Value::Int(self.index)),
+                Property::new(spa::sys::SPA_PARAM_ROUTE_device, Value::Int(self.device)),
+                Property::new(
+                    spa::sys::SPA_PARAM_ROUTE_props,
+                    Value::Object(Object {
+                        type_: spa::sys::SPA_TYPE_OBJECT_Props,
+                        id: spa::sys::SPA_PARAM_Route,
+                        properties: vec![
+                            Property::new(spa::sys::SPA_PROP_mute, Value::Bool(mute)),
+                            Property::new(
+                                spa::sys::SPA_PROP_channelVolumes,
+                                Value::ValueArray(ValueArray::Float(channel_volumes)),
+                            ),
+                        ],
+                    }),
+                ),
+                Property::new(spa::sys::SPA_PARAM_ROUTE_save, Value::Bool(true)),
+            ],
+        })
+    }
+}
+
+/// What the native backend found for the `--target` node. The device is
+/// dropped before the connection it was bound on.
+struct Found {
+    device: Device,
+    connection: Connection,
+    /// The id of the node's device.
+    device_id: u32,
+    status: Status,
+    route: Route,
+}
+
+/// The node that `--target` names, as the native backend looks it up: the
+/// default sink or source, a node id, or the node name an alias stands for.
+pub enum Native {
+    Default(&'static str),
+    Id(u32),
+    Name(String),
+}
+
+impl Native {
+    /// Finds the node and its device's active route, as `parse_dump` does
+    /// with pw-dump's output.
+    fn find(&self) -> anyhow::Result<Found> {
+        let connection = Connection::new()?;
+        let globals = connection.globals()?;
+        let name = match self {
+            Native::Default(key) => Some(connection.default(&globals, key)?.ok_or_else(|| {
+                let kind = if *key == "default.audio.source" {
+                    Kind::NoDefaultSource
+                } else {
+                    Kind::NoDefaultSink
+                };
+                Failure::new(kind, format!("no {} in the default metadata", key))
+            })?),
+            Native::Name(name) => Some(name.clone()),
+            Native::Id(_) => None,
+        };
+        let global = globals
+            .iter()
+            .find(|g| {
+                g.type_ == ObjectType::Node
+                    && match (self, &name) {
+                        (Native::Id(id), _) => g.id == *id,
+                        (_, Some(name)) => prop(g, "node.name") == Some(name.as_str()),
+                        _ => false,
+                    }
+            })
+            .ok_or_else(|| Failure::new(Kind::NoNode, "the node is not in the graph"))?;
+        let node_name = prop(global, "node.name").unwrap_or_default().to_string();
+        let description = prop(global, "node.description").map(str::to_string);
+        let source = prop(global, "media.class").is_some_and(|class| class.contains("Source"));
+        let device_id: u32 = prop(global, "device.id")
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(|| Failure::new(Kind::NoDevice, format!("{} has no device", node_name)))?;
+
+        // card.profile.device is among the node's properties, but not among
+        // those that the registry announces
+        let node: PwNode = connection.registry.bind(global)?;
+        let profile_device = Rc::new(Cell::new(None));
+        let _node_listener = node
+            .add_listener_local()
+            .info({
+                let profile_device = profile_device.clone();
+                move |info| {
+                    let device = info
+                        .props()
+                        .and_then(|props| props.get("card.profile.device"))
+                        .and_then(|device| device.parse::<i32>().ok());
+                    if device.is_some() {
+                        profile_device.set(device);
+                    }
+                }
+            })
+            .register();
+
+        let device_global = globals
+            .iter()
+            .find(|g| g.type_ == ObjectType::Device && g.id == device_id)
+            .ok_or_else(|| Failure::new(Kind::NoDevice, format!("no device {}", device_id)))?;
+        let device: Device = connection.registry.bind(device_global)?;
+        let routes = Rc::new(RefCell::new(Vec::new()));
+        let _device_listener = device
+            .add_listener_local()
+            .param({
+                let routes = routes.clone();
+                move |_, id, _, _, pod| {
+                    let pod = match pod {
+                        Some(pod) if id == ParamType::Route => pod,
+                        _ => return,
+                    };
+                    if let Ok((_, value)) = PodDeserializer::deserialize_any_from(pod.as_bytes()) {
+                        routes.borrow_mut().extend(Route::parse(&value));
+                    }
+                }
+            })
+            .register();
+        device.enum_params(0, Some(ParamType::Route), 0, u32::MAX);
+        connection.roundtrip()?;
+
+        let profile_device = profile_device.get();
+        let route = routes
+            .take()
+            .into_iter()
+            .find(|route| route.output != source && Some(route.device) == profile_device)
+            .ok_or_else(|| {
+                Failure::new(Kind::NoRoute, format!("{} has no active route", node_name))
+            })?;
+        let volumes: Vec<f64> = route
+            .channel_volumes
+            .iter()
+            .map(|&v| f64::from(v))
+            .collect();
+        let status = Status {
+            id: i64::from(global.id),
+            volume: scale(&node_name).scaled(volumes.first().copied().unwrap_or_default()),
+            mute: route.mute,
+            name: description.unwrap_or_else(|| node_name.clone()),
+            node: node_name,
+            port: route.description.clone(),
+            port_name: route.name.clone(),
+            channels: volumes.len(),
+            device: prop(device_global, "device.description").map(str::to_string),
+            channel_volumes: volumes,
+            ..Status::default()
+        };
+        Ok(Found {
+            device,
+            connection,
+            device_id,
+            status,
+            route,
+        })
+    }
+
+    /// Sets the route's mute state and volumes, or prints the pw-cli command
+    /// that would, for `--dry-run`.
+    fn set(&self, found: &Found, mute: bool, mut channel_volumes: Vec<f64>) -> anyhow::Result<()> {
+        Trims::load()?.apply(&found.status.node, &mut channel_volumes);
+        let json = serde_json::json!({
+            "index": found.route.index,
+            "device": found.route.device,
+            "props": { "mute": mute, "channelVolumes": channel_volumes },
+            "save": true,
+        })
+        .to_string();
+        let device = found.device_id.to_string();
+        if dry_run("pw-cli", &["set-param", &device, "Route", &json]) {
+            return Ok(());
+        }
+        log::debug(
+            1,
+            format_args!("setting Route on device {} to {}", device, json),
+        );
+        let volumes = channel_volumes.iter().map(|&v| v as f32).collect();
+        let (cursor, _) =
+            PodSerializer::serialize(Cursor::new(Vec::new()), &found.route.command(mute, volumes))
+                .map_err(|e| anyhow!("failed to build the Route param: {:?}", e))?;
+        let bytes = cursor.into_inner();
+        let pod =
+            Pod::from_bytes(&bytes).ok_or_else(|| anyhow!("failed to build the Route param"))?;
+        found.device.set_param(ParamType::Route, 0, pod);
+        found.connection.roundtrip()
+    }
+}
+
+impl Node for Native {
+    fn status(&self) -> anyhow::Result<Status> {
+        Ok(self.find()?.status)
+    }
+
+    fn set_volume(&self, volume: f64) -> anyhow::Result<()> {
+        let found = self.find()?;
+        let channels = found.status.channels.max(1);
+        self.set(&found, found.status.mute, vec![volume; channels])
+    }
+
+    fn set_mute(&self, mute: Option<bool>) -> anyhow::Result<()> {
+        let found = self.find()?;
+        let mute = mute.unwrap_or(!found.status.mute);
+        // pw-cli leaves the volumes out of a mute as well
+        self.set(&found, mute, Vec::new())
+    }
+}
+
+/// The `--target` node.
+pub fn node(matches: &ArgMatches<'_>, config: &Config) -> anyhow::Result<Native> {
+    match matches.value_of("target") {
+        Some(alias) if config.aliases.contains_key(alias) => {
+            let name = &config.aliases[alias];
+            log::debug(1, format_args!("{} is an alias for {}", alias, name));
+            Ok(Native::Name(name.clone()))
+        }
+        Some(s) => Ok(match s.parse().map_err(anyhow::Error::msg)? {
+            Target::DefaultSink => Native::Default("default.audio.sink"),
+            Target::DefaultSource => Native::Default("default.audio.source"),
+            Target::Id(id) => {
+                Native::Id(u32::try_from(id).map_err(|_| anyhow!("{} is not a node id", id))?)
+            }
+        }),
+        None => Ok(Native::Default("default.audio.sink")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_round_trip() -> anyhow::Result<()> {
+        let route = Route {
+            index: 1,
+            output: true,
+            device: 2,
+            ..Route::default()
+        };
+        let value = route.command(false, vec![0.5, 0.25]);
+        let (cursor, _) = PodSerializer::serialize(Cursor::new(Vec::new()), &value)
+            .map_err(|e| anyhow!("{:?}", e))?;
+        let bytes = cursor.into_inner();
+        let (_, value) =
+            PodDeserializer::deserialize_any_from(&bytes).map_err(|e| anyhow!("{:?}", e))?;
+        assert_eq!(
+            Route::parse(&value),
+            Some(Route {
+                output: false,
+                channel_volumes: vec![0.5, 0.25],
+                ..route
+            })
+        );
+        Ok(())
+    }
+}
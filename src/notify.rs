@@ -0,0 +1,133 @@
+//! The `[notify]` section's on-screen display: a desktop notification with
+//! the new volume after `change` and `mute`, or after those given `--notify`,
+//! which replaces the previous one rather than stacking up while a key is
+//! held.
+//!
+//! The previous notification is replaced by its id, which notify-send prints
+//! and `$XDG_RUNTIME_DIR/pw-volume/notification` keeps until the next one,
+//! and for notification servers that go by it instead, by the synchronous
+//! hint that Notify OSD and dunst know.
+
+use std::{fs, path::PathBuf, sync::atomic::Ordering};
+
+use clap::{Arg, ArgMatches};
+
+use crate::{
+    config::{runtime_dir, NotifyConfig},
+    format::Status,
+    tools, DeviceRoute, PipeWireCommand, PipeWireInterfaceNode, DRY_RUN,
+};
+
+/// `--notify`, shared by `change` and `mute`.
+pub fn arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("notify").long("notify").help(
+        "show a desktop notification with the new volume, as [notify] in the config file does",
+    )
+}
+
+/// The `[notify]` section, turned on if the subcommand was given `--notify`.
+pub fn config(config: NotifyConfig, arg: &ArgMatches<'_>) -> NotifyConfig {
+    NotifyConfig {
+        enabled: config.enabled || arg.is_present("notify"),
+        ..config
+    }
+}
+
+fn id_path() -> Option<PathBuf> {
+    runtime_dir().map(|dir| dir.join("notification"))
+}
+
+/// The freedesktop icon for a volume percentage, which shows as muted at 0%
+/// too, as it sounds.
+fn icon(percentage: f64, mute: bool) -> &'static str {
+    if mute || percentage.round() <= 0.0 {
+        "audio-volume-muted"
+    } else if percentage < 100.0 / 3.0 {
+        "audio-volume-low"
+    } else if percentage < 200.0 / 3.0 {
+        "audio-volume-medium"
+    } else {
+        "audio-volume-high"
+    }
+}
+
+/// The `value` hint that dunst, mako, and the like draw as a progress bar,
+/// which they take as a percentage of a full bar, so that a volume above 100%
+/// fills it rather than overflowing.
+fn value(percentage: f64) -> u32 {
+    percentage.round().clamp(0.0, 100.0) as u32
+}
+
+/// Shows what `cmd` set the node to.
+pub fn volume(
+    config: NotifyConfig,
+    node: &PipeWireInterfaceNode<'_>,
+    route: &DeviceRoute<'_>,
+    cmd: &PipeWireCommand,
+) {
+    let status = Status::after(node, route, cmd);
+    show(config, &status.name, status.volume, status.mute);
+}
+
+/// Shows a node's name, its volume on its scale as a percentage, and whether
+/// it's muted. Failing to show it doesn't fail the change.
+pub fn show(config: NotifyConfig, name: &str, volume: f64, mute: bool) {
+    if !config.enabled || DRY_RUN.load(Ordering::Relaxed) {
+        return;
+    }
+    let percentage = volume * 100.0;
+    let mut notify = tools::command("notify-send");
+    notify
+        .args(["--app-name", "pw-volume", "--icon"])
+        .arg(icon(percentage, mute))
+        .arg(format!("--hint=int:value:{}", value(percentage)))
+        .arg("--hint=string:x-canonical-private-synchronous:pw-volume");
+    if let Some(timeout) = config.timeout_ms {
+        notify.arg(format!("--expire-time={}", timeout));
+    }
+    let body = if mute {
+        format!("{:.0}% (muted)", percentage)
+    } else {
+        format!("{:.0}%", percentage)
+    };
+    let mut replacing = tools::command("notify-send");
+    replacing.args(notify.get_args()).arg("--print-id");
+    let path = id_path();
+    if let Some(id) = path.as_ref().and_then(|path| fs::read_to_string(path).ok()) {
+        replacing.arg(format!("--replace-id={}", id.trim()));
+    }
+    match tools::output(replacing.arg(name).arg(&body)) {
+        Ok(output) if output.status.success() => {
+            let id = String::from_utf8_lossy(&output.stdout)
+                .trim()
+                .parse::<u32>();
+            if let (Some(path), Ok(id)) = (path, id) {
+                if let Some(dir) = path.parent() {
+                    let _ = fs::create_dir_all(dir);
+                }
+                let _ = fs::write(&path, id.to_string());
+            }
+        }
+        // notify-send before libnotify 0.7.10 has no ids
+        _ => {
+            let _ = tools::status(notify.arg(name).arg(body));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(40.0, true => ("audio-volume-muted", 40))]
+    #[test_case(0.2, false => ("audio-volume-muted", 0))]
+    #[test_case(20.0, false => ("audio-volume-low", 20))]
+    #[test_case(50.0, false => ("audio-volume-medium", 50))]
+    #[test_case(90.0, false => ("audio-volume-high", 90))]
+    #[test_case(130.0, false => ("audio-volume-high", 100))]
+    fn progress(percentage: f64, mute: bool) -> (&'static str, u32) {
+        (icon(percentage, mute), value(percentage))
+    }
+}
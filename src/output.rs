@@ -0,0 +1,398 @@
+//! Status bar protocols that wrap the label rendered by a [`Format`].
+//!
+//! [`Format`]: crate::format::Format
+
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::{
+    config::Config,
+    format::{DeviceKind, State, Status, Tier},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    /// A single line with `%{F...}` color tags for a polybar script module.
+    Polybar,
+    /// i3blocks' `full_text`, `short_text`, and `color` lines.
+    I3blocks,
+    /// xmobar markup with `<fc>` colors and click and scroll actions.
+    Xmobar,
+    /// Conky text with `${color}` objects, for `${execpi}`.
+    Conky,
+    /// The XML of xfce4-genmon-plugin: an icon, the label, and a tooltip.
+    Genmon,
+    /// A sentence for reading in a terminal; ignores the label.
+    Plain,
+    /// One comma-separated row of sink, volume, mute, and channels; ignores
+    /// the label.
+    Csv,
+    /// Like `Csv`, but tab-separated.
+    Tsv,
+    /// A JSON object with everything known about the node, for widgets that
+    /// need more than Waybar's fields; ignores the label.
+    VerboseJson,
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "polybar" => Ok(Output::Polybar),
+            "i3blocks" => Ok(Output::I3blocks),
+            "xmobar" => Ok(Output::Xmobar),
+            "conky" => Ok(Output::Conky),
+            "genmon" => Ok(Output::Genmon),
+            "plain" => Ok(Output::Plain),
+            "csv" => Ok(Output::Csv),
+            "tsv" => Ok(Output::Tsv),
+            "verbose-json" => Ok(Output::VerboseJson),
+            _ => Err(format!(r#""{}" is not an output mode"#, s)),
+        }
+    }
+}
+
+impl Output {
+    pub fn render(self, status: &Status, label: &str, config: &Config) -> String {
+        match self {
+            Output::Polybar => polybar(status, label, &config.polybar),
+            Output::I3blocks => i3blocks(status, label, &config.i3blocks),
+            Output::Xmobar => xmobar(status, label, &config.xmobar),
+            Output::Conky => conky(status, label, &config.conky),
+            Output::Genmon => genmon(status, label, config),
+            Output::Plain => plain(status),
+            Output::Csv => delimited(status, ','),
+            Output::Tsv => delimited(status, '\t'),
+            Output::VerboseJson => verbose_json(status),
+        }
+    }
+}
+
+/// Foreground colors for each state, set in the `[polybar]`, `[i3blocks]`,
+/// `[xmobar]`, `[conky]`, and `[genmon]` sections of the config file; an empty
+/// string leaves the bar's color.
+/// The defaults are taken from polybar's sample config.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StateColors {
+    pub ok: String,
+    pub warning: String,
+    pub muted: String,
+    pub boosted: String,
+}
+
+impl Default for StateColors {
+    fn default() -> Self {
+        StateColors {
+            ok: String::new(),
+            warning: "#F0C674".to_string(),
+            muted: "#707880".to_string(),
+            boosted: "#A54242".to_string(),
+        }
+    }
+}
+
+impl StateColors {
+    pub fn color(&self, state: State) -> &str {
+        match state {
+            State::Ok => &self.ok,
+            State::Warning => &self.warning,
+            State::Muted => &self.muted,
+            State::Boosted => &self.boosted,
+        }
+    }
+}
+
+fn polybar(status: &Status, label: &str, colors: &StateColors) -> String {
+    let color = colors.color(status.state());
+    if color.is_empty() {
+        label.to_string()
+    } else {
+        format!("%{{F{}}}{}%{{F-}}", color, label)
+    }
+}
+
+/// The short text drops the icon so the block can shrink when space is tight.
+fn i3blocks(status: &Status, label: &str, colors: &StateColors) -> String {
+    let short = if status.mute {
+        "muted".to_string()
+    } else {
+        format!("{:.0}%", status.percentage())
+    };
+    format!("{}\n{}\n{}", label, short, colors.color(status.state()))
+}
+
+/// Clicking toggles mute and scrolling changes the volume. The label is
+/// passed through `<raw>` when it contains markup characters, such as the
+/// ASCII icons.
+fn xmobar(status: &Status, label: &str, colors: &StateColors) -> String {
+    let mut text = if label.contains('<') {
+        format!("<raw={}:{}/>", label.chars().count(), label)
+    } else {
+        label.to_string()
+    };
+    let color = colors.color(status.state());
+    if !color.is_empty() {
+        text = format!("<fc={}>{}</fc>", color, text);
+    }
+    format!(
+        "<action=`pw-volume mute toggle` button=1>\
+         <action=`pw-volume change +5%` button=4>\
+         <action=`pw-volume change -5%` button=5>\
+         {}</action></action></action>",
+        text
+    )
+}
+
+/// `$` starts a text object in conky, so the label's are doubled.
+fn conky(status: &Status, label: &str, colors: &StateColors) -> String {
+    let label = label.replace('$', "$$");
+    let color = colors.color(status.state());
+    if color.is_empty() {
+        label
+    } else {
+        format!("${{color {}}}{}${{color}}", color, label)
+    }
+}
+
+/// The label is Pango markup in genmon, so it is escaped before coloring.
+/// Clicking the icon or the label toggles mute.
+fn genmon(status: &Status, label: &str, config: &Config) -> String {
+    let escape = |s: &str| {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    };
+    let icon = if status.mute {
+        "audio-volume-muted"
+    } else {
+        match config.thresholds.tier(status) {
+            Tier::Low => "audio-volume-low",
+            Tier::Medium => "audio-volume-medium",
+            Tier::High => "audio-volume-high",
+        }
+    };
+    let mut text = escape(label);
+    let color = config.genmon.color(status.state());
+    if !color.is_empty() {
+        text = format!("<span foreground='{}'>{}</span>", color, text);
+    }
+    format!(
+        "<icon>{}</icon>\n\
+         <iconclick>pw-volume mute toggle</iconclick>\n\
+         <txt>{}</txt>\n\
+         <txtclick>pw-volume mute toggle</txtclick>\n\
+         <tool>{}</tool>",
+        icon,
+        text,
+        escape(&plain(status))
+    )
+}
+
+/// E.g. `Speakers (alsa_output.pci-0000_00_1f.3.analog-stereo): 42% [unmuted]`.
+fn plain(status: &Status) -> String {
+    format!(
+        "{} ({}): {:.0}% [{}]",
+        status.port.as_deref().unwrap_or(&status.name),
+        status.node,
+        status.percentage(),
+        if status.mute { "muted" } else { "unmuted" }
+    )
+}
+
+/// Columns are the node name, the volume as a whole percentage, `true` or
+/// `false` for mute, and the number of channels.
+fn delimited(status: &Status, separator: char) -> String {
+    row(
+        &[
+            status.node.clone(),
+            format!("{:.0}", status.percentage()),
+            status.mute.to_string(),
+            status.channels.to_string(),
+        ],
+        separator,
+    )
+}
+
+/// One row of `--output csv` or `tsv`. Fields containing the separator, a
+/// quote, or a line break are quoted as in RFC 4180.
+pub fn row(fields: &[String], separator: char) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            if field.contains([separator, '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(&separator.to_string())
+}
+
+fn verbose_json(status: &Status) -> String {
+    serde_json::json!({
+        "id": status.id,
+        "node": status.node,
+        "name": status.name,
+        "device": status.device,
+        "port": status.port,
+        "port_name": status.port_name,
+        "device_kind": status.device_kind().map(DeviceKind::as_str),
+        "battery": status.battery,
+        "percentage": status.percentage().round() as i64,
+        "mute": status.mute,
+        "state": status.state().as_str(),
+        "channel_map": status.channel_map,
+        "channel_volumes": status.channel_volumes,
+        "volume_base": status.volume_base,
+        "compress": status.compress,
+        "agc": status.agc,
+        "mic_active": status.mic_active,
+        "fade": status.fade.map(|fade| serde_json::json!({
+            "target": (fade.target * 100.0).round() as i64,
+            "progress": fade.progress,
+            "remaining": fade.remaining,
+        })),
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(false, 0.4 => "<) 40%")]
+    #[test_case(false, 0.95 => "%{F#F0C674}<) 95%%{F-}")]
+    #[test_case(true, 0.4 => "%{F#707880}<) 40%%{F-}")]
+    fn polybar_colors(mute: bool, volume: f64) -> String {
+        let status = Status {
+            volume,
+            mute,
+            ..Default::default()
+        };
+        Output::Polybar.render(
+            &status,
+            &format!("<) {:.0}%", volume * 100.0),
+            &Config::default(),
+        )
+    }
+
+    #[test_case(false, 0.4 => "<) 40%\n40%\n")]
+    #[test_case(true, 0.4 => "<x\nmuted\n#707880")]
+    fn i3blocks_lines(mute: bool, volume: f64) -> String {
+        let status = Status {
+            volume,
+            mute,
+            ..Default::default()
+        };
+        let label = if mute { "<x" } else { "<) 40%" };
+        Output::I3blocks.render(&status, label, &Config::default())
+    }
+
+    #[test_case(false, "42%" => "42%")]
+    #[test_case(false, "<) 42%" => "<raw=6:<) 42%/>")]
+    #[test_case(true, "<x" => "<fc=#707880><raw=2:<x/></fc>")]
+    fn xmobar_markup(mute: bool, label: &str) -> String {
+        let status = Status {
+            volume: 0.42,
+            mute,
+            ..Default::default()
+        };
+        let rendered = Output::Xmobar.render(&status, label, &Config::default());
+        let actions = "<action=`pw-volume mute toggle` button=1>\
+                       <action=`pw-volume change +5%` button=4>\
+                       <action=`pw-volume change -5%` button=5>";
+        rendered
+            .strip_prefix(actions)
+            .and_then(|rest| rest.strip_suffix("</action></action></action>"))
+            .unwrap()
+            .to_string()
+    }
+
+    #[test_case(false, "$ 42%" => "$$ 42%")]
+    #[test_case(true, "muted" => "${color #707880}muted${color}")]
+    fn conky_text(mute: bool, label: &str) -> String {
+        let status = Status {
+            volume: 0.42,
+            mute,
+            ..Default::default()
+        };
+        Output::Conky.render(&status, label, &Config::default())
+    }
+
+    #[test]
+    fn genmon_xml() {
+        let status = Status {
+            volume: 0.42,
+            node: "alsa_output.analog-stereo".to_string(),
+            port: Some("Speakers".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            Output::Genmon.render(&status, "<) 42%", &Config::default()),
+            "<icon>audio-volume-medium</icon>\n\
+             <iconclick>pw-volume mute toggle</iconclick>\n\
+             <txt>&lt;) 42%</txt>\n\
+             <txtclick>pw-volume mute toggle</txtclick>\n\
+             <tool>Speakers (alsa_output.analog-stereo): 42% [unmuted]</tool>"
+        );
+    }
+
+    #[test]
+    fn plain_sentence() {
+        let status = Status {
+            volume: 0.42,
+            name: "Built-in Audio Analog Stereo".to_string(),
+            node: "alsa_output.pci-0000_00_1f.3.analog-stereo".to_string(),
+            port: Some("Speakers".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            Output::Plain.render(&status, "", &Config::default()),
+            "Speakers (alsa_output.pci-0000_00_1f.3.analog-stereo): 42% [unmuted]"
+        );
+    }
+
+    #[test_case(Output::Csv, "alsa_output.analog-stereo" => "alsa_output.analog-stereo,42,true,2")]
+    #[test_case(Output::Tsv, "alsa_output.analog-stereo" => "alsa_output.analog-stereo\t42\ttrue\t2")]
+    #[test_case(Output::Csv, "odd,\"name\"" => "\"odd,\"\"name\"\"\",42,true,2")]
+    #[test_case(Output::Csv, "two\nlines" => "\"two\nlines\",42,true,2")]
+    #[test_case(Output::Tsv, "carriage\rreturn" => "\"carriage\rreturn\"\t42\ttrue\t2")]
+    fn delimited_row(output: Output, node: &str) -> String {
+        let status = Status {
+            volume: 0.42,
+            mute: true,
+            node: node.to_string(),
+            channels: 2,
+            ..Default::default()
+        };
+        output.render(&status, "", &Config::default())
+    }
+
+    #[test]
+    fn verbose_json_fields() -> anyhow::Result<()> {
+        let status = Status {
+            id: 53,
+            volume: 0.42,
+            node: "alsa_output.analog-stereo".to_string(),
+            device: Some("Built-in Audio".to_string()),
+            channel_map: vec!["FL".to_string(), "FR".to_string()],
+            channel_volumes: vec![0.42, 0.42],
+            volume_base: Some(1.0),
+            ..Default::default()
+        };
+        let rendered = Output::VerboseJson.render(&status, "", &Config::default());
+        let value: serde_json::Value = serde_json::from_str(&rendered)?;
+        assert_eq!(value["id"], 53);
+        assert_eq!(value["percentage"], 42);
+        assert_eq!(value["device"], "Built-in Audio");
+        assert_eq!(value["channel_map"], serde_json::json!(["FL", "FR"]));
+        assert_eq!(value["port"], serde_json::Value::Null);
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+//! The pactl backend, for machines where only pipewire-pulse, PipeWire's
+//! PulseAudio server, can be reached, e.g. from inside a Flatpak sandbox with
+//! the `--socket=pulseaudio` permission: the basic subcommands are translated
+//! to `pactl set-sink-volume`, `set-sink-mute`, and their source
+//! counterparts.
+//!
+//! PulseAudio's volumes are cubic, with 65536 at 100%, but pactl takes
+//! linear factors too.
+
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use clap::ArgMatches;
+use serde::Deserialize;
+
+use crate::{
+    backend::Node,
+    config::Config,
+    dry_run,
+    error::{self, Failure, Kind},
+    format::Status,
+    log, scale, shell_words, tools, Target,
+};
+
+/// `PA_VOLUME_NORM`, the volume of a channel at 100%.
+const NORM: f64 = 65536.0;
+
+/// A sink or source in `pactl --format=json list sinks`.
+#[derive(Deserialize, Debug, PartialEq)]
+struct Device {
+    index: i64,
+    name: String,
+    #[serde(default)]
+    description: Option<String>,
+    mute: bool,
+    /// Each channel's volume by position, e.g. `front-left`.
+    volume: BTreeMap<String, Channel>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct Channel {
+    value: u32,
+}
+
+impl Device {
+    /// The loudest channel's linear volume, which is what pavucontrol shows
+    /// as the device's volume.
+    fn volume(&self) -> f64 {
+        let value = self.volume.values().map(|c| c.value).max().unwrap_or(0);
+        (f64::from(value) / NORM).powi(3)
+    }
+}
+
+/// Runs pactl, returning what it printed.
+fn pactl(args: &[&str]) -> anyhow::Result<String> {
+    log::debug(1, format_args!("running {}", shell_words("pactl", args)));
+    let output =
+        tools::output(tools::command("pactl").args(args)).map_err(|e| error::tool("pactl", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Failure::new(
+            Kind::NoNode,
+            format!("pactl {} failed: {}", args.join(" "), stderr.trim()),
+        )
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Runs pactl to change a device, unless this is a dry run.
+fn set(args: &[&str]) -> anyhow::Result<()> {
+    if dry_run("pactl", args) {
+        return Ok(());
+    }
+    pactl(args).map(|_| ())
+}
+
+/// Every sink, or every source.
+fn list(class: &str) -> anyhow::Result<Vec<Device>> {
+    let output = pactl(&["--format=json", "list", &format!("{}s", class)])?;
+    serde_json::from_str(&output).context("failed to parse pactl's output")
+}
+
+/// A sink or source, by name.
+pub struct Pactl {
+    /// `sink` or `source`.
+    class: &'static str,
+    name: String,
+}
+
+impl Pactl {
+    /// The first sink, or else source, that `matches`.
+    fn find(what: &str, matches: impl Fn(&Device) -> bool) -> anyhow::Result<Pactl> {
+        for class in ["sink", "source"] {
+            if let Some(device) = list(class)?.into_iter().find(&matches) {
+                return Ok(Pactl {
+                    class,
+                    name: device.name,
+                });
+            }
+        }
+        Err(Failure::new(Kind::NoNode, format!("no sink or source {}", what)).into())
+    }
+}
+
+impl Node for Pactl {
+    fn status(&self) -> anyhow::Result<Status> {
+        let device = list(self.class)?
+            .into_iter()
+            .find(|device| device.name == self.name)
+            .ok_or_else(|| Failure::new(Kind::NoNode, format!("{} went away", self.name)))?;
+        Ok(Status {
+            id: device.index,
+            volume: scale(&device.name).scaled(device.volume()),
+            mute: device.mute,
+            name: device
+                .description
+                .clone()
+                .unwrap_or_else(|| device.name.clone()),
+            node: device.name,
+            channels: device.volume.len(),
+            ..Status::default()
+        })
+    }
+
+    fn set_volume(&self, volume: f64) -> anyhow::Result<()> {
+        // with a decimal point, pactl takes the volume as a linear factor
+        let volume = format!("{:.4}", volume);
+        set(&[&format!("set-{}-volume", self.class), &self.name, &volume])
+    }
+
+    fn set_mute(&self, mute: Option<bool>) -> anyhow::Result<()> {
+        let mute = match mute {
+            Some(true) => "1",
+            Some(false) => "0",
+            None => "toggle",
+        };
+        set(&[&format!("set-{}-mute", self.class), &self.name, mute])
+    }
+}
+
+/// The `--target` node. A number is the device's index, as pactl numbers
+/// it, which `status` reports.
+pub fn node(matches: &ArgMatches<'_>, config: &Config) -> anyhow::Result<Pactl> {
+    let default = |class| -> anyhow::Result<Pactl> {
+        let name = pactl(&[&format!("get-default-{}", class)])?;
+        Ok(Pactl {
+            class,
+            name: name.trim().to_string(),
+        })
+    };
+    match matches.value_of("target") {
+        Some(alias) if config.aliases.contains_key(alias) => {
+            let name = &config.aliases[alias];
+            log::debug(1, format_args!("{} is an alias for {}", alias, name));
+            Pactl::find(name, |device| &device.name == name)
+        }
+        Some(s) => match s.parse().map_err(anyhow::Error::msg)? {
+            Target::DefaultSink => default("sink"),
+            Target::DefaultSource => default("source"),
+            Target::Id(id) => Pactl::find(&id.to_string(), |device| device.index == id),
+        },
+        None => default("sink"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_list() -> anyhow::Result<()> {
+        let output = r#"[{"index":56,"state":"SUSPENDED","name":"alsa_output.pci-0000_00_1f.3.analog-stereo","description":"Built-in Audio Analog Stereo","driver":"PipeWire","mute":false,"volume":{"front-left":{"value":32768,"value_percent":"50%","db":"-18.06 dB"},"front-right":{"value":26214,"value_percent":"40%","db":"-23.88 dB"}},"balance":-0.2}]"#;
+        let devices: Vec<Device> = serde_json::from_str(output)?;
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].index, 56);
+        assert_eq!(
+            devices[0].description.as_deref(),
+            Some("Built-in Audio Analog Stereo")
+        );
+        assert!((devices[0].volume() - 0.125).abs() < 1e-9);
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+//! Compatibility with pamixer's command-line flags, so that existing
+//! keybindings and scripts can call pw-volume unchanged. The flags are
+//! accepted through `pw-volume pamixer ...`, or directly when the binary is
+//! invoked under the name `pamixer` (e.g. through a symlink or hardlink).
+
+use std::{ffi::OsString, path::Path};
+
+use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, SubCommand};
+
+use crate::{
+    format::Status, route_command, set_route, DeviceRoute, PipeWireInterfaceNode, VolumeOp,
+};
+
+pub fn invoked_as_pamixer(args: &[OsString]) -> bool {
+    args.first()
+        .and_then(|arg0| Path::new(arg0).file_name())
+        .is_some_and(|name| name == "pamixer")
+}
+
+fn is_percentage(value: String) -> Result<(), String> {
+    value
+        .parse::<u32>()
+        .map(|_| ())
+        .map_err(|_| format!(r#""{}" is not an integer percentage"#, value))
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("pamixer")
+        .about("accepts pamixer-compatible flags, e.g. '--increase 5'")
+        .setting(AppSettings::ArgRequiredElseHelp)
+        .arg(
+            Arg::with_name("increase")
+                .short("i")
+                .long("increase")
+                .value_name("N")
+                .help("increase the volume by N percent")
+                .validator(is_percentage),
+        )
+        .arg(
+            Arg::with_name("decrease")
+                .short("d")
+                .long("decrease")
+                .value_name("N")
+                .help("decrease the volume by N percent")
+                .validator(is_percentage),
+        )
+        .arg(
+            Arg::with_name("set-volume")
+                .long("set-volume")
+                .value_name("N")
+                .help("set the volume to N percent")
+                .validator(is_percentage),
+        )
+        .arg(
+            Arg::with_name("toggle-mute")
+                .short("t")
+                .long("toggle-mute")
+                .help("switch between mute and unmute"),
+        )
+        .arg(
+            Arg::with_name("mute")
+                .short("m")
+                .long("mute")
+                .help("set mute"),
+        )
+        .arg(
+            Arg::with_name("unmute")
+                .short("u")
+                .long("unmute")
+                .help("unset mute"),
+        )
+        .arg(
+            Arg::with_name("get-volume")
+                .long("get-volume")
+                .help("get the current volume"),
+        )
+        .arg(
+            Arg::with_name("get-volume-human")
+                .long("get-volume-human")
+                .help("get the current volume percentage or the string \"muted\""),
+        )
+        .arg(
+            Arg::with_name("get-mute")
+                .long("get-mute")
+                .help("get the current mute status"),
+        )
+        // a mute flag combines with a volume flag, but the getters stand alone
+        .group(ArgGroup::with_name("volume").args(&["increase", "decrease", "set-volume"]))
+        .group(ArgGroup::with_name("mute-state").args(&["toggle-mute", "mute", "unmute"]))
+        .group(
+            ArgGroup::with_name("query")
+                .args(&["get-volume", "get-volume-human", "get-mute"])
+                .conflicts_with_all(&["volume", "mute-state"]),
+        )
+}
+
+/// Runs a pamixer-style invocation. Like pamixer, the getters exit with 1
+/// when the output is unmuted or the volume is zero. A mute flag given with
+/// a volume flag is applied first, and the volume change then keeps the
+/// mute state it left, where on its own a volume change unmutes.
+pub fn run(
+    matches: &ArgMatches<'_>,
+    node: &PipeWireInterfaceNode<'_>,
+    route: &DeviceRoute<'_>,
+) -> anyhow::Result<i32> {
+    let percent = |name| -> anyhow::Result<f64> {
+        Ok(matches.value_of(name).unwrap_or_default().parse::<f64>()? * 0.01)
+    };
+    // on the node's scale, as --set-volume sets it and status shows it
+    let volume = Status::new(node, route).percentage().round();
+    let mute = route.props.mute;
+    if matches.is_present("get-volume") {
+        println!("{}", volume);
+        return Ok(if volume > 0.0 { 0 } else { 1 });
+    } else if matches.is_present("get-volume-human") {
+        if mute {
+            println!("muted");
+        } else {
+            println!("{}%", volume);
+        }
+        return Ok(0);
+    } else if matches.is_present("get-mute") {
+        println!("{}", mute);
+        return Ok(if mute { 0 } else { 1 });
+    }
+    let mute_op = if matches.is_present("toggle-mute") {
+        Some(VolumeOp::Mute(None))
+    } else if matches.is_present("mute") {
+        Some(VolumeOp::Mute(Some(true)))
+    } else if matches.is_present("unmute") {
+        Some(VolumeOp::Mute(Some(false)))
+    } else {
+        None
+    };
+    let volume_op = if matches.is_present("increase") {
+        Some(VolumeOp::Change(percent("increase")?))
+    } else if matches.is_present("decrease") {
+        Some(VolumeOp::Change(-percent("decrease")?))
+    } else if matches.is_present("set-volume") {
+        Some(VolumeOp::Set(percent("set-volume")?))
+    } else {
+        None
+    };
+    let cmd = match (mute_op, volume_op) {
+        (Some(op), None) | (None, Some(op)) => route_command(op, node, route),
+        (Some(mute_op), Some(volume_op)) => {
+            let mute = route_command(mute_op, node, route).props.mute;
+            let mut cmd = route_command(volume_op, node, route);
+            cmd.props.mute = mute;
+            cmd
+        }
+        (None, None) => unreachable!("argument parsing should have failed by now"),
+    };
+    set_route(node, &cmd)?;
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("pamixer" => true)]
+    #[test_case("/usr/local/bin/pamixer" => true)]
+    #[test_case("pw-volume" => false)]
+    fn argv0(name: &str) -> bool {
+        invoked_as_pamixer(&[name.into(), "--get-volume".into()])
+    }
+
+    #[test_case(&["--mute", "--increase", "5"] => true)]
+    #[test_case(&["--toggle-mute", "--set-volume", "40"] => true)]
+    #[test_case(&["--increase", "5", "--decrease", "5"] => false)]
+    #[test_case(&["--mute", "--unmute"] => false)]
+    #[test_case(&["--get-volume", "--mute"] => false)]
+    #[test_case(&["--get-volume", "--get-mute"] => false)]
+    fn combined_flags(flags: &[&str]) -> bool {
+        subcommand()
+            .get_matches_from_safe(std::iter::once("pamixer").chain(flags.iter().copied()))
+            .is_ok()
+    }
+}
@@ -0,0 +1,391 @@
+//! `pw-volume set-port`: switches a device's route to another of its ports,
+//! e.g. a card's speakers to its headphones, as a desktop's sound settings
+//! do. The port has to be one the active profile has; an HDMI port usually
+//! needs a profile of its own, which `doctor` or the desktop's settings pick.
+//! `pw-volume list-ports` lists them, for choosing one.
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde_json::json;
+
+use crate::{
+    error::{Failure, Kind},
+    log, output, parse_dump, pw_dump, set_param, unmarshal, DevicePort, PipeWireInterfaceDevice,
+    PipeWireObject, Target,
+};
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("set-port")
+        .about("switches a device to another port, e.g. 'set-port \"Built-in Audio\" headphones'")
+        .setting(AppSettings::ArgRequiredElseHelp)
+        .arg(
+            Arg::with_name("DEVICE")
+                .help("the device's id, name, e.g. 'alsa_card.pci-0000_00_1f.3', or description")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("PORT")
+                .help("the port's index, name, e.g. 'analog-output-headphones', or description")
+                .takes_value(true)
+                .required(true),
+        )
+}
+
+pub fn list_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("list-ports")
+        .about("lists a device's ports, whether each is plugged in, and which are active")
+        .arg(
+            Arg::with_name("DEVICE")
+                .help(
+                    "the device's id, name, or description; the default sink's device if left out",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("print a JSON array of the ports instead, e.g. for a device picker"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .help("print a row per port instead: index, name, direction, availability, active, in profile, and description")
+                .takes_value(true)
+                .possible_values(&["csv", "tsv"])
+                .conflicts_with("json"),
+        )
+}
+
+pub fn describe(device: &PipeWireInterfaceDevice<'_>) -> String {
+    let props = device.info.props.as_ref();
+    props
+        .and_then(|props| props.device_description.as_deref())
+        .or_else(|| props.and_then(|props| props.device_name.as_deref()))
+        .map_or_else(|| format!("device {}", device.id), str::to_string)
+}
+
+/// The audio device that `arg` is the id, name, or description of.
+pub fn find<'a>(
+    obj: &'a [PipeWireObject<'a>],
+    arg: &str,
+) -> Option<&'a PipeWireInterfaceDevice<'a>> {
+    obj.iter()
+        .filter_map(|o| match o {
+            PipeWireObject::Device(d)
+                if d.typ == "PipeWire:Interface:Device" && !d.info.params.enum_route.is_empty() =>
+            {
+                Some(d)
+            }
+            _ => None,
+        })
+        .find(|d| {
+            let props = d.info.props.as_ref();
+            d.id.to_string() == arg
+                || props.and_then(|p| p.device_name.as_deref()) == Some(arg)
+                || props
+                    .and_then(|p| p.device_description.as_deref())
+                    .is_some_and(|description| description.eq_ignore_ascii_case(arg))
+        })
+}
+
+/// Whether `arg` is the port's index, name, or description.
+fn is_port(port: &DevicePort<'_>, arg: &str) -> bool {
+    port.index.to_string() == arg
+        || port.name == arg
+        || port
+            .description
+            .as_deref()
+            .is_some_and(|description| description.eq_ignore_ascii_case(arg))
+}
+
+/// The `Route` param that makes `arg` the device's active port, and the
+/// port.
+fn route<'a>(
+    device: &'a PipeWireInterfaceDevice<'a>,
+    arg: &str,
+) -> anyhow::Result<(serde_json::Value, &'a DevicePort<'a>)> {
+    let params = &device.info.params;
+    let port = match params.enum_route.iter().find(|port| is_port(port, arg)) {
+        Some(port) => port,
+        None => {
+            let ports: Vec<_> = params
+                .enum_route
+                .iter()
+                .map(|port| match &port.description {
+                    Some(description) => format!("{} ({})", port.name, description),
+                    None => port.name.to_string(),
+                })
+                .collect();
+            return Err(Failure::new(
+                Kind::NoRoute,
+                format!(
+                    r#"{} has no port "{}"; its ports are {}"#,
+                    describe(device),
+                    arg,
+                    ports.join(", ")
+                ),
+            )
+            .into());
+        }
+    };
+    let profile = params.profile.first().map(|profile| profile.index);
+    if !profile.is_some_and(|index| port.profiles.contains(&index)) {
+        return Err(Failure::new(
+            Kind::NoRoute,
+            format!(
+                "{}'s {} isn't in its active profile",
+                describe(device),
+                port.name
+            ),
+        )
+        .into());
+    }
+    // the profile device whose route is switched, e.g. the card's sink rather
+    // than its source, is the one the active route of that direction is on
+    let active = params
+        .route
+        .iter()
+        .filter(|route| route.direction == port.direction)
+        .filter_map(|route| route.device)
+        .find(|id| port.devices.contains(id));
+    let profile_device = match active.or_else(|| port.devices.first().copied()) {
+        Some(id) => id,
+        None => {
+            return Err(Failure::new(
+                Kind::NoRoute,
+                format!("{}'s {} has no route", describe(device), port.name),
+            )
+            .into())
+        }
+    };
+    let value = json!({ "index": port.index, "device": profile_device, "save": true });
+    Ok((value, port))
+}
+
+/// How `list-ports` and `list-profiles` show whether a port is plugged in,
+/// or a profile's ports are.
+pub fn availability(available: Option<&str>) -> &'static str {
+    match available {
+        Some("yes") => "available",
+        Some("no") => "unavailable",
+        _ => "unknown",
+    }
+}
+
+/// Whether the port is the active route of its direction.
+fn active(device: &PipeWireInterfaceDevice<'_>, port: &DevicePort<'_>) -> bool {
+    device
+        .info
+        .params
+        .route
+        .iter()
+        .any(|route| route.index == port.index && route.direction == port.direction)
+}
+
+/// Whether the active profile has the port, so that `set-port` can switch to
+/// it.
+fn in_profile(device: &PipeWireInterfaceDevice<'_>, port: &DevicePort<'_>) -> bool {
+    let params = &device.info.params;
+    params
+        .profile
+        .first()
+        .is_some_and(|profile| port.profiles.contains(&profile.index))
+}
+
+/// The lines `list-ports` prints: the active ports marked with `*`, and
+/// those of other profiles noted.
+fn table(device: &PipeWireInterfaceDevice<'_>) -> String {
+    let ports = &device.info.params.enum_route;
+    let width = ports.iter().map(|port| port.name.len()).max().unwrap_or(0);
+    ports
+        .iter()
+        .map(|port| {
+            let mut line = format!(
+                "{} {:>2} {:<width$}  {:<6}  {:<11}  {}",
+                if active(device, port) { '*' } else { ' ' },
+                port.index,
+                port.name,
+                port.direction.to_lowercase(),
+                availability(port.available),
+                port.description.as_deref().unwrap_or_default(),
+                width = width,
+            );
+            if !in_profile(device, port) {
+                line.push_str(" (another profile)");
+            }
+            format!("{}\n", line.trim_end())
+        })
+        .collect()
+}
+
+/// The rows `list-ports --output csv` or `tsv` prints, with the same columns
+/// as the table's, less its marks.
+fn delimited(device: &PipeWireInterfaceDevice<'_>, separator: char) -> String {
+    device
+        .info
+        .params
+        .enum_route
+        .iter()
+        .map(|port| {
+            let fields = [
+                port.index.to_string(),
+                port.name.to_string(),
+                port.direction.to_lowercase(),
+                availability(port.available).to_string(),
+                active(device, port).to_string(),
+                in_profile(device, port).to_string(),
+                port.description.as_deref().unwrap_or_default().to_string(),
+            ];
+            format!("{}\n", output::row(&fields, separator))
+        })
+        .collect()
+}
+
+fn json(device: &PipeWireInterfaceDevice<'_>) -> serde_json::Value {
+    device
+        .info
+        .params
+        .enum_route
+        .iter()
+        .map(|port| {
+            json!({
+                "index": port.index,
+                "name": port.name,
+                "description": port.description,
+                "direction": port.direction.to_lowercase(),
+                "available": availability(port.available),
+                "active": active(device, port),
+                "in_profile": in_profile(device, port),
+            })
+        })
+        .collect()
+}
+
+/// The device that `arg` is, or the default sink's when it's left out.
+pub fn device<'a>(
+    obj: &'a [PipeWireObject<'a>],
+    arg: Option<&str>,
+) -> anyhow::Result<&'a PipeWireInterfaceDevice<'a>> {
+    let arg = match arg {
+        Some(arg) => arg.to_string(),
+        None => {
+            let (node, _) = parse_dump(obj, Target::DefaultSink)?;
+            node.info.props.device_id.to_string()
+        }
+    };
+    find(obj, &arg).ok_or_else(|| {
+        Failure::new(
+            Kind::NoDevice,
+            format!(r#"there is no audio device "{}""#, arg),
+        )
+        .into()
+    })
+}
+
+pub fn run_list(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let buf = pw_dump()?;
+    let obj = unmarshal(&buf)?;
+    let device = device(&obj, matches.value_of("DEVICE"))?;
+    if matches.is_present("json") {
+        println!("{}", json(device));
+    } else if let Some(output) = matches.value_of("output") {
+        print!(
+            "{}",
+            delimited(device, if output == "tsv" { '\t' } else { ',' })
+        );
+    } else {
+        print!("{}", table(device));
+    }
+    Ok(())
+}
+
+pub fn run(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let buf = pw_dump()?;
+    let obj = unmarshal(&buf)?;
+    let device = device(&obj, matches.value_of("DEVICE"))?;
+    let (value, port) = route(device, matches.value_of("PORT").unwrap_or_default())?;
+    if port.available == Some("no") {
+        log::debug(
+            1,
+            format_args!("{} is unplugged, so may stay silent", port.name),
+        );
+    }
+    set_param(device.id, "Route", &value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use test_case::test_case;
+
+    use super::*;
+
+    fn dump() -> Vec<u8> {
+        let path: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "testdata",
+            "dump_aria_16.txt",
+        ]
+        .iter()
+        .collect();
+        fs::read(path).unwrap()
+    }
+
+    #[test_case("Built-in Audio", "headphones" => Ok(r#"{"device":8,"index":3,"save":true}"#.to_string()))]
+    #[test_case("alsa_card.pci-0000_00_1f.3", "analog-input-headset-mic" => Ok(r#"{"device":0,"index":1,"save":true}"#.to_string()))]
+    #[test_case("42", "2" => Ok(r#"{"device":8,"index":2,"save":true}"#.to_string()))]
+    #[test_case("Built-in Audio", "hdmi-output-0" => Err("Built-in Audio's hdmi-output-0 isn't in its active profile".to_string()))]
+    #[test_case("Built-in Audio", "line-in" => Err(r#"Built-in Audio has no port "line-in"; its ports are "#.to_string()))]
+    #[test_case("Galaxy Buds", "headset-output" => Err(r#"there is no audio device "Galaxy Buds""#.to_string()))]
+    fn ports(device: &str, port: &str) -> Result<String, String> {
+        let buf = dump();
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf).unwrap();
+        let device = find(&obj, device)
+            .ok_or_else(|| format!(r#"there is no audio device "{}""#, device))?;
+        match route(device, port) {
+            Ok((value, _)) => Ok(value.to_string()),
+            // the list of ports is long
+            Err(e) => Err(e
+                .to_string()
+                .split_inclusive("are ")
+                .next()
+                .unwrap_or_default()
+                .to_string()),
+        }
+    }
+
+    #[test]
+    fn listed() -> anyhow::Result<()> {
+        let buf = dump();
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf)?;
+        let device = find(&obj, "Built-in Audio").unwrap();
+        let table = table(device);
+        let lines: Vec<_> = table.lines().take(5).collect();
+        assert_eq!(
+            lines,
+            [
+                "*  0 analog-input-internal-mic  input   unknown      Internal Microphone",
+                "   1 analog-input-headset-mic   input   unavailable  Headset Microphone",
+                "*  2 analog-output-speaker      output  unknown      Speakers",
+                "   3 analog-output-headphones   output  unavailable  Headphones",
+                "   4 hdmi-output-0              output  unavailable  HDMI / DisplayPort (another profile)",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test_case(',', 2 => "2,analog-output-speaker,output,unknown,true,true,Speakers")]
+    #[test_case('\t', 4 => "4\thdmi-output-0\toutput\tunavailable\tfalse\tfalse\tHDMI / DisplayPort")]
+    fn delimited_row(separator: char, line: usize) -> String {
+        let buf = dump();
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf).unwrap();
+        let device = find(&obj, "Built-in Audio").unwrap();
+        delimited(device, separator)
+            .lines()
+            .nth(line)
+            .unwrap()
+            .to_string()
+    }
+}
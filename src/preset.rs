@@ -0,0 +1,150 @@
+//! Volume presets: `pw-volume preset save night` keeps the default sink's
+//! volume and mute, or with `--all` those of every sink and source, and
+//! `preset load night` brings them back. They are state files, as `apply`
+//! takes, stored under `$XDG_STATE_HOME/pw-volume/presets`.
+
+use std::{fs, io, path::PathBuf};
+
+use anyhow::{anyhow, Context};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use crate::{
+    apply::{self, State},
+    config::{state_dir, Config},
+    cork::Corked,
+    default_name,
+    error::{Failure, Kind},
+    pw_dump, scene, unmarshal, PipeWireObject,
+};
+
+fn preset_dir() -> anyhow::Result<PathBuf> {
+    state_dir()
+        .map(|dir| dir.join("presets"))
+        .ok_or_else(|| anyhow!("failed to determine state directory"))
+}
+
+fn preset_path(name: &str) -> anyhow::Result<PathBuf> {
+    anyhow::ensure!(
+        !name.is_empty() && !name.contains('/') && !name.starts_with('.'),
+        "invalid preset name: {:?}",
+        name
+    );
+    Ok(preset_dir()?.join(format!("{}.toml", name)))
+}
+
+/// The volume and mute of the default sink, or of every sink and source,
+/// leaving out the default devices and applications.
+fn preset(config: &Config, obj: &[PipeWireObject<'_>], all: bool) -> anyhow::Result<State> {
+    let mut state = State {
+        default_sink: None,
+        default_source: None,
+        apps: Vec::new(),
+        // presets leave applications out, so whether they are corked doesn't matter
+        ..apply::export(config, obj, &Corked::default())
+    };
+    if !all {
+        let sink = default_name(obj, "default.audio.sink")
+            .ok_or_else(|| Failure::new(Kind::NoDefaultSink, "no default sink"))?;
+        // the export names nodes by their alias where they have one
+        state.devices.retain(|level| {
+            config
+                .aliases
+                .get(&level.name)
+                .map_or(level.name.as_str(), String::as_str)
+                == sink
+        });
+    }
+    Ok(state)
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("preset")
+        .about("saves and restores the volume and mute of devices by name")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("save")
+                .about("saves the default sink's volume and mute, replacing a preset of the name")
+                .arg(Arg::with_name("NAME").required(true))
+                .arg(
+                    Arg::with_name("all")
+                        .long("all")
+                        .help("save every sink and source rather than only the default sink"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("load")
+                .about("restores the devices in a preset, printing what changes")
+                .arg(Arg::with_name("NAME").required(true)),
+        )
+        .subcommand(SubCommand::with_name("list").about("lists saved presets"))
+}
+
+pub fn run(matches: &ArgMatches<'_>, config: &Config) -> anyhow::Result<()> {
+    match matches.subcommand() {
+        ("save", Some(arg)) => {
+            let path = preset_path(arg.value_of("NAME").unwrap_or_default())?;
+            let buf = pw_dump()?;
+            let obj = unmarshal(&buf)?;
+            let state = preset(config, &obj, arg.is_present("all"))?;
+            fs::create_dir_all(preset_dir()?)?;
+            fs::write(&path, toml::to_string(&state)?)
+                .with_context(|| format!("failed to write {}", path.display()))
+        }
+        ("load", Some(arg)) => {
+            let name = arg.value_of("NAME").unwrap_or_default();
+            let path = preset_path(name)?;
+            let contents = match fs::read_to_string(&path) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Err(Failure::new(Kind::Other, format!("no preset {:?}", name)).into())
+                }
+                result => result.with_context(|| format!("failed to read preset {:?}", name))?,
+            };
+            let state: State = toml::from_str(&contents)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            apply::converge(&state, config, false).map(|_| ())
+        }
+        ("list", _) => {
+            for name in scene::names(&preset_dir()?)? {
+                println!("{}", name);
+            }
+            Ok(())
+        }
+        (_, _) => unreachable!("argument parsing should have failed by now"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn default_sink_only() -> anyhow::Result<()> {
+        let path: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "testdata",
+            "with_discord.txt",
+        ]
+        .iter()
+        .collect();
+        let buf = fs::read(path)?;
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf)?;
+        let sink = default_name(&obj, "default.audio.sink").unwrap_or_default();
+        let config = Config {
+            aliases: BTreeMap::from([("speakers".to_string(), sink.to_string())]),
+            ..Config::default()
+        };
+        let state = preset(&config, &obj, false)?;
+        let names: Vec<&str> = state.devices.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, ["speakers"]);
+        assert!(state.devices[0].volume.is_some());
+        assert_eq!(state.default_sink, None);
+        assert!(state.apps.is_empty());
+
+        let all = preset(&config, &obj, true)?;
+        assert!(all.devices.len() > 1);
+        Ok(())
+    }
+}
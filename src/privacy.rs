@@ -0,0 +1,281 @@
+//! The daemon's privacy guard: tells the user whenever an application starts
+//! recording from a source, like the microphone indicators of desktops. With
+//! an allowlist, it also mutes recordings until the user approves them.
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{dry_run, hook, set_param, streams, tools, PipeWireObject};
+
+/// How long a question waits for an answer. A recording left unanswered
+/// stays muted.
+const ASK_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// The `[privacy]` section of the config file, which turns the guard on.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrivacyConfig {
+    /// Show a desktop notification with `notify-send`.
+    pub notify: bool,
+    /// A shell command to run, with the application and source in
+    /// `$PW_VOLUME_APP` and `$PW_VOLUME_SOURCE`, and `$PW_VOLUME_MUTED` set
+    /// to 1 if the recording was muted.
+    pub hook: Option<String>,
+    /// Applications that may record. When set, any other application's
+    /// recording is muted until it is allowed from the notification.
+    pub allow: Option<Vec<String>>,
+    /// Applications whose recordings are always muted, without asking.
+    pub deny: Vec<String>,
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> Self {
+        PrivacyConfig {
+            notify: true,
+            hook: None,
+            allow: None,
+            deny: Vec::new(),
+        }
+    }
+}
+
+/// A stream recording from a source.
+#[derive(Debug, PartialEq)]
+struct Capture<'a> {
+    stream: i64,
+    app: &'a str,
+    source: &'a str,
+}
+
+/// Streams that are recording from a source right now. Recordings of a sink's
+/// monitor, such as `pw-volume monitor`, aren't included.
+fn captures<'a>(obj: &'a [PipeWireObject<'_>]) -> Vec<Capture<'a>> {
+    let source = |id: i64| {
+        obj.iter().find_map(|o| match o {
+            PipeWireObject::Node(n)
+                if n.id == id && n.info.props.media_class == Some("Audio/Source") =>
+            {
+                Some(
+                    n.info
+                        .props
+                        .node_description
+                        .as_deref()
+                        .unwrap_or(n.info.props.node_name),
+                )
+            }
+            _ => None,
+        })
+    };
+    streams(obj)
+        .into_iter()
+        .filter(|(s, _)| {
+            s.info.props.media_class.starts_with("Stream/Input/") && s.info.state == Some("running")
+        })
+        .filter_map(|(s, peers)| {
+            let source = peers.into_iter().find_map(source)?;
+            let app = s
+                .info
+                .props
+                .application_name
+                .as_deref()
+                .unwrap_or(&s.info.props.node_name);
+            Some(Capture {
+                stream: s.id,
+                app,
+                source,
+            })
+        })
+        .collect()
+}
+
+/// What happens to a new recording.
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Allow,
+    Deny,
+    /// Muted until the user allows it.
+    Ask,
+}
+
+fn verdict(config: &PrivacyConfig, approved: &HashSet<String>, app: &str) -> Verdict {
+    let listed = |apps: &[String]| apps.iter().any(|a| a == app);
+    if listed(&config.deny) {
+        Verdict::Deny
+    } else if config
+        .allow
+        .as_deref()
+        .is_none_or(|allow| listed(allow) || approved.contains(app))
+    {
+        Verdict::Allow
+    } else {
+        Verdict::Ask
+    }
+}
+
+/// Streams that have already been announced, so that each recording is
+/// announced once, and the applications allowed from a notification since
+/// the daemon started.
+#[derive(Debug, Default)]
+pub struct Guard {
+    seen: HashSet<i64>,
+    approved: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Guard {
+    pub fn check(
+        &mut self,
+        config: &PrivacyConfig,
+        obj: &[PipeWireObject<'_>],
+    ) -> anyhow::Result<()> {
+        let captures = captures(obj);
+        // forget recordings that stopped, so that they are announced again
+        // if they start over
+        self.seen
+            .retain(|id| captures.iter().any(|c| c.stream == *id));
+        for capture in captures {
+            if !self.seen.insert(capture.stream) {
+                continue;
+            }
+            let verdict = verdict(config, &self.approved.lock().unwrap(), capture.app);
+            if verdict != Verdict::Allow {
+                set_mute(capture.stream, true)?;
+            }
+            if verdict == Verdict::Ask && config.notify {
+                ask(&capture, Arc::clone(&self.approved));
+            } else {
+                announce(config, &capture, verdict != Verdict::Allow)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Mutes or unmutes a stream through its own volume, which the application
+/// can't see, rather than the source, which other applications share.
+fn set_mute(stream: i64, mute: bool) -> anyhow::Result<()> {
+    set_param(
+        stream,
+        "Props",
+        &serde_json::json!({ "mute": mute }).to_string(),
+    )
+}
+
+/// Asks whether the muted recording may go on, unmuting it and allowing the
+/// application for the rest of the session if the user agrees. notify-send
+/// waits for an answer, so it runs on its own thread.
+fn ask(capture: &Capture<'_>, approved: Arc<Mutex<HashSet<String>>>) {
+    let stream = capture.stream;
+    let app = capture.app.to_string();
+    let summary = format!("{} wants to record", app);
+    let body = format!("from {}; it is muted until allowed", capture.source);
+    let args = [
+        "--app-name",
+        "pw-volume",
+        "--icon",
+        "audio-input-microphone",
+        "--wait",
+        "--action=allow=Allow",
+        "--action=deny=Deny",
+        &summary,
+        &body,
+    ];
+    if dry_run("notify-send", &args) {
+        return;
+    }
+    let args = args.map(str::to_string);
+    thread::spawn(move || {
+        let output = tools::output_within(tools::command("notify-send").args(args), ASK_TIMEOUT);
+        if let Ok(output) = output {
+            if String::from_utf8_lossy(&output.stdout).trim() == "allow" {
+                approved.lock().unwrap().insert(app);
+                let _ = set_mute(stream, false);
+            }
+        }
+    });
+}
+
+fn announce(config: &PrivacyConfig, capture: &Capture<'_>, muted: bool) -> anyhow::Result<()> {
+    let summary = if muted {
+        format!("{} was muted", capture.app)
+    } else {
+        format!("{} is recording", capture.app)
+    };
+    let body = format!("from {}", capture.source);
+    let args = [
+        "--app-name",
+        "pw-volume",
+        "--icon",
+        "audio-input-microphone",
+        &summary,
+        &body,
+    ];
+    if config.notify && !dry_run("notify-send", &args) {
+        tools::status(tools::command("notify-send").args(args))
+            .context("failed to execute notify-send")?;
+    }
+    if let Some(hook) = &config.hook {
+        // the hook may take its time, but shouldn't hold up the daemon
+        hook::spawn(
+            hook.clone(),
+            vec![
+                ("PW_VOLUME_APP", capture.app.to_string()),
+                ("PW_VOLUME_SOURCE", capture.source.to_string()),
+                ("PW_VOLUME_MUTED", if muted { "1" } else { "0" }.to_string()),
+            ],
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(None, "obs" => Verdict::Allow)]
+    #[test_case(Some(&["obs"]), "obs" => Verdict::Allow)]
+    #[test_case(Some(&["obs"]), "Firefox" => Verdict::Ask)]
+    #[test_case(Some(&["obs"]), "Discord" => Verdict::Allow; "approved")]
+    #[test_case(Some(&["spyware"]), "spyware" => Verdict::Deny; "denied")]
+    #[test_case(None, "spyware" => Verdict::Deny; "denied without allowlist")]
+    fn recording_verdict(allow: Option<&[&str]>, app: &str) -> Verdict {
+        let config = PrivacyConfig {
+            allow: allow.map(|apps| apps.iter().map(|a| a.to_string()).collect()),
+            deny: vec!["spyware".to_string()],
+            ..Default::default()
+        };
+        let approved = vec!["Discord".to_string()].into_iter().collect();
+        verdict(&config, &approved, app)
+    }
+
+    #[test]
+    fn recording_streams() -> anyhow::Result<()> {
+        let path: std::path::PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "testdata",
+            "with_discord.txt",
+        ]
+        .iter()
+        .collect();
+        let buf = std::fs::read(path)?;
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf)?;
+        assert_eq!(
+            captures(&obj),
+            [Capture {
+                stream: 85,
+                app: "WEBRTC VoiceEngine",
+                source: "Built-in Audio Analog Stereo",
+            }]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,331 @@
+//! `pw-volume set-profile`: switches a sound card to another profile, which
+//! decides the sinks and sources it has, e.g. from analog stereo to HDMI or
+//! to pro audio, as pavucontrol's configuration tab does. `pw-volume
+//! list-profiles` lists them, for choosing one.
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde_json::json;
+
+use crate::{
+    error::{Failure, Kind},
+    log, output, port, pw_dump, set_param, unmarshal, DeviceProfile, PipeWireInterfaceDevice,
+};
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("set-profile")
+        .about("switches a sound card to another profile, e.g. 'set-profile \"Built-in Audio\" pro-audio'")
+        .setting(AppSettings::ArgRequiredElseHelp)
+        .arg(
+            Arg::with_name("DEVICE")
+                .help("the device's id, name, e.g. 'alsa_card.pci-0000_00_1f.3', or description")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("PROFILE")
+                .help("the profile's index, name, e.g. 'output:hdmi-stereo', description, or what each of its parts is, e.g. 'analog-stereo'")
+                .takes_value(true)
+                .required(true),
+        )
+}
+
+pub fn list_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("list-profiles")
+        .about("lists a sound card's profiles, whether each is available, and which is active")
+        .arg(
+            Arg::with_name("DEVICE")
+                .help(
+                    "the device's id, name, or description; the default sink's device if left out",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("print a JSON array of the profiles instead, for scripts choosing one"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .help("print a row per profile instead: index, name, priority, availability, active, and description")
+                .takes_value(true)
+                .possible_values(&["csv", "tsv"])
+                .conflicts_with("json"),
+        )
+}
+
+/// Whether `arg` is what every part of the profile's name is, less its
+/// direction, e.g. `analog-stereo` for `output:analog-stereo+input:analog-stereo`.
+fn is_short_name(profile: &DeviceProfile<'_>, arg: &str) -> bool {
+    profile.name.split('+').all(|part| {
+        let part = part
+            .strip_prefix("output:")
+            .or_else(|| part.strip_prefix("input:"))
+            .unwrap_or(part);
+        part == arg
+    })
+}
+
+/// The profile that `arg` names: by index, name, or description, or else the
+/// highest priority one it is the short name of.
+pub fn profile<'a>(
+    device: &'a PipeWireInterfaceDevice<'a>,
+    arg: &str,
+) -> anyhow::Result<&'a DeviceProfile<'a>> {
+    let profiles = &device.info.params.enum_profile;
+    let exact = profiles.iter().find(|profile| {
+        profile.index.to_string() == arg
+            || profile.name == arg
+            || profile
+                .description
+                .as_deref()
+                .is_some_and(|description| description.eq_ignore_ascii_case(arg))
+    });
+    let short = || {
+        profiles
+            .iter()
+            .filter(|profile| is_short_name(profile, arg))
+            .max_by_key(|profile| profile.priority)
+    };
+    exact.or_else(short).ok_or_else(|| {
+        let names: Vec<_> = profiles
+            .iter()
+            .map(|profile| profile.name.as_ref())
+            .collect();
+        Failure::new(
+            Kind::Other,
+            format!(
+                r#"{} has no profile "{}"; its profiles are {}"#,
+                port::describe(device),
+                arg,
+                names.join(", ")
+            ),
+        )
+        .into()
+    })
+}
+
+/// Whether the profile is the device's active one.
+fn active(device: &PipeWireInterfaceDevice<'_>, profile: &DeviceProfile<'_>) -> bool {
+    device
+        .info
+        .params
+        .profile
+        .first()
+        .is_some_and(|active| active.index == profile.index)
+}
+
+/// The profile after the active one, wrapping around, for cycling through
+/// them in the mixer. Profiles that are unavailable are skipped, and so is
+/// `off`, which would take the card's devices out of the mixer.
+pub fn next<'a>(device: &'a PipeWireInterfaceDevice<'a>) -> Option<&'a DeviceProfile<'a>> {
+    let profiles = &device.info.params.enum_profile;
+    let current = profiles.iter().position(|profile| active(device, profile));
+    let start = current.map_or(0, |i| i + 1);
+    profiles
+        .iter()
+        .cycle()
+        .skip(start)
+        .take(profiles.len())
+        .find(|profile| {
+            profile.available != Some("no") && profile.name != "off" && !active(device, profile)
+        })
+}
+
+/// The lines `list-profiles` prints, the active profile marked with `*`.
+fn table(device: &PipeWireInterfaceDevice<'_>) -> String {
+    let profiles = &device.info.params.enum_profile;
+    let width = profiles
+        .iter()
+        .map(|profile| profile.name.len())
+        .max()
+        .unwrap_or(0);
+    profiles
+        .iter()
+        .map(|profile| {
+            let line = format!(
+                "{} {:>2} {:<width$}  {:<11}  {}",
+                if active(device, profile) { '*' } else { ' ' },
+                profile.index,
+                profile.name,
+                port::availability(profile.available),
+                profile.description.as_deref().unwrap_or_default(),
+                width = width,
+            );
+            format!("{}\n", line.trim_end())
+        })
+        .collect()
+}
+
+/// The rows `list-profiles --output csv` or `tsv` prints: the table's
+/// columns, less its mark, and each profile's priority.
+fn delimited(device: &PipeWireInterfaceDevice<'_>, separator: char) -> String {
+    device
+        .info
+        .params
+        .enum_profile
+        .iter()
+        .map(|profile| {
+            let fields = [
+                profile.index.to_string(),
+                profile.name.to_string(),
+                profile.priority.to_string(),
+                port::availability(profile.available).to_string(),
+                active(device, profile).to_string(),
+                profile
+                    .description
+                    .as_deref()
+                    .unwrap_or_default()
+                    .to_string(),
+            ];
+            format!("{}\n", output::row(&fields, separator))
+        })
+        .collect()
+}
+
+fn json(device: &PipeWireInterfaceDevice<'_>) -> serde_json::Value {
+    device
+        .info
+        .params
+        .enum_profile
+        .iter()
+        .map(|profile| {
+            json!({
+                "index": profile.index,
+                "name": profile.name,
+                "description": profile.description,
+                "priority": profile.priority,
+                "available": port::availability(profile.available),
+                "active": active(device, profile),
+            })
+        })
+        .collect()
+}
+
+pub fn run_list(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let buf = pw_dump()?;
+    let obj = unmarshal(&buf)?;
+    let device = port::device(&obj, matches.value_of("DEVICE"))?;
+    if matches.is_present("json") {
+        println!("{}", json(device));
+    } else if let Some(output) = matches.value_of("output") {
+        print!(
+            "{}",
+            delimited(device, if output == "tsv" { '\t' } else { ',' })
+        );
+    } else {
+        print!("{}", table(device));
+    }
+    Ok(())
+}
+
+pub fn run(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let buf = pw_dump()?;
+    let obj = unmarshal(&buf)?;
+    let device = port::device(&obj, matches.value_of("DEVICE"))?;
+    let profile = profile(device, matches.value_of("PROFILE").unwrap_or_default())?;
+    switch(device, profile)
+}
+
+/// Makes the profile the device's active one, saving the choice.
+pub fn switch(
+    device: &PipeWireInterfaceDevice<'_>,
+    profile: &DeviceProfile<'_>,
+) -> anyhow::Result<()> {
+    if profile.available == Some("no") {
+        log::debug(
+            1,
+            format_args!(
+                "{} is unavailable, so may have no sinks or sources",
+                profile.name
+            ),
+        );
+    }
+    set_param(
+        device.id,
+        "Profile",
+        &json!({ "index": profile.index, "save": true }).to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use test_case::test_case;
+
+    use super::*;
+    use crate::PipeWireObject;
+
+    fn dump() -> Vec<u8> {
+        let path: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "testdata",
+            "dump_aria_16.txt",
+        ]
+        .iter()
+        .collect();
+        fs::read(path).unwrap()
+    }
+
+    #[test_case("pro-audio" => Ok(34))]
+    #[test_case("analog-stereo" => Ok(1); "short name")]
+    #[test_case("hdmi-stereo" => Ok(4); "short name of one part")]
+    #[test_case("output:analog-stereo" => Ok(2))]
+    #[test_case("digital stereo (hdmi) output" => Ok(4); "description")]
+    #[test_case("3" => Ok(3); "index")]
+    #[test_case("stereo" => Err(()))]
+    fn profiles(arg: &str) -> Result<i64, ()> {
+        let buf = dump();
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf).unwrap();
+        let device = port::find(&obj, "Built-in Audio").unwrap();
+        profile(device, arg)
+            .map(|profile| profile.index)
+            .map_err(|_| ())
+    }
+
+    #[test]
+    fn next_profile() {
+        let buf = dump();
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf).unwrap();
+        let device = port::find(&obj, "Built-in Audio").unwrap();
+        assert_eq!(next(device).map(|profile| profile.index), Some(2));
+    }
+
+    #[test]
+    fn listed() -> anyhow::Result<()> {
+        let buf = dump();
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf)?;
+        let device = port::find(&obj, "Built-in Audio").unwrap();
+        let table = table(device);
+        let lines: Vec<_> = table.lines().take(4).collect();
+        assert_eq!(
+            lines,
+            [
+                "   0 off                                                available    Off",
+                "*  1 output:analog-stereo+input:analog-stereo           available    Analog Stereo Duplex",
+                "   2 output:analog-stereo                               available    Analog Stereo Output",
+                "   3 output:hdmi-stereo+input:analog-stereo             unavailable  Digital Stereo (HDMI) Output + Analog Stereo Input",
+            ]
+        );
+        let json = json(device);
+        assert_eq!(json[4]["name"], "output:hdmi-stereo");
+        assert_eq!(json[4]["available"], "unavailable");
+        assert_eq!(json[1]["active"], true);
+        Ok(())
+    }
+
+    #[test_case(',', 1 => "1,output:analog-stereo+input:analog-stereo,6565,available,true,Analog Stereo Duplex")]
+    #[test_case('\t', 4 => "4\toutput:hdmi-stereo\t5900\tunavailable\tfalse\tDigital Stereo (HDMI) Output")]
+    fn delimited_row(separator: char, line: usize) -> String {
+        let buf = dump();
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf).unwrap();
+        let device = port::find(&obj, "Built-in Audio").unwrap();
+        delimited(device, separator)
+            .lines()
+            .nth(line)
+            .unwrap()
+            .to_string()
+    }
+}
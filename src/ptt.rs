@@ -0,0 +1,39 @@
+//! `pw-volume ptt`: push-to-talk for keybindings that fire on key down and up.
+//! The microphone is unmuted only while the key is held; when the daemon is
+//! running, it mutes the microphone again if a release never arrives.
+
+use std::time::Duration;
+
+use clap::{App, AppSettings, Arg, SubCommand};
+use serde::Deserialize;
+
+/// The `[ptt]` section of the config file.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PttConfig {
+    /// Seconds after which the daemon mutes a pressed microphone.
+    pub timeout: u64,
+}
+
+impl Default for PttConfig {
+    fn default() -> Self {
+        PttConfig { timeout: 60 }
+    }
+}
+
+impl PttConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("ptt")
+        .about("unmutes the default source on press and mutes it on release")
+        .setting(AppSettings::ArgRequiredElseHelp)
+        .arg(
+            Arg::with_name("TRANSITION")
+                .possible_values(&["press", "release"])
+                .required(true),
+        )
+}
@@ -0,0 +1,314 @@
+//! `pw-volume record`: writes the graph's updates to a file as they arrive,
+//! one JSON line each, with when it arrived and what it changed: volumes,
+//! mute states, and sinks, sources, and devices coming and going. A
+//! recording can go with a bug report, and be replayed, the same way every
+//! time, through the daemon's rules with `daemon --simulate` or through the
+//! status with `watch --replay`.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::File,
+    io::{BufReader, Write},
+    process::Stdio,
+    time::Instant,
+};
+
+use anyhow::Context;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    display_percent, endpoints, error, format::Status, graph::Graph, log, port, tools, unmarshal,
+    PipeWireObject,
+};
+
+/// Volumes closer than this, in percent, are taken to be the same.
+const EPSILON: f64 = 1e-3;
+
+/// Something an update changed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Event {
+    Added { id: i64, name: String },
+    Removed { id: i64, name: String },
+    Volume { id: i64, name: String, volume: f64 },
+    Mute { id: i64, name: String, mute: bool },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Added { id, name } => write!(f, "{} ({}) was added", name, id),
+            Event::Removed { id, name } => write!(f, "{} ({}) was removed", name, id),
+            Event::Volume { id, name, volume } => write!(f, "{} ({}) is at {}%", name, id, volume),
+            Event::Mute { id, name, mute } => write!(
+                f,
+                "{} ({}) is {}",
+                name,
+                id,
+                if *mute { "muted" } else { "unmuted" }
+            ),
+        }
+    }
+}
+
+/// A line of a recording.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Record {
+    /// When the update arrived, counted from the start of the recording, or
+    /// `None` for output of `pw-dump --monitor`, which doesn't say.
+    pub at_ms: Option<u64>,
+    /// What it changed, for the people reading the recording.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<Event>,
+    /// The objects that changed, as pw-dump prints them.
+    pub update: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Document {
+    Record(Record),
+    Update(Vec<Value>),
+}
+
+/// The updates in a recording, or in what `pw-dump --monitor` printed.
+pub fn read(buf: &[u8]) -> anyhow::Result<Vec<Record>> {
+    serde_json::Deserializer::from_slice(buf)
+        .into_iter::<Document>()
+        .enumerate()
+        .map(|(i, document)| {
+            match document.with_context(|| format!("failed to parse update {}", i + 1))? {
+                Document::Record(record) => Ok(record),
+                Document::Update(update) => Ok(Record {
+                    at_ms: None,
+                    events: Vec::new(),
+                    update,
+                }),
+            }
+        })
+        .collect()
+}
+
+/// The sinks, sources, and sound cards in the graph, by id, with their
+/// names.
+fn present(obj: &[PipeWireObject<'_>]) -> BTreeMap<i64, String> {
+    obj.iter()
+        .filter_map(|o| match o {
+            PipeWireObject::Node(n)
+                if n.typ == "PipeWire:Interface:Node"
+                    && matches!(
+                        n.info.props.media_class,
+                        Some("Audio/Sink") | Some("Audio/Source")
+                    ) =>
+            {
+                Some((n.id, n.info.props.node_name.to_string()))
+            }
+            // cameras have no profiles
+            PipeWireObject::Device(d)
+                if d.typ == "PipeWire:Interface:Device"
+                    && !d.info.params.enum_profile.is_empty() =>
+            {
+                let name = d.info.props.as_ref().and_then(|p| p.device_name.as_deref());
+                Some((d.id, name.map_or_else(|| port::describe(d), str::to_string)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// What changed from one graph to the next.
+fn events(before: &[PipeWireObject<'_>], after: &[PipeWireObject<'_>]) -> Vec<Event> {
+    let (was, is) = (present(before), present(after));
+    let mut events: Vec<_> = was
+        .iter()
+        .filter(|(id, _)| !is.contains_key(id))
+        .map(|(&id, name)| Event::Removed {
+            id,
+            name: name.clone(),
+        })
+        .collect();
+    events.extend(
+        is.iter()
+            .filter(|(id, _)| !was.contains_key(id))
+            .map(|(&id, name)| Event::Added {
+                id,
+                name: name.clone(),
+            }),
+    );
+    let levels = |obj| -> BTreeMap<i64, Status> {
+        endpoints(obj)
+            .into_iter()
+            .map(|(node, route)| (node.id, Status::new(node, route)))
+            .collect()
+    };
+    let old = levels(before);
+    for (id, status) in levels(after) {
+        let last = match old.get(&id) {
+            Some(last) => last,
+            None => continue,
+        };
+        let volume = display_percent(status.volume);
+        if (volume - display_percent(last.volume)).abs() > EPSILON {
+            events.push(Event::Volume {
+                id,
+                name: status.node.clone(),
+                volume,
+            });
+        }
+        if status.mute != last.mute {
+            events.push(Event::Mute {
+                id,
+                name: status.node,
+                mute: status.mute,
+            });
+        }
+    }
+    events
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("record")
+        .about("writes the graph's updates to a file as they happen, for bug reports and replays")
+        .setting(AppSettings::ArgRequiredElseHelp)
+        .arg(
+            Arg::with_name("FILE")
+                .help("the file to write, e.g. events.out")
+                .takes_value(true)
+                .required(true),
+        )
+}
+
+/// Records until interrupted or until pw-dump exits, printing what each
+/// update changed.
+pub fn run(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let path = matches.value_of("FILE").unwrap_or_default();
+    let mut file = File::create(path).with_context(|| format!("failed to create {}", path))?;
+    let mut child = tools::spawn(
+        tools::command("pw-dump")
+            .args(["--monitor", "--no-colors"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null()),
+    )
+    .map_err(|e| error::tool("pw-dump", e))?;
+    let stdout = child.stdout.take().expect("pw-dump's stdout is piped");
+    let start = Instant::now();
+    let graph = Graph::replay();
+    let mut before: Option<Vec<u8>> = None;
+    let updates =
+        serde_json::Deserializer::from_reader(BufReader::new(stdout)).into_iter::<Vec<Value>>();
+    for update in updates {
+        let update = match update {
+            Ok(update) => update,
+            Err(e) => {
+                log::debug(1, format_args!("stopped reading pw-dump: {}", e));
+                break;
+            }
+        };
+        let at_ms = start.elapsed().as_millis() as u64;
+        graph.update(update.clone());
+        let after = graph.dump()?;
+        // the first update is the whole graph
+        let events = match &before {
+            Some(before) => events(&unmarshal(before)?, &unmarshal(&after)?),
+            None => Vec::new(),
+        };
+        for event in &events {
+            println!("{:>8.3}s  {}", at_ms as f64 / 1000.0, event);
+        }
+        let record = Record {
+            at_ms: Some(at_ms),
+            events,
+            update,
+        };
+        writeln!(file, "{}", serde_json::to_string(&record)?)
+            .with_context(|| format!("failed to write {}", path))?;
+        before = Some(after);
+    }
+    tools::kill(&mut child);
+    log::debug(1, format_args!("pw-dump exited"));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    fn dump(edit: impl Fn(&mut Value)) -> anyhow::Result<Vec<u8>> {
+        let path: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "testdata",
+            "dump_aria_16.txt",
+        ]
+        .iter()
+        .collect();
+        let mut dump: Value = serde_json::from_slice(&fs::read(path)?)?;
+        for o in dump.as_array_mut().into_iter().flatten() {
+            edit(o);
+        }
+        Ok(serde_json::to_vec(&dump)?)
+    }
+
+    #[test]
+    fn changes() -> anyhow::Result<()> {
+        let before = dump(|_| ())?;
+        let after = dump(|o| {
+            if o["id"] == 53 {
+                let props = &mut o["info"]["params"]["Route"][0]["props"];
+                props["mute"] = true.into();
+                props["channelVolumes"] = serde_json::json!([0.5, 0.5]);
+            }
+        })?;
+        let mut after: Value = serde_json::from_slice(&after)?;
+        if let Some(objects) = after.as_array_mut() {
+            objects.retain(|o| o["id"] != 42);
+        }
+        let after = serde_json::to_vec(&after)?;
+        let before: Vec<PipeWireObject> = serde_json::from_slice(&before)?;
+        let after: Vec<PipeWireObject> = serde_json::from_slice(&after)?;
+        let found: Vec<_> = events(&before, &after)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            found,
+            [
+                "alsa_card.pci-0000_00_1f.3 (42) was removed",
+                "bluez_output.18_54_CF_71_8C_FA.a2dp-sink (69) is at 50%",
+                "bluez_output.18_54_CF_71_8C_FA.a2dp-sink (69) is muted",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn read_both_formats() -> anyhow::Result<()> {
+        let recorded = br#"{"at-ms":0,"update":[{"id":1,"info":{}}]}
+{"at-ms":1520,"events":[{"type":"mute","id":1,"name":"sink","mute":true}],"update":[{"id":1,"info":{}}]}
+"#;
+        let records = read(recorded)?;
+        assert_eq!(records[1].at_ms, Some(1520));
+        assert_eq!(
+            records[1].events,
+            [Event::Mute {
+                id: 1,
+                name: "sink".to_string(),
+                mute: true
+            }]
+        );
+        let monitored = br#"[{"id":1,"info":{}}]
+[{"id":1,"info":null}]
+"#;
+        let records = read(monitored)?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].at_ms, None);
+        assert_eq!(records[1].update, [serde_json::json!({"id":1,"info":null})]);
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+//! `pw-volume render`: the volume as a picture, for widgets that show images
+//! rather than text, such as eww's image widget or conky. The SVG is a ring
+//! that fills with the volume around a speaker, colored by state.
+
+use std::{fs, io::Write, path::Path};
+
+use anyhow::Context;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde::Deserialize;
+
+use crate::{format::Status, output::StateColors};
+
+/// The `[svg]` section of the config file.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SvgConfig {
+    /// Width and height in pixels.
+    pub size: u32,
+    /// Fill behind the graphic; empty for transparent.
+    pub background: String,
+    /// Color of the unfilled part of the ring.
+    pub track: String,
+    /// Colors of the speaker and ring for each state.
+    pub colors: StateColors,
+}
+
+impl Default for SvgConfig {
+    fn default() -> Self {
+        SvgConfig {
+            size: 64,
+            background: String::new(),
+            track: "#373B41".to_string(),
+            colors: StateColors {
+                ok: "#C5C8C6".to_string(),
+                ..StateColors::default()
+            },
+        }
+    }
+}
+
+const RADIUS: f64 = 10.0;
+
+fn svg(status: &Status, config: &SvgConfig) -> String {
+    let color = config.colors.color(status.state());
+    let mut shapes = Vec::new();
+    if !config.background.is_empty() {
+        shapes.push(format!(
+            r#"<rect width="24" height="24" rx="4" fill="{}"/>"#,
+            config.background
+        ));
+    }
+    shapes.push(format!(
+        r#"<circle cx="12" cy="12" r="{}" fill="none" stroke="{}" stroke-width="2"/>"#,
+        RADIUS, config.track
+    ));
+    let fraction = if status.mute {
+        0.0
+    } else {
+        status.volume.clamp(0.0, 1.0)
+    };
+    if fraction > 0.0 {
+        // a dash as long as the filled part of the circumference, starting at
+        // the top and going clockwise
+        let circumference = 2.0 * std::f64::consts::PI * RADIUS;
+        shapes.push(format!(
+            r#"<circle cx="12" cy="12" r="{}" fill="none" stroke="{}" stroke-width="2" stroke-dasharray="{:.2} {:.2}" transform="rotate(-90 12 12)"/>"#,
+            RADIUS,
+            color,
+            fraction * circumference,
+            circumference
+        ));
+    }
+    shapes.push(format!(
+        r#"<path d="M6.5 10h2l3-3v10l-3-3h-2z" fill="{}"/>"#,
+        color
+    ));
+    shapes.push(if status.mute {
+        format!(
+            r#"<path d="M14 10l4 4m0-4l-4 4" fill="none" stroke="{}" stroke-width="1.5" stroke-linecap="round"/>"#,
+            color
+        )
+    } else {
+        format!(
+            r#"<path d="M14 9.5a3.5 3.5 0 0 1 0 5" fill="none" stroke="{}" stroke-width="1.5" stroke-linecap="round"/>"#,
+            color
+        )
+    });
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{size}\" height=\"{size}\" viewBox=\"0 0 24 24\">\
+         <title>{title}</title>{shapes}</svg>\n",
+        size = config.size,
+        title = if status.mute {
+            "muted".to_string()
+        } else {
+            format!("{:.0}%", status.percentage())
+        },
+        shapes = shapes.join("")
+    )
+}
+
+/// A bar of ten cells, e.g. `████░░░░░░ 42%`.
+fn text(status: &Status) -> String {
+    if status.mute {
+        return format!("{} muted", "░".repeat(10));
+    }
+    let filled = (status.volume.clamp(0.0, 1.0) * 10.0).round() as usize;
+    format!(
+        "{}{} {:.0}%",
+        "█".repeat(filled),
+        "░".repeat(10 - filled),
+        status.percentage()
+    )
+}
+
+/// Replaces the file in one step, so that widgets polling it never read it
+/// half-written.
+fn write_file(path: &Path, contents: &str) -> anyhow::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, contents).with_context(|| format!("failed to write {}", path.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("failed to write {}", path.display()))
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("render")
+        .about("draws the volume as an SVG image or a text bar")
+        .arg(
+            Arg::with_name("svg")
+                .long("svg")
+                .value_name("FILE")
+                .help("write an SVG image to FILE, or to stdout for '-'")
+                .takes_value(true),
+        )
+}
+
+pub fn run(
+    matches: &ArgMatches<'_>,
+    config: &SvgConfig,
+    status: &Status,
+    out: &mut dyn Write,
+) -> anyhow::Result<i32> {
+    match matches.value_of("svg") {
+        Some("-") => write!(out, "{}", svg(status, config))?,
+        Some(path) => write_file(Path::new(path), &svg(status, config))?,
+        None => writeln!(out, "{}", text(status))?,
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(false, 0.42 => "████░░░░░░ 42%")]
+    #[test_case(false, 1.2 => "██████████ 120%")]
+    #[test_case(true, 0.42 => "░░░░░░░░░░ muted")]
+    fn text_bar(mute: bool, volume: f64) -> String {
+        text(&Status {
+            volume,
+            mute,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn svg_ring() {
+        let status = Status {
+            volume: 0.5,
+            ..Default::default()
+        };
+        let image = svg(&status, &SvgConfig::default());
+        assert!(image.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" width="64""#));
+        assert!(image.contains(r#"stroke-dasharray="31.42 62.83""#));
+        assert!(image.contains("<title>50%</title>"));
+
+        let muted = svg(
+            &Status {
+                mute: true,
+                ..status
+            },
+            &SvgConfig::default(),
+        );
+        assert!(!muted.contains("stroke-dasharray"));
+        assert!(muted.contains(r##"fill="#707880""##));
+    }
+}
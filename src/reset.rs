@@ -0,0 +1,194 @@
+//! `pw-volume reset`: a one-shot fix for having no sound without knowing why.
+//! It stops running fades, unmutes the default sink and source and sets them
+//! to 40%, unmutes applications' streams and turns up the silent ones, and
+//! resumes the applications that `app NAME cork` paused. What it changes is
+//! printed as `apply` prints it, since it converges to a state the same way.
+
+use std::sync::atomic::Ordering;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use crate::{
+    apply::{self, AppLevel, Level, State},
+    config::Config,
+    cork::{self, Corked},
+    default_name, fade, is_decimal_percentage, node_id, pw_dump, streams, unmarshal, volume_config,
+    PipeWireObject, DRY_RUN,
+};
+
+/// Streams quieter than this are taken to be silenced.
+const SILENT: f64 = 0.005;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("reset")
+        .about("gets sound back: unmutes the default devices at 40%, and unmutes and resumes applications")
+        .arg(
+            Arg::with_name("volume")
+                .long("volume")
+                .value_name("PERCENT")
+                .help("the volume to set the default devices to, e.g. '25%'")
+                .takes_value(true)
+                .default_value("40%")
+                .validator(|s| {
+                    if is_decimal_percentage(&s) {
+                        Ok(())
+                    } else {
+                        Err(format!(r#""{}" is not a decimal percentage"#, s))
+                    }
+                }),
+        )
+}
+
+/// The application's entry among `apps`, added if it has none yet.
+fn app<'a>(apps: &'a mut Vec<AppLevel>, name: &str) -> &'a mut AppLevel {
+    let i = match apps.iter().position(|app| app.name == name) {
+        Some(i) => i,
+        None => {
+            apps.push(AppLevel {
+                name: name.to_string(),
+                volume: None,
+                mute: None,
+                target: None,
+                corked: None,
+            });
+            apps.len() - 1
+        }
+    };
+    &mut apps[i]
+}
+
+/// The state that `reset` converges to, at `volume` percent.
+fn state(obj: &[PipeWireObject<'_>], corked: &Corked, volume: f64) -> State {
+    let devices = ["default.audio.sink", "default.audio.source"]
+        .iter()
+        .filter_map(|key| default_name(obj, key))
+        .map(|name| Level {
+            name: name.to_string(),
+            volume: Some(volume),
+            mute: Some(false),
+        })
+        .collect();
+    // streams aren't devices, so `[devices]` doesn't apply to them
+    let stream_scale = volume_config().scale;
+    let mut apps = Vec::new();
+    for (stream, _) in streams(obj) {
+        let (name, level) = match (
+            &stream.info.props.application_name,
+            stream.info.params.volume(),
+        ) {
+            (Some(name), Some(level)) => (name, level),
+            _ => continue,
+        };
+        let silent = level
+            .channel_volumes
+            .iter()
+            .all(|v| stream_scale.scaled(*v) < SILENT);
+        if level.mute || silent {
+            let app = app(&mut apps, name);
+            app.mute = Some(false);
+            if silent {
+                app.volume = Some(100.0);
+            }
+        }
+    }
+    for name in corked.keys() {
+        app(&mut apps, name).corked = Some(false);
+    }
+    State {
+        devices,
+        apps,
+        ..State::default()
+    }
+}
+
+pub fn run(matches: &ArgMatches<'_>, config: &Config) -> anyhow::Result<()> {
+    let volume = matches
+        .value_of("volume")
+        .unwrap_or_default()
+        .trim_end_matches('%')
+        .parse::<f64>()?;
+    let buf = pw_dump()?;
+    let obj = unmarshal(&buf)?;
+    // a fade would carry on from wherever the reset left the volume
+    if !DRY_RUN.load(Ordering::Relaxed) {
+        for key in ["default.audio.sink", "default.audio.source"] {
+            if let Some(Ok(id)) = default_name(&obj, key).map(|name| node_id(&obj, name)) {
+                fade::stop(id);
+            }
+        }
+    }
+    let state = state(&obj, &cork::load(), volume);
+    if apply::converge(&state, config, false)? == 0 {
+        println!("nothing to reset");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn unmutes_everything() -> anyhow::Result<()> {
+        let path: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "testdata",
+            "with_discord.txt",
+        ]
+        .iter()
+        .collect();
+        let mut dump: Value = serde_json::from_slice(&fs::read(path)?)?;
+        // one of the call's streams was muted, and another turned all the way down
+        for o in dump.as_array_mut().into_iter().flatten() {
+            match o["id"].as_i64() {
+                Some(77) => o["info"]["params"]["Props"][0]["mute"] = true.into(),
+                Some(85) => {
+                    o["info"]["params"]["Props"][0]["channelVolumes"] = vec![0.0, 0.0].into()
+                }
+                _ => (),
+            }
+        }
+        let buf = serde_json::to_vec(&dump)?;
+        let obj: Vec<PipeWireObject> = serde_json::from_slice(&buf)?;
+        let corked = Corked::from([("Chromium".to_string(), Vec::new())]);
+        let state = state(&obj, &corked, 40.0);
+
+        let devices: Vec<_> = state.devices.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(
+            devices,
+            [
+                default_name(&obj, "default.audio.sink").unwrap_or_default(),
+                default_name(&obj, "default.audio.source").unwrap_or_default(),
+            ]
+        );
+        assert!(state
+            .devices
+            .iter()
+            .all(|l| l.volume == Some(40.0) && l.mute == Some(false)));
+        assert_eq!(
+            state.apps,
+            [
+                AppLevel {
+                    name: "WEBRTC VoiceEngine".to_string(),
+                    volume: Some(100.0),
+                    mute: Some(false),
+                    target: None,
+                    corked: None,
+                },
+                AppLevel {
+                    name: "Chromium".to_string(),
+                    volume: None,
+                    mute: None,
+                    target: None,
+                    corked: Some(false),
+                },
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+//! Retries for commands that fail while the graph is changing under them, e.g.
+//! when a device is plugged in between pw-dump and pw-cli.
+
+use std::{
+    io, thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+
+/// The `[retry]` section of the config file.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RetryConfig {
+    /// Tries in all, including the first; 1 turns retrying off.
+    pub attempts: u32,
+    /// Wait before the first retry, doubled for each one after it.
+    pub delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            attempts: 3,
+            delay_ms: 50,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// The wait before retry number `retry`, counting from 0, without jitter.
+    fn backoff(&self, retry: u32) -> Duration {
+        Duration::from_millis(self.delay_ms.saturating_mul(1 << retry.min(16)))
+    }
+
+    /// Runs `f` until it succeeds or the attempts run out, returning the last
+    /// error. Waits are stretched by up to half at random, so that commands
+    /// started together, such as from a held key, don't retry in lockstep.
+    /// A tool that timed out isn't retried, since PipeWire won't have come
+    /// back in the meantime.
+    pub fn run<T>(&self, mut f: impl FnMut() -> anyhow::Result<T>) -> anyhow::Result<T> {
+        let mut retry = 0;
+        loop {
+            match f() {
+                Err(e) if retry + 1 < self.attempts && !timed_out(&e) => {
+                    let backoff = self.backoff(retry);
+                    let nanos = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |d| d.subsec_nanos());
+                    thread::sleep(backoff.mul_f64(1.0 + f64::from(nanos % 500) / 1000.0));
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn timed_out(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|e| e.kind() == io::ErrorKind::TimedOut)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error;
+    use anyhow::bail;
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(3, 1 => (Ok(()), 2); "succeeds on retry")]
+    #[test_case(3, 5 => (Err(()), 3); "gives up")]
+    #[test_case(0, 5 => (Err(()), 1); "always tries once")]
+    fn attempts(attempts: u32, failures: u32) -> (Result<(), ()>, u32) {
+        let config = RetryConfig {
+            attempts,
+            delay_ms: 1,
+        };
+        let mut calls = 0;
+        let result = config.run(|| {
+            calls += 1;
+            if calls <= failures {
+                bail!("pw-cli did not exit successfully");
+            }
+            Ok(())
+        });
+        (result.map_err(|_| ()), calls)
+    }
+
+    #[test]
+    fn timeouts_are_final() {
+        let mut calls = 0;
+        let result: anyhow::Result<()> = RetryConfig::default().run(|| {
+            calls += 1;
+            Err(error::tool(
+                "pw-dump",
+                io::Error::from(io::ErrorKind::TimedOut),
+            ))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn doubling_backoff() {
+        let config = RetryConfig::default();
+        assert_eq!(config.backoff(0), Duration::from_millis(50));
+        assert_eq!(config.backoff(2), Duration::from_millis(200));
+    }
+}
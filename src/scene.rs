@@ -0,0 +1,168 @@
+//! Scenes: named sequences of volume operations, recorded in the mixer and
+//! replayed with `pw-volume scene play <name>`. They are stored as TOML under
+//! `$XDG_STATE_HOME/pw-volume/scenes`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::state_dir, node_id, parse_dump, pw_dump, route_command, set_route, unmarshal, Target,
+    VolumeOp,
+};
+
+/// A single operation, addressed by node name so it survives node id changes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Step {
+    pub node: String,
+    /// An operation such as `+5%`, `=40%`, or `mute toggle`.
+    pub op: String,
+}
+
+impl Step {
+    pub fn new(node: &str, op: VolumeOp) -> Step {
+        Step {
+            node: node.to_string(),
+            op: op.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Scene {
+    #[serde(rename = "step", default)]
+    pub steps: Vec<Step>,
+}
+
+fn scene_dir() -> anyhow::Result<PathBuf> {
+    state_dir()
+        .map(|dir| dir.join("scenes"))
+        .ok_or_else(|| anyhow!("failed to determine state directory"))
+}
+
+fn scene_path(name: &str) -> anyhow::Result<PathBuf> {
+    anyhow::ensure!(
+        !name.is_empty() && !name.contains('/') && !name.starts_with('.'),
+        "invalid scene name: {:?}",
+        name
+    );
+    Ok(scene_dir()?.join(format!("{}.toml", name)))
+}
+
+impl Scene {
+    /// Appends a step, folding it into the previous one when both set the
+    /// same node's volume (e.g. while dragging a slider).
+    pub fn record(&mut self, step: Step) {
+        if let Some(last) = self.steps.last_mut() {
+            if last.node == step.node && last.op.starts_with('=') && step.op.starts_with('=') {
+                *last = step;
+                return;
+            }
+        }
+        self.steps.push(step);
+    }
+
+    pub fn save(&self, name: &str) -> anyhow::Result<PathBuf> {
+        let path = scene_path(name)?;
+        fs::create_dir_all(scene_dir()?)?;
+        fs::write(&path, toml::to_string(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    pub fn load(name: &str) -> anyhow::Result<Scene> {
+        let path = scene_path(name)?;
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read scene {:?}", name))?;
+        toml::from_str(&contents).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub fn play(&self) -> anyhow::Result<()> {
+        for step in &self.steps {
+            let op: VolumeOp = step.op.parse().map_err(anyhow::Error::msg)?;
+            let buf = pw_dump()?;
+            let obj = unmarshal(&buf)?;
+            let (node, route) = parse_dump(&obj, Target::Id(node_id(&obj, &step.node)?))?;
+            set_route(node, &route_command(op, node, route))?;
+        }
+        Ok(())
+    }
+}
+
+/// The names of the TOML files in `dir`, sorted, such as the saved scenes.
+pub fn names(dir: &Path) -> anyhow::Result<Vec<String>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            match path.extension() {
+                Some(ext) if ext == "toml" => path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned()),
+                _ => None,
+            }
+        })
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("scene")
+        .about("replays scenes recorded in the mixer")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("play")
+                .about("applies a scene's steps in order")
+                .arg(Arg::with_name("NAME").required(true)),
+        )
+        .subcommand(SubCommand::with_name("list").about("lists saved scenes"))
+}
+
+pub fn run(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    match matches.subcommand() {
+        ("play", Some(arg)) => Scene::load(arg.value_of("NAME").unwrap_or_default())?.play(),
+        ("list", _) => {
+            for name in names(&scene_dir()?)? {
+                println!("{}", name);
+            }
+            Ok(())
+        }
+        (_, _) => unreachable!("argument parsing should have failed by now"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_folds_drags() {
+        let mut scene = Scene::default();
+        scene.record(Step::new("sink", VolumeOp::Set(0.2)));
+        scene.record(Step::new("sink", VolumeOp::Set(0.3)));
+        scene.record(Step::new("source", VolumeOp::Set(0.3)));
+        scene.record(Step::new("source", VolumeOp::Change(0.05)));
+        let ops: Vec<&str> = scene.steps.iter().map(|s| s.op.as_str()).collect();
+        assert_eq!(ops, ["=30%", "=30%", "+5%"]);
+    }
+
+    #[test]
+    fn toml_round_trip() -> anyhow::Result<()> {
+        let mut scene = Scene::default();
+        scene.record(Step::new("sink", VolumeOp::Mute(None)));
+        let parsed: Scene = toml::from_str(&toml::to_string(&scene)?)?;
+        assert_eq!(parsed, scene);
+        Ok(())
+    }
+}
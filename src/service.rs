@@ -0,0 +1,253 @@
+//! `pw-volume service`: starts the daemon with the session, through a systemd
+//! user unit or an XDG autostart entry, so that it doesn't take a hand-written
+//! one.
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, ensure, Context};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use crate::{config::config_dir, tools};
+
+const UNIT: &str = "pw-volume.service";
+const DESKTOP: &str = "pw-volume.desktop";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Method {
+    Systemd,
+    Autostart,
+}
+
+/// What the daemon is started with: the path of this executable, its
+/// arguments, and the config directory if it isn't the default, so that the
+/// daemon reads the same config file as the command installing it.
+#[derive(Debug, PartialEq)]
+struct Launch {
+    exe: PathBuf,
+    args: Vec<&'static str>,
+    config_home: Option<PathBuf>,
+}
+
+/// Quotes an argument for systemd's `ExecStart`, or a desktop entry's
+/// `Exec`, which both take double quotes with backslash escapes. systemd's
+/// own `%` and `$` are escaped in [`Launch::unit`].
+fn quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/-_.=+:,@".contains(c))
+    {
+        return arg.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        if matches!(c, '"' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Launch {
+    fn command(&self) -> String {
+        std::iter::once(quote(&self.exe.to_string_lossy()))
+            .chain(self.args.iter().map(|arg| quote(arg)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn unit(&self) -> String {
+        let escape = |line: String| line.replace('%', "%%").replace('$', "$$");
+        let environment = match &self.config_home {
+            Some(dir) => format!(
+                "Environment={}\n",
+                escape(quote(&format!("XDG_CONFIG_HOME={}", dir.display())))
+            ),
+            None => String::new(),
+        };
+        format!(
+            "[Unit]\n\
+             Description=pw-volume daemon\n\
+             After=pipewire.service wireplumber.service\n\
+             \n\
+             [Service]\n\
+             ExecStart={}\n\
+             {}Restart=on-failure\n\
+             \n\
+             [Install]\n\
+             WantedBy=default.target\n",
+            escape(self.command()),
+            environment
+        )
+    }
+
+    fn desktop_entry(&self) -> String {
+        // Exec has no way to set the environment, so env(1) does it
+        let exec = match &self.config_home {
+            Some(dir) => format!(
+                "env {} {}",
+                quote(&format!("XDG_CONFIG_HOME={}", dir.display())),
+                self.command()
+            ),
+            None => self.command(),
+        };
+        format!(
+            "[Desktop Entry]\n\
+             Type=Application\n\
+             Name=pw-volume daemon\n\
+             Exec={}\n\
+             NoDisplay=true\n\
+             X-GNOME-Autostart-enabled=true\n",
+            exec
+        )
+    }
+}
+
+fn path(method: Method) -> anyhow::Result<PathBuf> {
+    let dir = config_dir().ok_or_else(|| anyhow!("failed to determine config directory"))?;
+    Ok(match method {
+        Method::Systemd => dir.join("systemd").join("user").join(UNIT),
+        Method::Autostart => dir.join("autostart").join(DESKTOP),
+    })
+}
+
+fn systemctl(args: &[&str]) -> anyhow::Result<()> {
+    let status = tools::status(tools::command("systemctl").arg("--user").args(args))
+        .context("failed to execute systemctl")?;
+    ensure!(status.success(), "systemctl did not exit successfully");
+    Ok(())
+}
+
+fn remove(path: &Path) -> anyhow::Result<bool> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("failed to remove {}", path.display())),
+    }
+}
+
+fn install(method: Method, launch: &Launch) -> anyhow::Result<()> {
+    let path = path(method)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let contents = match method {
+        Method::Systemd => launch.unit(),
+        Method::Autostart => launch.desktop_entry(),
+    };
+    fs::write(&path, contents).with_context(|| format!("failed to write {}", path.display()))?;
+    println!("wrote {}", path.display());
+    if method == Method::Systemd {
+        systemctl(&["daemon-reload"])?;
+        systemctl(&["enable", "--now", UNIT])?;
+    }
+    Ok(())
+}
+
+/// Removes whichever of the two is installed.
+fn uninstall() -> anyhow::Result<()> {
+    let unit = path(Method::Systemd)?;
+    if unit.exists() {
+        systemctl(&["disable", "--now", UNIT])?;
+    }
+    if remove(&unit)? {
+        systemctl(&["daemon-reload"])?;
+        println!("removed {}", unit.display());
+    }
+    let desktop = path(Method::Autostart)?;
+    if remove(&desktop)? {
+        println!("removed {}", desktop.display());
+    }
+    Ok(())
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("service")
+        .about("installs or removes a systemd user unit or autostart entry for the daemon")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("install")
+                .about("starts the daemon with the session, and now")
+                .arg(
+                    Arg::with_name("systemd")
+                        .long("systemd")
+                        .help("write a systemd user unit (the default where systemd runs)"),
+                )
+                .arg(
+                    Arg::with_name("autostart")
+                        .long("autostart")
+                        .help("write an XDG autostart entry instead")
+                        .conflicts_with("systemd"),
+                )
+                .arg(
+                    Arg::with_name("dbus")
+                        .long("dbus")
+                        .help("run the daemon with --dbus"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("uninstall").about("stops starting the daemon"))
+}
+
+pub fn run(matches: &ArgMatches<'_>) -> anyhow::Result<()> {
+    match matches.subcommand() {
+        ("install", Some(arg)) => {
+            let method = if arg.is_present("autostart") {
+                Method::Autostart
+            } else if arg.is_present("systemd") || Path::new("/run/systemd/system").exists() {
+                Method::Systemd
+            } else {
+                Method::Autostart
+            };
+            let mut args = vec!["daemon"];
+            if arg.is_present("dbus") {
+                args.push("--dbus");
+            }
+            let launch = Launch {
+                exe: env::current_exe().context("failed to find the pw-volume executable")?,
+                args,
+                config_home: env::var_os("XDG_CONFIG_HOME")
+                    .filter(|dir| !dir.is_empty())
+                    .map(PathBuf::from),
+            };
+            install(method, &launch)
+        }
+        ("uninstall", _) => uninstall(),
+        _ => unreachable!("a subcommand is required"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn launch_files() {
+        let launch = Launch {
+            exe: PathBuf::from("/home/me/.cargo/bin/pw-volume"),
+            args: vec!["daemon", "--dbus"],
+            config_home: Some(PathBuf::from("/home/me/my config")),
+        };
+        assert_eq!(
+            launch.unit(),
+            "[Unit]\n\
+             Description=pw-volume daemon\n\
+             After=pipewire.service wireplumber.service\n\
+             \n\
+             [Service]\n\
+             ExecStart=/home/me/.cargo/bin/pw-volume daemon --dbus\n\
+             Environment=\"XDG_CONFIG_HOME=/home/me/my config\"\n\
+             Restart=on-failure\n\
+             \n\
+             [Install]\n\
+             WantedBy=default.target\n"
+        );
+        assert!(launch.desktop_entry().contains(
+            "Exec=env \"XDG_CONFIG_HOME=/home/me/my config\" /home/me/.cargo/bin/pw-volume daemon --dbus\n"
+        ));
+    }
+}
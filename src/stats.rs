@@ -0,0 +1,249 @@
+//! Usage statistics, for the curious and for tuning settings such as the step:
+//! how often each subcommand ran, the average volume changes left the node
+//! at, and how often the default sink was switched, by day. They are off
+//! unless `[stats]` turns them on, and only ever written to
+//! `$XDG_STATE_HOME/pw-volume/stats.toml`, which `pw-volume stats` prints.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs, io,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::state_dir, display_percent, format::Status, log, DRY_RUN};
+
+/// The `[stats]` section of the config file.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatsConfig {
+    pub enabled: bool,
+}
+
+/// Whether `[stats]` is on, set once the config file is read.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn set(config: StatsConfig) {
+    ENABLED.store(config.enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// What was done on one day.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Day {
+    /// The volumes that changes left the node at, in percent, added up, and
+    /// how many there were, for their average.
+    pub volume_total: f64,
+    pub volumes: u64,
+    pub device_switches: u64,
+    /// How many times each subcommand ran, last since TOML puts tables after
+    /// values.
+    pub commands: BTreeMap<String, u64>,
+}
+
+impl Day {
+    fn add(&mut self, other: &Day) {
+        for (name, count) in &other.commands {
+            *self.commands.entry(name.clone()).or_default() += count;
+        }
+        self.volume_total += other.volume_total;
+        self.volumes += other.volumes;
+        self.device_switches += other.device_switches;
+    }
+}
+
+/// Days by date, e.g. `2024-03-09`.
+pub type Stats = BTreeMap<String, Day>;
+
+fn path() -> Option<PathBuf> {
+    state_dir().map(|dir| dir.join("stats.toml"))
+}
+
+fn load() -> anyhow::Result<Stats> {
+    let path = path().ok_or_else(|| anyhow!("failed to determine state directory"))?;
+    match fs::read_to_string(&path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Stats::default()),
+        contents => toml::from_str(&contents?)
+            .with_context(|| format!("failed to parse {}", path.display())),
+    }
+}
+
+/// The UTC date of a time, counted in seconds since the epoch.
+fn date(secs: u64) -> String {
+    // Howard Hinnant's days_from_civil, inverted
+    let days = (secs / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    date(secs)
+}
+
+/// Adds to today's entry, if stats are on. Failing to doesn't fail the
+/// command.
+fn record(update: impl FnOnce(&mut Day)) {
+    if !enabled() || DRY_RUN.load(Ordering::Relaxed) {
+        return;
+    }
+    let result = load().and_then(|mut stats| {
+        update(stats.entry(today()).or_default());
+        let path = path().ok_or_else(|| anyhow!("failed to determine state directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, toml::to_string(&stats)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    });
+    if let Err(e) = result {
+        log::debug(1, format_args!("failed to record stats: {:#}", e));
+    }
+}
+
+/// Counts a subcommand that succeeded.
+pub fn command(name: &str) {
+    record(|day| *day.commands.entry(name.to_string()).or_default() += 1);
+}
+
+/// Adds the volume a change left the node at.
+pub fn volume(status: &Status) {
+    record(|day| {
+        day.volume_total += display_percent(status.volume);
+        day.volumes += 1;
+    });
+}
+
+/// Counts a switch of the default sink or source.
+pub fn switch() {
+    record(|day| day.device_switches += 1);
+}
+
+/// One line of `stats`: the commands by count, the average volume, and the
+/// device switches.
+fn line(label: &str, day: &Day) -> String {
+    let mut commands: Vec<_> = day.commands.iter().collect();
+    commands.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    let commands: Vec<_> = commands
+        .iter()
+        .map(|(name, count)| format!("{} {}", name, count))
+        .collect();
+    let mut line = format!("{:<10}  {}", label, commands.join(", "));
+    if day.volumes > 0 {
+        let average = day.volume_total / day.volumes as f64;
+        let _ = write!(line, "; average volume {:.0}%", average);
+    }
+    match day.device_switches {
+        0 => (),
+        1 => line.push_str("; 1 device switch"),
+        n => {
+            let _ = write!(line, "; {} device switches", n);
+        }
+    }
+    line
+}
+
+/// The last `days` days that have stats, and their total.
+fn report(stats: &Stats, days: usize) -> String {
+    let shown: Vec<_> = stats.iter().rev().take(days).collect();
+    let mut total = Day::default();
+    let mut report = String::new();
+    for (date, day) in shown.iter().rev() {
+        total.add(day);
+        let _ = writeln!(report, "{}", line(date, day));
+    }
+    if shown.len() > 1 {
+        let _ = writeln!(report, "{}", line("total", &total));
+    }
+    report
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("stats")
+        .about("prints what [stats] recorded: commands run, average volume, and device switches by day")
+        .arg(
+            Arg::with_name("days")
+                .long("days")
+                .value_name("N")
+                .help("how many of the last days with stats to print")
+                .takes_value(true)
+                .default_value("7")
+                .validator(|s| {
+                    s.parse::<usize>()
+                        .map(|_| ())
+                        .map_err(|_| format!(r#""{}" is not a number of days"#, s))
+                }),
+        )
+}
+
+pub fn run(matches: &ArgMatches<'_>, config: StatsConfig) -> anyhow::Result<()> {
+    let days = matches.value_of("days").unwrap_or_default().parse()?;
+    let stats = load()?;
+    if stats.is_empty() {
+        if config.enabled {
+            println!("no stats yet");
+        } else {
+            println!("stats are off; set enabled = true under [stats] in the config file");
+        }
+        return Ok(());
+    }
+    print!("{}", report(&stats, days));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(0 => "1970-01-01")]
+    #[test_case(951_782_400 => "2000-02-29")]
+    #[test_case(1_709_942_399 => "2024-03-08")]
+    #[test_case(4_102_444_800 => "2100-01-01")]
+    fn dates(secs: u64) -> String {
+        date(secs)
+    }
+
+    #[test]
+    fn report_totals() {
+        let day = |change, mute, total, volumes, switches| Day {
+            commands: BTreeMap::from([("change".to_string(), change), ("mute".to_string(), mute)]),
+            volume_total: total,
+            volumes,
+            device_switches: switches,
+        };
+        let stats = Stats::from([
+            ("2024-03-07".to_string(), day(1, 1, 50.0, 1, 0)),
+            ("2024-03-08".to_string(), day(3, 1, 120.0, 3, 1)),
+            ("2024-03-09".to_string(), day(6, 0, 180.0, 6, 2)),
+        ]);
+        assert_eq!(
+            report(&stats, 2),
+            "2024-03-08  change 3, mute 1; average volume 40%; 1 device switch\n\
+             2024-03-09  change 6, mute 0; average volume 30%; 2 device switches\n\
+             total       change 9, mute 1; average volume 33%; 3 device switches\n"
+        );
+        let written = toml::to_string(&stats).unwrap();
+        assert_eq!(toml::from_str::<Stats>(&written).unwrap(), stats);
+    }
+}
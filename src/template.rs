@@ -0,0 +1,203 @@
+//! User-defined `status` templates.
+//!
+//! A template is literal text with placeholders in braces, e.g.
+//! `{icon} {percentage}%`. A placeholder may also choose between two
+//! strings depending on whether its value is set, e.g. `{mute:muted|on}`.
+//! `{{` and `}}` produce literal braces.
+
+use std::str::FromStr;
+
+use crate::format::{Status, Style};
+
+/// Placeholders understood by templates.
+pub const PLACEHOLDERS: &[&str] = &[
+    "percentage",
+    "volume",
+    "mute",
+    "state",
+    "icon",
+    "name",
+    "node",
+    "port",
+    "port_name",
+    "device_kind",
+    "battery",
+    "channels",
+    "compress",
+    "agc",
+    "mic_active",
+    "fade_target",
+    "fade_progress",
+    "fade_remaining",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Placeholder {
+        key: String,
+        /// Strings used when the value is set and unset, respectively.
+        choice: Option<(String, String)>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template(Vec<Segment>);
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut inner = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => inner.push(c),
+                            None => return Err(format!("unclosed placeholder in {:?}", s)),
+                        }
+                    }
+                    let (key, choice) = match inner.split_once(':') {
+                        Some((key, choice)) => {
+                            let (set, unset) = choice.split_once('|').unwrap_or((choice, ""));
+                            (key, Some((set.to_string(), unset.to_string())))
+                        }
+                        None => (inner.as_str(), None),
+                    };
+                    if !PLACEHOLDERS.contains(&key) {
+                        return Err(format!(
+                            "unknown placeholder {{{}}}, expected one of: {}",
+                            key,
+                            PLACEHOLDERS.join(", ")
+                        ));
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Placeholder {
+                        key: key.to_string(),
+                        choice,
+                    });
+                }
+                '}' => return Err(format!("unmatched '}}' in {:?}", s)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Template(segments))
+    }
+}
+
+impl Template {
+    pub fn render(&self, status: &Status, style: &Style) -> String {
+        let mut out = String::new();
+        for segment in &self.0 {
+            match segment {
+                Segment::Literal(text) => out.push_str(text),
+                Segment::Placeholder { key, choice } => {
+                    let value = value(key, status, style);
+                    match choice {
+                        Some((set, unset)) => {
+                            let is_set = !matches!(value.as_str(), "" | "0" | "false");
+                            out.push_str(if is_set { set } else { unset });
+                        }
+                        None => out.push_str(&value),
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+fn value(key: &str, status: &Status, style: &Style) -> String {
+    match key {
+        "percentage" | "volume" => format!("{:.0}", status.percentage()),
+        "mute" => status.mute.to_string(),
+        "state" => status.state().as_str().to_string(),
+        "icon" => style.icons.icon(status).to_string(),
+        "name" => status.name.clone(),
+        "node" => status.node.clone(),
+        "port" => status.port.clone().unwrap_or_default(),
+        "port_name" => status.port_name.clone().unwrap_or_default(),
+        // empty when the kind isn't known
+        "device_kind" => status
+            .device_kind()
+            .map_or_else(String::new, |kind| kind.as_str().to_string()),
+        // empty when the device doesn't report one
+        "battery" => status
+            .battery
+            .map(|battery| battery.to_string())
+            .unwrap_or_default(),
+        "channels" => status.channels.to_string(),
+        "compress" => status.compress.to_string(),
+        "agc" => status.agc.to_string(),
+        "mic_active" => status.mic_active.to_string(),
+        // empty while no fade is running
+        "fade_target" => status
+            .fade
+            .map(|fade| format!("{:.0}", fade.target * 100.0))
+            .unwrap_or_default(),
+        "fade_progress" => status
+            .fade
+            .map(|fade| format!("{:.0}", fade.progress * 100.0))
+            .unwrap_or_default(),
+        "fade_remaining" => status
+            .fade
+            .map(|fade| format!("{:.1}", fade.remaining))
+            .unwrap_or_default(),
+        _ => unreachable!("placeholders are validated when parsing"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    fn status(mute: bool) -> Status {
+        Status {
+            volume: 0.42,
+            mute,
+            name: "Built-in Audio".to_string(),
+            node: "alsa_output.analog-stereo".to_string(),
+            port: Some("Speakers".to_string()),
+            channels: 2,
+            ..Default::default()
+        }
+    }
+
+    #[test_case("{percentage}% {mute:muted|}", false => "42% ")]
+    #[test_case("{percentage}%{fade_target: → |}{fade_target}", false => "42%")]
+    #[test_case("{percentage}% {mute:muted|}", true => "42% muted")]
+    #[test_case("{name} ({port}, {channels}ch)", false => "Built-in Audio (Speakers, 2ch)")]
+    #[test_case("{{{volume}}}", false => "{42}")]
+    #[test_case("{mute:M}", false => "")]
+    fn render(template: &str, mute: bool) -> String {
+        let template: Template = template.parse().unwrap();
+        template.render(&status(mute), &Style::default())
+    }
+
+    #[test_case("{bogus}")]
+    #[test_case("{percentage")]
+    #[test_case("percentage}")]
+    fn invalid(template: &str) {
+        assert!(template.parse::<Template>().is_err());
+    }
+}
@@ -0,0 +1,146 @@
+//! Colors and drawing characters for the terminal interfaces.
+
+use std::convert::TryFrom;
+
+use crossterm::style::Color;
+use serde::Deserialize;
+
+/// A terminal color, written in the config as a name (e.g. "dark_cyan"),
+/// "#rrggbb", or "default" for the terminal's own color.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+pub struct ThemeColor(pub Color);
+
+impl Default for ThemeColor {
+    fn default() -> Self {
+        ThemeColor(Color::Reset)
+    }
+}
+
+impl TryFrom<String> for ThemeColor {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        if s == "default" {
+            return Ok(ThemeColor(Color::Reset));
+        }
+        if let Some(hex) = s.strip_prefix('#') {
+            let channel = |i: usize| {
+                hex.get(i..i + 2)
+                    .and_then(|c| u8::from_str_radix(c, 16).ok())
+            };
+            return match (hex.len(), channel(0), channel(2), channel(4)) {
+                (6, Some(r), Some(g), Some(b)) => Ok(ThemeColor(Color::Rgb { r, g, b })),
+                _ => Err(format!(r#""{}" is not a #rrggbb color"#, s)),
+            };
+        }
+        Color::try_from(s.as_str())
+            .map(ThemeColor)
+            .map_err(|_| format!(r#""{}" is not a color"#, s))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum BorderStyle {
+    #[default]
+    Plain,
+    Rounded,
+    Double,
+    Ascii,
+    None,
+}
+
+/// Characters for a box: corners (top-left, top-right, bottom-left,
+/// bottom-right), then horizontal and vertical edges.
+pub struct BorderChars {
+    pub top_left: char,
+    pub top_right: char,
+    pub bottom_left: char,
+    pub bottom_right: char,
+    pub horizontal: char,
+    pub vertical: char,
+}
+
+impl BorderStyle {
+    pub fn chars(self) -> Option<BorderChars> {
+        let [top_left, top_right, bottom_left, bottom_right, horizontal, vertical] = match self {
+            BorderStyle::Plain => ['┌', '┐', '└', '┘', '─', '│'],
+            BorderStyle::Rounded => ['╭', '╮', '╰', '╯', '─', '│'],
+            BorderStyle::Double => ['╔', '╗', '╚', '╝', '═', '║'],
+            BorderStyle::Ascii => ['+', '+', '+', '+', '-', '|'],
+            BorderStyle::None => return None,
+        };
+        Some(BorderChars {
+            top_left,
+            top_right,
+            bottom_left,
+            bottom_right,
+            horizontal,
+            vertical,
+        })
+    }
+}
+
+/// Appearance of the terminal interfaces. Colors default to the terminal's
+/// own, so that they stay readable on both light and dark backgrounds; the
+/// selection is shown in reverse video.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Theme {
+    pub text: ThemeColor,
+    pub border: ThemeColor,
+    pub selected: ThemeColor,
+    pub meter: ThemeColor,
+    pub muted: ThemeColor,
+    pub meter_filled: char,
+    pub meter_empty: char,
+    pub border_style: BorderStyle,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            text: ThemeColor::default(),
+            border: ThemeColor::default(),
+            selected: ThemeColor::default(),
+            meter: ThemeColor::default(),
+            muted: ThemeColor::default(),
+            meter_filled: '█',
+            meter_empty: '·',
+            border_style: BorderStyle::default(),
+        }
+    }
+}
+
+impl Theme {
+    /// Renders a meter of `width` cells filled in proportion to `fraction`.
+    pub fn meter(&self, fraction: f64, width: usize) -> String {
+        let filled = ((fraction.clamp(0.0, 1.0) * width as f64).round() as usize).min(width);
+        self.meter_filled.to_string().repeat(filled)
+            + &self.meter_empty.to_string().repeat(width - filled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("default" => Ok(ThemeColor(Color::Reset)))]
+    #[test_case("dark_cyan" => Ok(ThemeColor(Color::DarkCyan)))]
+    #[test_case("#ff8000" => Ok(ThemeColor(Color::Rgb { r: 255, g: 128, b: 0 })))]
+    #[test_case("#ff80" => matches Err(_))]
+    #[test_case("chartreuse" => matches Err(_))]
+    fn parse_color(s: &str) -> Result<ThemeColor, String> {
+        ThemeColor::try_from(s.to_string())
+    }
+
+    #[test_case(0.5, 4 => "██··")]
+    #[test_case(1.5, 4 => "████")]
+    #[test_case(0.0, 0 => "")]
+    fn meter(fraction: f64, width: usize) -> String {
+        Theme::default().meter(fraction, width)
+    }
+}
@@ -0,0 +1,302 @@
+//! Where PipeWire's tools are run from. By default they're looked up in
+//! `PATH`, but the `[tools]` section, or `PW_VOLUME_PW_CLI` and the like, can
+//! point them elsewhere, e.g. at a Nix store path, or at a wrapper such as
+//! `flatpak-spawn --host pw-cli` inside a sandbox.
+//!
+//! A PipeWire that is wedged leaves its clients waiting forever, so the tools
+//! that should finish at once, such as pw-dump and pw-cli, are run with
+//! [`output`] and [`status`], which kill and reap them once they have taken
+//! longer than `timeout-ms`. Every child is started in a process group of
+//! its own, so that what it starts in turn, such as a hook's own commands,
+//! is killed with it, and the children still running when pw-volume is
+//! interrupted or terminated are killed before it exits.
+
+use std::{
+    io::{self, Read},
+    os::unix::process::CommandExt,
+    process::{self, Child, Command, ExitStatus, Output, Stdio},
+    sync::{Mutex, OnceLock, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
+
+/// The `[tools]` section. Each is a command line split at whitespace, so that
+/// a wrapper can take arguments.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ToolsConfig {
+    pub pw_cli: Option<String>,
+    pub pw_dump: Option<String>,
+    pub pw_metadata: Option<String>,
+    pub pw_play: Option<String>,
+    pub pw_record: Option<String>,
+    pub pipewire: Option<String>,
+    pub wpctl: Option<String>,
+    pub pactl: Option<String>,
+    /// How long a tool may take before it's killed, 10 seconds if unset.
+    pub timeout_ms: Option<u64>,
+}
+
+impl ToolsConfig {
+    /// The environment variable that overrides each tool, e.g.
+    /// `PW_VOLUME_PW_CLI`, and the setting it overrides.
+    pub fn overrides(&mut self) -> [(&'static str, &mut Option<String>); 8] {
+        [
+            ("PW_VOLUME_PW_CLI", &mut self.pw_cli),
+            ("PW_VOLUME_PW_DUMP", &mut self.pw_dump),
+            ("PW_VOLUME_PW_METADATA", &mut self.pw_metadata),
+            ("PW_VOLUME_PW_PLAY", &mut self.pw_play),
+            ("PW_VOLUME_PW_RECORD", &mut self.pw_record),
+            ("PW_VOLUME_PIPEWIRE", &mut self.pipewire),
+            ("PW_VOLUME_WPCTL", &mut self.wpctl),
+            ("PW_VOLUME_PACTL", &mut self.pactl),
+        ]
+    }
+
+    fn get(&self, tool: &str) -> Option<&str> {
+        match tool {
+            "pw-cli" => self.pw_cli.as_deref(),
+            "pw-dump" => self.pw_dump.as_deref(),
+            "pw-metadata" => self.pw_metadata.as_deref(),
+            "pw-play" => self.pw_play.as_deref(),
+            "pw-record" => self.pw_record.as_deref(),
+            "pipewire" => self.pipewire.as_deref(),
+            "wpctl" => self.wpctl.as_deref(),
+            "pactl" => self.pactl.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+static TOOLS: OnceLock<ToolsConfig> = OnceLock::new();
+
+/// Sets where the tools are run from for the rest of the process.
+pub fn set(config: ToolsConfig) {
+    let _ = TOOLS.set(config);
+}
+
+fn command_with(config: &ToolsConfig, tool: &str) -> Command {
+    let mut words = config.get(tool).unwrap_or(tool).split_whitespace();
+    let mut command = Command::new(words.next().unwrap_or(tool));
+    command.args(words);
+    command
+}
+
+/// A command running `tool`, e.g. `pw-cli`, from wherever it was configured.
+/// Other programs, such as notify-send, are looked up in `PATH`.
+pub fn command(tool: &str) -> Command {
+    command_with(TOOLS.get().unwrap_or(&ToolsConfig::default()), tool)
+}
+
+/// How long a tool may run before it's taken to be stuck.
+fn timeout() -> Duration {
+    TOOLS
+        .get()
+        .and_then(|config| config.timeout_ms)
+        .map_or(Duration::from_secs(10), Duration::from_millis)
+}
+
+/// The process groups of the children that haven't been reaped yet, which
+/// are those of the children themselves.
+static RUNNING: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+fn running() -> std::sync::MutexGuard<'static, Vec<u32>> {
+    RUNNING.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn killpg(group: u32) {
+    // SAFETY: killpg only sends a signal, and the group is one of our
+    // children's that hasn't been reaped, so its id can't have been reused
+    unsafe {
+        libc::killpg(group as libc::pid_t, libc::SIGKILL);
+    }
+}
+
+/// Starts the command in a process group of its own, to be waited for with
+/// [`wait`] or ended with [`kill`].
+pub fn spawn(command: &mut Command) -> io::Result<Child> {
+    // held while starting it, so that a signal can't come in between
+    let mut running = running();
+    let child = command.process_group(0).spawn()?;
+    running.push(child.id());
+    Ok(child)
+}
+
+/// Kills the child, with whatever it started, and reaps it.
+pub fn kill(child: &mut Child) {
+    let mut running = running();
+    if running.contains(&child.id()) {
+        killpg(child.id());
+    } else {
+        let _ = child.kill();
+    }
+    let _ = child.wait();
+    running.retain(|&group| group != child.id());
+}
+
+/// Kills the running children when pw-volume is interrupted or terminated,
+/// and exits as the shell would report it, e.g. with 130 for Ctrl-C. Being in
+/// process groups of their own, they wouldn't be signalled along with it.
+pub fn handle_signals() {
+    let mut signals = match Signals::new([SIGINT, SIGTERM]) {
+        Ok(signals) => signals,
+        Err(_) => return,
+    };
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            // kept locked, so that nothing more is started
+            let running = running();
+            for &group in running.iter() {
+                killpg(group);
+            }
+            process::exit(128 + signal);
+        }
+    });
+}
+
+/// Waits for the child until `timeout` has passed since `start`, then kills
+/// and reaps it, so that it is never left behind.
+fn wait(child: &mut Child, start: Instant, timeout: Duration) -> io::Result<ExitStatus> {
+    // most tools are done within milliseconds, so poll often at first
+    let mut pause = Duration::from_millis(1);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            running().retain(|&group| group != child.id());
+            return Ok(status);
+        }
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            kill(child);
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("killed it after {:?}", timeout),
+            ));
+        }
+        thread::sleep(pause.min(timeout - elapsed));
+        pause = (pause * 2).min(Duration::from_millis(50));
+    }
+}
+
+/// Reaps the child on a thread of its own once it exits, killing it if it
+/// outlasts the timeout, for a child that pw-volume doesn't wait for.
+pub fn reap(mut child: Child) {
+    let start = Instant::now();
+    thread::spawn(move || wait(&mut child, start, timeout()));
+}
+
+/// Reads a pipe to the end on a thread of its own, so that a child filling
+/// one pipe isn't kept from finishing while the other is read.
+fn drain(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+/// Runs the command as [`Command::output`] does, but for no longer than the
+/// timeout.
+pub fn output(command: &mut Command) -> io::Result<Output> {
+    output_within(command, timeout())
+}
+
+/// Runs the command as [`Command::output`] does, but for no longer than
+/// `timeout`.
+pub fn output_within(command: &mut Command, timeout: Duration) -> io::Result<Output> {
+    let start = Instant::now();
+    let mut child = spawn(
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )?;
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    let status = wait(&mut child, start, timeout)?;
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Runs the command as [`Command::status`] does, but for no longer than the
+/// timeout.
+pub fn status(command: &mut Command) -> io::Result<ExitStatus> {
+    status_within(command, timeout())
+}
+
+/// Runs the command as [`Command::status`] does, but for no longer than
+/// `timeout`.
+pub fn status_within(command: &mut Command, timeout: Duration) -> io::Result<ExitStatus> {
+    let start = Instant::now();
+    let mut child = spawn(command.stdin(Stdio::null()))?;
+    wait(&mut child, start, timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configured_commands() {
+        let config = ToolsConfig {
+            pw_cli: Some("flatpak-spawn --host pw-cli".to_string()),
+            pw_dump: Some("/nix/store/abc-pipewire/bin/pw-dump".to_string()),
+            ..ToolsConfig::default()
+        };
+        let cli = command_with(&config, "pw-cli");
+        assert_eq!(cli.get_program(), "flatpak-spawn");
+        assert_eq!(cli.get_args().collect::<Vec<_>>(), ["--host", "pw-cli"]);
+        let dump = command_with(&config, "pw-dump");
+        assert_eq!(dump.get_program(), "/nix/store/abc-pipewire/bin/pw-dump");
+        assert_eq!(command_with(&config, "pw-play").get_program(), "pw-play");
+    }
+
+    #[test]
+    fn kills_stuck_tools() -> io::Result<()> {
+        let start = Instant::now();
+        let mut child = spawn(Command::new("sleep").arg("10"))?;
+        let err = wait(&mut child, start, Duration::from_millis(100)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // reaped, so there's nothing left to wait for
+        assert!(child.try_wait()?.is_some());
+        assert!(!running().contains(&child.id()));
+        Ok(())
+    }
+
+    #[test]
+    fn kills_what_children_start() -> io::Result<()> {
+        let pid_file = std::env::temp_dir().join(format!("pw-volume-test-{}", process::id()));
+        // the shell's own sleep has to go too
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", "sleep 10 & echo $! > \"$0\"; wait"])
+            .arg(&pid_file);
+        let err = status_within(&mut command, Duration::from_millis(200)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        let pid = std::fs::read_to_string(&pid_file)?;
+        let _ = std::fs::remove_file(&pid_file);
+        let stat = format!("/proc/{}/stat", pid.trim());
+        let dead = || match std::fs::read_to_string(&stat) {
+            // left for init to reap
+            Ok(stat) => stat.contains(") Z "),
+            Err(_) => true,
+        };
+        let start = Instant::now();
+        while !dead() && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(dead());
+        Ok(())
+    }
+}
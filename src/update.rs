@@ -0,0 +1,226 @@
+//! `pw-volume check-update`: compares this version with the latest release on
+//! crates.io, and lists the highlights of the releases in between from their
+//! GitHub release notes. Nothing is fetched unless asked for, either by the
+//! subcommand or by the `[update]` section turning on the daemon's check.
+
+use std::{thread, time::Duration};
+
+use anyhow::{anyhow, ensure, Context};
+use clap::{App, SubCommand};
+use serde::Deserialize;
+
+use crate::tools;
+
+const CRATE_URL: &str = "https://crates.io/api/v1/crates/pw-volume";
+const RELEASES_URL: &str = "https://api.github.com/repos/smasher164/pw-volume/releases";
+
+/// Release notes lines shown for each release.
+const HIGHLIGHTS: usize = 3;
+
+/// The `[update]` section of the config file.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct UpdateConfig {
+    /// Have the daemon check for updates.
+    pub check: bool,
+    pub interval_hours: u64,
+}
+
+impl Default for UpdateConfig {
+    fn default() -> Self {
+        UpdateConfig {
+            check: false,
+            interval_hours: 24,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CrateResponse {
+    #[serde(rename = "crate")]
+    krate: CrateInfo,
+}
+
+#[derive(Deserialize)]
+struct CrateInfo {
+    max_stable_version: String,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+type Version = (u64, u64, u64);
+
+/// Parses `1.2.3` or `v1.2.3`.
+fn version(s: &str) -> Option<Version> {
+    let mut parts = s.trim_start_matches('v').splitn(3, '.');
+    let mut next = || parts.next()?.parse().ok();
+    Some((next()?, next()?, next()?))
+}
+
+/// Runs curl, which crates.io asks to identify the client.
+fn fetch(url: &str) -> anyhow::Result<Vec<u8>> {
+    let mut curl = tools::command("curl");
+    curl.args(["--silent", "--show-error", "--fail", "--location"])
+        .args(["--max-time", "10"])
+        .arg("--user-agent")
+        .arg(concat!(
+            "pw-volume/",
+            env!("CARGO_PKG_VERSION"),
+            " (https://github.com/smasher164/pw-volume)"
+        ))
+        .arg(url);
+    let output = tools::output(&mut curl).context("failed to execute curl")?;
+    ensure!(
+        output.status.success(),
+        "failed to fetch {}: {}",
+        url,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(output.stdout)
+}
+
+/// For each release newer than `current`, newest first, its tag and the first
+/// few list items of its notes.
+fn highlights(releases: &[Release], current: Version) -> Vec<String> {
+    let mut lines = Vec::new();
+    for release in releases {
+        if version(&release.tag_name).is_none_or(|v| v <= current) {
+            continue;
+        }
+        lines.push(format!("{}:", release.tag_name));
+        let items = release
+            .body
+            .as_deref()
+            .unwrap_or("")
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.starts_with("- ") || line.starts_with("* "))
+            .take(HIGHLIGHTS);
+        lines.extend(items.map(|item| format!("  {}", item)));
+    }
+    lines
+}
+
+/// A newer release, if there is one.
+#[derive(Debug, PartialEq)]
+pub struct Update {
+    pub latest: String,
+    pub highlights: Vec<String>,
+}
+
+pub fn check() -> anyhow::Result<Option<Update>> {
+    let current = version(env!("CARGO_PKG_VERSION")).expect("the package version is semver");
+    let response: CrateResponse = serde_json::from_slice(&fetch(CRATE_URL)?)
+        .context("failed to parse the reply from crates.io")?;
+    let latest = response.krate.max_stable_version;
+    let newer = version(&latest)
+        .ok_or_else(|| anyhow!("crates.io lists an odd version: {}", latest))?
+        > current;
+    if !newer {
+        return Ok(None);
+    }
+    // the notes are a nicety, so failing to get them doesn't fail the check
+    let highlights = fetch(RELEASES_URL)
+        .ok()
+        .and_then(|buf| serde_json::from_slice::<Vec<Release>>(&buf).ok())
+        .map_or_else(Vec::new, |releases| highlights(&releases, current));
+    Ok(Some(Update { latest, highlights }))
+}
+
+/// The daemon's check: tells the user about each new release once, through a
+/// desktop notification.
+pub fn spawn(config: UpdateConfig) {
+    if !config.check {
+        return;
+    }
+    thread::spawn(move || {
+        let mut told = None;
+        loop {
+            match check() {
+                Ok(Some(update)) if told.as_ref() != Some(&update.latest) => {
+                    let _ = tools::status(
+                        tools::command("notify-send")
+                            .args([
+                                "--app-name",
+                                "pw-volume",
+                                "--icon",
+                                "software-update-available",
+                            ])
+                            .arg(format!("pw-volume {} is available", update.latest))
+                            .arg(update.highlights.join("\n")),
+                    );
+                    told = Some(update.latest);
+                }
+                Ok(_) => (),
+                Err(e) => eprintln!("{:#}", e),
+            }
+            thread::sleep(Duration::from_secs(config.interval_hours.max(1) * 60 * 60));
+        }
+    });
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("check-update")
+        .about("checks crates.io for a newer release; exits with 1 if there is one")
+}
+
+pub fn run() -> anyhow::Result<i32> {
+    match check()? {
+        Some(update) => {
+            println!(
+                "pw-volume {} is available (running {}); update with `cargo install pw-volume`",
+                update.latest,
+                env!("CARGO_PKG_VERSION")
+            );
+            for line in update.highlights {
+                println!("{}", line);
+            }
+            Ok(1)
+        }
+        None => {
+            println!("pw-volume {} is up to date", env!("CARGO_PKG_VERSION"));
+            Ok(0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("0.5.0" => Some((0, 5, 0)))]
+    #[test_case("v1.12.3" => Some((1, 12, 3)))]
+    #[test_case("1.0" => None)]
+    fn parse_version(s: &str) -> Option<Version> {
+        version(s)
+    }
+
+    #[test]
+    fn release_highlights() -> anyhow::Result<()> {
+        let releases: Vec<Release> = serde_json::from_str(
+            r#"[
+                { "tag_name": "v0.7.0", "body": "Fixes\r\n- fix a crash when commands run concurrently\r\n- a\r\n- b\r\n- c" },
+                { "tag_name": "v0.6.0", "body": null },
+                { "tag_name": "v0.5.0", "body": "- already running this" }
+            ]"#,
+        )?;
+        assert_eq!(
+            highlights(&releases, (0, 5, 0)),
+            [
+                "v0.7.0:",
+                "  - fix a crash when commands run concurrently",
+                "  - a",
+                "  - b",
+                "v0.6.0:",
+            ]
+        );
+        Ok(())
+    }
+}
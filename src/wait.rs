@@ -0,0 +1,111 @@
+//! `--wait-for-pipewire`: holds the command back until PipeWire is up and has
+//! picked a default sink, for autostart scripts that run before the audio
+//! stack has finished starting at login.
+
+use std::{
+    env,
+    ffi::OsString,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    default_name,
+    error::{Failure, Kind},
+    log, pw_dump, PipeWireObject,
+};
+
+/// How long to wait when `--wait-for-pipewire` is given without a timeout.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+const POLL: Duration = Duration::from_millis(250);
+
+/// Where libpipewire looks for the daemon's socket: `PIPEWIRE_REMOTE`, which
+/// may be a path of its own, in `PIPEWIRE_RUNTIME_DIR` or else
+/// `XDG_RUNTIME_DIR`.
+fn socket_path(
+    remote: Option<OsString>,
+    runtime_dir: Option<OsString>,
+    xdg_runtime_dir: Option<OsString>,
+) -> Option<PathBuf> {
+    let remote = PathBuf::from(remote.unwrap_or_else(|| "pipewire-0".into()));
+    if remote.is_absolute() {
+        return Some(remote);
+    }
+    let dir = runtime_dir
+        .filter(|dir| !dir.is_empty())
+        .or(xdg_runtime_dir.filter(|dir| !dir.is_empty()))?;
+    Some(PathBuf::from(dir).join(remote))
+}
+
+/// What is still missing, or `None` once a default sink is set.
+fn missing() -> Option<Failure> {
+    let socket = socket_path(
+        env::var_os("PIPEWIRE_REMOTE"),
+        env::var_os("PIPEWIRE_RUNTIME_DIR"),
+        env::var_os("XDG_RUNTIME_DIR"),
+    );
+    // without a runtime directory to look in, pw-dump is left to find it
+    if let Some(socket) = socket.filter(|socket| !socket.exists()) {
+        return Some(Failure::new(
+            Kind::PipewireNotRunning,
+            format!("{} does not exist", socket.display()),
+        ));
+    }
+    let dump = match pw_dump() {
+        Ok(dump) => dump,
+        Err(e) => return Some(Failure::new(Kind::PipewireNotRunning, format!("{:#}", e))),
+    };
+    match serde_json::from_slice::<Vec<PipeWireObject>>(&dump) {
+        Ok(obj) if default_name(&obj, "default.audio.sink").is_some() => None,
+        Ok(_) => Some(Failure::new(
+            Kind::NoDefaultSink,
+            "no default audio sink is set",
+        )),
+        Err(e) => Some(Failure::new(Kind::BadDump, e.to_string())),
+    }
+}
+
+/// Blocks until PipeWire's socket exists and a default sink is set, failing
+/// with whatever was still missing after `timeout`.
+pub fn until_ready(timeout: Duration) -> anyhow::Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let failure = match missing() {
+            None => return Ok(()),
+            Some(failure) => failure,
+        };
+        if Instant::now() >= deadline {
+            return Err(anyhow::Error::new(failure).context(format!(
+                "PipeWire was not ready after {}s",
+                timeout.as_secs_f64()
+            )));
+        }
+        log::debug(1, format_args!("waiting for PipeWire: {}", failure));
+        thread::sleep(POLL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(None, None, Some("/run/user/1000") => Some(PathBuf::from("/run/user/1000/pipewire-0")))]
+    #[test_case(Some("pipewire-1"), Some("/tmp/pw"), Some("/run/user/1000") => Some(PathBuf::from("/tmp/pw/pipewire-1")))]
+    #[test_case(Some("/srv/pw.sock"), None, None => Some(PathBuf::from("/srv/pw.sock")))]
+    #[test_case(None, Some(""), None => None)]
+    fn socket(
+        remote: Option<&str>,
+        runtime_dir: Option<&str>,
+        xdg_runtime_dir: Option<&str>,
+    ) -> Option<PathBuf> {
+        socket_path(
+            remote.map(Into::into),
+            runtime_dir.map(Into::into),
+            xdg_runtime_dir.map(Into::into),
+        )
+    }
+}
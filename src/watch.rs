@@ -0,0 +1,394 @@
+//! `pw-volume watch`: prints the status again whenever it changes, so bars
+//! can read it continuously instead of polling or being signalled.
+//!
+//! With `--animate`, it also prints the frames in between, at most so many a
+//! second: while a fade runs, the volume on its way to the fade's target, and
+//! after a jump, such as from a volume key, the volume sliding from where it
+//! was to where it is. A slider can then move smoothly without interpolating
+//! on its own between updates that arrive late.
+//!
+//! With `--replay`, it prints what it would have as the updates in a
+//! recording arrived instead, without waiting between them.
+
+use std::{
+    fs,
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use crate::{
+    config::Config,
+    dbus::Signal,
+    degraded_status,
+    events::Changes,
+    format::Status,
+    graph::Graph,
+    parse_dump, pw_dump,
+    record::{self, Record},
+    render_statuses, scale, status_args, statuses, target, unmarshal, Target,
+};
+
+/// How often to check for changes if `pw-dump --monitor` isn't available.
+const POLL: Duration = Duration::from_secs(1);
+
+/// How long `--animate` takes to slide to a volume that jumped.
+const JUMP: Duration = Duration::from_millis(150);
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    status_args(SubCommand::with_name("watch"))
+        .about("prints the status, then again every time it changes")
+        .arg(
+            Arg::with_name("dbus")
+                .long("dbus")
+                .help("also send a D-Bus signal when the volume or mute state changes"),
+        )
+        .arg(
+            Arg::with_name("animate")
+                .long("animate")
+                .value_name("FPS")
+                .help("also print the frames in between while the volume fades or jumps, at most FPS a second")
+                .takes_value(true)
+                .validator(|s| frame_interval(&s).map(|_| ())),
+        )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .value_name("FILE")
+                .help("print the status as it was after each update in a recording from `pw-volume record`, then exit")
+                .takes_value(true)
+                .conflicts_with_all(&["dbus", "animate"]),
+        )
+}
+
+/// The time between frames at `fps` frames a second.
+fn frame_interval(fps: &str) -> Result<Duration, String> {
+    fps.parse::<f64>()
+        .ok()
+        .filter(|fps| fps.is_finite() && *fps > 0.0 && *fps <= 1000.0)
+        .map(|fps| Duration::from_secs_f64(1.0 / fps))
+        .ok_or_else(|| format!("\"{}\" is not a number of frames a second", fps))
+}
+
+/// A node's volume on its way from one value to another.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Track {
+    from: f64,
+    to: f64,
+    duration: Duration,
+}
+
+/// The statuses last read, and how each one's volume moves there from what
+/// was shown before.
+struct Animation {
+    statuses: Vec<Status>,
+    tracks: Vec<Track>,
+    start: Instant,
+}
+
+impl Animation {
+    /// Animates to `statuses`, read at `start`, from the `shown` ones, or
+    /// without `animate`, goes straight there.
+    fn new(shown: &[Status], statuses: Vec<Status>, start: Instant, animate: bool) -> Animation {
+        let tracks = statuses
+            .iter()
+            .map(|status| match status.fade {
+                // a fade's own steps show up late, so run ahead to its target
+                Some(fade) if animate => Track {
+                    from: status.volume,
+                    to: fade.target,
+                    duration: Duration::from_secs_f64(fade.remaining.max(0.0)),
+                },
+                _ if animate => Track {
+                    from: shown
+                        .iter()
+                        .find(|shown| shown.id == status.id)
+                        .map_or(status.volume, |shown| shown.volume),
+                    to: status.volume,
+                    duration: JUMP,
+                },
+                _ => Track {
+                    from: status.volume,
+                    to: status.volume,
+                    duration: Duration::ZERO,
+                },
+            })
+            .collect();
+        Animation {
+            statuses,
+            tracks,
+            start,
+        }
+    }
+
+    /// The statuses as of `now`.
+    fn frame(&self, now: Instant) -> Vec<Status> {
+        let elapsed = now.saturating_duration_since(self.start);
+        self.statuses
+            .iter()
+            .zip(&self.tracks)
+            .map(|(status, track)| {
+                let progress = if elapsed >= track.duration {
+                    1.0
+                } else {
+                    elapsed.as_secs_f64() / track.duration.as_secs_f64()
+                };
+                let volume = track.from + (track.to - track.from) * progress;
+                let mut frame = status.clone();
+                // the channels keep their balance
+                let scale = scale(&status.node);
+                if scale.linear(status.volume) > 0.0 {
+                    let ratio = scale.linear(volume) / scale.linear(status.volume);
+                    for v in &mut frame.channel_volumes {
+                        *v *= ratio;
+                    }
+                }
+                frame.volume = volume;
+                if let Some(fade) = &mut frame.fade {
+                    fade.progress += (1.0 - fade.progress) * progress;
+                    fade.remaining = (fade.remaining - elapsed.as_secs_f64()).max(0.0);
+                }
+                frame
+            })
+            .collect()
+    }
+
+    /// Whether every volume has got where it was going by `now`.
+    fn done(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.start);
+        self.tracks.iter().all(|track| elapsed >= track.duration)
+    }
+}
+
+fn read(
+    matches: &ArgMatches<'_>,
+    config: &Config,
+    buf: &[u8],
+    signal: Option<&mut Signal>,
+) -> anyhow::Result<Vec<Status>> {
+    let obj = unmarshal(buf)?;
+    // an alias is looked up again each time, since its node may come and go
+    let (node, route) = parse_dump(&obj, target(matches, config, &obj)?)?;
+    if let Some(signal) = signal {
+        signal.update(node, route)?;
+    }
+    Ok(statuses(matches, &obj, node, route))
+}
+
+/// The degraded status in place of a failure with `--never-fail`, since the
+/// default sink can briefly be missing while devices come and go.
+fn degrade(matches: &ArgMatches<'_>, report: anyhow::Result<String>) -> anyhow::Result<String> {
+    match report {
+        Err(e) if matches.is_present("never-fail") => {
+            eprintln!("{:#}", e);
+            Ok(degraded_status(matches, &e))
+        }
+        report => report,
+    }
+}
+
+/// The reports `watch` would have printed after each of the updates, leaving
+/// out repeats as it does.
+fn replayed(
+    matches: &ArgMatches<'_>,
+    config: &Config,
+    records: Vec<Record>,
+) -> anyhow::Result<Vec<String>> {
+    let graph = Graph::replay();
+    let mut reports: Vec<String> = Vec::new();
+    for record in records {
+        graph.update(record.update);
+        let buf = graph.dump()?;
+        let report = read(matches, config, &buf, None).and_then(|statuses| {
+            render_statuses(matches, config, &statuses).map(|(report, _)| report)
+        });
+        match degrade(matches, report) {
+            Ok(report) if reports.last() != Some(&report) => reports.push(report),
+            Ok(_) => (),
+            Err(e) => eprintln!("{:#}", e),
+        }
+    }
+    Ok(reports)
+}
+
+/// Prints a report unless it was the last one printed, returning false once
+/// the bar has gone away.
+fn emit(last: &mut String, report: String) -> anyhow::Result<bool> {
+    if report == *last {
+        return Ok(true);
+    }
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match writeln!(out, "{}", report).and_then(|()| out.flush()) {
+        Ok(()) => {
+            *last = report;
+            Ok(true)
+        }
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn run(matches: &ArgMatches<'_>, config: &Config) -> anyhow::Result<()> {
+    // fail now on a --target that can't ever be found, rather than each time
+    if let Some(s) = matches.value_of("target") {
+        if !config.aliases.contains_key(s) {
+            s.parse::<Target>().map_err(anyhow::Error::msg)?;
+        }
+    }
+    let interval = matches
+        .value_of("animate")
+        .map(frame_interval)
+        .transpose()
+        .map_err(anyhow::Error::msg)?;
+    if let Some(path) = matches.value_of("replay") {
+        let buf = fs::read(path).with_context(|| format!("failed to read {}", path))?;
+        let records = record::read(&buf).with_context(|| format!("failed to read {}", path))?;
+        let mut last = String::new();
+        for report in replayed(matches, config, records)? {
+            if !emit(&mut last, report)? {
+                break;
+            }
+        }
+        return Ok(());
+    }
+    let changes = Changes::spawn();
+    let mut last = String::new();
+    let mut signal = matches.is_present("dbus").then(Signal::default);
+    let mut shown: Vec<Status> = Vec::new();
+    let mut animation: Option<Animation> = None;
+    let mut refresh = true;
+    loop {
+        if refresh {
+            animation = None;
+            let report = pw_dump().and_then(|buf| {
+                let statuses = read(matches, config, &buf, signal.as_mut())?;
+                // from when the statuses were read, since reading takes a while
+                let now = Instant::now();
+                let next = Animation::new(&shown, statuses, now, interval.is_some());
+                shown = next.frame(now);
+                animation = Some(next);
+                render_statuses(matches, config, &shown).map(|(report, _)| report)
+            });
+            match degrade(matches, report) {
+                Ok(report) => {
+                    if !emit(&mut last, report)? {
+                        return Ok(());
+                    }
+                }
+                Err(e) => eprintln!("{:#}", e),
+            }
+        } else if let Some(animation) = &animation {
+            shown = animation.frame(Instant::now());
+            let (report, _) = render_statuses(matches, config, &shown)?;
+            if !emit(&mut last, report)? {
+                return Ok(());
+            }
+        }
+        refresh = match (interval, &animation) {
+            (Some(interval), Some(animation)) if !animation.done(Instant::now()) => {
+                changes.wait(interval)
+            }
+            _ => {
+                changes.wait(POLL);
+                true
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::Fade;
+
+    use super::*;
+
+    fn status(volume: f64, fade: Option<Fade>) -> Status {
+        Status {
+            id: 53,
+            volume,
+            fade,
+            ..Status::default()
+        }
+    }
+
+    #[test]
+    fn slides_after_jumps() {
+        let start = Instant::now();
+        let animation = Animation::new(&[status(0.4, None)], vec![status(0.5, None)], start, true);
+        assert_eq!(animation.frame(start)[0].volume, 0.4);
+        let halfway = animation.frame(start + JUMP / 2)[0].volume;
+        assert!((halfway - 0.45).abs() < 1e-9, "{}", halfway);
+        assert!(!animation.done(start + JUMP / 2));
+        assert_eq!(animation.frame(start + JUMP)[0].volume, 0.5);
+        assert!(animation.done(start + JUMP));
+
+        let still = Animation::new(&[status(0.4, None)], vec![status(0.5, None)], start, false);
+        assert_eq!(still.frame(start)[0].volume, 0.5);
+        assert!(still.done(start));
+    }
+
+    #[test]
+    fn runs_ahead_of_fades() {
+        let start = Instant::now();
+        let fade = Fade {
+            target: 0.6,
+            progress: 0.5,
+            remaining: 2.0,
+        };
+        let animation = Animation::new(&[], vec![status(0.4, Some(fade))], start, true);
+        let frame = &animation.frame(start + Duration::from_secs(1))[0];
+        assert!((frame.volume - 0.5).abs() < 1e-9, "{}", frame.volume);
+        assert_eq!(
+            frame.fade,
+            Some(Fade {
+                target: 0.6,
+                progress: 0.75,
+                remaining: 1.0,
+            })
+        );
+        assert!(animation.done(start + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn replays() -> anyhow::Result<()> {
+        let path: std::path::PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src",
+            "testdata",
+            "dump_aria_16.txt",
+        ]
+        .iter()
+        .collect();
+        let dump: Vec<serde_json::Value> = serde_json::from_slice(&fs::read(path)?)?;
+        let device = |mute: bool| {
+            let mut device = dump.iter().find(|o| o["id"] == 53).unwrap().clone();
+            device["info"]["params"]["Route"][0]["props"]["mute"] = mute.into();
+            device
+        };
+        let record = |update| Record {
+            at_ms: None,
+            events: Vec::new(),
+            update,
+        };
+        let records = vec![
+            record(dump.clone()),
+            record(vec![device(true)]),
+            // the same again, which isn't printed twice
+            record(vec![device(true)]),
+            record(vec![device(false)]),
+        ];
+        let matches = subcommand().get_matches_from(["watch", "--output", "plain"]);
+        assert_eq!(
+            replayed(&matches, &Config::default(), records)?,
+            [
+                "Headset (bluez_output.18_54_CF_71_8C_FA.a2dp-sink): 6% [unmuted]",
+                "Headset (bluez_output.18_54_CF_71_8C_FA.a2dp-sink): 6% [muted]",
+                "Headset (bluez_output.18_54_CF_71_8C_FA.a2dp-sink): 6% [unmuted]",
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+//! The wpctl backend, for systems that ship WirePlumber's `wpctl` without
+//! PipeWire's own tools: the basic subcommands are translated to
+//! `wpctl get-volume`, `set-volume`, and `set-mute`.
+//!
+//! wpctl works on the node rather than the device's route, and its volumes
+//! are cubic, as in pavucontrol.
+
+use anyhow::anyhow;
+use clap::ArgMatches;
+
+use crate::{
+    backend::Node,
+    config::Config,
+    dry_run,
+    error::{self, Failure, Kind},
+    format::Status,
+    log, scale, shell_words, tools, Target,
+};
+
+/// Runs wpctl, returning what it printed.
+fn wpctl(args: &[&str]) -> anyhow::Result<String> {
+    log::debug(1, format_args!("running {}", shell_words("wpctl", args)));
+    let output =
+        tools::output(tools::command("wpctl").args(args)).map_err(|e| error::tool("wpctl", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Failure::new(
+            Kind::NoNode,
+            format!("wpctl {} failed: {}", args.join(" "), stderr.trim()),
+        )
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Runs wpctl to change the graph, unless this is a dry run.
+fn set(args: &[&str]) -> anyhow::Result<()> {
+    if dry_run("wpctl", args) {
+        return Ok(());
+    }
+    wpctl(args).map(|_| ())
+}
+
+/// Parses `wpctl get-volume`'s output, e.g. `Volume: 0.40 [MUTED]`, into the
+/// cubic volume and whether the node is muted.
+fn parse_volume(output: &str) -> Option<(f64, bool)> {
+    let rest = output.trim().strip_prefix("Volume:")?;
+    let mut words = rest.split_whitespace();
+    let volume = words.next()?.parse().ok()?;
+    Some((volume, words.any(|word| word == "[MUTED]")))
+}
+
+/// The id, name, and description in `wpctl inspect`'s output, whose first
+/// line is e.g. `id 56, type PipeWire:Interface:Node`, and whose properties
+/// are `key = "value"`, some marked with a `*`.
+fn parse_inspect(output: &str) -> Option<(i64, String, Option<String>)> {
+    let id = output
+        .lines()
+        .next()?
+        .strip_prefix("id ")?
+        .split(',')
+        .next()?
+        .parse()
+        .ok()?;
+    let prop = |key: &str| {
+        output.lines().find_map(|line| {
+            let (k, v) = line.trim_start_matches([' ', '*']).split_once(" = ")?;
+            (k == key).then(|| v.trim_matches('"').to_string())
+        })
+    };
+    Some((id, prop("node.name")?, prop("node.description")))
+}
+
+/// A node as wpctl refers to it, by id or as a default, with what
+/// `wpctl inspect` said about it.
+pub struct Wpctl {
+    target: String,
+    id: i64,
+    node: String,
+    name: Option<String>,
+}
+
+impl Wpctl {
+    fn inspect(target: String) -> anyhow::Result<Wpctl> {
+        let output = wpctl(&["inspect", &target])?;
+        let (id, node, name) = parse_inspect(&output)
+            .ok_or_else(|| anyhow!("unexpected output from wpctl inspect"))?;
+        Ok(Wpctl {
+            target,
+            id,
+            node,
+            name,
+        })
+    }
+}
+
+impl Node for Wpctl {
+    fn status(&self) -> anyhow::Result<Status> {
+        let (volume, mute) = self.volume()?;
+        Ok(Status {
+            id: self.id,
+            volume,
+            mute,
+            name: self.name.clone().unwrap_or_else(|| self.node.clone()),
+            node: self.node.clone(),
+            ..Status::default()
+        })
+    }
+
+    fn volume(&self) -> anyhow::Result<(f64, bool)> {
+        let output = wpctl(&["get-volume", &self.target])?;
+        let (cubic, mute) = parse_volume(&output)
+            .ok_or_else(|| anyhow!("unexpected output from wpctl get-volume: {}", output.trim()))?;
+        Ok((scale(&self.node).scaled(cubic.powi(3)), mute))
+    }
+
+    fn set_volume(&self, volume: f64) -> anyhow::Result<()> {
+        let cubic = format!("{:.4}", volume.cbrt());
+        set(&["set-volume", &self.target, &cubic])
+    }
+
+    fn set_mute(&self, mute: Option<bool>) -> anyhow::Result<()> {
+        let mute = match mute {
+            Some(true) => "1",
+            Some(false) => "0",
+            None => "toggle",
+        };
+        set(&["set-mute", &self.target, mute])
+    }
+}
+
+/// The `--target` node. wpctl has no way to look up the names that aliases
+/// stand for.
+pub fn node(matches: &ArgMatches<'_>, config: &Config) -> anyhow::Result<Wpctl> {
+    let target = match matches.value_of("target") {
+        Some(alias) if config.aliases.contains_key(alias) => {
+            return Err(Failure::new(
+                Kind::MissingTool,
+                "aliases are looked up with pw-dump, which was not found",
+            )
+            .into())
+        }
+        Some(s) => s.parse().map_err(anyhow::Error::msg)?,
+        None => Target::default(),
+    };
+    Wpctl::inspect(match target {
+        Target::DefaultSink => "@DEFAULT_AUDIO_SINK@".to_string(),
+        Target::DefaultSource => "@DEFAULT_AUDIO_SOURCE@".to_string(),
+        Target::Id(id) => id.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("Volume: 0.40\n" => Some((0.4, false)))]
+    #[test_case("Volume: 1.00 [MUTED]\n" => Some((1.0, true)))]
+    #[test_case("Volume: loud" => None)]
+    #[test_case("" => None)]
+    fn get_volume(output: &str) -> Option<(f64, bool)> {
+        parse_volume(output)
+    }
+
+    #[test]
+    fn inspect() {
+        let output = r#"id 56, type PipeWire:Interface:Node
+    alsa.card = "0"
+    media.class = "Audio/Sink"
+  * node.description = "Built-in Audio Analog Stereo"
+  * node.name = "alsa_output.pci-0000_00_1f.3.analog-stereo"
+    object.serial = "57"
+"#;
+        assert_eq!(
+            parse_inspect(output),
+            Some((
+                56,
+                "alsa_output.pci-0000_00_1f.3.analog-stereo".to_string(),
+                Some("Built-in Audio Analog Stereo".to_string())
+            ))
+        );
+    }
+}